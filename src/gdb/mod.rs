@@ -0,0 +1,376 @@
+use std::collections::HashSet;
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+
+use crate::vsoc::Vsoc;
+
+// Stop replies
+const SIGINT: u8 = 2;
+const SIGTRAP: u8 = 5;
+const SIGSEGV: u8 = 11;
+
+// Steps between two checks for a GDB interrupt while running
+const POLL_STEPS: u64 = 4096;
+
+#[derive(Debug, PartialEq)]
+pub enum Session {
+    Detached,
+    Killed,
+}
+
+#[derive(Debug, PartialEq)]
+enum Action {
+    Reply(String),
+    Resume(bool),
+    Detach,
+    Kill,
+}
+
+fn checksum(data: &str) -> u8 {
+    data.bytes().fold(0u8, |acc, b| acc.wrapping_add(b))
+}
+
+fn to_hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+// "addr,len" as used by the memory and qXfer packets
+fn addr_len(s: &str) -> Option<(u64, usize)> {
+    let (addr, len) = s.split_once(',')?;
+
+    Some((
+        u64::from_str_radix(addr, 16).ok()?,
+        usize::from_str_radix(len, 16).ok()?,
+    ))
+}
+
+// Debugger state independent from the connection
+#[derive(Debug, Default)]
+struct Stub {
+    breakpoints: HashSet<u64>,
+}
+
+impl Stub {
+    fn handle(&mut self, vsoc: &mut Vsoc, packet: &str) -> Action {
+        let reply = |s: &str| Action::Reply(String::from(s));
+        let (cmd, args) = packet.split_at(packet.len().min(1));
+
+        match cmd {
+            "?" => Action::Reply(format!("S{:02x}", SIGTRAP)),
+            "g" => {
+                let regs: Option<Vec<String>> = (0..=32)
+                    .map(|n| vsoc.read_register(n).map(|v| to_hex(&v)))
+                    .collect();
+
+                match regs {
+                    Some(r) => Action::Reply(r.concat()),
+                    None => reply("E01"),
+                }
+            }
+            "G" => {
+                let data: Vec<u8> = match from_hex(args) {
+                    Some(d) => d,
+                    None => return reply("E01"),
+                };
+                let width: usize = data.len() / 33;
+
+                for (n, value) in data.chunks(width.max(1)).take(33).enumerate() {
+                    vsoc.write_register(n, value);
+                }
+                reply("OK")
+            }
+            "p" => match usize::from_str_radix(args, 16)
+                .ok()
+                .and_then(|n| vsoc.read_register(n))
+            {
+                Some(v) => Action::Reply(to_hex(&v)),
+                None => reply("E01"),
+            },
+            "P" => {
+                let written: Option<bool> = args.split_once('=').and_then(|(n, v)| {
+                    Some(vsoc.write_register(usize::from_str_radix(n, 16).ok()?, &from_hex(v)?))
+                });
+
+                match written {
+                    Some(true) => reply("OK"),
+                    _ => reply("E01"),
+                }
+            }
+            "m" => match addr_len(args).and_then(|(a, l)| vsoc.read_memory(a, l)) {
+                Some(v) => Action::Reply(to_hex(&v)),
+                None => reply("E14"),
+            },
+            "M" => {
+                let written: Option<bool> = args.split_once(':').and_then(|(al, data)| {
+                    let (addr, _) = addr_len(al)?;
+
+                    Some(vsoc.write_memory(addr, &from_hex(data)?))
+                });
+
+                match written {
+                    Some(true) => reply("OK"),
+                    _ => reply("E14"),
+                }
+            }
+            "Z" | "z" if args.starts_with("0,") => match addr_len(&args[2..]) {
+                Some((addr, _)) => {
+                    if cmd == "Z" {
+                        self.breakpoints.insert(addr);
+                    } else {
+                        self.breakpoints.remove(&addr);
+                    }
+                    reply("OK")
+                }
+                None => reply("E01"),
+            },
+            "s" => Action::Resume(true),
+            "c" => Action::Resume(false),
+            "D" => Action::Detach,
+            "k" => Action::Kill,
+            "H" => reply("OK"),
+            "q" => self.query(vsoc, packet),
+            _ => reply(""),
+        }
+    }
+
+    fn query(&self, vsoc: &mut Vsoc, packet: &str) -> Action {
+        if packet.starts_with("qSupported") {
+            return Action::Reply(String::from(
+                "PacketSize=4000;qXfer:features:read+;swbreak+",
+            ));
+        }
+
+        if let Some(args) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+            let xml: String = vsoc.target_xml();
+            let (offset, len) = match addr_len(args) {
+                Some((o, l)) => (o as usize, l),
+                None => return Action::Reply(String::from("E01")),
+            };
+            let start: usize = offset.min(xml.len());
+            let end: usize = (start + len).min(xml.len());
+            let more: &str = if end < xml.len() { "m" } else { "l" };
+
+            return Action::Reply(format!("{}{}", more, &xml[start..end]));
+        }
+
+        let s: &str = match packet {
+            "qAttached" => "1",
+            "qC" => "QC1",
+            "qfThreadInfo" => "m1",
+            "qsThreadInfo" => "l",
+            _ => "",
+        };
+
+        Action::Reply(String::from(s))
+    }
+
+    // Run until a breakpoint, the end of the program or an interruption
+    fn resume(&self, vsoc: &mut Vsoc, step: bool, interrupted: &mut dyn FnMut() -> bool) -> String {
+        let mut steps: u64 = 0;
+
+        loop {
+            let e = vsoc.step();
+
+            if let Some(code) = vsoc.exit_code() {
+                return format!("W{:02x}", code as u8);
+            }
+
+            if e.is_some() {
+                return format!("S{:02x}", SIGSEGV);
+            }
+
+            if step {
+                return format!("S{:02x}", SIGTRAP);
+            }
+
+            if self.breakpoints.contains(&vsoc.pc()) {
+                return format!("T{:02x}swbreak:;", SIGTRAP);
+            }
+
+            steps += 1;
+            if steps.is_multiple_of(POLL_STEPS) && interrupted() {
+                return format!("S{:02x}", SIGINT);
+            }
+        }
+    }
+}
+
+struct Connection {
+    stream: TcpStream,
+}
+
+impl Connection {
+    fn read_byte(&mut self) -> io::Result<u8> {
+        let mut b: [u8; 1] = [0];
+
+        match self.stream.read(&mut b)? {
+            0 => Err(io::Error::from(ErrorKind::UnexpectedEof)),
+            _ => Ok(b[0]),
+        }
+    }
+
+    // Wait for the next packet, acknowledging it
+    fn read_packet(&mut self) -> io::Result<String> {
+        loop {
+            while self.read_byte()? != b'$' {}
+
+            let mut data: Vec<u8> = Vec::new();
+            let mut b: u8 = self.read_byte()?;
+
+            while b != b'#' {
+                data.push(b);
+                b = self.read_byte()?;
+            }
+
+            let sum: String =
+                String::from_utf8_lossy(&[self.read_byte()?, self.read_byte()?]).into_owned();
+            let data: String = String::from_utf8_lossy(&data).into_owned();
+
+            if u8::from_str_radix(&sum, 16).ok() == Some(checksum(&data)) {
+                self.stream.write_all(b"+")?;
+                return Ok(data);
+            }
+
+            self.stream.write_all(b"-")?;
+        }
+    }
+
+    fn write_packet(&mut self, data: &str) -> io::Result<()> {
+        loop {
+            write!(self.stream, "${}#{:02x}", data, checksum(data))?;
+
+            match self.read_byte()? {
+                b'-' => continue,
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    // Check for a pending ^C without blocking
+    fn interrupted(&mut self) -> bool {
+        let mut b: [u8; 1] = [0];
+
+        if self.stream.set_nonblocking(true).is_err() {
+            return false;
+        }
+
+        let r: bool = matches!(self.stream.read(&mut b), Ok(1) if b[0] == 0x03);
+
+        self.stream.set_nonblocking(false).ok();
+        r
+    }
+}
+
+// Serve the GDB remote serial protocol on localhost until GDB detaches or kills the target
+pub fn serve(vsoc: &mut Vsoc, port: u16) -> io::Result<Session> {
+    let listener: TcpListener = TcpListener::bind(("127.0.0.1", port))?;
+
+    println!("> vemu: gdb: waiting on 127.0.0.1:{}...", port);
+
+    let (stream, peer) = listener.accept()?;
+    let mut conn: Connection = Connection { stream };
+    let mut stub: Stub = Stub::default();
+
+    println!("> vemu: gdb: connected to {}", peer);
+
+    loop {
+        let packet: String = conn.read_packet()?;
+
+        match stub.handle(vsoc, &packet) {
+            Action::Reply(r) => conn.write_packet(&r)?,
+            Action::Resume(step) => {
+                let r: String = stub.resume(vsoc, step, &mut || conn.interrupted());
+
+                conn.write_packet(&r)?;
+            }
+            Action::Detach => {
+                conn.write_packet("OK")?;
+                return Ok(Session::Detached);
+            }
+            Action::Kill => return Ok(Session::Killed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{checksum, from_hex, Action, Stub};
+    use crate::vsoc::Vsoc;
+
+    // addi a0, zero, 1; addi a0, a0, 1; j .
+    const PROGRAM: [u32; 3] = [0x0010_0513, 0x0015_0513, 0x0000_006f];
+
+    fn reply(s: &str) -> Action {
+        Action::Reply(String::from(s))
+    }
+
+    fn setup(arch: &String) -> Vsoc<'_> {
+        let mut vsoc: Vsoc = Vsoc::new(arch);
+        let binary: Vec<u8> = PROGRAM.iter().flat_map(|i| i.to_le_bytes()).collect();
+
+        vsoc.load(&binary).unwrap();
+        vsoc
+    }
+
+    #[test]
+    fn test_packet_helpers() {
+        assert_eq!(checksum("OK"), 0x9a);
+        assert_eq!(from_hex("0a1B"), Some(vec![0x0a, 0x1b]));
+        assert_eq!(from_hex("0a1"), None);
+    }
+
+    #[test]
+    fn test_registers_memory() {
+        let arch: String = String::from("rv32i_zicsr");
+        let mut vsoc: Vsoc = setup(&arch);
+        let mut stub: Stub = Stub::default();
+
+        assert_eq!(stub.handle(&mut vsoc, "p20"), reply("00000080"));
+        assert_eq!(stub.handle(&mut vsoc, "Pa=78563412"), reply("OK"));
+        assert_eq!(stub.handle(&mut vsoc, "pa"), reply("78563412"));
+        assert_eq!(stub.handle(&mut vsoc, "m80000000,4"), reply("13051000"));
+        assert_eq!(stub.handle(&mut vsoc, "M80000100,2:beef"), reply("OK"));
+        assert_eq!(stub.handle(&mut vsoc, "m80000100,2"), reply("beef"));
+        assert_eq!(stub.handle(&mut vsoc, "m10,4"), reply("E14"));
+
+        match stub.handle(&mut vsoc, "g") {
+            Action::Reply(r) => assert_eq!(r.len(), 33 * 8),
+            a => panic!("{:?}", a),
+        }
+        match stub.handle(&mut vsoc, "qXfer:features:read:target.xml:0,a") {
+            Action::Reply(r) => assert_eq!(r, "m<?xml vers"),
+            a => panic!("{:?}", a),
+        }
+    }
+
+    #[test]
+    fn test_step_breakpoint() {
+        let arch: String = String::from("rv32i_zicsr");
+        let mut vsoc: Vsoc = setup(&arch);
+        let mut stub: Stub = Stub::default();
+
+        assert_eq!(stub.handle(&mut vsoc, "s"), Action::Resume(true));
+        assert_eq!(stub.resume(&mut vsoc, true, &mut || false), "S05");
+        assert_eq!(vsoc.pc(), 0x8000_0004);
+
+        assert_eq!(stub.handle(&mut vsoc, "Z0,80000008,4"), reply("OK"));
+        assert_eq!(stub.resume(&mut vsoc, false, &mut || false), "T05swbreak:;");
+        assert_eq!(vsoc.pc(), 0x8000_0008);
+        assert_eq!(stub.handle(&mut vsoc, "pa"), reply("02000000"));
+
+        // The loop only stops when interrupted once the breakpoint is removed
+        assert_eq!(stub.handle(&mut vsoc, "z0,80000008,4"), reply("OK"));
+        assert_eq!(stub.resume(&mut vsoc, false, &mut || true), "S02");
+    }
+}
@@ -1,43 +1,256 @@
+pub mod gdb;
+pub mod runner;
 pub mod vsoc;
 
+use std::path::Path;
 use std::{fs::File, io::Read};
 
+use crate::vsoc::machine::Machine;
+use crate::vsoc::replay::{Player, Recorder};
+use crate::vsoc::trace::Trace;
 use crate::vsoc::Vsoc;
 use clap::Parser;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Args {
-    /// Binary path
-    #[arg(short, long)]
-    binary: String,
+    /// Binary path, an ELF file or a raw binary loaded in sram
+    #[arg(short, long, required_unless_present_any = ["suite", "dump_dtb", "snapshot_in", "bench"])]
+    binary: Option<String>,
+
+    /// Vsoc description, overrides the isa of the machine description
+    #[arg(short, long, required_unless_present = "machine")]
+    arch: Option<String>,
 
-    /// Vsoc description
+    /// Machine description file: cpu, memory map and peripherals
     #[arg(short, long)]
-    arch: String,
+    machine: Option<String>,
+
+    /// Number of harts, overrides the machine description
+    #[arg(long)]
+    harts: Option<usize>,
+
+    /// Write the generated device tree to a file, as source when it ends with .dts
+    #[arg(long)]
+    dump_dtb: Option<String>,
+
+    /// HTIF tohost address, defaults to the `tohost` ELF symbol
+    #[arg(long, value_parser = parse_address)]
+    tohost: Option<u64>,
+
+    /// Run the riscv-tests of a directory matching the architecture
+    #[arg(long)]
+    suite: Option<String>,
+
+    /// Wait for a GDB connection on this localhost port before running
+    #[arg(long)]
+    gdb: Option<u16>,
+
+    /// Trace executed instructions to stderr, or to a file with --trace=<file>
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "-")]
+    trace: Option<String>,
+
+    /// Resume the machine from a snapshot, after loading the binary if any
+    #[arg(long)]
+    snapshot_in: Option<String>,
+
+    /// Save the machine to a snapshot after --snapshot-at steps, then exit
+    #[arg(long, requires = "snapshot_at")]
+    snapshot_out: Option<String>,
+
+    /// Steps after which --snapshot-out is saved
+    #[arg(long, requires = "snapshot_out")]
+    snapshot_at: Option<u64>,
+
+    /// Log the console input with the step it arrived at
+    #[arg(long, conflicts_with = "replay")]
+    record: Option<String>,
+
+    /// Feed the console input from a --record log instead of stdin
+    #[arg(long)]
+    replay: Option<String>,
+
+    /// Run the built-in benchmark for this number of steps and report the speed
+    #[arg(long)]
+    bench: Option<u64>,
+
+    /// Steps after which a riscv-tests run is reported as a timeout
+    #[arg(long, default_value_t = 10_000_000)]
+    max_steps: u64,
+}
+
+fn parse_address(s: &str) -> Result<u64, String> {
+    let digits: &str = s.trim_start_matches("0x").trim_start_matches("0X");
+
+    u64::from_str_radix(&digits.replace('_', ""), 16).map_err(|e| e.to_string())
 }
 
 fn main() {
     let args = Args::parse();
-    let mut file = File::open(&args.binary).unwrap();
-    let mut contents = Vec::new();
-    let vsoc_name: String = args.arch;
-    let mut vsoc: Vsoc = Vsoc::new(&vsoc_name);
+    let mut machine: Machine = match &args.machine {
+        Some(path) => match std::fs::read_to_string(path) {
+            Ok(text) => match Machine::parse(&text) {
+                Ok(m) => m,
+                Err(e) => {
+                    println!("< vemu: machine: {}: {}", path, e);
+                    std::process::exit(1);
+                }
+            },
+            Err(e) => {
+                println!("< vemu: machine: cannot open {}: {}", path, e);
+                std::process::exit(1);
+            }
+        },
+        None => Machine::board(""),
+    };
+
+    if let Some(arch) = args.arch {
+        machine.isa = arch;
+    }
+    if let Some(harts) = args.harts {
+        machine.harts = harts;
+    }
+
+    let vsoc_name: String = machine.isa.clone();
+
+    if let Some(dir) = args.suite {
+        let passed: bool = runner::suite(&vsoc_name, Path::new(&dir), args.max_steps);
+
+        std::process::exit(if passed { 0 } else { 1 });
+    }
+
+    if let Some(steps) = args.bench {
+        match runner::bench(&vsoc_name, steps) {
+            Ok(speed) => println!("< vemu: bench: {}: {:.0} steps/s", vsoc_name, speed),
+            Err(e) => {
+                println!("< vemu: bench: {}: {}", vsoc_name, e);
+                std::process::exit(1);
+            }
+        }
+        std::process::exit(0);
+    }
+
+    let mut vsoc: Vsoc = match Vsoc::with_machine(&machine) {
+        Ok(vsoc) => vsoc,
+        Err(e) => {
+            println!("< vemu: machine: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    if let Some(path) = args.dump_dtb {
+        let tree = vsoc.device_tree();
+        let data: Vec<u8> = if path.ends_with(".dts") {
+            tree.to_dts().into_bytes()
+        } else {
+            tree.to_dtb()
+        };
+
+        if let Err(e) = std::fs::write(&path, data) {
+            println!("< vemu: dtb: cannot write {}: {}", path, e);
+            std::process::exit(1);
+        }
+    }
+
+    if args.binary.is_none() && args.snapshot_in.is_none() {
+        std::process::exit(0);
+    }
 
     println!("> vemu");
     println!("{}", vsoc);
-    println!("> vemu: vsoc: load flash from {}...", &args.binary);
-    file.read_to_end(&mut contents).unwrap();
+    if let Some(binary) = args.binary {
+        let mut file = File::open(&binary).unwrap();
+        let mut contents = Vec::new();
+
+        println!("> vemu: vsoc: load flash from {}...", &binary);
+        file.read_to_end(&mut contents).unwrap();
+        if let Err(e) = vsoc.load(&contents) {
+            println!("< vemu: vsoc: {}: cannot load {}: {}", vsoc_name, &binary, e);
+            std::process::exit(1);
+        }
+    }
+    if let Some(path) = args.snapshot_in {
+        println!("> vemu: vsoc: restore {}...", path);
+        if let Err(e) = vsoc.restore(&path) {
+            println!("< vemu: vsoc: {}: cannot restore {}: {}", vsoc_name, path, e);
+            std::process::exit(1);
+        }
+    }
+    if let Some(tohost) = args.tohost {
+        vsoc.set_tohost(tohost);
+    }
+    if let Some(path) = args.trace {
+        match Trace::new(&path) {
+            Ok(t) => vsoc.set_trace(t),
+            Err(e) => {
+                println!("< vemu: trace: cannot open {}: {}", path, e);
+                std::process::exit(1);
+            }
+        }
+    }
+    if let Some(port) = args.gdb {
+        match gdb::serve(&mut vsoc, port) {
+            Ok(gdb::Session::Detached) => (),
+            Ok(gdb::Session::Killed) => std::process::exit(vsoc.exit_code().unwrap_or(0) as i32),
+            Err(e) => {
+                println!("< vemu: gdb: {}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+    if let Some(path) = args.record {
+        match Recorder::new(&path) {
+            Ok(r) => vsoc.record(r),
+            Err(e) => {
+                println!("< vemu: record: cannot open {}: {}", path, e);
+                std::process::exit(1);
+            }
+        }
+    }
+    match args.replay {
+        Some(path) => match Player::open(&path) {
+            Ok(p) => vsoc.replay(p),
+            Err(e) => {
+                println!("< vemu: replay: cannot open {}: {}", path, e);
+                std::process::exit(1);
+            }
+        },
+        None => vsoc.attach_stdin(),
+    }
     println!("> vemu: vsoc: {}: run...", vsoc_name);
-    vsoc.load(&contents);
+
+    let mut steps: u64 = 0;
+
     loop {
-        if let Some(e) = vsoc.step() {
+        let e = vsoc.step();
+
+        steps += 1;
+        if let (Some(path), Some(at)) = (&args.snapshot_out, args.snapshot_at) {
+            if steps == at {
+                if let Err(e) = vsoc.save(path) {
+                    println!("< vemu: vsoc: {}: cannot save {}: {}", vsoc_name, path, e);
+                    std::process::exit(1);
+                }
+                println!("< vemu: vsoc: {}: saved to {} after {} steps", vsoc_name, path, steps);
+                std::process::exit(0);
+            }
+        }
+
+        if let Some(code) = vsoc.exit_code() {
+            match code {
+                0 => println!("< vemu: vsoc: {}: PASS", vsoc_name),
+                n => println!("< vemu: vsoc: {}: FAIL (test {})", vsoc_name, n),
+            }
+            std::process::exit(code as i32);
+        }
+
+        if let Some(e) = e {
             println!("Exception: {}", e);
 
             println!("< vemu: vsoc: {}: halt", vsoc_name);
             println!("{}", vsoc);
             println!("< vemu");
-            return;
+            std::process::exit(1);
         }
     }
 }
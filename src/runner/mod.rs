@@ -0,0 +1,237 @@
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use crate::vsoc::Vsoc;
+
+#[derive(Debug, PartialEq)]
+pub enum Outcome {
+    Pass,
+    Fail(u64),
+    Exception(String),
+    Timeout,
+    Error(String),
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Pass => write!(f, "PASS"),
+            Self::Fail(n) => write!(f, "FAIL (test {})", n),
+            Self::Exception(e) => write!(f, "EXCEPTION ({})", e),
+            Self::Timeout => write!(f, "TIMEOUT"),
+            Self::Error(e) => write!(f, "ERROR ({})", e),
+        }
+    }
+}
+
+// Run until the program reports its result through HTIF
+pub fn run(vsoc: &mut Vsoc, max_steps: u64) -> Outcome {
+    for _ in 0..max_steps {
+        let e = vsoc.step();
+
+        match vsoc.exit_code() {
+            Some(0) => return Outcome::Pass,
+            Some(n) => return Outcome::Fail(n),
+            None => (),
+        }
+
+        if let Some(e) = e {
+            return Outcome::Exception(e.to_string());
+        }
+    }
+
+    Outcome::Timeout
+}
+
+// riscv-tests names are rv<xlen><env><extension>-<p|v>-<test>, e.g. rv64ui-p-add
+// or rv32uzba-p-sh1add: keep the physical memory tests the architecture supports
+pub fn selected(arch: &str, name: &str) -> bool {
+    let base: &str = arch.split('_').next().unwrap_or("");
+    let xlen: &str = base
+        .trim_start_matches("rv")
+        .split(|c: char| !c.is_ascii_digit())
+        .next()
+        .unwrap_or("");
+    let letters: &str = &base[2 + xlen.len()..];
+    let mut parts = name.splitn(3, '-');
+    let group: &str = parts.next().unwrap_or("");
+    let env: &str = parts.next().unwrap_or("");
+
+    if name.contains('.') || env != "p" || !group.starts_with(&format!("rv{}", xlen)) {
+        return false;
+    }
+
+    let group: &str = &group[2 + xlen.len()..];
+    let (mode, ext) = match group.chars().next() {
+        Some(m) => (m, &group[1..]),
+        None => return false,
+    };
+    let mode_supported: bool = match mode {
+        'u' => true,
+        's' => letters.contains('s') && arch.contains("zicsr"),
+        'm' => arch.contains("zicsr"),
+        _ => false,
+    };
+    let ext_supported: bool = if ext.starts_with('z') {
+        arch.split('_').any(|e| e == ext)
+    } else {
+        !ext.is_empty() && ext.chars().all(|c| letters.contains(c))
+    };
+
+    mode_supported && ext_supported
+}
+
+fn run_file(arch: &String, path: &Path, max_steps: u64) -> Outcome {
+    let contents: Vec<u8> = match fs::read(path) {
+        Ok(c) => c,
+        Err(e) => return Outcome::Error(e.to_string()),
+    };
+    let mut vsoc: Vsoc = Vsoc::new(arch);
+
+    if let Err(e) = vsoc.load(&contents) {
+        return Outcome::Error(e.to_string());
+    }
+
+    if vsoc.symbol("tohost").is_none() {
+        return Outcome::Error(String::from("no tohost symbol"));
+    }
+
+    run(&mut vsoc, max_steps)
+}
+
+// Run the riscv-tests of a directory matching the architecture, returns
+// whether they all passed
+pub fn suite(arch: &String, dir: &Path, max_steps: u64) -> bool {
+    let mut tests: Vec<PathBuf> = match fs::read_dir(dir) {
+        Ok(entries) => entries
+            .filter_map(|e| e.ok().map(|e| e.path()))
+            .filter(|p| {
+                p.file_name()
+                    .and_then(|n| n.to_str())
+                    .is_some_and(|n| selected(arch, n))
+            })
+            .collect(),
+        Err(e) => {
+            println!("< vemu: suite: {}: {}", dir.display(), e);
+            return false;
+        }
+    };
+    let mut results: Vec<(String, Outcome)> = Vec::new();
+
+    tests.sort();
+    for path in tests.iter() {
+        let name: String = path.file_name().unwrap().to_string_lossy().into_owned();
+
+        results.push((name, run_file(arch, path, max_steps)));
+    }
+
+    let passed: usize = results.iter().filter(|(_, r)| *r == Outcome::Pass).count();
+
+    println!("> vemu: suite: {} on {}", arch, dir.display());
+    for (name, result) in results.iter() {
+        println!("  {:<32} {}", name, result);
+    }
+    println!("< vemu: suite: {}/{} passed", passed, results.len());
+
+    passed == results.len()
+}
+
+// auipc gp, 0; addi gp, gp, 256; 1: li t0, 64; mv t1, gp
+// 2: lw t2, 0(t1); add t2, t2, t0; xor t3, t2, t0; slli t3, t3, 1; sw t3, 0(t1)
+// addi t1, t1, 4; addi t0, t0, -1; bnez t0, 2b; j 1b
+const BENCH: [u32; 13] = [
+    0x0000_0197,
+    0x1001_8193,
+    0x0400_0293,
+    0x0001_8313,
+    0x0003_2383,
+    0x0053_83b3,
+    0x0053_ce33,
+    0x001e_1e13,
+    0x01c3_2023,
+    0x0043_0313,
+    0xfff2_8293,
+    0xfe02_92e3,
+    0xfd9f_f06f,
+];
+
+// Run an integer loop for a number of steps, returns the steps per second
+pub fn bench(arch: &String, steps: u64) -> Result<f64, String> {
+    let binary: Vec<u8> = BENCH.iter().flat_map(|i| i.to_le_bytes()).collect();
+    let mut vsoc: Vsoc = Vsoc::new(arch);
+
+    vsoc.load(&binary).map_err(|e| e.to_string())?;
+
+    let start: Instant = Instant::now();
+
+    for _ in 0..steps {
+        if let Some(e) = vsoc.step() {
+            return Err(e.to_string());
+        }
+    }
+
+    let elapsed: Duration = start.elapsed();
+
+    Ok(steps as f64 / elapsed.as_secs_f64())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{bench, run, selected, Outcome};
+    use crate::vsoc::Vsoc;
+
+    // lui t1, 0x80001; addi t0, zero, <value>; sw t0, 0(t1); j .
+    fn program(value: u32) -> Vec<u8> {
+        [0x8000_1337, (value << 20) | 0x293, 0x0053_2023, 0x0000_006f]
+            .iter()
+            .flat_map(|i: &u32| i.to_le_bytes())
+            .collect()
+    }
+
+    #[test]
+    fn test_run() {
+        let arch: String = String::from("rv32i_zicsr");
+        let mut vsoc: Vsoc = Vsoc::new(&arch);
+
+        vsoc.load(&program(1)).unwrap();
+        vsoc.set_tohost(0x8000_1000);
+        assert_eq!(run(&mut vsoc, 100), Outcome::Pass);
+
+        let mut vsoc: Vsoc = Vsoc::new(&arch);
+
+        vsoc.load(&program(7)).unwrap();
+        vsoc.set_tohost(0x8000_1000);
+        assert_eq!(run(&mut vsoc, 100), Outcome::Fail(3));
+
+        let mut vsoc: Vsoc = Vsoc::new(&arch);
+
+        vsoc.load(&program(7)).unwrap();
+        assert_eq!(run(&mut vsoc, 100), Outcome::Timeout);
+    }
+
+    #[test]
+    fn test_bench() {
+        for arch in ["rv32i", "rv64i", "rv128i"] {
+            assert!(bench(&String::from(arch), 1000).unwrap() > 0.0);
+        }
+    }
+
+    #[test]
+    fn test_selected() {
+        let arch: &str = "rv64imac_zicsr_zifencei_zba";
+
+        assert!(selected(arch, "rv64ui-p-add"));
+        assert!(selected(arch, "rv64uc-p-rvc"));
+        assert!(selected(arch, "rv64mi-p-csr"));
+        assert!(selected(arch, "rv64uzba-p-sh1add"));
+        assert!(!selected(arch, "rv64ui-p-add.dump"));
+        assert!(!selected(arch, "rv64ui-v-add"));
+        assert!(!selected(arch, "rv32ui-p-add"));
+        assert!(!selected(arch, "rv64uf-p-fadd"));
+        assert!(!selected(arch, "rv64si-p-csr"));
+        assert!(!selected(arch, "rv64uzbb-p-andn"));
+        assert!(selected("rv32imsu_zicsr", "rv32si-p-csr"));
+    }
+}
@@ -1,12 +1,28 @@
 use std::fmt;
 
-use super::riscv::hart::Rv;
+use super::riscv::hart::{arch_xlen, Rv};
+use super::riscv::interrupt::RvInterrupt;
+use super::riscv::xlen::Xlen;
 use super::state::State;
-use crate::vsoc::{arch::interface::ArchInterface, bus::Bus, VsocException};
+use crate::vsoc::snapshot::{Reader, SnapshotError, Writer};
+use crate::vsoc::{arch::interface::ArchInterface, bus::Bus, trace::Trace, VsocException};
 
 #[derive(Debug)]
 enum CpuCore {
-    CoreRv(Rv),
+    CoreRv32(Rv<u32>),
+    CoreRv64(Rv<u64>),
+    CoreRv128(Box<Rv<u128>>),
+}
+
+// The hart is monomorphized over its XLEN, forward to whichever one is used
+macro_rules! core {
+    ($core:expr, $c:ident => $e:expr) => {
+        match $core {
+            CpuCore::CoreRv32($c) => $e,
+            CpuCore::CoreRv64($c) => $e,
+            CpuCore::CoreRv128($c) => $e,
+        }
+    };
 }
 
 #[derive(Debug)]
@@ -38,31 +54,94 @@ impl fmt::Display for CpuCore {
         // stream: `f`. Returns `fmt::Result` which indicates whether the
         // operation succeeded or failed. Note that `write!` uses syntax which
         // is very similar to `println!`.
-        match self {
-            CpuCore::CoreRv(core) => write!(f, "{}", core),
-        }
+        core!(self, core => write!(f, "{}", core))
     }
 }
 
 impl<'a> Cpu<'a> {
-    pub fn new(desc: &'a String) -> Cpu<'a> {
+    pub fn new(desc: &'a String, hartid: usize) -> Cpu<'a> {
+        let mut core: CpuCore = match arch_xlen(desc) {
+            32 => CpuCore::CoreRv32(Rv::new(desc)),
+            64 => CpuCore::CoreRv64(Rv::new(desc)),
+            _ => CpuCore::CoreRv128(Box::new(Rv::new(desc))),
+        };
+
+        core!(&mut core, c => c.set_hartid(hartid));
         Cpu {
             desc,
             state: State::Initialised,
-            core: CpuCore::CoreRv(Rv::new(desc)),
+            core,
         }
     }
 
     pub fn set_pc(&mut self, pc: u128) {
-        match &mut self.core {
-            CpuCore::CoreRv(core) => core.set_pc(pc),
-        }
+        core!(&mut self.core, core => core.set_pc(pc))
+    }
+
+    pub fn snoop(&mut self, addr: u64, width: usize) {
+        core!(&mut self.core, core => core.snoop(addr, width))
+    }
+
+    pub fn invalidate(&mut self, addr: u64, width: usize) {
+        core!(&mut self.core, core => core.invalidate(addr, width))
+    }
+
+    pub fn desc(&self) -> &str {
+        self.desc
+    }
+
+    pub fn save(&self, w: &mut Writer) {
+        w.u8(self.state as u8);
+        core!(&self.core, core => core.save(w))
+    }
+
+    pub fn restore(&mut self, r: &mut Reader) -> Result<(), SnapshotError> {
+        self.state = match r.u8()? {
+            0 => State::Unknown,
+            1 => State::Initialised,
+            2 => State::Loaded,
+            3 => State::Running,
+            4 => State::Halted,
+            5 => State::Shutdown,
+            _ => return Err(SnapshotError::Mismatch(String::from("cpu state"))),
+        };
+        core!(&mut self.core, core => core.restore(r))
+    }
+
+    pub fn set_boot_args(&mut self, dtb: u64) {
+        core!(&mut self.core, core => core.set_boot_args(dtb))
+    }
+
+    pub fn pc(&self) -> u64 {
+        core!(&self.core, core => core.pc.to_u64())
+    }
+
+    pub fn debug_target_xml(&self) -> String {
+        core!(&self.core, core => core.gdb_target_xml())
+    }
+
+    pub fn debug_read_register(&self, regnum: usize) -> Option<Vec<u8>> {
+        core!(&self.core, core => core.gdb_read_register(regnum))
+    }
+
+    pub fn debug_write_register(&mut self, regnum: usize, value: &[u8]) -> bool {
+        core!(&mut self.core, core => core.gdb_write_register(regnum, value))
+    }
+
+    pub fn set_trace(&mut self, trace: Trace) {
+        core!(&mut self.core, core => core.trace = Some(trace))
+    }
+
+    pub fn set_pending(&mut self, irq: RvInterrupt, level: bool) {
+        core!(&mut self.core, core => core.set_pending(irq, level))
+    }
+
+    pub fn set_time(&mut self, time: u64) {
+        core!(&mut self.core, core => core.set_time(time))
     }
 
     pub fn step(&mut self, bus: &mut Bus) -> Option<VsocException> {
-        match &mut self.core {
-            CpuCore::CoreRv(core) => core.step(bus).map(VsocException::from),
-        }
+        core!(&mut self.core, core => core.step(bus).map(VsocException::from))
     }
 
     pub fn halt(&mut self) -> &mut Self {
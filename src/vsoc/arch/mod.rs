@@ -1,6 +1,6 @@
 pub mod cpu;
 mod interface;
 mod registers;
-mod riscv;
+pub mod riscv;
 pub mod state;
 pub mod types;
@@ -18,10 +18,6 @@ impl ArchRegister {
         }
     }
 
-    pub fn name(&self) -> &str {
-        &self.name
-    }
-
     pub fn set(&mut self, value: &Uint) {
         self.value = value.clone();
     }
@@ -1,5 +1,7 @@
 use std::fmt::{self, Display};
 
+use crate::vsoc::snapshot::{Reader, SnapshotError, Writer};
+
 #[derive(Debug)]
 pub struct AtomicCtx {
     xlen: usize,
@@ -25,6 +27,29 @@ impl AtomicCtx {
         if self.valid && self.address == address { true } else { false }
     }
 
+    // A store by another agent to the reservation set, the naturally aligned
+    // XLEN-bit word holding the reserved address, breaks the reservation
+    pub fn snoop(&mut self, address: u64, width: usize) {
+        let granule: u64 = (self.xlen / 8) as u64;
+        let set: u64 = self.address & !(granule - 1);
+
+        if self.valid && address < set + granule && set < address + width as u64 {
+            self.release();
+        }
+    }
+
+    pub fn save(&self, w: &mut Writer) {
+        w.u64(self.address);
+        w.bool(self.valid);
+    }
+
+    pub fn restore(&mut self, r: &mut Reader) -> Result<(), SnapshotError> {
+        self.address = r.u64()?;
+        self.valid = r.bool()?;
+
+        Ok(())
+    }
+
     pub fn release(&mut self) {
         self.address = 0;
         self.valid = false;
@@ -5,6 +5,15 @@ pub const FFLAGS: usize = 0x001;
 pub const FRM: usize = 0x002;
 pub const FCSR: usize = 0x003;
 
+// Unpriviledge Vector CSRs
+pub const VSTART: usize = 0x008;
+pub const VXSAT: usize = 0x009;
+pub const VXRM: usize = 0x00a;
+pub const VCSR: usize = 0x00f;
+pub const VL: usize = 0xc20;
+pub const VTYPE: usize = 0xc21;
+pub const VLENB: usize = 0xc22;
+
 // Unpriviledge Counter/Timers CSRs
 pub const CYCLE: usize = 0xc00;
 pub const TIME: usize = 0xc01;
@@ -14,6 +23,8 @@ pub const CYCLEH: usize = 0xc80;
 pub const TIMEH: usize = 0xc81;
 pub const INSTRETH: usize = 0xc82;
 pub const HPMCOUNTER3H: usize = 0xc83; //..0xc9f
+pub const HPMCOUNTER31: usize = 0xc1f;
+pub const HPMCOUNTER31H: usize = 0xc9f;
 
 // Supervisor Trap Setup
 pub const SSTATUS: usize = 0x100;
@@ -97,8 +108,10 @@ pub const MENVCFGH: usize = 0x31a;
 pub const MSECCFG: usize = 0x747;
 pub const MSECCFGH: usize = 0x757;
 // Machine Memory Protection
-pub const PMPCFG0: usize = 0x3a0; //..0x3af
-pub const PMPADDR0: usize = 0x3b0; //..0x3ef
+pub const PMPCFG0: usize = 0x3a0;
+pub const PMPCFG15: usize = 0x3af;
+pub const PMPADDR0: usize = 0x3b0;
+pub const PMPADDR63: usize = 0x3ef;
                                    // Machine Non-Maskable Interrupt Handling
 pub const MNSCRATCH: usize = 0x740;
 pub const MNEPC: usize = 0x741;
@@ -111,9 +124,12 @@ pub const MHPMCOUNTER3: usize = 0xb03; //..0xb1f
 pub const MCYCLEH: usize = 0xb80;
 pub const MINSTRETH: usize = 0xb82;
 pub const MHPMCOUNTER3H: usize = 0xb83; //..0xb9f
+pub const MHPMCOUNTER31: usize = 0xb1f;
+pub const MHPMCOUNTER31H: usize = 0xb9f;
                                         // Machine Counter Setup
 pub const MCOUNTINHIBIT: usize = 0x320;
 pub const MHPMEVENT3: usize = 0x323; //..0x33f
+pub const MHPMEVENT31: usize = 0x33f;
                                      // Debug/Trace Registers (shared with Debug Mode)
 pub const TSELECT: usize = 0x7a0;
 pub const TDATA1: usize = 0x7a1;
@@ -126,63 +142,155 @@ pub const DPC: usize = 0x7b1;
 pub const DSCRATCH0: usize = 0x7b2;
 pub const DSCRATCH1: usize = 0x7b3;
 
-use crate::vsoc::arch::{registers::ArchRegister, types::Uint};
+// mstatus fields
+pub const MSTATUS_SIE: u128 = 1 << 1;
+pub const MSTATUS_MIE: u128 = 1 << 3;
+pub const MSTATUS_SPIE: u128 = 1 << 5;
+pub const MSTATUS_UBE: u128 = 1 << 6;
+pub const MSTATUS_MPIE: u128 = 1 << 7;
+pub const MSTATUS_SPP: u128 = 1 << 8;
+pub const MSTATUS_VS: u128 = 0x3 << 9;
+pub const MSTATUS_MPP: u128 = 0x3 << 11;
+pub const MSTATUS_MPP_SHIFT: usize = 11;
+pub const MSTATUS_FS: u128 = 0x3 << 13;
+pub const MSTATUS_XS: u128 = 0x3 << 15;
+pub const MSTATUS_MPRV: u128 = 1 << 17;
+pub const MSTATUS_SUM: u128 = 1 << 18;
+pub const MSTATUS_MXR: u128 = 1 << 19;
+pub const MSTATUS_TVM: u128 = 1 << 20;
+pub const MSTATUS_TW: u128 = 1 << 21;
+pub const MSTATUS_TSR: u128 = 1 << 22;
+pub const MSTATUS_UXL: u128 = 0x3 << 32;
+pub const MSTATUS_GVA: u128 = 1 << 38;
+pub const MSTATUS_MPV: u128 = 1 << 39;
+// Values of the FS, VS and XS fields
+pub const STATUS_INITIAL: u128 = 0x1;
+pub const STATUS_DIRTY: u128 = 0x3;
+
+// sstatus is the view of mstatus restricted to these fields
+pub const SSTATUS_MASK: u128 = MSTATUS_SIE
+    | MSTATUS_SPIE
+    | MSTATUS_UBE
+    | MSTATUS_SPP
+    | MSTATUS_VS
+    | MSTATUS_FS
+    | MSTATUS_XS
+    | MSTATUS_SUM
+    | MSTATUS_MXR
+    | MSTATUS_UXL;
+
+// hstatus fields
+pub const HSTATUS_GVA: u128 = 1 << 6;
+pub const HSTATUS_SPV: u128 = 1 << 7;
+pub const HSTATUS_SPVP: u128 = 1 << 8;
+pub const HSTATUS_HU: u128 = 1 << 9;
+pub const HSTATUS_VTVM: u128 = 1 << 20;
+pub const HSTATUS_VTW: u128 = 1 << 21;
+pub const HSTATUS_VTSR: u128 = 1 << 22;
+pub const HSTATUS_VSXL_64: u128 = 0x2 << 32;
+
+// mip/mie fields
+pub const MIP_SSIP: u128 = 1 << 1;
+pub const MIP_VSSIP: u128 = 1 << 2;
+pub const MIP_STIP: u128 = 1 << 5;
+pub const MIP_VSTIP: u128 = 1 << 6;
+pub const MIP_SEIP: u128 = 1 << 9;
+pub const MIP_VSEIP: u128 = 1 << 10;
+pub const MIP_SGEIP: u128 = 1 << 12;
+// Interrupts of the VS level, and the ones hip/hie hold
+pub const MIP_VS: u128 = MIP_VSSIP | MIP_VSTIP | MIP_VSEIP;
+pub const MIP_H: u128 = MIP_VS | MIP_SGEIP;
+
+// mtvec modes
+pub const MTVEC_MODE_VECTORED: u128 = 0x1;
+
+// mcountinhibit fields, bit n stops mhpmcounter<n>
+pub const MCOUNTINHIBIT_CY: u32 = 1 << 0;
+pub const MCOUNTINHIBIT_IR: u32 = 1 << 2;
+
+// mhpmevent selectors, 0 counts nothing
+pub const HPMEVENT_LOAD: u64 = 1;
+pub const HPMEVENT_STORE: u64 = 2;
+pub const HPMEVENT_BRANCH_TAKEN: u64 = 3;
+pub const HPMEVENT_TRAP: u64 = 4;
+pub const HPMEVENT_AMO: u64 = 5;
+
+// Index of the counters in `Csr::counters`, the same as the low bits of
+// their CSR address
+const COUNTER_CYCLE: usize = 0;
+const COUNTER_TIME: usize = 1;
+const COUNTER_INSTRET: usize = 2;
 
+use crate::vsoc::arch::types::Uint;
+
+use super::exception::RvException;
 use super::ext::RvExtensions;
+use super::pmp::{Pmp, PMP_ENTRIES};
+use super::privilege::RvPrivilege;
+use crate::vsoc::snapshot::{Reader, SnapshotError, Writer};
 
 #[derive(Debug, Default)]
 pub struct Csr {
     xlen: usize,
-    bank: Vec<ArchRegister>,
+    names: Vec<String>,
+    bank: Vec<u128>,
+    supervisor: bool,
+    user: bool,
+    hypervisor: bool,
+    float: bool,
+    vector: bool,
+    // Virtualization mode, S/U-mode are VS/VU-mode when set
+    virt: bool,
+    pmp: Pmp,
+    // 64-bit counters behind the counter CSRs, the mhpmevent selectors and
+    // mcountinhibit, kept apart from the bank as they change every step
+    counters: [u64; 32],
+    events: [u64; 32],
+    inhibit: u32,
+    // Counters written by the current instruction, which does not count
+    written: u32,
 }
 
 impl Csr {
     pub fn new(xlen: usize, extensions: &RvExtensions) -> Csr {
-        let mut csr: Vec<ArchRegister> = Vec::<ArchRegister>::with_capacity(4096);
+        let mut names: Vec<String> = vec![String::from("invalid"); 4096];
 
         println!("* Creating CSR registers");
 
-        for i in 0..4096 {
-            csr.push(ArchRegister::new(
-                String::from("invalid"),
-                i,
-                Uint::zero(xlen),
-            ));
-        }
-
         println!("* Populating CSR registers");
         if extensions.f {
-            csr[FFLAGS] = ArchRegister::new(String::from("fflags"), FFLAGS, Uint::zero(xlen));
-            csr[FRM] = ArchRegister::new(String::from("frm"), FRM, Uint::zero(xlen));
-            csr[FCSR] = ArchRegister::new(String::from("fcsr"), FCSR, Uint::zero(xlen));
+            names[FFLAGS] = String::from("fflags");
+            names[FRM] = String::from("frm");
+            names[FCSR] = String::from("fcsr");
+        }
+
+        // vxsat and vxrm are views of vcsr
+        if extensions.v {
+            names[VSTART] = String::from("vstart");
+            names[VXSAT] = String::from("vxsat");
+            names[VXRM] = String::from("vxrm");
+            names[VCSR] = String::from("vcsr");
+            names[VL] = String::from("vl");
+            names[VTYPE] = String::from("vtype");
+            names[VLENB] = String::from("vlenb");
         }
 
         if extensions.zicntr {
-            csr[CYCLE] = ArchRegister::new(String::from("cycle"), CYCLE, Uint::zero(xlen));
-            csr[TIME] = ArchRegister::new(String::from("time"), TIME, Uint::zero(xlen));
-            csr[INSTRET] = ArchRegister::new(String::from("instret"), INSTRET, Uint::zero(xlen));
+            names[CYCLE] = String::from("cycle");
+            names[TIME] = String::from("time");
+            names[INSTRET] = String::from("instret");
             if xlen == 32 {
-                csr[CYCLEH] =
-                    ArchRegister::new(String::from("cycleh"), CYCLEH, Uint::zero(xlen));
-                csr[TIMEH] = ArchRegister::new(String::from("timeh"), TIMEH, Uint::zero(xlen));
-                csr[INSTRETH] =
-                    ArchRegister::new(String::from("instreth"), INSTRETH, Uint::zero(xlen));
+                names[CYCLEH] = String::from("cycleh");
+                names[TIMEH] = String::from("timeh");
+                names[INSTRETH] = String::from("instreth");
             }
         }
 
         if extensions.zihpm {
             for i in 0..0x1c {
-                csr[HPMCOUNTER3 + i] = ArchRegister::new(
-                    format!("hpmcounter{}", i + 3),
-                    HPMCOUNTER3 + i,
-                    Uint::zero(xlen),
-                );
+                names[HPMCOUNTER3 + i] = format!("hpmcounter{}", i + 3);
                 if xlen == 32 {
-                    csr[HPMCOUNTER3H + i] = ArchRegister::new(
-                        format!("hpmcounterh{}", i + 3),
-                        HPMCOUNTER3H + i,
-                        Uint::zero(xlen),
-                    );
+                    names[HPMCOUNTER3H + i] = format!("hpmcounter{}h", i + 3);
                 }
             }
         }
@@ -190,226 +298,768 @@ impl Csr {
         // Supervisor
         if extensions.s {
             println!("* Populating CSR registers for supervisor mode");
-            csr[SSTATUS] = ArchRegister::new(String::from("sstatus"), SSTATUS, Uint::zero(xlen));
-            csr[SIE] = ArchRegister::new(String::from("sie"), SIE, Uint::zero(xlen));
-            csr[STVEC] = ArchRegister::new(String::from("stvec"), STVEC, Uint::zero(xlen));
-            csr[SCOUNTEREN] =
-                ArchRegister::new(String::from("scounteren"), SCOUNTEREN, Uint::zero(xlen));
-            csr[SENVCFG] = ArchRegister::new(String::from("senvcfg"), SENVCFG, Uint::zero(xlen));
-            csr[SSCRATCH] =
-                ArchRegister::new(String::from("sscratch"), SSCRATCH, Uint::zero(xlen));
-            csr[SEPC] = ArchRegister::new(String::from("sepc"), SEPC, Uint::zero(xlen));
-            csr[SCAUSE] = ArchRegister::new(String::from("scause"), SCAUSE, Uint::zero(xlen));
-            csr[STVAL] = ArchRegister::new(String::from("stval"), STVAL, Uint::zero(xlen));
-            csr[SIP] = ArchRegister::new(String::from("sip"), SIP, Uint::zero(xlen));
-            csr[SATP] = ArchRegister::new(String::from("satp"), SATP, Uint::zero(xlen));
-            csr[SCONTEXT] =
-                ArchRegister::new(String::from("scontext"), SCONTEXT, Uint::zero(xlen));
+            names[SSTATUS] = String::from("sstatus");
+            names[SIE] = String::from("sie");
+            names[STVEC] = String::from("stvec");
+            names[SCOUNTEREN] = String::from("scounteren");
+            names[SENVCFG] = String::from("senvcfg");
+            names[SSCRATCH] = String::from("sscratch");
+            names[SEPC] = String::from("sepc");
+            names[SCAUSE] = String::from("scause");
+            names[STVAL] = String::from("stval");
+            names[SIP] = String::from("sip");
+            names[SATP] = String::from("satp");
+            names[SCONTEXT] = String::from("scontext");
         }
 
         // Hypervisor
         if extensions.h {
             println!("* Populating CSR registers for hypervisor mode");
-            csr[HSTATUS] = ArchRegister::new(String::from("hstatus"), HSTATUS, Uint::zero(xlen));
-            csr[HEDELEG] = ArchRegister::new(String::from("hedeleg"), HEDELEG, Uint::zero(xlen));
-            csr[HIDELEG] = ArchRegister::new(String::from("hideleg"), HIDELEG, Uint::zero(xlen));
-            csr[HIE] = ArchRegister::new(String::from("hie"), HIE, Uint::zero(xlen));
-            csr[HCOUNTEREN] =
-                ArchRegister::new(String::from("hcounteren"), HCOUNTEREN, Uint::zero(xlen));
-            csr[HGEIE] = ArchRegister::new(String::from("hgeie"), HGEIE, Uint::zero(xlen));
-            csr[HTVAL] = ArchRegister::new(String::from("htval"), HTVAL, Uint::zero(xlen));
-            csr[HIP] = ArchRegister::new(String::from("hip"), HIP, Uint::zero(xlen));
-            csr[HVIP] = ArchRegister::new(String::from("hvip"), HVIP, Uint::zero(xlen));
-            csr[HTINST] = ArchRegister::new(String::from("htinst"), HTINST, Uint::zero(xlen));
-            csr[HGEIP] = ArchRegister::new(String::from("hgeip"), HGEIP, Uint::zero(xlen));
-            csr[HENVCFG] = ArchRegister::new(String::from("henvcfg"), HENVCFG, Uint::zero(xlen));
+            names[HSTATUS] = String::from("hstatus");
+            names[HEDELEG] = String::from("hedeleg");
+            names[HIDELEG] = String::from("hideleg");
+            names[HIE] = String::from("hie");
+            names[HCOUNTEREN] = String::from("hcounteren");
+            names[HGEIE] = String::from("hgeie");
+            names[HTVAL] = String::from("htval");
+            names[HIP] = String::from("hip");
+            names[HVIP] = String::from("hvip");
+            names[HTINST] = String::from("htinst");
+            names[HGEIP] = String::from("hgeip");
+            names[HENVCFG] = String::from("henvcfg");
             if xlen == 32 {
-                csr[HENVCFGH] =
-                    ArchRegister::new(String::from("henvcfgh"), HENVCFGH, Uint::zero(xlen));
+                names[HENVCFGH] = String::from("henvcfgh");
             }
-            csr[HGATP] = ArchRegister::new(String::from("hgatp"), HGATP, Uint::zero(xlen));
-            csr[HCONTEXT] =
-                ArchRegister::new(String::from("hcontext"), HCONTEXT, Uint::zero(xlen));
-            csr[HTIMEDELTA] =
-                ArchRegister::new(String::from("htimedelta"), HTIMEDELTA, Uint::zero(xlen));
+            names[HGATP] = String::from("hgatp");
+            names[HCONTEXT] = String::from("hcontext");
+            names[HTIMEDELTA] = String::from("htimedelta");
             if xlen == 32 {
-                csr[HTIMEDELTAH] = ArchRegister::new(
-                    String::from("htimedeltah"),
-                    HTIMEDELTAH,
-                    Uint::zero(xlen),
-                );
+                names[HTIMEDELTAH] = String::from("htimedeltah");
             }
-            csr[VSSTATUS] =
-                ArchRegister::new(String::from("vsstatus"), VSSTATUS, Uint::zero(xlen));
-            csr[VSIE] = ArchRegister::new(String::from("vsie"), VSIE, Uint::zero(xlen));
-            csr[VSTVEC] = ArchRegister::new(String::from("vstvec"), VSTVEC, Uint::zero(xlen));
-            csr[VSSCRATCH] =
-                ArchRegister::new(String::from("vsscratch"), VSSCRATCH, Uint::zero(xlen));
-            csr[VSEPC] = ArchRegister::new(String::from("vsepc"), VSEPC, Uint::zero(xlen));
-            csr[VSCAUSE] = ArchRegister::new(String::from("vscause"), VSCAUSE, Uint::zero(xlen));
-            csr[VSTVAL] = ArchRegister::new(String::from("vstval"), VSTVAL, Uint::zero(xlen));
-            csr[VSIP] = ArchRegister::new(String::from("vsip"), VSIP, Uint::zero(xlen));
-            csr[VSATP] = ArchRegister::new(String::from("vsatp"), VSATP, Uint::zero(xlen));
+            names[VSSTATUS] = String::from("vsstatus");
+            names[VSIE] = String::from("vsie");
+            names[VSTVEC] = String::from("vstvec");
+            names[VSSCRATCH] = String::from("vsscratch");
+            names[VSEPC] = String::from("vsepc");
+            names[VSCAUSE] = String::from("vscause");
+            names[VSTVAL] = String::from("vstval");
+            names[VSIP] = String::from("vsip");
+            names[VSATP] = String::from("vsatp");
         }
 
         // Machine
         println!("* Populating CSR registers for machine mode");
-        csr[MVENDORID] =
-            ArchRegister::new(String::from("mvendorid"), MVENDORID, Uint::zero(xlen));
-        csr[MARCHID] = ArchRegister::new(String::from("marchid"), MARCHID, Uint::zero(xlen));
-        csr[MIMPID] = ArchRegister::new(String::from("mimpid"), MIMPID, Uint::zero(xlen));
-        csr[MHARTID] = ArchRegister::new(String::from("mhartid"), MHARTID, Uint::zero(xlen));
-        csr[MCONFIGPTR] =
-            ArchRegister::new(String::from("mconfigptr"), MCONFIGPTR, Uint::zero(xlen));
-        csr[MSTATUS] = ArchRegister::new(String::from("mstatus"), MSTATUS, Uint::zero(xlen));
+        names[MVENDORID] = String::from("mvendorid");
+        names[MARCHID] = String::from("marchid");
+        names[MIMPID] = String::from("mimpid");
+        names[MHARTID] = String::from("mhartid");
+        names[MCONFIGPTR] = String::from("mconfigptr");
+        names[MSTATUS] = String::from("mstatus");
         if xlen == 32 {
-            csr[MSTATUSH] =
-                ArchRegister::new(String::from("mstatush"), MSTATUSH, Uint::zero(xlen));
-        }
-        csr[MISA] = ArchRegister::new(String::from("misa"), MISA, Uint::zero(xlen));
-        csr[MEDELEG] = ArchRegister::new(String::from("medeleg"), MEDELEG, Uint::zero(xlen));
-        csr[MIDELEG] = ArchRegister::new(String::from("mideleg"), MIDELEG, Uint::zero(xlen));
-        csr[MIE] = ArchRegister::new(String::from("mie"), MIE, Uint::zero(xlen));
-        csr[MTVEC] = ArchRegister::new(String::from("mtvec"), MTVEC, Uint::zero(xlen));
-        csr[MCOUNTEREN] =
-            ArchRegister::new(String::from("mcounteren"), MCOUNTEREN, Uint::zero(xlen));
-        csr[MSCRATCH] = ArchRegister::new(String::from("mscratch"), MSCRATCH, Uint::zero(xlen));
-        csr[MEPC] = ArchRegister::new(String::from("mepc"), MEPC, Uint::zero(xlen));
-        csr[MCAUSE] = ArchRegister::new(String::from("mcause"), MCAUSE, Uint::zero(xlen));
-        csr[MTVAL] = ArchRegister::new(String::from("mtval"), MTVAL, Uint::zero(xlen));
-        csr[MIP] = ArchRegister::new(String::from("mip"), MIP, Uint::zero(xlen));
-        csr[MTINST] = ArchRegister::new(String::from("mtinst"), MTINST, Uint::zero(xlen));
-        csr[MTVAL2] = ArchRegister::new(String::from("mtval2"), MTVAL2, Uint::zero(xlen));
-        csr[MENVCFG] = ArchRegister::new(String::from("menvcfg"), MENVCFG, Uint::zero(xlen));
-        csr[MSECCFG] = ArchRegister::new(String::from("mseccfg"), MSECCFG, Uint::zero(xlen));
+            names[MSTATUSH] = String::from("mstatush");
+        }
+        names[MISA] = String::from("misa");
+        names[MEDELEG] = String::from("medeleg");
+        names[MIDELEG] = String::from("mideleg");
+        names[MIE] = String::from("mie");
+        names[MTVEC] = String::from("mtvec");
+        names[MCOUNTEREN] = String::from("mcounteren");
+        names[MSCRATCH] = String::from("mscratch");
+        names[MEPC] = String::from("mepc");
+        names[MCAUSE] = String::from("mcause");
+        names[MTVAL] = String::from("mtval");
+        names[MIP] = String::from("mip");
+        names[MTINST] = String::from("mtinst");
+        names[MTVAL2] = String::from("mtval2");
+        names[MENVCFG] = String::from("menvcfg");
+        names[MSECCFG] = String::from("mseccfg");
         if xlen == 32 {
-            csr[MENVCFGH] =
-                ArchRegister::new(String::from("menvcfgh"), MENVCFGH, Uint::zero(xlen));
-            csr[MSECCFGH] =
-                ArchRegister::new(String::from("mseccfgh"), MSECCFGH, Uint::zero(xlen));
-        }
-        for i in 0..0x0f {
-            csr[PMPCFG0 + i] =
-                ArchRegister::new(format!("pmpcfg{}", i), PMPCFG0 + i, Uint::zero(xlen));
-            csr[PMPADDR0 + i] =
-                ArchRegister::new(format!("pmpaddr{}", i), PMPADDR0 + i, Uint::zero(xlen));
-        }
-        csr[MNSCRATCH] =
-            ArchRegister::new(String::from("mnscratch"), MNSCRATCH, Uint::zero(xlen));
-        csr[MNEPC] = ArchRegister::new(String::from("mnepc"), MNEPC, Uint::zero(xlen));
-        csr[MNCAUSE] = ArchRegister::new(String::from("mncause"), MNCAUSE, Uint::zero(xlen));
-        csr[MNSTATUS] = ArchRegister::new(String::from("mnstatus"), MNSTATUS, Uint::zero(xlen));
-        csr[MCYCLE] = ArchRegister::new(String::from("mcycle"), MCYCLE, Uint::zero(xlen));
-        csr[MINSTRET] = ArchRegister::new(String::from("minstret"), MINSTRET, Uint::zero(xlen));
+            names[MENVCFGH] = String::from("menvcfgh");
+            names[MSECCFGH] = String::from("mseccfgh");
+        }
+        // Each pmpcfg packs xlen/8 entries, only even ones exist on RV64
+        for i in (0..=PMPCFG15 - PMPCFG0).step_by(xlen / 32) {
+            names[PMPCFG0 + i] = format!("pmpcfg{}", i);
+        }
+        for i in 0..=PMPADDR63 - PMPADDR0 {
+            names[PMPADDR0 + i] = format!("pmpaddr{}", i);
+        }
+        names[MNSCRATCH] = String::from("mnscratch");
+        names[MNEPC] = String::from("mnepc");
+        names[MNCAUSE] = String::from("mncause");
+        names[MNSTATUS] = String::from("mnstatus");
+        names[MCYCLE] = String::from("mcycle");
+        names[MINSTRET] = String::from("minstret");
         if xlen == 32 {
-            csr[MCYCLEH] = ArchRegister::new(String::from("mcycleh"), MCYCLE, Uint::zero(xlen));
-            csr[MINSTRETH] =
-                ArchRegister::new(String::from("minstreth"), MINSTRET, Uint::zero(xlen));
+            names[MCYCLEH] = String::from("mcycleh");
+            names[MINSTRETH] = String::from("minstreth");
         }
         for i in 0..0x1c {
-            csr[MHPMCOUNTER3 + i] = ArchRegister::new(
-                format!("mhpmcounter{}", i + 3),
-                MHPMCOUNTER3 + i,
-                Uint::zero(xlen),
-            );
+            names[MHPMCOUNTER3 + i] = format!("mhpmcounter{}", i + 3);
             if xlen == 32 {
-                csr[MHPMCOUNTER3H + i] = ArchRegister::new(
-                    format!("mhpmcounterh{}", i + 3),
-                    MHPMCOUNTER3H + i,
-                    Uint::zero(xlen),
-                );
+                names[MHPMCOUNTER3H + i] = format!("mhpmcounter{}h", i + 3);
             }
         }
-        csr[MCOUNTINHIBIT] = ArchRegister::new(
-            String::from("mcountinhibit"),
-            MCOUNTINHIBIT,
-            Uint::zero(xlen),
-        );
+        names[MCOUNTINHIBIT] = String::from("mcountinhibit");
         for i in 0..0x1c {
-            csr[MHPMEVENT3 + i] = ArchRegister::new(
-                format!("mhpmevent{}", i + 3),
-                MHPMEVENT3 + i,
-                Uint::zero(xlen),
-            );
+            names[MHPMEVENT3 + i] = format!("mhpmevent{}", i + 3);
         }
 
         println!("* Populating CSR registers for debug mode");
-        csr[TSELECT] = ArchRegister::new(String::from("tselect"), TSELECT, Uint::zero(xlen));
-        csr[TDATA1] = ArchRegister::new(String::from("tdata1"), TDATA1, Uint::zero(xlen));
-        csr[TDATA2] = ArchRegister::new(String::from("tdata2"), TDATA2, Uint::zero(xlen));
-        csr[TDATA3] = ArchRegister::new(String::from("tdata3"), TDATA3, Uint::zero(xlen));
-        csr[MCONTEXT] = ArchRegister::new(String::from("mcontext"), MCONTEXT, Uint::zero(xlen));
-        csr[DCSR] = ArchRegister::new(String::from("dcsr"), DCSR, Uint::zero(xlen));
-        csr[DPC] = ArchRegister::new(String::from("dpc"), DPC, Uint::zero(xlen));
-        csr[DSCRATCH0] =
-            ArchRegister::new(String::from("dscratch0"), DSCRATCH0, Uint::zero(xlen));
-        csr[DSCRATCH1] =
-            ArchRegister::new(String::from("dscratch1"), DSCRATCH1, Uint::zero(xlen));
+        names[TSELECT] = String::from("tselect");
+        names[TDATA1] = String::from("tdata1");
+        names[TDATA2] = String::from("tdata2");
+        names[TDATA3] = String::from("tdata3");
+        names[MCONTEXT] = String::from("mcontext");
+        names[DCSR] = String::from("dcsr");
+        names[DPC] = String::from("dpc");
+        names[DSCRATCH0] = String::from("dscratch0");
+        names[DSCRATCH1] = String::from("dscratch1");
+
+        let mut c: Csr = Csr {
+            xlen,
+            names,
+            bank: vec![0; 4096],
+            supervisor: extensions.s,
+            user: extensions.u,
+            hypervisor: extensions.h,
+            float: extensions.f,
+            vector: extensions.v,
+            virt: false,
+            pmp: Pmp::new(xlen),
+            counters: [0; 32],
+            events: [0; 32],
+            inhibit: 0,
+            written: 0,
+        };
+
+        // VS-level interrupts are always delegated out of M-mode, and VS-mode
+        // runs with the same XLEN as HS-mode
+        if extensions.h {
+            c.set_raw(MIDELEG, MIP_H);
+            if xlen == 64 {
+                c.set_raw(HSTATUS, HSTATUS_VSXL_64);
+            }
+        }
+
+        // The FP and vector states start enabled, for programs that do not
+        // know about FS and VS
+        if extensions.f {
+            c.set_raw(MSTATUS, field(MSTATUS_FS, STATUS_INITIAL));
+        }
+        if extensions.v {
+            c.set_raw(MSTATUS, c.raw(MSTATUS) | field(MSTATUS_VS, STATUS_INITIAL));
+        }
+
+        c
+    }
 
-        Csr { xlen, bank: csr }
+    pub fn xlen(&self) -> usize {
+        self.xlen
+    }
+
+    pub fn pmp(&self) -> &Pmp {
+        &self.pmp
+    }
+
+    pub fn virt(&self) -> bool {
+        self.virt
+    }
+
+    pub fn set_virt(&mut self, virt: bool) {
+        self.virt = virt;
+    }
+
+    // In VS/VU-mode the supervisor CSRs accessed by instructions are their VS
+    // counterparts
+    pub fn alias(&self, addr: usize) -> usize {
+        match addr {
+            SSTATUS | SIE | STVEC | SSCRATCH | SEPC | SCAUSE | STVAL | SIP | SATP if self.virt => {
+                addr + VSSTATUS - SSTATUS
+            }
+            _ => addr,
+        }
+    }
+
+    // mstatus.GVA and mstatus.MPV, held by mstatush on RV32
+    pub fn machine_virt(&self) -> (bool, bool) {
+        let value: u128 = if self.xlen == 32 {
+            self.raw(MSTATUSH) << 32
+        } else {
+            self.raw(MSTATUS)
+        };
+
+        (value & MSTATUS_GVA != 0, value & MSTATUS_MPV != 0)
+    }
+
+    pub fn set_machine_virt(&mut self, gva: bool, mpv: bool) {
+        let (addr, shift) = if self.xlen == 32 {
+            (MSTATUSH, 32)
+        } else {
+            (MSTATUS, 0)
+        };
+        let mut value: u128 = (self.raw(addr) << shift) & !(MSTATUS_GVA | MSTATUS_MPV);
+
+        if gva {
+            value |= MSTATUS_GVA;
+        }
+        if mpv {
+            value |= MSTATUS_MPV;
+        }
+        self.set_raw(addr, value >> shift);
     }
 
     pub fn name(&self, addr: usize) -> &str {
-        self.bank[addr].name()
+        &self.names[addr]
+    }
+
+    pub fn exists(&self, addr: usize) -> bool {
+        addr < self.names.len() && self.names[addr] != "invalid"
+    }
+
+    // Check an access done by a csr instruction from the given privilege level
+    pub fn check(&self, addr: usize, privilege: RvPrivilege, write: bool) -> Option<RvException> {
+        let level: usize = (addr >> 8) & 0x3;
+        // HS-mode reaches the hypervisor and VS CSRs
+        let hs: bool = privilege == RvPrivilege::Supervisor && !self.virt;
+        let host: RvPrivilege = if level == 2 && hs {
+            RvPrivilege::Machine
+        } else {
+            privilege
+        };
+
+        if !self.exists(addr) || (write && !write_is_allowed(addr, RvPrivilege::Machine)) {
+            return Some(RvException::InstructionIllegal);
+        }
+
+        // VS/VU-mode accesses HS-mode would be allowed to do are virtual
+        // instructions
+        if !read_is_allowed(addr, host) {
+            if self.virt && level < 3 {
+                return Some(RvException::VirtualInstruction);
+            }
+            return Some(RvException::InstructionIllegal);
+        }
+
+        if (FFLAGS..=FCSR).contains(&addr) && !self.fs_enabled() {
+            return Some(RvException::InstructionIllegal);
+        }
+        if matches!(addr, VSTART | VXSAT | VXRM | VCSR | VL | VTYPE | VLENB) && !self.vs_enabled() {
+            return Some(RvException::InstructionIllegal);
+        }
+
+        // User counters are enabled by mcounteren below M-mode, and also by
+        // scounteren in U-mode
+        if (CYCLE..=HPMCOUNTER31).contains(&addr) || (CYCLEH..=HPMCOUNTER31H).contains(&addr) {
+            let bit: u128 = 1 << (addr & 0x1f);
+
+            if privilege < RvPrivilege::Machine && self.raw(MCOUNTEREN) & bit == 0 {
+                return Some(RvException::InstructionIllegal);
+            }
+            if self.virt && self.raw(HCOUNTEREN) & bit == 0 {
+                return Some(RvException::VirtualInstruction);
+            }
+            if privilege == RvPrivilege::User && self.supervisor && self.raw(SCOUNTEREN) & bit == 0 {
+                if self.virt {
+                    return Some(RvException::VirtualInstruction);
+                }
+                return Some(RvException::InstructionIllegal);
+            }
+        }
+
+        // satp and hgatp are trapped in HS-mode when mstatus.TVM is set, and
+        // satp in VS-mode when hstatus.VTVM is set
+        if privilege == RvPrivilege::Supervisor {
+            if self.virt && addr == SATP && self.raw(HSTATUS) & HSTATUS_VTVM != 0 {
+                return Some(RvException::VirtualInstruction);
+            }
+            let tvm: bool = self.raw(MSTATUS) & MSTATUS_TVM != 0;
+
+            if !self.virt && (addr == SATP || addr == HGATP) && tvm {
+                return Some(RvException::InstructionIllegal);
+            }
+        }
+
+        None
+    }
+
+    // The FP instructions and CSRs are illegal while FS is Off, in mstatus or
+    // in vsstatus in VS/VU-mode
+    pub fn fs_enabled(&self) -> bool {
+        self.enabled(MSTATUS_FS)
+    }
+
+    // The FP state was written
+    pub fn dirty_fs(&mut self) {
+        self.dirty(MSTATUS_FS);
+    }
+
+    // The same for the vector instructions and CSRs with VS
+    pub fn vs_enabled(&self) -> bool {
+        self.enabled(MSTATUS_VS)
+    }
+
+    pub fn dirty_vs(&mut self) {
+        self.dirty(MSTATUS_VS);
+    }
+
+    fn enabled(&self, status: u128) -> bool {
+        self.raw(MSTATUS) & status != 0 && (!self.virt || self.raw(VSSTATUS) & status != 0)
+    }
+
+    fn dirty(&mut self, status: u128) {
+        let mstatus: u128 = self.raw(MSTATUS) | status;
+
+        self.set_raw(MSTATUS, mstatus);
+        if self.virt {
+            let vsstatus: u128 = self.raw(VSSTATUS) | status;
+
+            self.set_raw(VSSTATUS, vsstatus);
+        }
+    }
+
+    // mstatus.SD, set when a state is dirty
+    fn sd(&self, status: u128) -> u128 {
+        let dirty: bool = [MSTATUS_FS, MSTATUS_VS, MSTATUS_XS]
+            .iter()
+            .any(|f| status & f == field(*f, STATUS_DIRTY));
+
+        if dirty {
+            1 << (self.xlen - 1)
+        } else {
+            0
+        }
+    }
+
+    // Drive mip bits from an interrupt source
+    pub fn set_pending(&mut self, mask: u128, level: bool) {
+        let mip: u128 = if level {
+            self.raw(MIP) | mask
+        } else {
+            self.raw(MIP) & !mask
+        };
+
+        self.set_raw(MIP, mip);
+    }
+
+    // Time read by the time/timeh CSRs
+    pub fn set_time(&mut self, time: u64) {
+        self.counters[COUNTER_TIME] = time;
+    }
+
+    // One step of the hart, retired or not
+    pub fn tick(&mut self) {
+        if self.inhibit & MCOUNTINHIBIT_CY == 0 && self.written & MCOUNTINHIBIT_CY == 0 {
+            self.counters[COUNTER_CYCLE] = self.counters[COUNTER_CYCLE].wrapping_add(1);
+        }
+        self.written = 0;
+    }
+
+    pub fn retire(&mut self) {
+        if self.inhibit & MCOUNTINHIBIT_IR == 0 && self.written & MCOUNTINHIBIT_IR == 0 {
+            self.counters[COUNTER_INSTRET] = self.counters[COUNTER_INSTRET].wrapping_add(1);
+        }
+    }
+
+    // Count an event in the mhpmcounters selecting it
+    pub fn count(&mut self, event: u64) {
+        for i in 3..32 {
+            if self.events[i] == event && (self.inhibit | self.written) & (1 << i) == 0 {
+                self.counters[i] = self.counters[i].wrapping_add(1);
+            }
+        }
+    }
+
+    // Counter behind a counter CSR, and whether the CSR is its upper half
+    fn counter(addr: usize) -> Option<(usize, bool)> {
+        match addr {
+            MCYCLE..=MHPMCOUNTER31 => Some((addr - MCYCLE, false)),
+            MCYCLEH..=MHPMCOUNTER31H => Some((addr - MCYCLEH, true)),
+            CYCLE..=HPMCOUNTER31 => Some((addr - CYCLE, false)),
+            CYCLEH..=HPMCOUNTER31H => Some((addr - CYCLEH, true)),
+            _ => None,
+        }
+    }
+
+    fn get_counter(&self, i: usize, high: bool) -> u128 {
+        if high {
+            (self.counters[i] >> 32) as u128
+        } else {
+            self.counters[i] as u128
+        }
+    }
+
+    // RV32 writes one half of the counter, the others all of it
+    fn set_counter(&mut self, i: usize, high: bool, value: u128) {
+        let old: u64 = self.counters[i];
+
+        self.counters[i] = match (self.xlen, high) {
+            (32, false) => old & !0xffff_ffff | value as u64 & 0xffff_ffff,
+            (32, true) => old & 0xffff_ffff | (value as u64) << 32,
+            _ => value as u64,
+        };
+    }
+
+    fn raw(&self, addr: usize) -> u128 {
+        self.bank[addr]
+    }
+
+    fn set_raw(&mut self, addr: usize, value: u128) {
+        self.bank[addr] = value & self.mask();
+    }
+
+    fn mask(&self) -> u128 {
+        u128::MAX >> (128 - self.xlen)
+    }
+
+    // Keep the previous MPP when the new one is not a supported privilege level
+    fn legalize_mstatus(&self, value: u128) -> u128 {
+        let mstatus: u128 = self.raw(MSTATUS);
+        let mut value: u128 = value;
+        let supported: bool = match RvPrivilege::from_mpp((value & MSTATUS_MPP) >> MSTATUS_MPP_SHIFT) {
+            Some(RvPrivilege::Machine) => true,
+            Some(RvPrivilege::Supervisor) => self.supervisor,
+            Some(RvPrivilege::User) => self.user,
+            None => false,
+        };
+
+        if !supported {
+            value = value & !MSTATUS_MPP | mstatus & MSTATUS_MPP;
+        }
+
+        if !self.supervisor {
+            value &= !(MSTATUS_SPP | MSTATUS_SIE | MSTATUS_SPIE | MSTATUS_SUM | MSTATUS_MXR);
+        }
+
+        if !self.hypervisor {
+            value &= !(MSTATUS_GVA | MSTATUS_MPV);
+        }
+
+        // SD is read-only
+        value & !(1 << (self.xlen - 1)) & self.status_mask()
+    }
+
+    // FS and VS are read-only zero without the F and V extensions
+    fn status_mask(&self) -> u128 {
+        let mut msk: u128 = u128::MAX;
+
+        if !self.float {
+            msk &= !MSTATUS_FS;
+        }
+        if !self.vector {
+            msk &= !MSTATUS_VS;
+        }
+        msk
+    }
+
+    // The VS-level bits of mip are driven by hvip
+    fn mip(&self) -> u128 {
+        self.raw(MIP) | self.raw(HVIP) & MIP_VS
+    }
+
+    // Only the software interrupt is writable through the mip views
+    fn set_vssip(&mut self, value: u128, mask: u128) {
+        let msk: u128 = mask & MIP_VSSIP;
+        let hvip: u128 = self.raw(HVIP) & !msk | value & msk;
+
+        self.set_raw(HVIP, hvip);
     }
 
     pub fn set(&mut self, addr: usize, value: &Uint) {
-        if write_is_allowed(addr) {
-            match addr {
-                FFLAGS => {
-                    let msk: Uint = Uint::from(0x1fu8).extend(self.xlen).clone();
-                    let fcsr = self.bank[FCSR].get() & !msk.clone() | value.clone() & msk;
-                    self.bank[FCSR].set(&fcsr);
-                },
-                FRM => {
-                    let msk: Uint = Uint::from(0x3u8 << 5).extend(self.xlen).clone();
-                    let shift: Uint = Uint::from(5u8).extend(self.xlen).clone();
-                    let fcsr = self.bank[FCSR].get() & !msk.clone() | ((value.clone() & msk) << shift.clone());
-                    self.bank[FCSR].set(&(fcsr >> shift));
-                },
-                _ => {
-                    self.bank[addr].set(value);
-                },
+        self.write(addr, u128::from(value.clone()));
+    }
+
+    pub fn write(&mut self, addr: usize, value: u128) {
+        match addr {
+            FFLAGS => {
+                let fcsr: u128 = self.raw(FCSR) & !0x1f | value & 0x1f;
+                self.set_raw(FCSR, fcsr);
+                self.dirty_fs();
+            },
+            FRM => {
+                let fcsr: u128 = self.raw(FCSR) & !0xe0 | (value << 5) & 0xe0;
+                self.set_raw(FCSR, fcsr);
+                self.dirty_fs();
+            },
+            FCSR => {
+                self.set_raw(FCSR, value & 0xff);
+                self.dirty_fs();
+            },
+            VXSAT => {
+                let vcsr: u128 = self.raw(VCSR) & !0x1 | value & 0x1;
+                self.set_raw(VCSR, vcsr);
+                self.dirty_vs();
+            },
+            VXRM => {
+                let vcsr: u128 = self.raw(VCSR) & !0x6 | (value & 0x3) << 1;
+                self.set_raw(VCSR, vcsr);
+                self.dirty_vs();
+            },
+            VCSR => {
+                self.set_raw(VCSR, value & 0x7);
+                self.dirty_vs();
+            },
+            VSTART | VL | VTYPE => {
+                self.set_raw(addr, value);
+                self.dirty_vs();
+            },
+            SSTATUS => {
+                let msk: u128 = SSTATUS_MASK & self.status_mask();
+                let mstatus: u128 = self.raw(MSTATUS) & !msk | value & msk;
+                self.set_raw(MSTATUS, mstatus);
+            },
+            SIE => {
+                let mideleg: u128 = self.raw(MIDELEG) & !MIP_H;
+                let mie: u128 = self.raw(MIE) & !mideleg | value & mideleg;
+                self.set_raw(MIE, mie);
+            },
+            SIP => {
+                let msk: u128 = self.raw(MIDELEG) & MIP_SSIP;
+                let mip: u128 = self.raw(MIP) & !msk | value & msk;
+                self.set_raw(MIP, mip);
+            },
+            MIP => {
+                // Machine-level bits are driven by the interrupt controllers only
+                let msk: u128 = if self.supervisor {
+                    MIP_SSIP | MIP_STIP | MIP_SEIP
+                } else {
+                    0
+                };
+                let mip: u128 = self.raw(MIP) & !msk | value & msk;
+                self.set_raw(MIP, mip);
+                if self.hypervisor {
+                    self.set_vssip(value, MIP_VSSIP);
+                }
+            },
+            MSTATUS => {
+                let mstatus: u128 = self.legalize_mstatus(value);
+                self.set_raw(MSTATUS, mstatus);
+            },
+            MSTATUSH => {
+                let msk: u128 = if self.hypervisor {
+                    (MSTATUS_GVA | MSTATUS_MPV) >> 32
+                } else {
+                    0
+                };
+                self.set_raw(MSTATUSH, value & msk);
+            },
+            MIDELEG if self.hypervisor => {
+                self.set_raw(MIDELEG, value | MIP_H);
+            },
+            HSTATUS => {
+                let msk: u128 = HSTATUS_GVA
+                    | HSTATUS_SPV
+                    | HSTATUS_SPVP
+                    | HSTATUS_HU
+                    | HSTATUS_VTVM
+                    | HSTATUS_VTW
+                    | HSTATUS_VTSR;
+                let hstatus: u128 = self.raw(HSTATUS) & !msk | value & msk;
+                self.set_raw(HSTATUS, hstatus);
+            },
+            HEDELEG => {
+                // Environment calls from HS/VS/M-mode and the guest faults stay in HS-mode
+                let hedeleg: u128 = value & !0xf0_0e00;
+                self.set_raw(HEDELEG, hedeleg);
+            },
+            HIDELEG => {
+                self.set_raw(HIDELEG, value & MIP_VS);
+            },
+            HIE => {
+                let mie: u128 = self.raw(MIE) & !MIP_H | value & MIP_H;
+                self.set_raw(MIE, mie);
+            },
+            HIP => {
+                self.set_vssip(value, MIP_VSSIP);
+            },
+            HVIP => {
+                self.set_raw(HVIP, value & MIP_VS);
+            },
+            HGATP => {
+                // The root of the G-stage page table is 16 KiB aligned
+                self.set_raw(HGATP, value & !0x3);
+            },
+            VSSTATUS => {
+                self.set_raw(VSSTATUS, value & SSTATUS_MASK & self.status_mask());
+            },
+            VSIE => {
+                let msk: u128 = self.raw(HIDELEG) & MIP_VS;
+                let mie: u128 = self.raw(MIE) & !msk | (value << 1) & msk;
+                self.set_raw(MIE, mie);
+            },
+            VSIP => {
+                let hideleg: u128 = self.raw(HIDELEG);
+                self.set_vssip(value << 1, hideleg);
+            },
+            MEDELEG => {
+                // Environment calls from M-mode cannot be delegated
+                let medeleg: u128 = value & !(1 << RvException::EnvironmentCallMMode as usize);
+                self.set_raw(MEDELEG, medeleg);
+            },
+            PMPCFG0..=PMPCFG15 => {
+                if !self.exists(addr) {
+                    return;
+                }
+
+                let first: usize = (addr - PMPCFG0) * 4;
+                let entries = (first..first + self.xlen / 8).filter(|i| *i < PMP_ENTRIES);
+                let mut cfg: u128 = 0;
+
+                for (n, i) in entries.enumerate() {
+                    self.pmp.set_cfg(i, (value >> (8 * n)) as u8);
+                    cfg |= (self.pmp.cfg(i) as u128) << (8 * n);
+                }
+                self.set_raw(addr, cfg);
+            },
+            PMPADDR0..=PMPADDR63 => {
+                let i: usize = addr - PMPADDR0;
+
+                self.pmp.set_addr(i, value as u64);
+                self.set_raw(addr, self.pmp.addr(i) as u128);
+            },
+            MCYCLE..=MHPMCOUNTER31 | MCYCLEH..=MHPMCOUNTER31H => {
+                let (i, high) = Self::counter(addr).unwrap();
+
+                self.set_counter(i, high, value);
+                self.written |= 1 << i;
+            },
+            MCOUNTINHIBIT => {
+                // mcountinhibit.TM does not exist, time is not a hart counter
+                self.inhibit = value as u32 & !0x2;
+                self.set_raw(MCOUNTINHIBIT, self.inhibit as u128);
+            },
+            MHPMEVENT3..=MHPMEVENT31 => {
+                self.events[addr & 0x1f] = value as u64;
+                self.set_raw(addr, value);
+            },
+            _ => {
+                self.set_raw(addr, value);
+            },
+        }
+    }
+
+    // The counters are saved in the bank slots of their CSRs
+    pub fn save(&self, w: &mut Writer) {
+        for (addr, reg) in self.bank.iter().enumerate() {
+            let value: u128 = match Self::counter(addr).filter(|_| self.exists(addr)) {
+                Some((i, high)) => self.get_counter(i, high),
+                None => *reg,
+            };
+
+            w.bytes(&Vec::<u8>::from(to_xlen(self.xlen, value)));
+        }
+        self.pmp.save(w);
+        if self.hypervisor {
+            w.u8(self.virt as u8);
+        }
+    }
+
+    pub fn restore(&mut self, r: &mut Reader) -> Result<(), SnapshotError> {
+        for reg in self.bank.iter_mut() {
+            *reg = u128::from(Uint::new(r.bytes()?.to_vec()));
+        }
+
+        for addr in 0..self.bank.len() {
+            if let Some((i, high)) = Self::counter(addr).filter(|_| self.exists(addr)) {
+                self.set_counter(i, high, self.raw(addr));
             }
         }
+        for i in 3..32 {
+            self.events[i] = self.raw(MHPMEVENT3 + i - 3) as u64;
+        }
+        self.inhibit = self.raw(MCOUNTINHIBIT) as u32;
+        self.written = 0;
+
+        self.pmp.restore(r)?;
+        if self.hypervisor {
+            self.virt = r.u8()? != 0;
+        }
+
+        Ok(())
     }
 
     pub fn get(&self, addr: usize) -> Option<Uint> {
-        if addr > self.bank.len() {
+        self.read(addr).map(|v| to_xlen(self.xlen, v))
+    }
+
+    pub fn read(&self, addr: usize) -> Option<u128> {
+        self.view(addr).map(|v| v & self.mask())
+    }
+
+    // Value of a CSR, from the bank or from what it is a view of
+    fn view(&self, addr: usize) -> Option<u128> {
+        if addr >= self.bank.len() {
             return None;
         }
 
-        if read_is_allowed(addr) {
-            match addr {
-                FFLAGS => {
-                    let msk: Uint = Uint::from(0x1fu8).extend(self.xlen).clone();
-                    return Some(self.bank[FCSR].get() & msk);
-                },
-                FRM => {
-                    let msk: Uint = Uint::from(0x3u8 << 5).extend(self.xlen).clone();
-                    let shift: Uint = Uint::from(5u8).extend(self.xlen).clone();
-                    return Some((self.bank[FCSR].get() & msk) >> shift);
-                },
-                _ => {
-                    return Some(self.bank[addr].get());
-                },
-            }
-        }
+        match addr {
+            FFLAGS => Some(self.raw(FCSR) & 0x1f),
+            FRM => Some((self.raw(FCSR) >> 5) & 0x7),
+            VXSAT => Some(self.raw(VCSR) & 0x1),
+            VXRM => Some((self.raw(VCSR) >> 1) & 0x3),
+            MSTATUS => Some(self.raw(MSTATUS) | self.sd(self.raw(MSTATUS))),
+            SSTATUS => {
+                let mstatus: u128 = self.raw(MSTATUS);
+
+                Some(mstatus & SSTATUS_MASK | self.sd(mstatus))
+            },
+            VSSTATUS => Some(self.raw(VSSTATUS) | self.sd(self.raw(VSSTATUS))),
+            SIE => Some(self.raw(MIE) & self.raw(MIDELEG) & !MIP_H),
+            SIP => Some(self.raw(MIP) & self.raw(MIDELEG) & !MIP_H),
+            MIP => Some(self.mip()),
+            HIE => Some(self.raw(MIE) & MIP_H),
+            HIP => Some(self.mip() & MIP_H),
+            VSIE => Some((self.raw(MIE) & self.raw(HIDELEG) & MIP_VS) >> 1),
+            VSIP => Some((self.mip() & self.raw(HIDELEG) & MIP_VS) >> 1),
+            // VS/VU-mode see the time shifted by htimedelta
+            TIME | TIMEH if self.virt => {
+                let delta: u128 = if self.xlen == 32 {
+                    self.raw(HTIMEDELTAH) << 32 | self.raw(HTIMEDELTA)
+                } else {
+                    self.raw(HTIMEDELTA)
+                };
+                let time: u128 = (self.counters[COUNTER_TIME] as u128 + delta) as u64 as u128;
+
+                if addr == TIMEH {
+                    Some(time >> 32)
+                } else {
+                    Some(time)
+                }
+            },
+            MCYCLE..=MHPMCOUNTER31
+            | MCYCLEH..=MHPMCOUNTER31H
+            | CYCLE..=HPMCOUNTER31
+            | CYCLEH..=HPMCOUNTER31H => {
+                let (i, high) = Self::counter(addr).unwrap();
 
-        Some(Uint::zero(self.xlen))
+                Some(self.get_counter(i, high))
+            },
+            _ => Some(self.raw(addr)),
+        }
     }
 }
 
-pub fn write_is_allowed(regidx: usize) -> bool {
-    if regidx < 0xc00 {
-        return true;
-    }
+// Value of a field of a CSR, given its mask
+pub fn field(mask: u128, value: u128) -> u128 {
+    value << mask.trailing_zeros() & mask
+}
+
+// Build a CSR value of xlen bits
+pub fn to_xlen(xlen: usize, value: u128) -> Uint {
+    let mut v: Uint = Uint::from(value);
 
-    false
+    v.truncate(xlen / 8);
+    v
 }
 
-pub fn read_is_allowed(_regidx: usize) -> bool {
-    true
+// csr[9:8] encodes the lowest privilege level allowed to access the register
+pub fn read_is_allowed(regidx: usize, privilege: RvPrivilege) -> bool {
+    privilege as usize >= (regidx >> 8) & 0x3
+}
+
+// csr[11:10] == 0b11 encodes a read-only register
+pub fn write_is_allowed(regidx: usize, privilege: RvPrivilege) -> bool {
+    read_is_allowed(regidx, privilege) && (regidx >> 10) & 0x3 != 0x3
 }
 
 impl fmt::Display for Csr {
@@ -417,26 +1067,235 @@ impl fmt::Display for Csr {
         write!(
             f,
             "(csr ((xlen {})\n       (bank: {:?})))\n",
-            self.xlen, self.bank
+            self.xlen,
+            self.names.iter().zip(self.bank.iter()).collect::<Vec<_>>()
         )
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::vsoc::arch::riscv::csr;
+    use crate::vsoc::arch::riscv::{
+        csr, exception::RvException, ext::RvExtensions, privilege::RvPrivilege,
+    };
+    use crate::vsoc::arch::types::Uint;
 
     #[test]
     fn test_write_allowed() {
-        assert_eq!(csr::write_is_allowed(0x000), true);
-        assert_eq!(csr::write_is_allowed(0xc00), false);
-        assert_eq!(csr::write_is_allowed(0xfff), false);
+        assert!(csr::write_is_allowed(0x000, RvPrivilege::User));
+        assert!(!csr::write_is_allowed(0xc00, RvPrivilege::User));
+        assert!(!csr::write_is_allowed(0xfff, RvPrivilege::Machine));
+        assert!(!csr::write_is_allowed(0x100, RvPrivilege::User));
+        assert!(csr::write_is_allowed(0x100, RvPrivilege::Supervisor));
+        assert!(!csr::write_is_allowed(0x300, RvPrivilege::Supervisor));
+        assert!(csr::write_is_allowed(0x300, RvPrivilege::Machine));
     }
 
     #[test]
     fn test_read_allowed() {
-        assert_eq!(csr::read_is_allowed(0x000), true);
-        assert_eq!(csr::read_is_allowed(0xc00), true);
-        assert_eq!(csr::read_is_allowed(0xfff), true);
+        assert!(csr::read_is_allowed(0x000, RvPrivilege::User));
+        assert!(csr::read_is_allowed(0xc00, RvPrivilege::User));
+        assert!(!csr::read_is_allowed(0xf11, RvPrivilege::Supervisor));
+        assert!(csr::read_is_allowed(0xfff, RvPrivilege::Machine));
+    }
+
+    #[test]
+    fn test_sstatus_view() {
+        let ext: RvExtensions = RvExtensions {
+            s: true,
+            u: true,
+            ..Default::default()
+        };
+        let mut c: csr::Csr = csr::Csr::new(64, &ext);
+
+        c.set(csr::MSTATUS, &Uint::from((csr::MSTATUS_MIE | csr::MSTATUS_SIE) as u64));
+        assert_eq!(u64::from(c.get(csr::SSTATUS).unwrap()), csr::MSTATUS_SIE as u64);
+
+        c.set(csr::SSTATUS, &Uint::from(csr::MSTATUS_SPP as u64));
+        assert_eq!(
+            u64::from(c.get(csr::MSTATUS).unwrap()),
+            (csr::MSTATUS_MIE | csr::MSTATUS_SPP) as u64
+        );
+    }
+
+    #[test]
+    fn test_fs() {
+        let ext: RvExtensions = RvExtensions {
+            f: true,
+            s: true,
+            ..Default::default()
+        };
+        let mut c: csr::Csr = csr::Csr::new(64, &ext);
+        let sd: u128 = 1 << 63;
+
+        assert_eq!(
+            c.read(csr::MSTATUS).unwrap(),
+            csr::field(csr::MSTATUS_FS, csr::STATUS_INITIAL)
+        );
+        c.write(csr::FFLAGS, 0x1);
+        assert_eq!(c.read(csr::MSTATUS).unwrap(), csr::MSTATUS_FS | sd);
+        assert_eq!(c.read(csr::SSTATUS).unwrap(), csr::MSTATUS_FS | sd);
+
+        // SD is read-only, and FS Off makes the FP CSRs illegal
+        c.write(csr::MSTATUS, sd);
+        assert_eq!(c.read(csr::MSTATUS).unwrap(), 0);
+        assert!(!c.fs_enabled());
+        assert_eq!(
+            c.check(csr::FCSR, RvPrivilege::Machine, false),
+            Some(RvException::InstructionIllegal)
+        );
+
+        // FS is read-only zero without F
+        let mut c: csr::Csr = csr::Csr::new(64, &RvExtensions::default());
+
+        c.write(csr::MSTATUS, csr::MSTATUS_FS);
+        assert_eq!(c.read(csr::MSTATUS).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_vs() {
+        let ext: RvExtensions = RvExtensions {
+            v: true,
+            ..Default::default()
+        };
+        let mut c: csr::Csr = csr::Csr::new(32, &ext);
+
+        assert_eq!(
+            c.read(csr::MSTATUS).unwrap(),
+            csr::field(csr::MSTATUS_VS, csr::STATUS_INITIAL)
+        );
+        c.write(csr::VXRM, 0x2);
+        assert_eq!(c.read(csr::MSTATUS).unwrap(), csr::MSTATUS_VS | 1 << 31);
+
+        c.write(csr::MSTATUS, 0);
+        assert_eq!(
+            c.check(csr::VL, RvPrivilege::Machine, false),
+            Some(RvException::InstructionIllegal)
+        );
+
+        // VS is read-only zero without V
+        let mut c: csr::Csr = csr::Csr::new(32, &RvExtensions::default());
+
+        c.write(csr::MSTATUS, csr::MSTATUS_VS);
+        assert_eq!(c.read(csr::MSTATUS).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_virtual_csrs() {
+        let ext: RvExtensions = RvExtensions {
+            s: true,
+            u: true,
+            h: true,
+            ..Default::default()
+        };
+        let mut c: csr::Csr = csr::Csr::new(64, &ext);
+
+        // VS-level interrupts are always delegated past M-mode
+        assert_eq!(c.read(csr::MIDELEG).unwrap(), csr::MIP_H);
+
+        assert_eq!(c.alias(csr::SSTATUS), csr::SSTATUS);
+        assert_eq!(c.check(csr::HSTATUS, RvPrivilege::Supervisor, true), None);
+        c.set_virt(true);
+        assert_eq!(c.alias(csr::SSTATUS), csr::VSSTATUS);
+        assert_eq!(c.alias(csr::SATP), csr::VSATP);
+        assert_eq!(
+            c.check(csr::HSTATUS, RvPrivilege::Supervisor, false),
+            Some(RvException::VirtualInstruction)
+        );
+        assert_eq!(
+            c.check(csr::SSTATUS, RvPrivilege::User, false),
+            Some(RvException::VirtualInstruction)
+        );
+        assert_eq!(
+            c.check(csr::MSTATUS, RvPrivilege::Supervisor, false),
+            Some(RvException::InstructionIllegal)
+        );
+
+        // hvip injects VS-level interrupts, seen as S-level ones in vsip
+        c.set(csr::HIDELEG, &Uint::from(csr::MIP_VS as u64));
+        c.set(csr::HVIP, &Uint::from(csr::MIP_VSSIP as u64));
+        assert_eq!(c.read(csr::VSIP).unwrap(), csr::MIP_SSIP);
+        c.set(csr::VSIP, &Uint::from(0u64));
+        assert_eq!(c.read(csr::HVIP).unwrap(), 0);
+        c.set(csr::VSIE, &Uint::from(csr::MIP_STIP as u64));
+        assert_eq!(c.read(csr::HIE).unwrap(), csr::MIP_VSTIP);
+    }
+
+    #[test]
+    fn test_mstatus_mpp_warl() {
+        let mut c: csr::Csr = csr::Csr::new(32, &RvExtensions::default());
+
+        c.set(csr::MSTATUS, &Uint::from(csr::MSTATUS_MPP as u32));
+        c.set(csr::MSTATUS, &Uint::from(0u32));
+        assert_eq!(u32::from(c.get(csr::MSTATUS).unwrap()), csr::MSTATUS_MPP as u32);
+    }
+
+    #[test]
+    fn test_counters() {
+        let ext: RvExtensions = RvExtensions {
+            s: true,
+            u: true,
+            zicntr: true,
+            zihpm: true,
+            ..Default::default()
+        };
+        let mut c: csr::Csr = csr::Csr::new(32, &ext);
+        let get = |c: &csr::Csr, addr: usize| u32::from(c.get(addr).unwrap());
+
+        assert_eq!(c.name(csr::MCYCLEH), "mcycleh");
+        assert_eq!(c.name(csr::HPMCOUNTER3H), "hpmcounter3h");
+
+        c.set(csr::MCYCLE, &Uint::from(0xffff_ffffu32));
+        c.tick();
+        assert_eq!(get(&c, csr::MCYCLE), 0xffff_ffff);
+        c.tick();
+        assert_eq!(get(&c, csr::CYCLE), 0);
+        assert_eq!(get(&c, csr::CYCLEH), 1);
+
+        c.set(csr::MHPMEVENT3 + 1, &Uint::from(csr::HPMEVENT_STORE as u32));
+        c.set(csr::MCOUNTINHIBIT, &Uint::from(0x7u32));
+        assert_eq!(get(&c, csr::MCOUNTINHIBIT), 0x5);
+        c.retire();
+        c.count(csr::HPMEVENT_STORE);
+        c.tick();
+        assert_eq!(get(&c, csr::MINSTRET), 0);
+        assert_eq!(get(&c, csr::MHPMCOUNTER3 + 1), 1);
+        assert_eq!(get(&c, csr::HPMCOUNTER3 + 1), 1);
+
+        c.set_time(0x1_0000_0002);
+        assert_eq!(get(&c, csr::TIME), 2);
+        assert_eq!(get(&c, csr::TIMEH), 1);
+
+        // U-mode needs both mcounteren and scounteren, S-mode only mcounteren
+        assert!(c.check(csr::TIME, RvPrivilege::Machine, false).is_none());
+        assert!(c.check(csr::TIME, RvPrivilege::Supervisor, false).is_some());
+        c.set(csr::MCOUNTEREN, &Uint::from(0x2u32));
+        assert!(c.check(csr::TIME, RvPrivilege::Supervisor, false).is_none());
+        assert!(c.check(csr::TIME, RvPrivilege::User, false).is_some());
+        c.set(csr::SCOUNTEREN, &Uint::from(0x2u32));
+        assert!(c.check(csr::TIMEH, RvPrivilege::User, false).is_none());
+        assert!(c.check(csr::CYCLE, RvPrivilege::User, false).is_some());
+        assert!(c.check(csr::TIME, RvPrivilege::Machine, true).is_some());
+    }
+
+    #[test]
+    fn test_pmp_warl() {
+        let mut c: csr::Csr = csr::Csr::new(64, &RvExtensions::default());
+
+        assert!(c.exists(csr::PMPCFG0 + 14));
+        assert!(!c.exists(csr::PMPCFG0 + 1));
+        assert!(c.exists(csr::PMPADDR63));
+
+        // R=0/W=1 is reserved, entry 1 keeps its previous value
+        c.set(csr::PMPCFG0, &Uint::from(0x0219u64));
+        assert_eq!(u64::from(c.get(csr::PMPCFG0).unwrap()), 0x19);
+
+        // A locked TOR entry freezes its own and the previous address
+        c.set(csr::PMPADDR0, &Uint::from(0x1000u64));
+        c.set(csr::PMPCFG0, &Uint::from(0x8b19u64));
+        c.set(csr::PMPADDR0, &Uint::from(0x2000u64));
+        c.set(csr::PMPCFG0, &Uint::from(0u64));
+        assert_eq!(u64::from(c.get(csr::PMPADDR0).unwrap()), 0x1000);
+        assert_eq!(u64::from(c.get(csr::PMPCFG0).unwrap()), 0x8b00);
     }
 }
@@ -0,0 +1,1181 @@
+use super::csr;
+use super::instr::Instr;
+
+pub const X_NAMES: [&str; 32] = [
+    "zero", "ra", "sp", "gp", "tp", "t0", "t1", "t2", "s0", "s1", "a0", "a1", "a2", "a3", "a4",
+    "a5", "a6", "a7", "s2", "s3", "s4", "s5", "s6", "s7", "s8", "s9", "s10", "s11", "t3", "t4",
+    "t5", "t6",
+];
+
+pub const F_NAMES: [&str; 32] = [
+    "ft0", "ft1", "ft2", "ft3", "ft4", "ft5", "ft6", "ft7", "fs0", "fs1", "fa0", "fa1", "fa2",
+    "fa3", "fa4", "fa5", "fa6", "fa7", "fs2", "fs3", "fs4", "fs5", "fs6", "fs7", "fs8", "fs9",
+    "fs10", "fs11", "ft8", "ft9", "ft10", "ft11",
+];
+
+const RM_NAMES: [&str; 8] = ["rne", "rtz", "rdn", "rup", "rmm", "", "", "dyn"];
+
+fn bits(i: u32, hi: u32, lo: u32) -> u32 {
+    (i >> lo) & ((1 << (hi - lo + 1)) - 1)
+}
+
+fn x(r: u32) -> &'static str {
+    X_NAMES[r as usize]
+}
+
+fn f(r: u32) -> &'static str {
+    F_NAMES[r as usize]
+}
+
+fn i_imm(i: u32) -> i32 {
+    i as i32 >> 20
+}
+
+fn s_imm(i: u32) -> i32 {
+    ((i as i32 >> 25) << 5) | bits(i, 11, 7) as i32
+}
+
+fn b_imm(i: u32) -> i32 {
+    ((i as i32 >> 31) << 12)
+        | (bits(i, 7, 7) << 11) as i32
+        | (bits(i, 30, 25) << 5) as i32
+        | (bits(i, 11, 8) << 1) as i32
+}
+
+fn j_imm(i: u32) -> i32 {
+    ((i as i32 >> 31) << 20)
+        | (bits(i, 19, 12) << 12) as i32
+        | (bits(i, 20, 20) << 11) as i32
+        | (bits(i, 30, 21) << 1) as i32
+}
+
+fn csr_name(addr: u32) -> String {
+    let addr: usize = addr as usize;
+    let s: &str = match addr {
+        csr::FFLAGS => "fflags",
+        csr::FRM => "frm",
+        csr::FCSR => "fcsr",
+        csr::VSTART => "vstart",
+        csr::VXSAT => "vxsat",
+        csr::VXRM => "vxrm",
+        csr::VCSR => "vcsr",
+        csr::VL => "vl",
+        csr::VTYPE => "vtype",
+        csr::VLENB => "vlenb",
+        csr::CYCLE => "cycle",
+        csr::TIME => "time",
+        csr::INSTRET => "instret",
+        csr::CYCLEH => "cycleh",
+        csr::TIMEH => "timeh",
+        csr::INSTRETH => "instreth",
+        csr::SSTATUS => "sstatus",
+        csr::SIE => "sie",
+        csr::STVEC => "stvec",
+        csr::SCOUNTEREN => "scounteren",
+        csr::SENVCFG => "senvcfg",
+        csr::SSCRATCH => "sscratch",
+        csr::SEPC => "sepc",
+        csr::SCAUSE => "scause",
+        csr::STVAL => "stval",
+        csr::SIP => "sip",
+        csr::SATP => "satp",
+        csr::HSTATUS => "hstatus",
+        csr::HEDELEG => "hedeleg",
+        csr::HIDELEG => "hideleg",
+        csr::HIE => "hie",
+        csr::HCOUNTEREN => "hcounteren",
+        csr::HGEIE => "hgeie",
+        csr::HTVAL => "htval",
+        csr::HIP => "hip",
+        csr::HVIP => "hvip",
+        csr::HTINST => "htinst",
+        csr::HGEIP => "hgeip",
+        csr::HENVCFG => "henvcfg",
+        csr::HGATP => "hgatp",
+        csr::HTIMEDELTA => "htimedelta",
+        csr::VSSTATUS => "vsstatus",
+        csr::VSIE => "vsie",
+        csr::VSTVEC => "vstvec",
+        csr::VSSCRATCH => "vsscratch",
+        csr::VSEPC => "vsepc",
+        csr::VSCAUSE => "vscause",
+        csr::VSTVAL => "vstval",
+        csr::VSIP => "vsip",
+        csr::VSATP => "vsatp",
+        csr::MVENDORID => "mvendorid",
+        csr::MARCHID => "marchid",
+        csr::MIMPID => "mimpid",
+        csr::MHARTID => "mhartid",
+        csr::MCONFIGPTR => "mconfigptr",
+        csr::MSTATUS => "mstatus",
+        csr::MISA => "misa",
+        csr::MEDELEG => "medeleg",
+        csr::MIDELEG => "mideleg",
+        csr::MIE => "mie",
+        csr::MTVEC => "mtvec",
+        csr::MCOUNTEREN => "mcounteren",
+        csr::MSTATUSH => "mstatush",
+        csr::MSCRATCH => "mscratch",
+        csr::MEPC => "mepc",
+        csr::MCAUSE => "mcause",
+        csr::MTVAL => "mtval",
+        csr::MIP => "mip",
+        csr::MTINST => "mtinst",
+        csr::MTVAL2 => "mtval2",
+        csr::MENVCFG => "menvcfg",
+        csr::MENVCFGH => "menvcfgh",
+        csr::MSECCFG => "mseccfg",
+        csr::MCYCLE => "mcycle",
+        csr::MINSTRET => "minstret",
+        csr::MCYCLEH => "mcycleh",
+        csr::MINSTRETH => "minstreth",
+        csr::MCOUNTINHIBIT => "mcountinhibit",
+        csr::TSELECT => "tselect",
+        csr::TDATA1 => "tdata1",
+        csr::TDATA2 => "tdata2",
+        csr::TDATA3 => "tdata3",
+        csr::DCSR => "dcsr",
+        csr::DPC => "dpc",
+        csr::DSCRATCH0 => "dscratch0",
+        csr::DSCRATCH1 => "dscratch1",
+        a if (csr::PMPCFG0..=csr::PMPCFG15).contains(&a) => {
+            return format!("pmpcfg{}", a - csr::PMPCFG0)
+        }
+        a if (csr::PMPADDR0..=csr::PMPADDR63).contains(&a) => {
+            return format!("pmpaddr{}", a - csr::PMPADDR0)
+        }
+        a if (csr::HPMCOUNTER3..csr::HPMCOUNTER3 + 29).contains(&a) => {
+            return format!("hpmcounter{}", a - csr::HPMCOUNTER3 + 3)
+        }
+        a if (csr::HPMCOUNTER3H..csr::HPMCOUNTER3H + 29).contains(&a) => {
+            return format!("hpmcounter{}h", a - csr::HPMCOUNTER3H + 3)
+        }
+        a if (csr::MHPMCOUNTER3..csr::MHPMCOUNTER3 + 29).contains(&a) => {
+            return format!("mhpmcounter{}", a - csr::MHPMCOUNTER3 + 3)
+        }
+        a if (csr::MHPMCOUNTER3H..csr::MHPMCOUNTER3H + 29).contains(&a) => {
+            return format!("mhpmcounter{}h", a - csr::MHPMCOUNTER3H + 3)
+        }
+        a if (csr::MHPMEVENT3..csr::MHPMEVENT3 + 29).contains(&a) => {
+            return format!("mhpmevent{}", a - csr::MHPMEVENT3 + 3)
+        }
+        a => return format!("{:#x}", a),
+    };
+
+    String::from(s)
+}
+
+// Floating-point format suffix of the fmt field
+fn fmt_suffix(fmt: u32) -> &'static str {
+    ["s", "d", "h", "q"][fmt as usize & 0x3]
+}
+
+// Optional rounding mode operand, omitted when dynamic
+fn rm(i: u32) -> String {
+    match bits(i, 14, 12) {
+        7 => String::new(),
+        r => format!(",{}", RM_NAMES[r as usize]),
+    }
+}
+
+fn fence_set(set: u32) -> String {
+    let s: String = "iorw"
+        .chars()
+        .enumerate()
+        .filter(|(n, _)| set & (0x8 >> n) != 0)
+        .map(|(_, c)| c)
+        .collect();
+
+    if s.is_empty() {
+        String::from("0")
+    } else {
+        s
+    }
+}
+
+fn op(i: u32, name: &str, w: bool) -> Option<String> {
+    let (rd, rs1, rs2) = (bits(i, 11, 7), bits(i, 19, 15), bits(i, 24, 20));
+
+    match (name, rs1, rs2) {
+        ("sub", 0, _) if !w => Some(format!("neg\t{},{}", x(rd), x(rs2))),
+        ("sub", 0, _) => Some(format!("negw\t{},{}", x(rd), x(rs2))),
+        ("sltu", 0, _) => Some(format!("snez\t{},{}", x(rd), x(rs2))),
+        ("slt", _, 0) => Some(format!("sltz\t{},{}", x(rd), x(rs1))),
+        ("slt", 0, _) => Some(format!("sgtz\t{},{}", x(rd), x(rs2))),
+        _ => {
+            let suffix: &str = if w { "w" } else { "" };
+
+            Some(format!(
+                "{}{}\t{},{},{}",
+                name,
+                suffix,
+                x(rd),
+                x(rs1),
+                x(rs2)
+            ))
+        }
+    }
+}
+
+fn disasm_op(i: u32, w: bool) -> Option<String> {
+    let (rd, rs1, rs2) = (bits(i, 11, 7), bits(i, 19, 15), bits(i, 24, 20));
+
+    // Zba and Zbb forms without the usual w suffix
+    match (bits(i, 31, 25), bits(i, 14, 12), w) {
+        (0x04, 0x4, _) if rs2 == 0 => return Some(format!("zext.h\t{},{}", x(rd), x(rs1))),
+        (0x04, 0x0, true) if rs2 == 0 => return Some(format!("zext.w\t{},{}", x(rd), x(rs1))),
+        (0x04, 0x0, true) => return Some(format!("add.uw\t{},{},{}", x(rd), x(rs1), x(rs2))),
+        (0x10, f @ (0x2 | 0x4 | 0x6), true) => {
+            return Some(format!("sh{}add.uw\t{},{},{}", f / 2, x(rd), x(rs1), x(rs2)))
+        }
+        _ => (),
+    }
+
+    let name: &str = match (bits(i, 31, 25), bits(i, 14, 12)) {
+        (0x00, 0x0) => "add",
+        (0x20, 0x0) => "sub",
+        (0x00, 0x1) => "sll",
+        (0x00, 0x2) if !w => "slt",
+        (0x00, 0x3) if !w => "sltu",
+        (0x00, 0x4) if !w => "xor",
+        (0x00, 0x5) => "srl",
+        (0x20, 0x5) => "sra",
+        (0x00, 0x6) if !w => "or",
+        (0x00, 0x7) if !w => "and",
+        (0x01, 0x0) => "mul",
+        (0x01, 0x1) if !w => "mulh",
+        (0x01, 0x2) if !w => "mulhsu",
+        (0x01, 0x3) if !w => "mulhu",
+        (0x01, 0x4) => "div",
+        (0x01, 0x5) => "divu",
+        (0x01, 0x6) => "rem",
+        (0x01, 0x7) => "remu",
+        (0x10, 0x2) if !w => "sh1add",
+        (0x10, 0x4) if !w => "sh2add",
+        (0x10, 0x6) if !w => "sh3add",
+        (0x20, 0x7) if !w => "andn",
+        (0x20, 0x6) if !w => "orn",
+        (0x20, 0x4) if !w => "xnor",
+        (0x05, 0x1) if !w => "clmul",
+        (0x05, 0x2) if !w => "clmulr",
+        (0x05, 0x3) if !w => "clmulh",
+        (0x05, 0x4) if !w => "min",
+        (0x05, 0x5) if !w => "minu",
+        (0x05, 0x6) if !w => "max",
+        (0x05, 0x7) if !w => "maxu",
+        (0x30, 0x1) => "rol",
+        (0x30, 0x5) => "ror",
+        (0x14, 0x1) if !w => "bset",
+        (0x24, 0x1) if !w => "bclr",
+        (0x34, 0x1) if !w => "binv",
+        (0x24, 0x5) if !w => "bext",
+        _ => return None,
+    };
+
+    op(i, name, w)
+}
+
+fn disasm_opimm(i: u32, xlen: usize) -> Option<String> {
+    let (rd, rs1, imm) = (bits(i, 11, 7), bits(i, 19, 15), i_imm(i));
+    let shamt: u32 = match xlen {
+        128 => bits(i, 26, 20),
+        _ => bits(i, 25, 20) & (xlen as u32 - 1),
+    };
+    let funct6: u32 = bits(i, 31, 26);
+
+    Some(match bits(i, 14, 12) {
+        0x0 if rd == 0 && rs1 == 0 && imm == 0 => String::from("nop"),
+        0x0 if rs1 == 0 => format!("li\t{},{}", x(rd), imm),
+        0x0 if imm == 0 => format!("mv\t{},{}", x(rd), x(rs1)),
+        0x0 => format!("addi\t{},{},{}", x(rd), x(rs1), imm),
+        0x1 if funct6 == 0x18 => {
+            let name: &str = match bits(i, 24, 20) {
+                0x0 => "clz",
+                0x1 => "ctz",
+                0x2 => "cpop",
+                0x4 => "sext.b",
+                0x5 => "sext.h",
+                _ => return None,
+            };
+
+            format!("{}\t{},{}", name, x(rd), x(rs1))
+        }
+        0x1 if funct6 == 0x0a => format!("bseti\t{},{},{:#x}", x(rd), x(rs1), shamt),
+        0x1 if funct6 == 0x12 => format!("bclri\t{},{},{:#x}", x(rd), x(rs1), shamt),
+        0x1 if funct6 == 0x1a => format!("binvi\t{},{},{:#x}", x(rd), x(rs1), shamt),
+        0x1 => format!("slli\t{},{},{:#x}", x(rd), x(rs1), shamt),
+        0x2 => format!("slti\t{},{},{}", x(rd), x(rs1), imm),
+        0x3 if imm == 1 => format!("seqz\t{},{}", x(rd), x(rs1)),
+        0x3 => format!("sltiu\t{},{},{}", x(rd), x(rs1), imm),
+        0x4 if imm == -1 => format!("not\t{},{}", x(rd), x(rs1)),
+        0x4 => format!("xori\t{},{},{}", x(rd), x(rs1), imm),
+        0x5 if bits(i, 31, 20) == 0x287 => format!("orc.b\t{},{}", x(rd), x(rs1)),
+        0x5 if bits(i, 31, 20) == 0x680 | (xlen as u32 - 8) => {
+            format!("rev8\t{},{}", x(rd), x(rs1))
+        }
+        0x5 if funct6 == 0x18 => format!("rori\t{},{},{:#x}", x(rd), x(rs1), shamt),
+        0x5 if funct6 == 0x12 => format!("bexti\t{},{},{:#x}", x(rd), x(rs1), shamt),
+        0x5 if bits(i, 30, 30) != 0 => format!("srai\t{},{},{:#x}", x(rd), x(rs1), shamt),
+        0x5 => format!("srli\t{},{},{:#x}", x(rd), x(rs1), shamt),
+        0x6 => format!("ori\t{},{},{}", x(rd), x(rs1), imm),
+        _ => format!("andi\t{},{},{}", x(rd), x(rs1), imm),
+    })
+}
+
+fn disasm_opimm32(i: u32) -> Option<String> {
+    let (rd, rs1, imm) = (bits(i, 11, 7), bits(i, 19, 15), i_imm(i));
+    let shamt: u32 = bits(i, 24, 20);
+
+    Some(match (bits(i, 14, 12), bits(i, 31, 25)) {
+        (0x0, _) if imm == 0 => format!("sext.w\t{},{}", x(rd), x(rs1)),
+        (0x0, _) => format!("addiw\t{},{},{}", x(rd), x(rs1), imm),
+        (0x1, 0x00) => format!("slliw\t{},{},{:#x}", x(rd), x(rs1), shamt),
+        (0x1, 0x04 | 0x05) => format!("slli.uw\t{},{},{:#x}", x(rd), x(rs1), bits(i, 25, 20)),
+        (0x1, 0x30) if shamt == 0 => format!("clzw\t{},{}", x(rd), x(rs1)),
+        (0x1, 0x30) if shamt == 1 => format!("ctzw\t{},{}", x(rd), x(rs1)),
+        (0x1, 0x30) if shamt == 2 => format!("cpopw\t{},{}", x(rd), x(rs1)),
+        (0x5, 0x30) => format!("roriw\t{},{},{:#x}", x(rd), x(rs1), shamt),
+        (0x5, 0x00) => format!("srliw\t{},{},{:#x}", x(rd), x(rs1), shamt),
+        (0x5, 0x20) => format!("sraiw\t{},{},{:#x}", x(rd), x(rs1), shamt),
+        _ => return None,
+    })
+}
+
+fn disasm_opimm64(i: u32) -> Option<String> {
+    let (rd, rs1, imm) = (bits(i, 11, 7), bits(i, 19, 15), i_imm(i));
+    let shamt: u32 = bits(i, 25, 20);
+
+    Some(match (bits(i, 14, 12), bits(i, 31, 26)) {
+        (0x0, _) => format!("addid\t{},{},{}", x(rd), x(rs1), imm),
+        (0x1, 0x00) => format!("sllid\t{},{},{:#x}", x(rd), x(rs1), shamt),
+        (0x5, 0x00) => format!("srlid\t{},{},{:#x}", x(rd), x(rs1), shamt),
+        (0x5, 0x10) => format!("sraid\t{},{},{:#x}", x(rd), x(rs1), shamt),
+        _ => return None,
+    })
+}
+
+fn disasm_op64(i: u32) -> Option<String> {
+    let name: &str = match (bits(i, 31, 25), bits(i, 14, 12)) {
+        (0x00, 0x0) => "addd",
+        (0x20, 0x0) => "subd",
+        (0x00, 0x1) => "slld",
+        (0x00, 0x5) => "srld",
+        (0x20, 0x5) => "srad",
+        (0x01, 0x0) => "muld",
+        (0x01, 0x4) => "divd",
+        (0x01, 0x5) => "divud",
+        (0x01, 0x6) => "remd",
+        (0x01, 0x7) => "remud",
+        _ => return None,
+    };
+
+    Some(format!(
+        "{}\t{},{},{}",
+        name,
+        x(bits(i, 11, 7)),
+        x(bits(i, 19, 15)),
+        x(bits(i, 24, 20))
+    ))
+}
+
+fn disasm_branch(i: u32, pc: u64) -> Option<String> {
+    let (rs1, rs2) = (bits(i, 19, 15), bits(i, 24, 20));
+    let target: u64 = pc.wrapping_add(b_imm(i) as i64 as u64);
+    let name: &str = match bits(i, 14, 12) {
+        0x0 => "beq",
+        0x1 => "bne",
+        0x4 => "blt",
+        0x5 => "bge",
+        0x6 => "bltu",
+        0x7 => "bgeu",
+        _ => return None,
+    };
+
+    Some(match (name, rs1, rs2) {
+        ("beq" | "bne" | "blt" | "bge", _, 0) => format!("{}z\t{},{:x}", name, x(rs1), target),
+        ("blt", 0, _) => format!("bgtz\t{},{:x}", x(rs2), target),
+        ("bge", 0, _) => format!("blez\t{},{:x}", x(rs2), target),
+        _ => format!("{}\t{},{},{:x}", name, x(rs1), x(rs2), target),
+    })
+}
+
+fn disasm_jalr(i: u32) -> Option<String> {
+    let (rd, rs1, imm) = (bits(i, 11, 7), bits(i, 19, 15), i_imm(i));
+
+    Some(match (rd, rs1, imm) {
+        (0, 1, 0) => String::from("ret"),
+        (0, _, 0) => format!("jr\t{}", x(rs1)),
+        (0, _, _) => format!("jr\t{}({})", imm, x(rs1)),
+        (1, _, 0) => format!("jalr\t{}", x(rs1)),
+        _ => format!("jalr\t{},{}({})", x(rd), imm, x(rs1)),
+    })
+}
+
+fn disasm_jal(i: u32, pc: u64) -> Option<String> {
+    let rd: u32 = bits(i, 11, 7);
+    let target: u64 = pc.wrapping_add(j_imm(i) as i64 as u64);
+
+    Some(match rd {
+        0 => format!("j\t{:x}", target),
+        1 => format!("jal\t{:x}", target),
+        _ => format!("jal\t{},{:x}", x(rd), target),
+    })
+}
+
+fn disasm_load(i: u32) -> Option<String> {
+    let name: &str = match bits(i, 14, 12) {
+        0x0 => "lb",
+        0x1 => "lh",
+        0x2 => "lw",
+        0x3 => "ld",
+        0x4 => "lbu",
+        0x5 => "lhu",
+        0x6 => "lwu",
+        _ => "ldu",
+    };
+
+    Some(format!(
+        "{}\t{},{}({})",
+        name,
+        x(bits(i, 11, 7)),
+        i_imm(i),
+        x(bits(i, 19, 15))
+    ))
+}
+
+fn disasm_store(i: u32) -> Option<String> {
+    let name: &str = match bits(i, 14, 12) {
+        0x0 => "sb",
+        0x1 => "sh",
+        0x2 => "sw",
+        0x3 => "sd",
+        0x4 => "sq",
+        _ => return None,
+    };
+
+    Some(format!(
+        "{}\t{},{}({})",
+        name,
+        x(bits(i, 24, 20)),
+        s_imm(i),
+        x(bits(i, 19, 15))
+    ))
+}
+
+fn disasm_load_fp(i: u32) -> Option<String> {
+    if matches!(bits(i, 14, 12), 0x0 | 0x5 | 0x6 | 0x7) {
+        return disasm_vmem(i, false);
+    }
+
+    let name: &str = match bits(i, 14, 12) {
+        0x1 => "flh",
+        0x2 => "flw",
+        0x3 => "fld",
+        0x4 => "flq",
+        _ => return None,
+    };
+
+    Some(format!(
+        "{}\t{},{}({})",
+        name,
+        f(bits(i, 11, 7)),
+        i_imm(i),
+        x(bits(i, 19, 15))
+    ))
+}
+
+fn disasm_store_fp(i: u32) -> Option<String> {
+    if matches!(bits(i, 14, 12), 0x0 | 0x5 | 0x6 | 0x7) {
+        return disasm_vmem(i, true);
+    }
+
+    let name: &str = match bits(i, 14, 12) {
+        0x1 => "fsh",
+        0x2 => "fsw",
+        0x3 => "fsd",
+        0x4 => "fsq",
+        _ => return None,
+    };
+
+    Some(format!(
+        "{}\t{},{}({})",
+        name,
+        f(bits(i, 24, 20)),
+        s_imm(i),
+        x(bits(i, 19, 15))
+    ))
+}
+
+fn v(r: u32) -> String {
+    format!("v{}", r)
+}
+
+// Mask operand of a masked vector instruction
+fn vmask(i: u32) -> &'static str {
+    if bits(i, 25, 25) == 0 {
+        ",v0.t"
+    } else {
+        ""
+    }
+}
+
+fn vtype_name(vtype: u32) -> Option<String> {
+    let lmul: &str = ["m1", "m2", "m4", "m8", "", "mf8", "mf4", "mf2"][bits(vtype, 2, 0) as usize];
+
+    if lmul.is_empty() || bits(vtype, 5, 5) != 0 || vtype >> 8 != 0 {
+        return None;
+    }
+
+    Some(format!(
+        "e{},{},{},{}",
+        8 << bits(vtype, 4, 3),
+        lmul,
+        ["tu", "ta"][bits(vtype, 6, 6) as usize],
+        ["mu", "ma"][bits(vtype, 7, 7) as usize]
+    ))
+}
+
+fn disasm_vmem(i: u32, store: bool) -> Option<String> {
+    let (vd, rs1, rs2) = (bits(i, 11, 7), bits(i, 19, 15), bits(i, 24, 20));
+    let eew: u32 = match bits(i, 14, 12) {
+        0x0 => 8,
+        0x5 => 16,
+        0x6 => 32,
+        _ => 64,
+    };
+    let nf: u32 = bits(i, 31, 29) + 1;
+    let seg: String = if nf > 1 {
+        format!("seg{}", nf)
+    } else {
+        String::new()
+    };
+    let dir: &str = if store { "s" } else { "l" };
+
+    if bits(i, 28, 28) != 0 {
+        return None;
+    }
+
+    Some(match (bits(i, 27, 26), rs2) {
+        (0x0, 0x00) => format!("v{}{}e{}.v\t{},({}){}", dir, seg, eew, v(vd), x(rs1), vmask(i)),
+        (0x0, 0x10) if !store => format!("vl{}e{}ff.v\t{},({}){}", seg, eew, v(vd), x(rs1), vmask(i)),
+        (0x0, 0x0b) if nf == 1 && eew == 8 => format!("v{}m.v\t{},({})", dir, v(vd), x(rs1)),
+        (0x0, 0x08) if store => format!("vs{}r.v\t{},({})", nf, v(vd), x(rs1)),
+        (0x0, 0x08) => format!("vl{}re{}.v\t{},({})", nf, eew, v(vd), x(rs1)),
+        (0x0, _) => return None,
+        (0x2, _) => format!(
+            "v{}s{}e{}.v\t{},({}),{}{}",
+            dir,
+            seg,
+            eew,
+            v(vd),
+            x(rs1),
+            x(rs2),
+            vmask(i)
+        ),
+        (mop, _) => format!(
+            "v{}{}x{}ei{}.v\t{},({}),{}{}",
+            dir,
+            ["", "u", "", "o"][mop as usize],
+            seg,
+            eew,
+            v(vd),
+            x(rs1),
+            v(rs2),
+            vmask(i)
+        ),
+    })
+}
+
+fn disasm_vsetvl(i: u32) -> Option<String> {
+    let (rd, rs1, rs2) = (bits(i, 11, 7), bits(i, 19, 15), bits(i, 24, 20));
+
+    Some(match bits(i, 31, 30) {
+        0x0 | 0x1 => format!("vsetvli\t{},{},{}", x(rd), x(rs1), vtype_name(bits(i, 30, 20))?),
+        0x3 => format!("vsetivli\t{},{},{}", x(rd), rs1, vtype_name(bits(i, 29, 20))?),
+        _ if bits(i, 31, 25) == 0x40 => format!("vsetvl\t{},{},{}", x(rd), x(rs1), x(rs2)),
+        _ => return None,
+    })
+}
+
+// Name of an integer OP-V instruction, and its operand suffix before the
+// .vv/.vx/.vi form
+fn vop_name(funct6: u32, mvv: bool, vm: bool) -> Option<(&'static str, &'static str)> {
+    let carry: &str = if vm { "" } else { "m" };
+
+    Some(match (mvv, funct6) {
+        (false, 0x00) => ("vadd", ""),
+        (false, 0x02) => ("vsub", ""),
+        (false, 0x03) => ("vrsub", ""),
+        (false, 0x04) => ("vminu", ""),
+        (false, 0x05) => ("vmin", ""),
+        (false, 0x06) => ("vmaxu", ""),
+        (false, 0x07) => ("vmax", ""),
+        (false, 0x09) => ("vand", ""),
+        (false, 0x0a) => ("vor", ""),
+        (false, 0x0b) => ("vxor", ""),
+        (false, 0x0c) => ("vrgather", ""),
+        (false, 0x0e) => ("vslideup", ""),
+        (false, 0x0f) => ("vslidedown", ""),
+        (false, 0x10) => ("vadc", "m"),
+        (false, 0x11) => ("vmadc", carry),
+        (false, 0x12) => ("vsbc", "m"),
+        (false, 0x13) => ("vmsbc", carry),
+        (false, 0x17) => ("vmerge", "m"),
+        (false, 0x18) => ("vmseq", ""),
+        (false, 0x19) => ("vmsne", ""),
+        (false, 0x1a) => ("vmsltu", ""),
+        (false, 0x1b) => ("vmslt", ""),
+        (false, 0x1c) => ("vmsleu", ""),
+        (false, 0x1d) => ("vmsle", ""),
+        (false, 0x1e) => ("vmsgtu", ""),
+        (false, 0x1f) => ("vmsgt", ""),
+        (false, 0x20) => ("vsaddu", ""),
+        (false, 0x21) => ("vsadd", ""),
+        (false, 0x22) => ("vssubu", ""),
+        (false, 0x23) => ("vssub", ""),
+        (false, 0x25) => ("vsll", ""),
+        (false, 0x27) => ("vsmul", ""),
+        (false, 0x28) => ("vsrl", ""),
+        (false, 0x29) => ("vsra", ""),
+        (false, 0x2a) => ("vssrl", ""),
+        (false, 0x2b) => ("vssra", ""),
+        (false, 0x2c) => ("vnsrl", "w"),
+        (false, 0x2d) => ("vnsra", "w"),
+        (false, 0x2e) => ("vnclipu", "w"),
+        (false, 0x2f) => ("vnclip", "w"),
+        (false, 0x30) => ("vwredsumu", "s"),
+        (false, 0x31) => ("vwredsum", "s"),
+        (true, 0x00) => ("vredsum", "s"),
+        (true, 0x01) => ("vredand", "s"),
+        (true, 0x02) => ("vredor", "s"),
+        (true, 0x03) => ("vredxor", "s"),
+        (true, 0x04) => ("vredminu", "s"),
+        (true, 0x05) => ("vredmin", "s"),
+        (true, 0x06) => ("vredmaxu", "s"),
+        (true, 0x07) => ("vredmax", "s"),
+        (true, 0x08) => ("vaaddu", ""),
+        (true, 0x09) => ("vaadd", ""),
+        (true, 0x0a) => ("vasubu", ""),
+        (true, 0x0b) => ("vasub", ""),
+        (true, 0x0e) => ("vslide1up", ""),
+        (true, 0x0f) => ("vslide1down", ""),
+        (true, 0x20) => ("vdivu", ""),
+        (true, 0x21) => ("vdiv", ""),
+        (true, 0x22) => ("vremu", ""),
+        (true, 0x23) => ("vrem", ""),
+        (true, 0x24) => ("vmulhu", ""),
+        (true, 0x25) => ("vmul", ""),
+        (true, 0x26) => ("vmulhsu", ""),
+        (true, 0x27) => ("vmulh", ""),
+        (true, 0x29) => ("vmadd", ""),
+        (true, 0x2b) => ("vnmsub", ""),
+        (true, 0x2d) => ("vmacc", ""),
+        (true, 0x2f) => ("vnmsac", ""),
+        (true, 0x30) => ("vwaddu", ""),
+        (true, 0x31) => ("vwadd", ""),
+        (true, 0x32) => ("vwsubu", ""),
+        (true, 0x33) => ("vwsub", ""),
+        (true, 0x34) => ("vwaddu", "w"),
+        (true, 0x35) => ("vwadd", "w"),
+        (true, 0x36) => ("vwsubu", "w"),
+        (true, 0x37) => ("vwsub", "w"),
+        (true, 0x38) => ("vwmulu", ""),
+        (true, 0x3a) => ("vwmulsu", ""),
+        (true, 0x3b) => ("vwmul", ""),
+        (true, 0x3c) => ("vwmaccu", ""),
+        (true, 0x3d) => ("vwmacc", ""),
+        (true, 0x3e) => ("vwmaccus", ""),
+        (true, 0x3f) => ("vwmaccsu", ""),
+        _ => return None,
+    })
+}
+
+fn disasm_op_v(i: u32) -> Option<String> {
+    let (vd, rs1, vs2) = (bits(i, 11, 7), bits(i, 19, 15), bits(i, 24, 20));
+    let funct6: u32 = bits(i, 31, 26);
+    let funct3: u32 = bits(i, 14, 12);
+    let vm: bool = bits(i, 25, 25) != 0;
+    let mvv: bool = matches!(funct3, 0x2 | 0x6);
+    let simm: i32 = (rs1 as i32) << 27 >> 27;
+    // Second source, shifts and slides taking an unsigned immediate
+    let (form, src): (&str, String) = match funct3 {
+        0x0 | 0x2 => ("v", v(rs1)),
+        0x3 if matches!(funct6, 0x0c | 0x0e | 0x0f | 0x25 | 0x28..=0x2f) => ("i", rs1.to_string()),
+        0x3 => ("i", simm.to_string()),
+        0x4 | 0x6 => ("x", String::from(x(rs1))),
+        0x7 => return disasm_vsetvl(i),
+        _ => return None,
+    };
+
+    // Unary and mask instructions
+    match (funct3, funct6) {
+        (0x0 | 0x3 | 0x4, 0x17) if vm && vs2 == 0 => {
+            return Some(format!("vmv.v.{}\t{},{}", form, v(vd), src))
+        }
+        (0x3, 0x27) if vm => return Some(format!("vmv{}r.v\t{},{}", rs1 + 1, v(vd), v(vs2))),
+        (0x0, 0x0e) => {
+            return Some(format!("vrgatherei16.vv\t{},{},{}{}", v(vd), v(vs2), v(rs1), vmask(i)))
+        }
+        (0x2, 0x10) => {
+            let name: &str = match rs1 {
+                0x00 => return Some(format!("vmv.x.s\t{},{}", x(vd), v(vs2))),
+                0x10 => "vcpop.m",
+                0x11 => "vfirst.m",
+                _ => return None,
+            };
+            return Some(format!("{}\t{},{}{}", name, x(vd), v(vs2), vmask(i)));
+        }
+        (0x6, 0x10) if vs2 == 0 => return Some(format!("vmv.s.x\t{},{}", v(vd), x(rs1))),
+        (0x2, 0x12) => {
+            let name: &str = match rs1 {
+                0x02 => "vzext.vf8",
+                0x03 => "vsext.vf8",
+                0x04 => "vzext.vf4",
+                0x05 => "vsext.vf4",
+                0x06 => "vzext.vf2",
+                0x07 => "vsext.vf2",
+                _ => return None,
+            };
+            return Some(format!("{}\t{},{}{}", name, v(vd), v(vs2), vmask(i)));
+        }
+        (0x2, 0x14) => {
+            return Some(match rs1 {
+                0x01 => format!("vmsbf.m\t{},{}{}", v(vd), v(vs2), vmask(i)),
+                0x02 => format!("vmsof.m\t{},{}{}", v(vd), v(vs2), vmask(i)),
+                0x03 => format!("vmsif.m\t{},{}{}", v(vd), v(vs2), vmask(i)),
+                0x10 => format!("viota.m\t{},{}{}", v(vd), v(vs2), vmask(i)),
+                0x11 => format!("vid.v\t{}{}", v(vd), vmask(i)),
+                _ => return None,
+            })
+        }
+        (0x2, 0x17) => return Some(format!("vcompress.vm\t{},{},{}", v(vd), v(vs2), v(rs1))),
+        (0x2, 0x18..=0x1f) => {
+            let name: &str =
+                ["vmandn", "vmand", "vmor", "vmxor", "vmorn", "vmnand", "vmnor", "vmxnor"]
+                    [funct6 as usize - 0x18];
+            return Some(format!("{}.mm\t{},{},{}", name, v(vd), v(vs2), v(rs1)));
+        }
+        _ => (),
+    }
+
+    let (name, kind) = vop_name(funct6, mvv, vm)?;
+    let mask: &str = if kind == "m" { ",v0" } else { vmask(i) };
+
+    Some(match kind {
+        "s" if form == "v" => format!("{}.vs\t{},{},{}{}", name, v(vd), v(vs2), src, vmask(i)),
+        "s" => return None,
+        "w" => format!("{}.w{}\t{},{},{}{}", name, form, v(vd), v(vs2), src, mask),
+        // Multiply-adds name the multiplier first
+        _ if mvv && (funct6 & 0x29 == 0x29 || funct6 >= 0x3c) => {
+            format!("{}.v{}\t{},{},{}{}", name, form, v(vd), src, v(vs2), mask)
+        }
+        _ => format!("{}.v{}{}\t{},{},{}{}", name, form, kind, v(vd), v(vs2), src, mask),
+    })
+}
+
+fn disasm_mem(i: u32) -> Option<String> {
+    let (pred, succ) = (bits(i, 27, 24), bits(i, 23, 20));
+
+    Some(match (bits(i, 14, 12), bits(i, 31, 28)) {
+        (0x0, 0x8) if pred == 0x3 && succ == 0x3 => String::from("fence.tso"),
+        (0x0, 0x0) if pred == 0xf && succ == 0xf => String::from("fence"),
+        (0x0, 0x0) => format!("fence\t{},{}", fence_set(pred), fence_set(succ)),
+        (0x1, _) => String::from("fence.i"),
+        (0x2, _) => format!(
+            "lq\t{},{}({})",
+            x(bits(i, 11, 7)),
+            i_imm(i),
+            x(bits(i, 19, 15))
+        ),
+        _ => return None,
+    })
+}
+
+fn disasm_amo(i: u32) -> Option<String> {
+    let (rd, rs1, rs2) = (bits(i, 11, 7), bits(i, 19, 15), bits(i, 24, 20));
+    let width: &str = match bits(i, 14, 12) {
+        0x2 => "w",
+        0x3 => "d",
+        0x4 => "q",
+        _ => return None,
+    };
+    let order: &str = ["", ".rl", ".aq", ".aqrl"][bits(i, 26, 25) as usize];
+    let name: &str = match bits(i, 31, 27) {
+        0x00 => "amoadd",
+        0x01 => "amoswap",
+        0x02 if rs2 == 0 => return Some(format!("lr.{}{}\t{},({})", width, order, x(rd), x(rs1))),
+        0x03 => "sc",
+        0x04 => "amoxor",
+        0x05 => "amocas",
+        0x08 => "amoor",
+        0x0c => "amoand",
+        0x10 => "amomin",
+        0x14 => "amomax",
+        0x18 => "amominu",
+        0x1c => "amomaxu",
+        _ => return None,
+    };
+
+    Some(format!(
+        "{}.{}{}\t{},{},({})",
+        name,
+        width,
+        order,
+        x(rd),
+        x(rs2),
+        x(rs1)
+    ))
+}
+
+fn disasm_fmadd(i: u32) -> Option<String> {
+    let name: &str = ["fmadd", "fmsub", "fnmsub", "fnmadd"][bits(i, 3, 2) as usize];
+
+    Some(format!(
+        "{}.{}\t{},{},{},{}{}",
+        name,
+        fmt_suffix(bits(i, 26, 25)),
+        f(bits(i, 11, 7)),
+        f(bits(i, 19, 15)),
+        f(bits(i, 24, 20)),
+        f(bits(i, 31, 27)),
+        rm(i)
+    ))
+}
+
+fn disasm_op_fp(i: u32, xlen: usize) -> Option<String> {
+    let (rd, rs1, rs2) = (bits(i, 11, 7), bits(i, 19, 15), bits(i, 24, 20));
+    let funct3: u32 = bits(i, 14, 12);
+    let fmt: &str = fmt_suffix(bits(i, 26, 25));
+    let int: &str = match rs2 {
+        0x0 => "w",
+        0x1 => "wu",
+        0x2 if xlen > 32 => "l",
+        0x3 if xlen > 32 => "lu",
+        _ => "",
+    };
+    let arith = |name: &str| format!("{}.{}\t{},{},{}{}", name, fmt, f(rd), f(rs1), f(rs2), rm(i));
+
+    Some(match bits(i, 31, 27) {
+        0x00 => arith("fadd"),
+        0x01 => arith("fsub"),
+        0x02 => arith("fmul"),
+        0x03 => arith("fdiv"),
+        0x0b if rs2 == 0 => format!("fsqrt.{}\t{},{}{}", fmt, f(rd), f(rs1), rm(i)),
+        0x04 if rs1 == rs2 && funct3 < 3 => {
+            let name: &str = ["fmv", "fneg", "fabs"][funct3 as usize];
+
+            format!("{}.{}\t{},{}", name, fmt, f(rd), f(rs1))
+        }
+        0x04 if funct3 < 3 => {
+            let name: &str = ["fsgnj", "fsgnjn", "fsgnjx"][funct3 as usize];
+
+            format!("{}.{}\t{},{},{}", name, fmt, f(rd), f(rs1), f(rs2))
+        }
+        0x05 if funct3 < 2 => {
+            let name: &str = ["fmin", "fmax"][funct3 as usize];
+
+            format!("{}.{}\t{},{},{}", name, fmt, f(rd), f(rs1), f(rs2))
+        }
+        0x08 if rs2 < 4 => format!(
+            "fcvt.{}.{}\t{},{}{}",
+            fmt,
+            fmt_suffix(rs2),
+            f(rd),
+            f(rs1),
+            rm(i)
+        ),
+        0x14 if funct3 < 3 => {
+            let name: &str = ["fle", "flt", "feq"][funct3 as usize];
+
+            format!("{}.{}\t{},{},{}", name, fmt, x(rd), f(rs1), f(rs2))
+        }
+        0x18 if !int.is_empty() => format!("fcvt.{}.{}\t{},{}{}", int, fmt, x(rd), f(rs1), rm(i)),
+        0x1a if !int.is_empty() => format!("fcvt.{}.{}\t{},{}{}", fmt, int, f(rd), x(rs1), rm(i)),
+        0x1c if rs2 == 0 && funct3 == 0 => {
+            let w: &str = if fmt == "s" { "w" } else { fmt };
+
+            format!("fmv.x.{}\t{},{}", w, x(rd), f(rs1))
+        }
+        0x1c if rs2 == 0 && funct3 == 1 => format!("fclass.{}\t{},{}", fmt, x(rd), f(rs1)),
+        0x1e if rs2 == 0 && funct3 == 0 => {
+            let w: &str = if fmt == "s" { "w" } else { fmt };
+
+            format!("fmv.{}.x\t{},{}", w, f(rd), x(rs1))
+        }
+        _ => return None,
+    })
+}
+
+fn disasm_csr(i: u32) -> Option<String> {
+    let (rd, rs1, funct3) = (bits(i, 11, 7), bits(i, 19, 15), bits(i, 14, 12));
+    let addr: u32 = bits(i, 31, 20);
+    let name: String = csr_name(addr);
+    // Register or 5-bit immediate source operand
+    let src: String = if funct3 & 0x4 != 0 {
+        rs1.to_string()
+    } else {
+        String::from(x(rs1))
+    };
+    let base: &str = ["", "csrrw", "csrrs", "csrrc"][funct3 as usize & 0x3];
+    let imm: &str = if funct3 & 0x4 != 0 { "i" } else { "" };
+
+    // Dedicated pseudo-instructions of the floating-point and counter CSRs
+    let alias: Option<&str> = match (addr as usize, funct3) {
+        (csr::FFLAGS, 0x1) => Some("fsflags"),
+        (csr::FFLAGS, 0x5) => Some("fsflagsi"),
+        (csr::FRM, 0x1) => Some("fsrm"),
+        (csr::FRM, 0x5) => Some("fsrmi"),
+        (csr::FCSR, 0x1) => Some("fscsr"),
+        _ => None,
+    };
+
+    Some(match (funct3 & 0x3, rd, rs1) {
+        (0x0, _, _) => return None,
+        (0x2, _, 0) if funct3 == 0x2 => match addr as usize {
+            csr::FFLAGS => format!("frflags\t{}", x(rd)),
+            csr::FRM => format!("frrm\t{}", x(rd)),
+            csr::FCSR => format!("frcsr\t{}", x(rd)),
+            csr::CYCLE => format!("rdcycle\t{}", x(rd)),
+            csr::TIME => format!("rdtime\t{}", x(rd)),
+            csr::INSTRET => format!("rdinstret\t{}", x(rd)),
+            csr::CYCLEH => format!("rdcycleh\t{}", x(rd)),
+            csr::TIMEH => format!("rdtimeh\t{}", x(rd)),
+            csr::INSTRETH => format!("rdinstreth\t{}", x(rd)),
+            _ => format!("csrr\t{},{}", x(rd), name),
+        },
+        (_, 0, _) if alias.is_some() => format!("{}\t{}", alias.unwrap(), src),
+        (_, _, _) if alias.is_some() => format!("{}\t{},{}", alias.unwrap(), x(rd), src),
+        (0x1, 0, _) => format!("csrw{}\t{},{}", imm, name, src),
+        (0x2, 0, _) => format!("csrs{}\t{},{}", imm, name, src),
+        (0x3, 0, _) => format!("csrc{}\t{},{}", imm, name, src),
+        _ => format!("{}{}\t{},{},{}", base, imm, x(rd), name, src),
+    })
+}
+
+// hlv, hlvx and hsv
+fn disasm_hlv(i: u32) -> Option<String> {
+    let (rd, rs1, rs2) = (bits(i, 11, 7), bits(i, 19, 15), bits(i, 24, 20));
+    let funct7: u32 = bits(i, 31, 25);
+    let size: &str = ["b", "h", "w", "d"][bits(i, 27, 26) as usize];
+
+    if funct7 >> 3 != 0x6 {
+        return None;
+    }
+
+    Some(match (funct7 & 0x1, rs2) {
+        (0x1, _) if rd == 0 => format!("hsv.{}	{},({})", size, x(rs2), x(rs1)),
+        (0x0, 0x0) => format!("hlv.{}	{},({})", size, x(rd), x(rs1)),
+        (0x0, 0x1) if size != "d" => format!("hlv.{}u	{},({})", size, x(rd), x(rs1)),
+        (0x0, 0x3) if size == "h" || size == "w" => {
+            format!("hlvx.{}u	{},({})", size, x(rd), x(rs1))
+        }
+        _ => return None,
+    })
+}
+
+fn disasm_system(i: u32) -> Option<String> {
+    let (rd, rs1, rs2) = (bits(i, 11, 7), bits(i, 19, 15), bits(i, 24, 20));
+
+    if bits(i, 14, 12) == 0x4 {
+        return disasm_hlv(i);
+    }
+
+    if bits(i, 14, 12) != 0 {
+        return disasm_csr(i);
+    }
+
+    if rd != 0 {
+        return None;
+    }
+
+    Some(match (bits(i, 31, 20), rs1) {
+        (0x000, 0) => String::from("ecall"),
+        (0x001, 0) => String::from("ebreak"),
+        (0x102, 0) => String::from("sret"),
+        (0x302, 0) => String::from("mret"),
+        (0x105, 0) => String::from("wfi"),
+        (f12, _) if f12 >> 5 == 0x09 => match (rs1, rs2) {
+            (0, 0) => String::from("sfence.vma"),
+            (_, 0) => format!("sfence.vma\t{}", x(rs1)),
+            _ => format!("sfence.vma\t{},{}", x(rs1), x(rs2)),
+        },
+        (f12, _) if f12 >> 5 == 0x11 || f12 >> 5 == 0x31 => {
+            let name: &str = if f12 >> 5 == 0x11 {
+                "hfence.vvma"
+            } else {
+                "hfence.gvma"
+            };
+
+            match (rs1, rs2) {
+                (0, 0) => String::from(name),
+                (_, 0) => format!("{}\t{}", name, x(rs1)),
+                _ => format!("{}\t{},{}", name, x(rs1), x(rs2)),
+            }
+        }
+        _ => return None,
+    })
+}
+
+fn disasm_32(i: u32, pc: u64, xlen: usize) -> Option<String> {
+    let rd: u32 = bits(i, 11, 7);
+
+    match bits(i, 6, 2) {
+        0x00 => disasm_load(i),
+        0x01 => disasm_load_fp(i),
+        0x03 => disasm_mem(i),
+        0x04 => disasm_opimm(i, xlen),
+        0x05 => Some(format!("auipc\t{},{:#x}", x(rd), bits(i, 31, 12))),
+        0x06 if xlen > 32 => disasm_opimm32(i),
+        0x08 => disasm_store(i),
+        0x09 => disasm_store_fp(i),
+        0x0b => disasm_amo(i),
+        0x0c => disasm_op(i, false),
+        0x0d => Some(format!("lui\t{},{:#x}", x(rd), bits(i, 31, 12))),
+        0x0e if xlen > 32 => disasm_op(i, true),
+        0x10..=0x13 => disasm_fmadd(i),
+        0x14 => disasm_op_fp(i, xlen),
+        0x15 => disasm_op_v(i),
+        0x16 if xlen == 128 => disasm_opimm64(i),
+        0x18 => disasm_branch(i, pc),
+        0x19 if bits(i, 14, 12) == 0 => disasm_jalr(i),
+        0x1b => disasm_jal(i, pc),
+        0x1c => disasm_system(i),
+        0x1e if xlen == 128 => disasm_op64(i),
+        _ => None,
+    }
+}
+
+// objdump-style text of the instruction at `pc`, with pseudo-instructions and
+// ABI register names. Compressed instructions are shown as their expansion
+pub fn disasm(instr: &Instr, pc: u64, xlen: usize) -> String {
+    let raw: u32 = match instr {
+        Instr::Invalid => return String::from("<invalid>"),
+        _ => instr.get_raw(),
+    };
+    let text: Option<String> = instr.expand(xlen).and_then(|i| disasm_32(i, pc, xlen));
+
+    match (text, instr) {
+        (Some(s), _) => s,
+        (None, Instr::Instr32(_)) => format!(".4byte\t{:#x}", raw),
+        (None, _) if raw == 0 => String::from("unimp"),
+        (None, _) => format!(".2byte\t{:#x}", raw),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::disasm;
+    use crate::vsoc::arch::riscv::instr::Instr;
+
+    fn dis(raw: u32, pc: u64, xlen: usize) -> String {
+        disasm(&Instr::new(raw), pc, xlen)
+    }
+
+    #[test]
+    fn test_base() {
+        assert_eq!(dis(0x0000_0013, 0, 32), "nop");
+        assert_eq!(dis(0x0010_0513, 0, 32), "li\ta0,1");
+        assert_eq!(dis(0x0005_8513, 0, 32), "mv\ta0,a1");
+        assert_eq!(dis(0xff01_0113, 0, 32), "addi\tsp,sp,-16");
+        assert_eq!(dis(0x0035_1513, 0, 64), "slli\ta0,a0,0x3");
+        assert_eq!(dis(0x4035_5513, 0, 64), "srai\ta0,a0,0x3");
+        assert_eq!(dis(0x0005_051b, 0, 64), "sext.w\ta0,a0");
+        assert_eq!(dis(0x40b0_0533, 0, 32), "neg\ta0,a1");
+        assert_eq!(dis(0x02c5_8533, 0, 32), "mul\ta0,a1,a2");
+        assert_eq!(dis(0x0001_22b7, 0, 32), "lui\tt0,0x12");
+        assert_eq!(dis(0x0000_0297, 0, 32), "auipc\tt0,0x0");
+        assert_eq!(dis(0x0081_2503, 0, 32), "lw\ta0,8(sp)");
+        assert_eq!(dis(0xfea1_3c23, 0, 64), "sd\ta0,-8(sp)");
+        assert_eq!(dis(0x0ff0_000f, 0, 32), "fence");
+        assert_eq!(dis(0x0000_100f, 0, 32), "fence.i");
+        assert_eq!(dis(0x0000_0073, 0, 32), "ecall");
+        assert_eq!(dis(0x3020_0073, 0, 32), "mret");
+        assert_eq!(dis(0x0000_000b, 0, 32), ".4byte\t0xb");
+    }
+
+    #[test]
+    fn test_rv128() {
+        assert_eq!(dis(0x0645_1513, 0, 128), "slli\ta0,a0,0x64");
+        assert_eq!(dis(0x0101_250f, 0, 128), "lq\ta0,16(sp)");
+        assert_eq!(dis(0x0015_055b, 0, 128), "addid\ta0,a0,1");
+        assert_eq!(dis(0x00c5_857b, 0, 128), "addd\ta0,a1,a2");
+        assert_eq!(dis(0x00c5_857b, 0, 64), ".4byte\t0xc5857b");
+    }
+
+    #[test]
+    fn test_bitmanip() {
+        assert_eq!(dis(0x20b6_46b3, 0, 64), "sh2add\ta3,a2,a1");
+        assert_eq!(dis(0x0805_873b, 0, 64), "zext.w\ta4,a1");
+        assert_eq!(dis(0x0815_9f1b, 0, 64), "slli.uw\tt5,a1,0x1");
+        assert_eq!(dis(0x40b6_77b3, 0, 64), "andn\ta5,a2,a1");
+        assert_eq!(dis(0x6006_1813, 0, 64), "clz\ta6,a2");
+        assert_eq!(dis(0x6b86_5993, 0, 64), "rev8\ts3,a2");
+        assert_eq!(dis(0x6986_5993, 0, 32), "rev8\ts3,a2");
+        assert_eq!(dis(0x2876_5a13, 0, 64), "orc.b\ts4,a2");
+        assert_eq!(dis(0x6046_5c1b, 0, 64), "roriw\ts8,a2,0x4");
+        assert_eq!(dis(0x0805_ce3b, 0, 64), "zext.h\tt3,a1");
+        assert_eq!(dis(0x0ac6_1cb3, 0, 64), "clmul\ts9,a2,a2");
+        assert_eq!(dis(0x4846_5d93, 0, 64), "bexti\ts11,a2,0x4");
+    }
+
+    #[test]
+    fn test_hypervisor() {
+        assert_eq!(dis(0x6805_c573, 0, 64), "hlv.w\ta0,(a1)");
+        assert_eq!(dis(0x6013_42f3, 0, 64), "hlv.bu\tt0,(t1)");
+        assert_eq!(dis(0x6436_c673, 0, 64), "hlvx.hu\ta2,(a3)");
+        assert_eq!(dis(0x6ee7_c073, 0, 64), "hsv.d\ta4,(a5)");
+        assert_eq!(dis(0x6200_0073, 0, 64), "hfence.gvma");
+        assert_eq!(dis(0x22b5_0073, 0, 64), "hfence.vvma\ta0,a1");
+    }
+
+    #[test]
+    fn test_vector() {
+        assert_eq!(dis(0xcd02_7357, 0, 64), "vsetivli\tt1,4,e32,m1,ta,ma");
+        assert_eq!(dis(0x0db0_7b57, 0, 64), "vsetvli\ts6,zero,e64,m8,ta,ma");
+        assert_eq!(dis(0x81e0_7bd7, 0, 64), "vsetvl\ts7,zero,t5");
+        assert_eq!(dis(0x0212_b157, 0, 64), "vadd.vi\tv2,v1,5");
+        assert_eq!(dis(0x9621_21d7, 0, 64), "vmul.vv\tv3,v2,v2");
+        assert_eq!(dis(0x868e_44d7, 0, 64), "vsadd.vx\tv9,v8,t3");
+        assert_eq!(dis(0x5c1f_b357, 0, 64), "vmerge.vim\tv6,v1,-1,v0");
+        assert_eq!(dis(0x0230_22d7, 0, 64), "vredsum.vs\tv5,v3,v0");
+        assert_eq!(dis(0x4250_25d7, 0, 64), "vmv.x.s\ta1,v5");
+        assert_eq!(dis(0x5208_a0d7, 0, 64), "vid.v\tv1");
+        assert_eq!(dis(0x0202_e1a7, 0, 64), "vse32.v\tv3,(t0)");
+        assert_eq!(dis(0x0a72_e507, 0, 64), "vlse32.v\tv10,(t0),t2");
+        assert_eq!(dis(0x06c2_e687, 0, 64), "vluxei32.v\tv13,(t0),v12");
+        assert_eq!(dis(0x0302_e087, 0, 64), "vle32ff.v\tv1,(t0)");
+        assert_eq!(dis(0x00c2_a503, 0, 64), "lw\ta0,12(t0)");
+    }
+
+    #[test]
+    fn test_control_flow() {
+        assert_eq!(dis(0x0000_006f, 0x8000_0000, 32), "j\t80000000");
+        assert_eq!(dis(0x0100_00ef, 0x8000_0000, 32), "jal\t80000010");
+        assert_eq!(dis(0x0000_8067, 0, 32), "ret");
+        assert_eq!(dis(0x0005_0067, 0, 32), "jr\ta0");
+        assert_eq!(dis(0x0005_00e7, 0, 32), "jalr\ta0");
+        assert_eq!(dis(0x0005_0463, 0x8000_0000, 32), "beqz\ta0,80000008");
+        assert_eq!(dis(0xfeb5_1ee3, 0x8000_0010, 32), "bne\ta0,a1,8000000c");
+    }
+
+    #[test]
+    fn test_csr_amo_fp() {
+        assert_eq!(dis(0xf140_2573, 0, 32), "csrr\ta0,mhartid");
+        assert_eq!(dis(0x3052_9073, 0, 32), "csrw\tmtvec,t0");
+        assert_eq!(dis(0x3004_6073, 0, 32), "csrsi\tmstatus,8");
+        assert_eq!(dis(0x0010_2573, 0, 32), "frflags\ta0");
+        assert_eq!(dis(0x1005_a52f, 0, 32), "lr.w\ta0,(a1)");
+        assert_eq!(dis(0x06c5_b52f, 0, 64), "amoadd.d.aqrl\ta0,a2,(a1)");
+        assert_eq!(dis(0x00c5_f553, 0, 32), "fadd.s\tfa0,fa1,fa2");
+        assert_eq!(dis(0x22b5_9553, 0, 32), "fneg.d\tfa0,fa1");
+        assert_eq!(dis(0xc005_1553, 0, 32), "fcvt.w.s\ta0,fa0,rtz");
+        assert_eq!(dis(0x0085_3507, 0, 32), "fld\tfa0,8(a0)");
+    }
+
+    #[test]
+    fn test_compressed() {
+        assert_eq!(dis(0x4505, 0, 64), "li\ta0,1");
+        assert_eq!(dis(0x8082, 0, 64), "ret");
+        assert_eq!(dis(0x0000, 0, 64), "unimp");
+    }
+}
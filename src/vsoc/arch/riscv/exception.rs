@@ -2,7 +2,7 @@ use std::fmt;
 
 use crate::vsoc::{bus::BusException, VsocException};
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum RvException {
     InstructionAddressMisaligned = 0x00,
     InstructionAccessFault = 0x01,
@@ -14,9 +14,15 @@ pub enum RvException {
     StoreAccessFault = 0x07,
     EnvironmentCallUMode = 0x08,
     EnvironmentCallSMode = 0x09,
+    EnvironmentCallVSMode = 0x0a,
+    EnvironmentCallMMode = 0x0b,
     InstructionPageFault = 0x0c,
     LoadPageFault = 0x0d,
     StorePageFault = 0x0f,
+    InstructionGuestPageFault = 0x14,
+    LoadGuestPageFault = 0x15,
+    VirtualInstruction = 0x16,
+    StoreGuestPageFault = 0x17,
 }
 
 impl fmt::Display for RvException {
@@ -38,9 +44,15 @@ impl fmt::Display for RvException {
             Self::StoreAccessFault => s = String::from("StoreAccessFault"),
             Self::EnvironmentCallUMode => s = String::from("EnvironmentCallUMode"),
             Self::EnvironmentCallSMode => s = String::from("EnvironmentCallSMode"),
+            Self::EnvironmentCallVSMode => s = String::from("EnvironmentCallVSMode"),
+            Self::EnvironmentCallMMode => s = String::from("EnvironmentCallMMode"),
             Self::InstructionPageFault => s = String::from("InstructionPageFault"),
             Self::LoadPageFault => s = String::from("LoadPageFault"),
             Self::StorePageFault => s = String::from("StorePageFault"),
+            Self::InstructionGuestPageFault => s = String::from("InstructionGuestPageFault"),
+            Self::LoadGuestPageFault => s = String::from("LoadGuestPageFault"),
+            Self::VirtualInstruction => s = String::from("VirtualInstruction"),
+            Self::StoreGuestPageFault => s = String::from("StoreGuestPageFault"),
         }
         write!(f, "RvException::{}", s)
     }
@@ -61,9 +73,15 @@ impl From<RvException> for VsocException {
             RvException::StoreAccessFault => VsocException::StoreAccessFault,
             RvException::EnvironmentCallUMode => VsocException::EnvironmentCallUMode,
             RvException::EnvironmentCallSMode => VsocException::EnvironmentCallSMode,
+            RvException::EnvironmentCallVSMode => VsocException::EnvironmentCallVSMode,
+            RvException::EnvironmentCallMMode => VsocException::EnvironmentCallMMode,
             RvException::InstructionPageFault => VsocException::InstructionPageFault,
             RvException::LoadPageFault => VsocException::LoadPageFault,
             RvException::StorePageFault => VsocException::StorePageFault,
+            RvException::InstructionGuestPageFault => VsocException::InstructionGuestPageFault,
+            RvException::LoadGuestPageFault => VsocException::LoadGuestPageFault,
+            RvException::VirtualInstruction => VsocException::VirtualInstruction,
+            RvException::StoreGuestPageFault => VsocException::StoreGuestPageFault,
         }
     }
 }
@@ -1,6 +1,7 @@
 use std::fmt;
 
 pub const EXT_A: u32 = 1 << 0;
+pub const EXT_B: u32 = 1 << 1;
 pub const EXT_C: u32 = 1 << 2;
 pub const EXT_D: u32 = 1 << 3;
 pub const EXT_E: u32 = 1 << 4;
@@ -26,6 +27,7 @@ pub struct RvExtensions {
     pub h: bool,
     pub u: bool,
     pub c: bool,
+    pub v: bool,
     pub zalrsc: bool, // a
     pub zamo: bool, // a
     pub zacas: bool, // a
@@ -34,6 +36,10 @@ pub struct RvExtensions {
     pub zmmul: bool,
     pub zicntr: bool,
     pub zihpm: bool,
+    pub zba: bool, // b
+    pub zbb: bool, // b
+    pub zbc: bool,
+    pub zbs: bool, // b
 }
 
 impl fmt::Display for RvExtensions {
@@ -80,6 +86,10 @@ impl fmt::Display for RvExtensions {
             _ = writeln!(f, "     (u\t{})", self.u);
         }
 
+        if self.v {
+            _ = writeln!(f, "     (v\t{})", self.v);
+        }
+
         if self.zalrsc {
             _ = writeln!(f, "     (zalrsc\t{})", self.zalrsc);
         }
@@ -112,6 +122,22 @@ impl fmt::Display for RvExtensions {
             _ = writeln!(f, "     (zihpm\t{})", self.zihpm);
         }
 
+        if self.zba {
+            _ = writeln!(f, "     (zba\t{})", self.zba);
+        }
+
+        if self.zbb {
+            _ = writeln!(f, "     (zbb\t{})", self.zbb);
+        }
+
+        if self.zbc {
+            _ = writeln!(f, "     (zbc\t{})", self.zbc);
+        }
+
+        if self.zbs {
+            _ = writeln!(f, "     (zbs\t{})", self.zbs);
+        }
+
         write!(f, "    )")
     }
 }
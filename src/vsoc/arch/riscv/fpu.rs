@@ -0,0 +1,918 @@
+use std::cmp::Ordering;
+
+// Accrued exception flags (fflags)
+pub const FLAG_NX: u8 = 1 << 0; // inexact
+pub const FLAG_UF: u8 = 1 << 1; // underflow
+pub const FLAG_OF: u8 = 1 << 2; // overflow
+pub const FLAG_DZ: u8 = 1 << 3; // divide by zero
+pub const FLAG_NV: u8 = 1 << 4; // invalid operation
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RoundingMode {
+    NearestEven = 0x0,
+    TowardZero = 0x1,
+    Down = 0x2,
+    Up = 0x3,
+    NearestMaxMagnitude = 0x4,
+}
+
+impl RoundingMode {
+    pub fn from_rm(rm: usize) -> Option<RoundingMode> {
+        match rm {
+            0x0 => Some(RoundingMode::NearestEven),
+            0x1 => Some(RoundingMode::TowardZero),
+            0x2 => Some(RoundingMode::Down),
+            0x3 => Some(RoundingMode::Up),
+            0x4 => Some(RoundingMode::NearestMaxMagnitude),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FpFormat {
+    Single,
+    Double,
+}
+
+impl FpFormat {
+    pub fn from_fmt(fmt: usize) -> Option<FpFormat> {
+        match fmt {
+            0x0 => Some(FpFormat::Single),
+            0x1 => Some(FpFormat::Double),
+            _ => None,
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        match self {
+            FpFormat::Single => 32,
+            FpFormat::Double => 64,
+        }
+    }
+
+    pub fn canonical_nan(&self) -> u64 {
+        match self {
+            FpFormat::Single => 0x7fc0_0000,
+            FpFormat::Double => 0x7ff8_0000_0000_0000,
+        }
+    }
+
+    fn exp_bits(&self) -> u32 {
+        match self {
+            FpFormat::Single => 8,
+            FpFormat::Double => 11,
+        }
+    }
+
+    fn man_bits(&self) -> u32 {
+        match self {
+            FpFormat::Single => 23,
+            FpFormat::Double => 52,
+        }
+    }
+
+    fn bias(&self) -> i32 {
+        (1 << (self.exp_bits() - 1)) - 1
+    }
+
+    fn sign_bit(&self, sign: bool) -> u64 {
+        (sign as u64) << (self.width() - 1)
+    }
+
+    fn infinity(&self, sign: bool) -> u64 {
+        self.sign_bit(sign) | (((1u64 << self.exp_bits()) - 1) << self.man_bits())
+    }
+
+    fn max_finite(&self, sign: bool) -> u64 {
+        self.infinity(sign) - 1
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Class {
+    Zero,
+    Subnormal,
+    Normal,
+    Infinite,
+    QuietNan,
+    SignalingNan,
+}
+
+// value = (-1)^sign * sig * 2^exp for the finite classes
+#[derive(Debug, Clone, Copy)]
+struct Unpacked {
+    class: Class,
+    sign: bool,
+    exp: i32,
+    sig: u128,
+}
+
+impl Unpacked {
+    fn is_nan(&self) -> bool {
+        self.class == Class::QuietNan || self.class == Class::SignalingNan
+    }
+
+    fn is_finite(&self) -> bool {
+        matches!(self.class, Class::Zero | Class::Subnormal | Class::Normal)
+    }
+}
+
+fn unpack(fmt: FpFormat, bits: u64) -> Unpacked {
+    let man_bits: u32 = fmt.man_bits();
+    let exp_mask: u64 = (1u64 << fmt.exp_bits()) - 1;
+    let sign: bool = (bits >> (fmt.width() - 1)) & 1 == 1;
+    let biased: u64 = (bits >> man_bits) & exp_mask;
+    let frac: u64 = bits & ((1u64 << man_bits) - 1);
+    let emin: i32 = 1 - fmt.bias() - man_bits as i32;
+
+    let (class, exp, sig) = if biased == exp_mask {
+        if frac == 0 {
+            (Class::Infinite, 0, 0)
+        } else if (frac >> (man_bits - 1)) & 1 == 1 {
+            (Class::QuietNan, 0, 0)
+        } else {
+            (Class::SignalingNan, 0, 0)
+        }
+    } else if biased == 0 {
+        if frac == 0 {
+            (Class::Zero, emin, 0)
+        } else {
+            (Class::Subnormal, emin, frac as u128)
+        }
+    } else {
+        (
+            Class::Normal,
+            emin + biased as i32 - 1,
+            (frac | (1u64 << man_bits)) as u128,
+        )
+    };
+
+    Unpacked {
+        class,
+        sign,
+        exp,
+        sig,
+    }
+}
+
+fn shift_right_jam(sig: u128, shift: i32) -> u128 {
+    if shift <= 0 {
+        sig
+    } else if shift >= 128 {
+        (sig != 0) as u128
+    } else {
+        (sig >> shift) | ((sig & ((1u128 << shift) - 1)) != 0) as u128
+    }
+}
+
+// Drop the `shift` least significant bits of `sig` and round the remaining
+// integer according to `rm`. Returns the rounded integer and whether bits
+// were lost.
+fn round_bits(rm: RoundingMode, sign: bool, sig: u128, shift: i32) -> (u128, bool) {
+    if shift <= 0 {
+        return (sig, false);
+    }
+
+    let (q, half): (u128, Ordering) = if shift > 128 {
+        (0, Ordering::Less)
+    } else if shift == 128 {
+        (0, sig.cmp(&(1u128 << 127)))
+    } else {
+        let rem: u128 = sig & ((1u128 << shift) - 1);
+
+        (sig >> shift, rem.cmp(&(1u128 << (shift - 1))))
+    };
+    let inexact: bool = if shift >= 128 {
+        sig != 0
+    } else {
+        sig & ((1u128 << shift) - 1) != 0
+    };
+
+    let increment: bool = match rm {
+        RoundingMode::NearestEven => {
+            half == Ordering::Greater || (half == Ordering::Equal && q & 1 == 1)
+        }
+        RoundingMode::NearestMaxMagnitude => half != Ordering::Less,
+        RoundingMode::TowardZero => false,
+        RoundingMode::Down => inexact && sign,
+        RoundingMode::Up => inexact && !sign,
+    };
+
+    (q + increment as u128, inexact)
+}
+
+// Round the exact value (-1)^sign * sig * 2^exp to the destination format.
+// The least significant bit of `sig` may be a sticky bit.
+fn round_pack(fmt: FpFormat, rm: RoundingMode, sign: bool, exp: i32, sig: u128) -> (u64, u8) {
+    if sig == 0 {
+        return (fmt.sign_bit(sign), 0);
+    }
+
+    let lz: u32 = sig.leading_zeros();
+    let sig: u128 = sig << lz;
+    let p: i32 = fmt.man_bits() as i32 + 1;
+    let emin: i32 = 1 - fmt.bias();
+    let emax: i32 = fmt.bias();
+    // Exponent of the most significant bit
+    let mut e: i32 = exp + 127 - lz as i32;
+    let mut flags: u8 = 0;
+
+    // RISC-V detects tininess after rounding
+    let carry: bool = round_bits(rm, sign, sig, 128 - p).0 >> p != 0;
+    let tiny: bool = e < emin - 1 || (e == emin - 1 && !carry);
+
+    let kept: i32 = if e < emin { p - (emin - e) } else { p };
+    let (mut q, inexact) = round_bits(rm, sign, sig, 128 - kept);
+
+    if inexact {
+        flags |= FLAG_NX;
+        if tiny {
+            flags |= FLAG_UF;
+        }
+    }
+
+    if e < emin {
+        // A carry out of the subnormal range encodes the smallest normal
+        return (fmt.sign_bit(sign) | q as u64, flags);
+    }
+
+    if q >> p != 0 {
+        q >>= 1;
+        e += 1;
+    }
+
+    if e > emax {
+        let to_infinity: bool = match rm {
+            RoundingMode::NearestEven | RoundingMode::NearestMaxMagnitude => true,
+            RoundingMode::TowardZero => false,
+            RoundingMode::Down => sign,
+            RoundingMode::Up => !sign,
+        };
+
+        flags |= FLAG_OF | FLAG_NX;
+        if to_infinity {
+            return (fmt.infinity(sign), flags);
+        } else {
+            return (fmt.max_finite(sign), flags);
+        }
+    }
+
+    (
+        fmt.sign_bit(sign)
+            | (((e + fmt.bias()) as u64) << fmt.man_bits())
+            | (q as u64 & ((1u64 << fmt.man_bits()) - 1)),
+        flags,
+    )
+}
+
+fn nan_flags(operands: &[Unpacked]) -> u8 {
+    if operands.iter().any(|u| u.class == Class::SignalingNan) {
+        FLAG_NV
+    } else {
+        0
+    }
+}
+
+fn propagate_nan(fmt: FpFormat, operands: &[Unpacked]) -> Option<(u64, u8)> {
+    if operands.iter().any(|u| u.is_nan()) {
+        return Some((fmt.canonical_nan(), nan_flags(operands)));
+    }
+
+    None
+}
+
+// Exact sum of two finite operands, the result carries a sticky bit
+fn add_unpacked(a: Unpacked, b: Unpacked) -> (bool, i32, u128) {
+    if a.sig == 0 {
+        return (b.sign, b.exp, b.sig);
+    }
+
+    if b.sig == 0 {
+        return (a.sign, a.exp, a.sig);
+    }
+
+    // Leave two bits of headroom for the carry
+    let na: u32 = a.sig.leading_zeros() - 2;
+    let nb: u32 = b.sig.leading_zeros() - 2;
+    let (ea, sa) = (a.exp - na as i32, a.sig << na);
+    let (eb, sb) = (b.exp - nb as i32, b.sig << nb);
+    let (big, small) = if ea >= eb {
+        ((a.sign, ea, sa), (b.sign, eb, sb))
+    } else {
+        ((b.sign, eb, sb), (a.sign, ea, sa))
+    };
+    let small_sig: u128 = shift_right_jam(small.2, big.1 - small.1);
+
+    if big.0 == small.0 {
+        (big.0, big.1, big.2 + small_sig)
+    } else if big.2 >= small_sig {
+        (big.0, big.1, big.2 - small_sig)
+    } else {
+        (small.0, big.1, small_sig - big.2)
+    }
+}
+
+pub fn add(fmt: FpFormat, rm: RoundingMode, a: u64, b: u64) -> (u64, u8) {
+    let ua: Unpacked = unpack(fmt, a);
+    let ub: Unpacked = unpack(fmt, b);
+
+    if let Some(r) = propagate_nan(fmt, &[ua, ub]) {
+        return r;
+    }
+
+    match (ua.class, ub.class) {
+        (Class::Infinite, Class::Infinite) => {
+            if ua.sign != ub.sign {
+                (fmt.canonical_nan(), FLAG_NV)
+            } else {
+                (a, 0)
+            }
+        }
+        (Class::Infinite, _) => (a, 0),
+        (_, Class::Infinite) => (b, 0),
+        _ => {
+            let (sign, exp, sig) = add_unpacked(ua, ub);
+
+            if sig == 0 {
+                let sign: bool = if ua.sign == ub.sign {
+                    ua.sign
+                } else {
+                    rm == RoundingMode::Down
+                };
+
+                return (fmt.sign_bit(sign), 0);
+            }
+
+            round_pack(fmt, rm, sign, exp, sig)
+        }
+    }
+}
+
+pub fn sub(fmt: FpFormat, rm: RoundingMode, a: u64, b: u64) -> (u64, u8) {
+    add(fmt, rm, a, b ^ fmt.sign_bit(true))
+}
+
+pub fn mul(fmt: FpFormat, rm: RoundingMode, a: u64, b: u64) -> (u64, u8) {
+    let ua: Unpacked = unpack(fmt, a);
+    let ub: Unpacked = unpack(fmt, b);
+    let sign: bool = ua.sign ^ ub.sign;
+
+    if let Some(r) = propagate_nan(fmt, &[ua, ub]) {
+        return r;
+    }
+
+    match (ua.class, ub.class) {
+        (Class::Infinite, Class::Zero) | (Class::Zero, Class::Infinite) => {
+            (fmt.canonical_nan(), FLAG_NV)
+        }
+        (Class::Infinite, _) | (_, Class::Infinite) => (fmt.infinity(sign), 0),
+        _ => round_pack(fmt, rm, sign, ua.exp + ub.exp, ua.sig * ub.sig),
+    }
+}
+
+pub fn div(fmt: FpFormat, rm: RoundingMode, a: u64, b: u64) -> (u64, u8) {
+    let ua: Unpacked = unpack(fmt, a);
+    let ub: Unpacked = unpack(fmt, b);
+    let sign: bool = ua.sign ^ ub.sign;
+
+    if let Some(r) = propagate_nan(fmt, &[ua, ub]) {
+        return r;
+    }
+
+    match (ua.class, ub.class) {
+        (Class::Infinite, Class::Infinite) | (Class::Zero, Class::Zero) => {
+            (fmt.canonical_nan(), FLAG_NV)
+        }
+        (Class::Infinite, _) => (fmt.infinity(sign), 0),
+        (_, Class::Infinite) | (Class::Zero, _) => (fmt.sign_bit(sign), 0),
+        (_, Class::Zero) => (fmt.infinity(sign), FLAG_DZ),
+        _ => {
+            let shift: u32 = ua.sig.leading_zeros();
+            let num: u128 = ua.sig << shift;
+            let q: u128 = num / ub.sig;
+            let r: u128 = num % ub.sig;
+
+            round_pack(
+                fmt,
+                rm,
+                sign,
+                ua.exp - shift as i32 - ub.exp,
+                q | (r != 0) as u128,
+            )
+        }
+    }
+}
+
+fn isqrt(n: u128) -> u128 {
+    let mut x: u128 = n;
+    let mut r: u128 = 0;
+    let mut bit: u128 = 1u128 << 126;
+
+    while bit > n {
+        bit >>= 2;
+    }
+
+    while bit != 0 {
+        if x >= r + bit {
+            x -= r + bit;
+            r = (r >> 1) + bit;
+        } else {
+            r >>= 1;
+        }
+        bit >>= 2;
+    }
+
+    r
+}
+
+pub fn sqrt(fmt: FpFormat, rm: RoundingMode, a: u64) -> (u64, u8) {
+    let ua: Unpacked = unpack(fmt, a);
+
+    if let Some(r) = propagate_nan(fmt, &[ua]) {
+        return r;
+    }
+
+    match ua.class {
+        Class::Zero => (a, 0),
+        _ if ua.sign => (fmt.canonical_nan(), FLAG_NV),
+        Class::Infinite => (a, 0),
+        _ => {
+            let mut shift: i32 = ua.sig.leading_zeros() as i32;
+
+            if (ua.exp - shift) % 2 != 0 {
+                shift -= 1;
+            }
+
+            let n: u128 = ua.sig << shift;
+            let s: u128 = isqrt(n);
+
+            round_pack(
+                fmt,
+                rm,
+                false,
+                (ua.exp - shift) / 2,
+                s | (s * s != n) as u128,
+            )
+        }
+    }
+}
+
+// (-1)^negate_product * (a * b) + (-1)^negate_addend * c with a single rounding
+pub fn fma(
+    fmt: FpFormat,
+    rm: RoundingMode,
+    operands: (u64, u64, u64),
+    negate_product: bool,
+    negate_addend: bool,
+) -> (u64, u8) {
+    let ua: Unpacked = unpack(fmt, operands.0);
+    let ub: Unpacked = unpack(fmt, operands.1);
+    let mut uc: Unpacked = unpack(fmt, operands.2);
+    let sign: bool = ua.sign ^ ub.sign ^ negate_product;
+
+    // Invalid even when the addend is a quiet NaN
+    if matches!(
+        (ua.class, ub.class),
+        (Class::Infinite, Class::Zero) | (Class::Zero, Class::Infinite)
+    ) {
+        return (fmt.canonical_nan(), FLAG_NV);
+    }
+
+    if let Some(r) = propagate_nan(fmt, &[ua, ub, uc]) {
+        return r;
+    }
+
+    uc.sign ^= negate_addend;
+
+    if ua.class == Class::Infinite || ub.class == Class::Infinite {
+        if uc.class == Class::Infinite && uc.sign != sign {
+            return (fmt.canonical_nan(), FLAG_NV);
+        }
+
+        return (fmt.infinity(sign), 0);
+    }
+
+    if uc.class == Class::Infinite {
+        return (fmt.infinity(uc.sign), 0);
+    }
+
+    let product: Unpacked = Unpacked {
+        class: Class::Normal,
+        sign,
+        exp: ua.exp + ub.exp,
+        sig: ua.sig * ub.sig,
+    };
+    let (rsign, exp, sig) = add_unpacked(product, uc);
+
+    if sig == 0 {
+        let rsign: bool = if product.sign == uc.sign {
+            uc.sign
+        } else {
+            rm == RoundingMode::Down
+        };
+
+        return (fmt.sign_bit(rsign), 0);
+    }
+
+    round_pack(fmt, rm, rsign, exp, sig)
+}
+
+fn ordered_key(fmt: FpFormat, bits: u64) -> i128 {
+    let magnitude: i128 = (bits & (fmt.sign_bit(true) - 1)) as i128;
+
+    if bits & fmt.sign_bit(true) != 0 {
+        -magnitude
+    } else {
+        magnitude
+    }
+}
+
+pub fn eq(fmt: FpFormat, a: u64, b: u64) -> (bool, u8) {
+    let ua: Unpacked = unpack(fmt, a);
+    let ub: Unpacked = unpack(fmt, b);
+
+    if ua.is_nan() || ub.is_nan() {
+        return (false, nan_flags(&[ua, ub]));
+    }
+
+    (ordered_key(fmt, a) == ordered_key(fmt, b), 0)
+}
+
+pub fn lt(fmt: FpFormat, a: u64, b: u64) -> (bool, u8) {
+    let ua: Unpacked = unpack(fmt, a);
+    let ub: Unpacked = unpack(fmt, b);
+
+    if ua.is_nan() || ub.is_nan() {
+        return (false, FLAG_NV);
+    }
+
+    (ordered_key(fmt, a) < ordered_key(fmt, b), 0)
+}
+
+pub fn le(fmt: FpFormat, a: u64, b: u64) -> (bool, u8) {
+    let ua: Unpacked = unpack(fmt, a);
+    let ub: Unpacked = unpack(fmt, b);
+
+    if ua.is_nan() || ub.is_nan() {
+        return (false, FLAG_NV);
+    }
+
+    (ordered_key(fmt, a) <= ordered_key(fmt, b), 0)
+}
+
+fn min_max(fmt: FpFormat, a: u64, b: u64, max: bool) -> (u64, u8) {
+    let ua: Unpacked = unpack(fmt, a);
+    let ub: Unpacked = unpack(fmt, b);
+    let flags: u8 = nan_flags(&[ua, ub]);
+
+    match (ua.is_nan(), ub.is_nan()) {
+        (true, true) => (fmt.canonical_nan(), flags),
+        (true, false) => (b, flags),
+        (false, true) => (a, flags),
+        _ => {
+            // -0.0 is considered less than +0.0
+            let ka: i128 = ordered_key(fmt, a) * 2 - ua.sign as i128;
+            let kb: i128 = ordered_key(fmt, b) * 2 - ub.sign as i128;
+
+            if (ka < kb) ^ max {
+                (a, flags)
+            } else {
+                (b, flags)
+            }
+        }
+    }
+}
+
+pub fn min(fmt: FpFormat, a: u64, b: u64) -> (u64, u8) {
+    min_max(fmt, a, b, false)
+}
+
+pub fn max(fmt: FpFormat, a: u64, b: u64) -> (u64, u8) {
+    min_max(fmt, a, b, true)
+}
+
+pub fn class(fmt: FpFormat, a: u64) -> u64 {
+    let ua: Unpacked = unpack(fmt, a);
+
+    match (ua.class, ua.sign) {
+        (Class::Infinite, true) => 1 << 0,
+        (Class::Normal, true) => 1 << 1,
+        (Class::Subnormal, true) => 1 << 2,
+        (Class::Zero, true) => 1 << 3,
+        (Class::Zero, false) => 1 << 4,
+        (Class::Subnormal, false) => 1 << 5,
+        (Class::Normal, false) => 1 << 6,
+        (Class::Infinite, false) => 1 << 7,
+        (Class::SignalingNan, _) => 1 << 8,
+        (Class::QuietNan, _) => 1 << 9,
+    }
+}
+
+// Convert to a `width`-bit integer, the result is returned as raw bits
+pub fn to_int(fmt: FpFormat, rm: RoundingMode, a: u64, signed: bool, width: u32) -> (u64, u8) {
+    let ua: Unpacked = unpack(fmt, a);
+    let mask: u128 = (1u128 << width) - 1;
+    let (max, min): (u128, u128) = if signed {
+        ((1u128 << (width - 1)) - 1, 1u128 << (width - 1))
+    } else {
+        (mask, 0)
+    };
+
+    if ua.is_nan() {
+        return (max as u64, FLAG_NV);
+    }
+
+    if !ua.is_finite() {
+        return (if ua.sign { min } else { max } as u64, FLAG_NV);
+    }
+
+    let (magnitude, inexact) = if ua.exp >= 0 {
+        if ua.sig != 0 && ua.exp + (128 - ua.sig.leading_zeros() as i32) > width as i32 {
+            return (if ua.sign { min } else { max } as u64, FLAG_NV);
+        }
+
+        (ua.sig << ua.exp, false)
+    } else {
+        round_bits(rm, ua.sign, ua.sig, -ua.exp)
+    };
+
+    let overflow: bool = if signed {
+        if ua.sign {
+            magnitude > 1u128 << (width - 1)
+        } else {
+            magnitude > max
+        }
+    } else {
+        (ua.sign && magnitude != 0) || magnitude > max
+    };
+
+    if overflow {
+        return (if ua.sign { min } else { max } as u64, FLAG_NV);
+    }
+
+    let value: u128 = if ua.sign {
+        magnitude.wrapping_neg() & mask
+    } else {
+        magnitude
+    };
+
+    (value as u64, if inexact { FLAG_NX } else { 0 })
+}
+
+// Convert the `width`-bit integer held in the low bits of `value`
+pub fn from_int(
+    fmt: FpFormat,
+    rm: RoundingMode,
+    value: u64,
+    signed: bool,
+    width: u32,
+) -> (u64, u8) {
+    let value: u128 = value as u128 & ((1u128 << width) - 1);
+    let negative: bool = signed && (value >> (width - 1)) & 1 == 1;
+    let magnitude: u128 = if negative {
+        (1u128 << width) - value
+    } else {
+        value
+    };
+
+    round_pack(fmt, rm, negative, 0, magnitude)
+}
+
+pub fn convert(from: FpFormat, to: FpFormat, rm: RoundingMode, a: u64) -> (u64, u8) {
+    let ua: Unpacked = unpack(from, a);
+
+    if let Some(r) = propagate_nan(to, &[ua]) {
+        return r;
+    }
+
+    match ua.class {
+        Class::Infinite => (to.infinity(ua.sign), 0),
+        _ => round_pack(to, rm, ua.sign, ua.exp, ua.sig),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RNE: RoundingMode = RoundingMode::NearestEven;
+
+    fn s(v: f32) -> u64 {
+        v.to_bits() as u64
+    }
+
+    fn d(v: f64) -> u64 {
+        v.to_bits()
+    }
+
+    #[test]
+    fn test_add_matches_host() {
+        let values: [f64; 8] = [0.0, -0.0, 1.0, -1.5, 3.25e10, 1e-310, 7.0e300, -2.2e-308];
+
+        for a in values {
+            for b in values {
+                let (r, _) = add(FpFormat::Double, RNE, d(a), d(b));
+                assert_eq!(r, d(a + b), "{} + {}", a, b);
+                let (r, _) = mul(FpFormat::Double, RNE, d(a), d(b));
+                assert_eq!(r, d(a * b), "{} * {}", a, b);
+                if b != 0.0 {
+                    let (r, _) = div(FpFormat::Double, RNE, d(a), d(b));
+                    assert_eq!(r, d(a / b), "{} / {}", a, b);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_rounding_modes() {
+        let one: u64 = s(1.0);
+        let eps: u64 = s(f32::EPSILON / 4.0);
+
+        assert_eq!(add(FpFormat::Single, RNE, one, eps), (one, FLAG_NX));
+        assert_eq!(
+            add(FpFormat::Single, RoundingMode::Up, one, eps),
+            (one + 1, FLAG_NX)
+        );
+        assert_eq!(
+            sub(FpFormat::Single, RoundingMode::Down, one, eps),
+            (s(1.0) - 1, FLAG_NX)
+        );
+        assert_eq!(
+            sub(FpFormat::Single, RoundingMode::TowardZero, one, eps),
+            (s(1.0) - 1, FLAG_NX)
+        );
+    }
+
+    #[test]
+    fn test_special_values() {
+        assert_eq!(
+            add(
+                FpFormat::Single,
+                RNE,
+                s(f32::INFINITY),
+                s(f32::NEG_INFINITY)
+            ),
+            (0x7fc0_0000, FLAG_NV)
+        );
+        assert_eq!(
+            div(FpFormat::Single, RNE, s(1.0), s(0.0)),
+            (s(f32::INFINITY), FLAG_DZ)
+        );
+        assert_eq!(sqrt(FpFormat::Single, RNE, s(-1.0)), (0x7fc0_0000, FLAG_NV));
+        assert_eq!(sqrt(FpFormat::Double, RNE, d(2.0)).0, d(2f64.sqrt()));
+        assert_eq!(
+            mul(FpFormat::Single, RNE, s(f32::MAX), s(2.0)),
+            (s(f32::INFINITY), FLAG_OF | FLAG_NX)
+        );
+        assert_eq!(
+            mul(
+                FpFormat::Single,
+                RoundingMode::TowardZero,
+                s(f32::MAX),
+                s(2.0)
+            ),
+            (s(f32::MAX), FLAG_OF | FLAG_NX)
+        );
+        assert_eq!(
+            sub(FpFormat::Double, RoundingMode::Down, d(1.0), d(1.0)),
+            (d(-0.0), 0)
+        );
+    }
+
+    #[test]
+    fn test_underflow() {
+        let (r, flags) = mul(FpFormat::Single, RNE, s(f32::MIN_POSITIVE), s(0.75));
+        assert_eq!(r, s(f32::MIN_POSITIVE * 0.75));
+        assert_eq!(flags, 0);
+
+        let (r, flags) = div(FpFormat::Single, RNE, s(f32::MIN_POSITIVE), s(3.0));
+        assert_eq!(r, s(f32::MIN_POSITIVE / 3.0));
+        assert_eq!(flags, FLAG_UF | FLAG_NX);
+    }
+
+    #[test]
+    fn test_fma() {
+        let (r, flags) = fma(
+            FpFormat::Double,
+            RNE,
+            (d(2.0), d(3.0), d(1.0)),
+            false,
+            false,
+        );
+        assert_eq!((r, flags), (d(7.0), 0));
+
+        let (r, _) = fma(FpFormat::Double, RNE, (d(2.0), d(3.0), d(1.0)), true, true);
+        assert_eq!(r, d(-7.0));
+
+        // a * b - a * b is exactly the rounding error of the product
+        let a: f64 = 1.0 + f64::EPSILON;
+        let (r, _) = fma(FpFormat::Double, RNE, (d(a), d(a), d(a * a)), false, true);
+        assert_eq!(r, d(a.mul_add(a, -(a * a))));
+
+        let (r, flags) = fma(
+            FpFormat::Single,
+            RNE,
+            (s(f32::INFINITY), s(0.0), 0x7fc0_0000),
+            false,
+            false,
+        );
+        assert_eq!((r, flags), (0x7fc0_0000, FLAG_NV));
+    }
+
+    #[test]
+    fn test_compare() {
+        assert_eq!(eq(FpFormat::Single, s(0.0), s(-0.0)), (true, 0));
+        assert_eq!(lt(FpFormat::Single, s(-1.0), s(1.0)), (true, 0));
+        assert_eq!(le(FpFormat::Single, 0x7fc0_0000, s(1.0)), (false, FLAG_NV));
+        assert_eq!(eq(FpFormat::Single, 0x7fc0_0000, s(1.0)), (false, 0));
+        assert_eq!(eq(FpFormat::Single, 0x7f80_0001, s(1.0)), (false, FLAG_NV));
+        assert_eq!(min(FpFormat::Single, s(0.0), s(-0.0)), (s(-0.0), 0));
+        assert_eq!(max(FpFormat::Single, s(0.0), s(-0.0)), (s(0.0), 0));
+        assert_eq!(min(FpFormat::Single, 0x7fc0_0000, s(2.0)), (s(2.0), 0));
+    }
+
+    #[test]
+    fn test_class() {
+        assert_eq!(class(FpFormat::Single, s(f32::NEG_INFINITY)), 1 << 0);
+        assert_eq!(class(FpFormat::Single, s(-0.0)), 1 << 3);
+        assert_eq!(class(FpFormat::Double, d(1e-310)), 1 << 5);
+        assert_eq!(class(FpFormat::Double, 0x7ff8_0000_0000_0000), 1 << 9);
+    }
+
+    #[test]
+    fn test_to_int() {
+        assert_eq!(
+            to_int(FpFormat::Single, RNE, s(2.5), true, 32),
+            (2, FLAG_NX)
+        );
+        assert_eq!(
+            to_int(
+                FpFormat::Single,
+                RoundingMode::NearestMaxMagnitude,
+                s(2.5),
+                true,
+                32
+            ),
+            (3, FLAG_NX)
+        );
+        assert_eq!(
+            to_int(FpFormat::Single, RoundingMode::Down, s(-2.5), true, 32),
+            (-3i32 as u32 as u64, FLAG_NX)
+        );
+        assert_eq!(
+            to_int(FpFormat::Double, RNE, d(-1.0), false, 32),
+            (0, FLAG_NV)
+        );
+        assert_eq!(
+            to_int(
+                FpFormat::Double,
+                RoundingMode::TowardZero,
+                d(-0.5),
+                false,
+                32
+            ),
+            (0, FLAG_NX)
+        );
+        assert_eq!(
+            to_int(FpFormat::Double, RNE, d(1e20), true, 64),
+            (i64::MAX as u64, FLAG_NV)
+        );
+        assert_eq!(
+            to_int(FpFormat::Double, RNE, 0x7ff8_0000_0000_0000, true, 32),
+            (i32::MAX as u64, FLAG_NV)
+        );
+        assert_eq!(
+            to_int(FpFormat::Double, RNE, d(-2147483648.0), true, 32),
+            (i32::MIN as u32 as u64, 0)
+        );
+    }
+
+    #[test]
+    fn test_from_int_convert() {
+        assert_eq!(
+            from_int(FpFormat::Single, RNE, -7i32 as u32 as u64, true, 32),
+            (s(-7.0), 0)
+        );
+        assert_eq!(
+            from_int(FpFormat::Single, RNE, 0xffff_ffff, false, 32),
+            (s(4294967296.0), FLAG_NX)
+        );
+        assert_eq!(
+            from_int(FpFormat::Double, RNE, u64::MAX, true, 64),
+            (d(-1.0), 0)
+        );
+        assert_eq!(
+            convert(FpFormat::Single, FpFormat::Double, RNE, s(1.5)),
+            (d(1.5), 0)
+        );
+        assert_eq!(
+            convert(FpFormat::Double, FpFormat::Single, RNE, d(0.1)),
+            (s(0.1), FLAG_NX)
+        );
+        assert_eq!(
+            convert(FpFormat::Single, FpFormat::Double, RNE, 0x7f80_0001),
+            (0x7ff8_0000_0000_0000, FLAG_NV)
+        );
+    }
+}
@@ -0,0 +1,47 @@
+use super::Rv;
+use crate::vsoc::arch::riscv::xlen::Xlen;
+use crate::vsoc::arch::riscv::{csr, instr::Decoded};
+
+impl<X: Xlen> Rv<X> {
+    // Time base of the platform, read through time/timeh
+    pub fn set_time(&mut self, time: u64) {
+        if let Some(c) = self.csr.as_mut() {
+            c.set_time(time);
+        }
+    }
+
+    // Count a retired instruction in minstret and in the mhpmcounters
+    // selecting its kind, a branch to the next instruction is not taken
+    pub(super) fn retire(&mut self, decoded: &Decoded<X>, offset: i128) {
+        let csr: &mut csr::Csr = match self.csr.as_mut() {
+            Some(c) => c,
+            None => return,
+        };
+        let len: i128 = decoded.len();
+        let event: Option<u64> = match decoded.expanded.map(|i| (i & 0x7f, i >> 12 & 0x7, i >> 27))
+        {
+            Some((0x03 | 0x07, _, _)) => Some(csr::HPMEVENT_LOAD),
+            // lq takes the MISC-MEM opcode
+            Some((0x0f, 0x2, _)) if self.xlen == 128 => Some(csr::HPMEVENT_LOAD),
+            Some((0x23 | 0x27, _, _)) => Some(csr::HPMEVENT_STORE),
+            // lr and sc
+            Some((0x2f, _, 0x02)) => Some(csr::HPMEVENT_LOAD),
+            Some((0x2f, _, 0x03)) => Some(csr::HPMEVENT_STORE),
+            Some((0x2f, _, _)) => Some(csr::HPMEVENT_AMO),
+            Some((0x63, _, _)) if offset != len => Some(csr::HPMEVENT_BRANCH_TAKEN),
+            _ => None,
+        };
+
+        csr.retire();
+        if let Some(event) = event {
+            csr.count(event);
+        }
+    }
+
+    // End of a step, retired or not
+    pub(super) fn tick(&mut self) {
+        if let Some(c) = self.csr.as_mut() {
+            c.tick();
+        }
+    }
+}
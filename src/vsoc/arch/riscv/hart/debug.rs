@@ -0,0 +1,167 @@
+use super::Rv;
+use crate::vsoc::arch::riscv::csr;
+use crate::vsoc::arch::riscv::xlen::Xlen;
+use crate::vsoc::arch::types::Uint;
+
+// Register numbers of the GDB RISC-V target description
+const GDB_PC: usize = 32;
+const GDB_FPR0: usize = 33;
+const GDB_CSR0: usize = 65;
+
+const GDB_X_NAMES: [&str; 32] = [
+    "zero", "ra", "sp", "gp", "tp", "t0", "t1", "t2", "fp", "s1", "a0", "a1", "a2", "a3", "a4",
+    "a5", "a6", "a7", "s2", "s3", "s4", "s5", "s6", "s7", "s8", "s9", "s10", "s11", "t3", "t4",
+    "t5", "t6",
+];
+
+// The FPU status registers belong to the fpu feature
+fn fpu_csr(addr: usize) -> bool {
+    addr == csr::FFLAGS || addr == csr::FRM || addr == csr::FCSR
+}
+
+impl<X: Xlen> Rv<X> {
+    pub fn gdb_target_xml(&self) -> String {
+        let reg = |name: &str, bitsize: usize, kind: &str, regnum: usize| {
+            format!(
+                "<reg name=\"{}\" bitsize=\"{}\" type=\"{}\" regnum=\"{}\"/>",
+                name, bitsize, kind, regnum
+            )
+        };
+        let mut xml: String = format!(
+            "<?xml version=\"1.0\"?><!DOCTYPE target SYSTEM \"gdb-target.dtd\">\
+             <target version=\"1.0\"><architecture>riscv:rv{}</architecture>\
+             <feature name=\"org.gnu.gdb.riscv.cpu\">",
+            self.xlen
+        );
+
+        for (i, name) in GDB_X_NAMES.iter().enumerate().take(self.x.count()) {
+            xml += &reg(name, self.xlen, "int", i);
+        }
+        xml += &reg("pc", self.xlen, "code_ptr", GDB_PC);
+        xml += "</feature>";
+
+        if self.f.is_some() {
+            let kind: &str = match self.flen {
+                32 => "ieee_single",
+                64 => "ieee_double",
+                _ => "int",
+            };
+
+            xml += "<feature name=\"org.gnu.gdb.riscv.fpu\">";
+            for i in 0..32 {
+                xml += &reg(&format!("f{}", i), self.flen, kind, GDB_FPR0 + i);
+            }
+            for addr in [csr::FFLAGS, csr::FRM, csr::FCSR] {
+                xml += &reg(
+                    self.csr.as_ref().unwrap().name(addr),
+                    32,
+                    "int",
+                    GDB_CSR0 + addr,
+                );
+            }
+            xml += "</feature>";
+        }
+
+        if let Some(c) = self.csr.as_ref() {
+            xml += "<feature name=\"org.gnu.gdb.riscv.csr\">";
+            for addr in (0..4096).filter(|a| c.exists(*a) && !fpu_csr(*a)) {
+                xml += &reg(c.name(addr), self.xlen, "int", GDB_CSR0 + addr);
+            }
+            xml += "</feature>";
+        }
+
+        xml + "</target>"
+    }
+
+    // Little-endian value of a register, None when it does not exist
+    pub fn gdb_read_register(&self, regnum: usize) -> Option<Vec<u8>> {
+        match regnum {
+            n if n < self.x.count() => Some(Vec::from(self.x.get(n))),
+            GDB_PC => Some(self.pc.to_le_vec()),
+            n if (GDB_FPR0..GDB_CSR0).contains(&n) => {
+                self.f.as_ref().map(|f| Vec::from(f.get(n - GDB_FPR0)))
+            }
+            n if n >= GDB_CSR0 => {
+                let c: &csr::Csr = self.csr.as_ref()?;
+                let addr: usize = n - GDB_CSR0;
+
+                if !c.exists(addr) {
+                    return None;
+                }
+
+                let mut value: Vec<u8> = Vec::from(c.get(addr)?);
+
+                if fpu_csr(addr) {
+                    value.truncate(4);
+                }
+                Some(value)
+            }
+            _ => None,
+        }
+    }
+
+    pub fn gdb_write_register(&mut self, regnum: usize, value: &[u8]) -> bool {
+        let xlen: usize = self.xlen;
+        let resize = |width: usize| {
+            let mut v: Vec<u8> = value.to_vec();
+
+            v.resize(width / 8, 0);
+            Uint::new(v)
+        };
+
+        match regnum {
+            0 => (),
+            n if n < self.x.count() => self.x.set(n, &resize(xlen)),
+            GDB_PC => self.pc = X::from(resize(xlen)),
+            n if (GDB_FPR0..GDB_CSR0).contains(&n) => match self.f.as_mut() {
+                Some(f) => f.set(n - GDB_FPR0, &resize(self.flen)),
+                None => return false,
+            },
+            n if n >= GDB_CSR0 => match self.csr.as_mut() {
+                Some(c) if c.exists(n - GDB_CSR0) => c.set(n - GDB_CSR0, &resize(xlen)),
+                _ => return false,
+            },
+            _ => return false,
+        }
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{GDB_CSR0, GDB_FPR0, GDB_PC};
+    use crate::vsoc::arch::riscv::{csr, hart::Rv};
+
+    #[test]
+    fn test_gdb_registers() {
+        let mut hart: Rv<u64> = Rv::new("rv64ifd_zicsr");
+
+        assert!(hart.gdb_write_register(10, &[0x34, 0x12]));
+        assert_eq!(
+            hart.gdb_read_register(10),
+            Some(vec![0x34, 0x12, 0, 0, 0, 0, 0, 0])
+        );
+        assert!(hart.gdb_write_register(GDB_PC, &0x8000_0000u64.to_le_bytes()));
+        assert_eq!(hart.pc, 0x8000_0000);
+        assert!(hart.gdb_write_register(GDB_FPR0 + 1, &[1; 8]));
+        assert_eq!(hart.gdb_read_register(GDB_FPR0 + 1), Some(vec![1; 8]));
+        assert!(hart.gdb_write_register(GDB_CSR0 + csr::MSCRATCH, &[0xaa; 8]));
+        assert_eq!(
+            hart.gdb_read_register(GDB_CSR0 + csr::MSCRATCH),
+            Some(vec![0xaa; 8])
+        );
+        assert_eq!(
+            hart.gdb_read_register(GDB_CSR0 + csr::FCSR).unwrap().len(),
+            4
+        );
+        assert_eq!(hart.gdb_read_register(GDB_CSR0 + 0x7ff), None);
+
+        let xml: String = hart.gdb_target_xml();
+        assert!(xml.contains("<architecture>riscv:rv64</architecture>"));
+        assert!(xml.contains("<reg name=\"mstatus\" bitsize=\"64\" type=\"int\" regnum=\"833\"/>"));
+        assert!(
+            xml.contains("<reg name=\"f31\" bitsize=\"64\" type=\"ieee_double\" regnum=\"64\"/>")
+        );
+    }
+}
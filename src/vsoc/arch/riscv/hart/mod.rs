@@ -1,55 +1,89 @@
+mod counters;
+mod debug;
+mod snapshot;
+mod trace;
+mod trap;
+
 use std::fmt;
 
 use super::atomic::AtomicCtx;
 use super::exception::RvException;
-use super::instr::Instr;
+use super::icache::ICache;
+use super::instr::{Decoded, Instr};
+use super::mmu::{Access, Mmu, PAGE_MASK};
+use super::privilege::RvPrivilege;
 use super::registers::RvFpuRegisters;
 use super::registers::RvRegisters;
+use super::registers::RvVectorRegisters;
 use crate::vsoc::arch::interface::ArchInterface;
 use crate::vsoc::arch::riscv::csr;
 use crate::vsoc::arch::riscv::ext;
+use crate::vsoc::arch::riscv::xlen::Xlen;
 use crate::vsoc::arch::types::Uint;
 use crate::vsoc::bus::Bus;
 use crate::vsoc::bus::BusException;
+use crate::vsoc::trace::Trace;
 
+// The width of the integer registers is chosen at construction, each XLEN
+// having its own monomorphized hart
 #[derive(Debug)]
-pub struct Rv {
+pub struct Rv<X: Xlen> {
     xlen: usize,
     flen: usize,
 
-    pub pc: Uint,
-    pub x: RvRegisters,
+    pub privilege: RvPrivilege,
+    pub pc: X,
+    pub x: RvRegisters<X>,
     pub f: Option<RvFpuRegisters>,
+    pub v: Option<RvVectorRegisters>,
 
     pub csr: Option<csr::Csr>,
 
     pub extensions: ext::RvExtensions,
 
     pub atomic_ctx: Option<AtomicCtx>,
+
+    pub mmu: Mmu,
+    pub icache: ICache<X>,
+
+    pub trace: Option<Trace>,
 }
 
-impl Rv {
-    pub fn new(arch: &str) -> Rv {
+// Width of the integer registers of an architecture string
+pub fn arch_xlen(arch: &str) -> usize {
+    let base: &str = arch.trim();
+
+    if !base.starts_with("rv") {
+        panic!(
+            "Unsupported architecture: {}",
+            base.split('_').next().unwrap_or("")
+        );
+    }
+
+    if base.starts_with("rv32") {
+        32
+    } else if base.starts_with("rv64") {
+        64
+    } else if base.starts_with("rv128") {
+        128
+    } else {
+        panic!("Unsupported architecture width 32/64/128");
+    }
+}
+
+impl<X: Xlen> Rv<X> {
+    pub fn new(arch: &str) -> Rv<X> {
         let mut extensions: u32 = 0;
         let mut registers: usize = 32;
-        let xlen: usize;
+        let xlen: usize = arch_xlen(arch);
         let mut flen: usize = 0;
+        let mut vlen: usize = 128;
         let mut ext: ext::RvExtensions = ext::RvExtensions::default();
         let argv: Vec<&str> = arch.trim().split('_').collect();
         let mut atomic_ctx: Option<AtomicCtx> = None;
 
-        if argv[0].starts_with("rv") {
-            if argv[0].starts_with("rv32") {
-                xlen = 32;
-            } else if argv[0].starts_with("rv64") {
-                xlen = 64;
-            } else if argv[0].starts_with("rv128") {
-                xlen = 128;
-            } else {
-                panic!("Unsupported architecture width 32/64/128");
-            }
-        } else {
-            panic!("Unsupported architecture: {}", argv[0]);
+        if xlen != X::BITS {
+            panic!("Architecture {} is not RV{}", argv[0], X::BITS);
         }
 
         if argv[0].contains('i') {
@@ -82,6 +116,14 @@ impl Rv {
             ext.zacas = true;
         }
 
+        if argv[0].contains('b') {
+            println!("Extension: b");
+            extensions |= ext::EXT_B;
+            ext.zba = true;
+            ext.zbb = true;
+            ext.zbs = true;
+        }
+
         if argv[0].contains('h') {
             println!("Extension: h");
             extensions |= ext::EXT_H;
@@ -111,6 +153,34 @@ impl Rv {
             ext.zicsr = true;
         }
 
+        if ext.h && !ext.s {
+            panic!("Missing s extension");
+        }
+
+        if ext.h && !ext.zicsr {
+            panic!("Missing zicsr extension");
+        }
+
+        // "rv" itself holds a 'v'
+        if argv[0][2..].contains('v') {
+            println!("Extension: v");
+            extensions |= ext::EXT_V;
+            ext.v = true;
+
+            if !ext.zicsr {
+                panic!("Missing zicsr extension");
+            }
+
+            // zvl<N>b raises VLEN from its minimum of 128 bits
+            for zvl in argv.iter().filter_map(|e| e.strip_prefix("zvl")) {
+                println!("Extension: zvl{}", zvl);
+                vlen = match zvl.strip_suffix('b').and_then(|n| n.parse::<usize>().ok()) {
+                    Some(n) if n.is_power_of_two() && (128..=65536).contains(&n) => n.max(vlen),
+                    _ => panic!("Unsupported vector length: zvl{}", zvl),
+                };
+            }
+        }
+
         if arch.contains("zalrsc") {
             println!("Extension: zalrsc");
             ext.zalrsc = true;
@@ -178,10 +248,34 @@ impl Rv {
             ext.zihpm = true;
         }
 
+        if arch.contains("zba") {
+            println!("Extension: zba");
+            ext.zba = true;
+        }
+
+        if arch.contains("zbb") {
+            println!("Extension: zbb");
+            ext.zbb = true;
+        }
+
+        if arch.contains("zbc") {
+            println!("Extension: zbc");
+            ext.zbc = true;
+        }
+
+        if arch.contains("zbs") {
+            println!("Extension: zbs");
+            ext.zbs = true;
+        }
+
         let csr = if ext.zicsr {
             let mut c = csr::Csr::new(xlen, &ext);
             c.set(csr::MISA, Uint::from(extensions).extend(xlen));
-            
+            if ext.v {
+                c.write(csr::VLENB, (vlen / 8) as u128);
+                c.write(csr::VTYPE, 1 << (xlen - 1));
+            }
+
             Some(c)
         } else {
             None
@@ -190,70 +284,200 @@ impl Rv {
         Rv {
             xlen,
             flen,
-            pc: Uint::zero(xlen),
-            x: RvRegisters::new(xlen, registers),
+            privilege: RvPrivilege::Machine,
+            pc: X::ZERO,
+            x: RvRegisters::new(registers),
             f: if ext.f {
                 Some(RvFpuRegisters::new(flen))
             } else {
                 None
             },
+            v: if ext.v {
+                Some(RvVectorRegisters::new(vlen))
+            } else {
+                None
+            },
             csr,
             extensions: ext,
             atomic_ctx,
+            mmu: Mmu::new(),
+            icache: ICache::new(),
+            trace: None,
         }
     }
 
     pub fn set_pc(&mut self, addr: u128) {
-        self.pc = Uint::from(addr);
-        self.pc.truncate(self.xlen);
+        self.pc = X::from_u128(addr);
+    }
+
+    pub fn set_hartid(&mut self, hartid: usize) {
+        if let Some(c) = self.csr.as_mut() {
+            c.write(csr::MHARTID, hartid as u128);
+        }
+    }
+
+    // Another hart stored to memory
+    pub fn snoop(&mut self, addr: u64, width: usize) {
+        if let Some(ctx) = self.atomic_ctx.as_mut() {
+            ctx.snoop(addr, width);
+        }
+        self.icache.invalidate(addr, width);
+    }
+
+    // The hart itself stored to memory, its reservation is kept
+    pub fn invalidate(&mut self, addr: u64, width: usize) {
+        self.icache.invalidate(addr, width);
+    }
+
+    // Boot convention: a0 holds the hart id and a1 the device tree address
+    pub fn set_boot_args(&mut self, dtb: u64) {
+        let hartid: u128 = self
+            .csr
+            .as_ref()
+            .and_then(|c| c.read(csr::MHARTID))
+            .unwrap_or(0);
+
+        self.x.write(10, X::from_u128(hartid));
+        self.x.write(11, X::from_u128(dtb as u128));
+    }
+
+    // Decoded instruction at pc, from the cache when it was already fetched
+    // from the same physical address. The translation is still done, for its
+    // faults and PMP checks
+    fn decode(&mut self, bus: &mut Bus, pc: u64) -> Result<Decoded<X>, (RvException, u64)> {
+        let parcel: usize = if self.extensions.c { 2 } else { 4 };
+        let paddr: u64 = self.translate_fetch(bus, parcel, pc)?;
+
+        if let Some(decoded) = self.icache.get(paddr) {
+            // The upper parcel of a 32-bit instruction is checked on its own
+            if decoded.len() == 4 && parcel == 2 {
+                self.translate_fetch(bus, 2, pc + 2)?;
+            }
+            return Ok(decoded);
+        }
+
+        let raw: u32 = self.fetch(bus, pc)?;
+        let decoded: Decoded<X> = Instr::new(raw).decode(self.xlen, self.extensions.c);
+
+        // An instruction crossing a page may not be contiguous in memory
+        if (pc & PAGE_MASK) + decoded.len() as u64 <= PAGE_MASK + 1 {
+            self.icache.insert(paddr, decoded);
+        }
+
+        Ok(decoded)
+    }
+
+    // Fetch a parcel at a time when compressed instructions are enabled, so that
+    // a 16-bit instruction at the end of a memory region does not fault
+    fn fetch(&mut self, bus: &mut Bus, pc: u64) -> Result<u32, (RvException, u64)> {
+        if !self.extensions.c {
+            return self.fetch_parcel(bus, 4, pc);
+        }
+
+        let mut instr: u32 = self.fetch_parcel(bus, 2, pc)?;
+
+        if instr & 0x3 == 0x3 {
+            instr |= self.fetch_parcel(bus, 2, pc + 2)? << 16;
+        }
+
+        Ok(instr)
+    }
+
+    fn fetch_parcel(
+        &mut self,
+        bus: &mut Bus,
+        width: usize,
+        addr: u64,
+    ) -> Result<u32, (RvException, u64)> {
+        let paddr: u64 = self.translate_fetch(bus, width, addr)?;
+
+        match bus.load(width, paddr) {
+            Ok(raw) => Ok(raw as u32),
+            Err(BusException::LoadAddressMisaligned) => {
+                Err((RvException::InstructionAddressMisaligned, addr))
+            }
+            Err(_) => Err((RvException::InstructionAccessFault, addr)),
+        }
+    }
+
+    fn translate_fetch(
+        &mut self,
+        bus: &mut Bus,
+        width: usize,
+        addr: u64,
+    ) -> Result<u64, (RvException, u64)> {
+        self.mmu
+            .translate(
+                addr,
+                width,
+                Access::Fetch,
+                self.privilege,
+                self.csr.as_ref(),
+                bus,
+            )
+            .map_err(|e| (e, addr))
     }
 }
 
-impl ArchInterface for Rv {
+impl<X: Xlen> ArchInterface for Rv<X> {
     fn step(&mut self, bus: &mut Bus) -> Option<RvException> {
-        let pc = match self.xlen {
-            32 => u32::from(self.pc.clone()) as u64,
-            64 => u64::from(self.pc.clone()),
-            128 => u128::from(self.pc.clone()) as u64,
-            _ => unreachable!(),
+        // Interrupts are taken between instructions
+        if let Some(irq) = self.pending_interrupt() {
+            self.trace_interrupt(irq);
+            self.trap(irq as usize, true, 0);
+            self.tick();
+
+            return None;
+        }
+
+        let pc: u128 = self.pc.to_u128();
+        let snapshot: Option<trace::Snapshot> = self.trace_snapshot();
+        // Physical addresses are 64 bits wide, a pc above them cannot be fetched
+        let fetched: Result<Decoded<X>, (RvException, u128)> = match u64::try_from(pc) {
+            Ok(pc) => self.decode(bus, pc).map_err(|(e, tval)| (e, tval as u128)),
+            Err(_) => Err((RvException::InstructionAccessFault, pc)),
         };
-        match bus.fetch(4, pc as u64) {
-            Ok(instr) => {
-                match Instr::from(instr).process(self, bus) {
-                    Ok(offset) => match self.xlen {
-                        32 => self.pc = Uint::from(i32::from(self.pc.clone()) + offset as i32),
-                        64 => self.pc = Uint::from(i64::from(self.pc.clone()) + offset as i64),
-                        128 => self.pc = Uint::from(i128::from(self.pc.clone()) + offset),
-                        _ => unreachable!(),
-                    },
-                    Err(e) => match e {
-                        RvException::Breakpoint => {
-                            println!("(ebreak @{})", self.pc);
-                        }
-                        RvException::EnvironmentCallSMode | RvException::EnvironmentCallUMode => {
-                            println!("(ecall @{})", self.pc);
-                        }
-                        _ => {
-                            println!("(invalid instruction)");
-                            return Some(e);
-                        }
-                    },
+        let result: Result<(), (RvException, u128)> = match fetched {
+            Ok(decoded) => {
+                let instr: Instr = decoded.instr;
+                let result: Result<i128, RvException> = decoded.process(self, bus);
+
+                self.trace_instr(pc as u64, &instr, snapshot, result.err());
+
+                match result {
+                    Ok(offset) => {
+                        self.pc = self.pc.wrapping_add(X::from_i128(offset));
+                        self.retire(&decoded, offset);
+                        Ok(())
+                    }
+                    Err(e) => Err((e, instr.trap_value(e, &self.x, self.pc, self.v.as_ref()))),
                 }
+            }
+            Err((e, tval)) => {
+                self.trace_fetch_fault(pc, e);
+                Err((e, tval))
+            }
+        };
 
-                // Reset register $zero to 0
-                self.x.set(0, &Uint::zero(self.xlen));
+        // Reset register $zero to 0
+        self.x.write(0, X::ZERO);
+        self.tick();
+
+        if let Err((e, tval)) = result {
+            if !self.trap(e as usize, false, tval) {
+                return Some(e);
             }
-            Err(e) => {
-                println!("<invalid>");
-                match e {
-                    BusException::LoadAccessFault => {
-                        return Some(RvException::InstructionAccessFault)
-                    }
-                    BusException::LoadAddressMisaligned => {
-                        return Some(RvException::InstructionAddressMisaligned)
-                    }
-                    _ => unreachable!(),
-                }
+
+            // The trap handler itself cannot be fetched, stop here instead of looping
+            let fetch_fault: bool = matches!(
+                e,
+                RvException::InstructionAddressMisaligned
+                    | RvException::InstructionAccessFault
+                    | RvException::InstructionPageFault
+                    | RvException::InstructionGuestPageFault
+            );
+            if fetch_fault && self.pc.to_u128() == pc {
+                return Some(e);
             }
         }
 
@@ -261,7 +485,7 @@ impl ArchInterface for Rv {
     }
 }
 
-impl fmt::Display for Rv {
+impl<X: Xlen> fmt::Display for Rv<X> {
     // This trait requires `fmt` with this exact signature.
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         // Write strictly the first element into the supplied output
@@ -271,15 +495,19 @@ impl fmt::Display for Rv {
         if self.f.is_none() {
             write!(
                 f,
-                "(Rv{}\n    {}\n    (pc {})\n    {}   )\n",
-                self. xlen, self.extensions, self.pc, self.x
+                "(Rv{}\n    {}\n    (pc {:#x})\n    {}   )\n",
+                self.xlen, self.extensions, self.pc, self.x
             )
         } else {
             write!(
                 f,
-                "(Rv{}\n    (flen {})\n    {}\n    (pc {})\n    {}\n    {}   )\n",
-                self.xlen, self.flen,
-                self.extensions, self.pc, self.x, self.f.as_ref().unwrap()
+                "(Rv{}\n    (flen {})\n    {}\n    (pc {:#x})\n    {}\n    {}   )\n",
+                self.xlen,
+                self.flen,
+                self.extensions,
+                self.pc,
+                self.x,
+                self.f.as_ref().unwrap()
             )
         }
     }
@@ -0,0 +1,54 @@
+use super::Rv;
+use crate::vsoc::arch::riscv::privilege::RvPrivilege;
+use crate::vsoc::arch::riscv::xlen::Xlen;
+use crate::vsoc::snapshot::{Reader, SnapshotError, Writer};
+
+// The optional units (FPU, vector unit, CSRs, reservation) follow from the ISA string,
+// checked by the Vsoc before restoring the harts
+impl<X: Xlen> Rv<X> {
+    pub fn save(&self, w: &mut Writer) {
+        w.u64(self.xlen as u64);
+        w.u64(self.flen as u64);
+        w.u8(self.privilege as u8);
+        w.bytes(&self.pc.to_le_vec());
+        self.x.save(w);
+        if let Some(f) = &self.f {
+            f.save(w);
+        }
+        if let Some(v) = &self.v {
+            v.save(w);
+        }
+        if let Some(c) = &self.csr {
+            c.save(w);
+        }
+        if let Some(ctx) = &self.atomic_ctx {
+            ctx.save(w);
+        }
+        self.mmu.save(w);
+    }
+
+    pub fn restore(&mut self, r: &mut Reader) -> Result<(), SnapshotError> {
+        r.expect(self.xlen as u64, "xlen")?;
+        r.expect(self.flen as u64, "flen")?;
+        self.privilege = RvPrivilege::from_mpp(r.u8()? as u128)
+            .ok_or(SnapshotError::Mismatch(String::from("privilege")))?;
+        self.pc = X::from_le_slice(r.bytes_exact(self.xlen / 8, "pc")?);
+        // The memory is restored behind the back of the cache
+        self.icache.flush();
+        self.x.restore(r)?;
+        if let Some(f) = self.f.as_mut() {
+            f.restore(r)?;
+        }
+        if let Some(v) = self.v.as_mut() {
+            v.restore(r)?;
+        }
+        if let Some(c) = self.csr.as_mut() {
+            c.restore(r)?;
+        }
+        if let Some(ctx) = self.atomic_ctx.as_mut() {
+            ctx.restore(r)?;
+        }
+
+        self.mmu.restore(r)
+    }
+}
@@ -0,0 +1,101 @@
+use super::Rv;
+use crate::vsoc::arch::riscv::disasm::{self, F_NAMES, X_NAMES};
+use crate::vsoc::arch::riscv::exception::RvException;
+use crate::vsoc::arch::riscv::instr::Instr;
+use crate::vsoc::arch::riscv::interrupt::RvInterrupt;
+use crate::vsoc::arch::riscv::xlen::Xlen;
+use crate::vsoc::arch::types::Uint;
+
+// Register values before an instruction, compared afterwards to find its writebacks
+pub struct Snapshot {
+    x: Vec<Uint>,
+    f: Vec<Uint>,
+}
+
+impl<X: Xlen> Rv<X> {
+    fn trace_pc(&self, pc: u128) -> String {
+        format!("{:0w$x}", pc, w = self.xlen.min(64) / 4)
+    }
+
+    pub(super) fn trace_snapshot(&self) -> Option<Snapshot> {
+        self.trace.as_ref()?;
+
+        Some(Snapshot {
+            x: (0..self.x.count()).map(|i| self.x.get(i)).collect(),
+            f: match &self.f {
+                Some(f) => (0..32).map(|i| f.get(i)).collect(),
+                None => Vec::new(),
+            },
+        })
+    }
+
+    pub(super) fn trace_instr(
+        &mut self,
+        pc: u64,
+        instr: &Instr,
+        before: Option<Snapshot>,
+        result: Option<RvException>,
+    ) {
+        let before: Snapshot = match before {
+            Some(b) => b,
+            None => return,
+        };
+        let raw: String = match instr {
+            Instr::Instr32(i) => format!("{:08x}", i),
+            Instr::Invalid => String::from("????????"),
+            _ => format!("{:04x}    ", instr.get_raw()),
+        };
+        let mut line: String = format!(
+            "{} ({}) {}",
+            self.trace_pc(pc as u128),
+            raw,
+            disasm::disasm(instr, pc, self.xlen)
+        );
+
+        for (i, v) in before.x.iter().enumerate().skip(1) {
+            let now: Uint = self.x.get(i);
+
+            if now != *v {
+                line += &format!("\t{}={}", X_NAMES[i], now);
+            }
+        }
+
+        if let Some(f) = &self.f {
+            for (i, v) in before.f.iter().enumerate() {
+                let now: Uint = f.get(i);
+
+                if now != *v {
+                    line += &format!("\t{}={}", F_NAMES[i], now);
+                }
+            }
+        }
+
+        if let Some(e) = result {
+            line += &format!("\t# {}", e);
+        }
+
+        if let Some(t) = self.trace.as_mut() {
+            t.line(&line);
+        }
+    }
+
+    pub(super) fn trace_fetch_fault(&mut self, pc: u128, e: RvException) {
+        let line: String = format!("{} <fetch>\t# {}", self.trace_pc(pc), e);
+
+        if let Some(t) = self.trace.as_mut() {
+            t.line(&line);
+        }
+    }
+
+    pub(super) fn trace_interrupt(&mut self, irq: RvInterrupt) {
+        let line: String = format!(
+            "{} <interrupt>\t# {}",
+            self.trace_pc(self.pc.to_u128()),
+            irq
+        );
+
+        if let Some(t) = self.trace.as_mut() {
+            t.line(&line);
+        }
+    }
+}
@@ -0,0 +1,435 @@
+use super::Rv;
+use crate::vsoc::arch::riscv::{
+    csr,
+    interrupt::{RvInterrupt, PRIORITY},
+    privilege::RvPrivilege,
+    xlen::Xlen,
+};
+
+impl<X: Xlen> Rv<X> {
+    // Raise or clear an interrupt line of the hart
+    pub fn set_pending(&mut self, irq: RvInterrupt, level: bool) {
+        if let Some(c) = self.csr.as_mut() {
+            c.set_pending(1 << irq as usize, level);
+        }
+    }
+
+    // Highest priority interrupt both pending and enabled for the current
+    // privilege level: M-level interrupts are always enabled below M-mode,
+    // delegated ones are never taken in M-mode, HS-level ones are always
+    // enabled in VS/VU-mode and VS-level ones only taken there
+    pub fn pending_interrupt(&self) -> Option<RvInterrupt> {
+        let csr: &csr::Csr = self.csr.as_ref()?;
+        let pending: u128 = csr.read(csr::MIP).unwrap() & csr.read(csr::MIE).unwrap();
+
+        if pending == 0 {
+            return None;
+        }
+
+        let mstatus: u128 = csr.read(csr::MSTATUS).unwrap();
+        let mideleg: u128 = csr.read(csr::MIDELEG).unwrap();
+        let hideleg: u128 = csr.read(csr::HIDELEG).unwrap();
+        let vsstatus: u128 = csr.read(csr::VSSTATUS).unwrap();
+        let virt: bool = csr.virt();
+        let m_enabled: bool = self.privilege < RvPrivilege::Machine || mstatus & csr::MSTATUS_MIE != 0;
+        let s_enabled: bool = virt
+            || self.privilege < RvPrivilege::Supervisor
+            || (self.privilege == RvPrivilege::Supervisor && mstatus & csr::MSTATUS_SIE != 0);
+        let vs_enabled: bool =
+            virt && (self.privilege < RvPrivilege::Supervisor || vsstatus & csr::MSTATUS_SIE != 0);
+        let mut enabled: u128 = 0;
+
+        if m_enabled {
+            enabled |= pending & !mideleg;
+        }
+        if s_enabled {
+            enabled |= pending & mideleg & !hideleg;
+        }
+        if vs_enabled {
+            enabled |= pending & mideleg & hideleg;
+        }
+
+        PRIORITY
+            .iter()
+            .find(|irq| enabled & (1 << **irq as usize) != 0)
+            .copied()
+    }
+
+    // Enter the trap handler, in S-mode when the cause is delegated through
+    // medeleg/mideleg, in M-mode otherwise. A trap delegated from VS/VU-mode
+    // through hedeleg/hideleg stays in VS-mode. Returns false when the hart
+    // has no CSRs to handle the trap, the caller must then stop the hart
+    pub fn trap(&mut self, cause: usize, interrupt: bool, tval: u128) -> bool {
+        let xlen: usize = self.xlen;
+        let pc: u128 = self.pc.to_u128();
+        let privilege: RvPrivilege = self.privilege;
+        let (gva, gpa) = self.mmu.fault();
+        let csr: &mut csr::Csr = match self.csr.as_mut() {
+            Some(c) => c,
+            None => return false,
+        };
+        let (deleg, hdeleg) = if interrupt {
+            (csr::MIDELEG, csr::HIDELEG)
+        } else {
+            (csr::MEDELEG, csr::HEDELEG)
+        };
+        let virt: bool = csr.virt();
+        let delegated: bool =
+            privilege <= RvPrivilege::Supervisor && (csr.read(deleg).unwrap() >> cause) & 0x1 != 0;
+        let guest: bool = delegated && virt && (csr.read(hdeleg).unwrap() >> cause) & 0x1 != 0;
+
+        if guest {
+            return self.trap_guest(cause, interrupt, tval);
+        }
+
+        // tval holds a guest virtual address for the address faults of a guest
+        // access, guest page faults also report the guest physical address
+        let gva: bool =
+            gva && !interrupt && matches!(cause, 0x0..=0x7 | 0xc | 0xd | 0xf | 0x14 | 0x15 | 0x17);
+        let tval2: u128 = if !interrupt && matches!(cause, 0x14 | 0x15 | 0x17) {
+            (gpa >> 2) as u128
+        } else {
+            0
+        };
+        let (xtvec, xepc, xcause, xtval) = if delegated {
+            (csr::STVEC, csr::SEPC, csr::SCAUSE, csr::STVAL)
+        } else {
+            (csr::MTVEC, csr::MEPC, csr::MCAUSE, csr::MTVAL)
+        };
+        let mut mstatus: u128 = csr.read(csr::MSTATUS).unwrap();
+        let tvec: u128 = csr.read(xtvec).unwrap();
+        let mut mcause: u128 = cause as u128;
+        let mut target: u128 = tvec & !0x3;
+
+        if interrupt {
+            mcause |= 1 << (xlen - 1);
+
+            if tvec & 0x3 == csr::MTVEC_MODE_VECTORED {
+                target += 4 * cause as u128;
+            }
+        }
+
+        if delegated {
+            if mstatus & csr::MSTATUS_SIE != 0 {
+                mstatus |= csr::MSTATUS_SPIE;
+            } else {
+                mstatus &= !csr::MSTATUS_SPIE;
+            }
+            mstatus &= !(csr::MSTATUS_SIE | csr::MSTATUS_SPP);
+            if privilege == RvPrivilege::Supervisor {
+                mstatus |= csr::MSTATUS_SPP;
+            }
+            self.privilege = RvPrivilege::Supervisor;
+        } else {
+            if mstatus & csr::MSTATUS_MIE != 0 {
+                mstatus |= csr::MSTATUS_MPIE;
+            } else {
+                mstatus &= !csr::MSTATUS_MPIE;
+            }
+            mstatus &= !(csr::MSTATUS_MIE | csr::MSTATUS_MPP);
+            mstatus |= (privilege as u128) << csr::MSTATUS_MPP_SHIFT;
+            self.privilege = RvPrivilege::Machine;
+        }
+
+        csr.write(xepc, pc);
+        csr.write(xcause, mcause);
+        csr.write(xtval, tval);
+        csr.write(csr::MSTATUS, mstatus);
+
+        if csr.exists(csr::HSTATUS) {
+            if delegated {
+                let mut hstatus: u128 =
+                    csr.read(csr::HSTATUS).unwrap() & !(csr::HSTATUS_GVA | csr::HSTATUS_SPV);
+
+                if virt {
+                    hstatus &= !csr::HSTATUS_SPVP;
+                    hstatus |= csr::HSTATUS_SPV;
+                    if privilege == RvPrivilege::Supervisor {
+                        hstatus |= csr::HSTATUS_SPVP;
+                    }
+                }
+                if gva {
+                    hstatus |= csr::HSTATUS_GVA;
+                }
+                csr.write(csr::HSTATUS, hstatus);
+                csr.write(csr::HTVAL, tval2);
+                csr.write(csr::HTINST, 0);
+            } else {
+                csr.set_machine_virt(gva, virt);
+                csr.write(csr::MTVAL2, tval2);
+                csr.write(csr::MTINST, 0);
+            }
+            csr.set_virt(false);
+        }
+
+        csr.count(csr::HPMEVENT_TRAP);
+
+        self.set_pc(target);
+
+        true
+    }
+
+    // Trap to VS-mode through the VS CSRs, the VS-level interrupts are seen
+    // there as their S-level counterparts
+    fn trap_guest(&mut self, cause: usize, interrupt: bool, tval: u128) -> bool {
+        let xlen: usize = self.xlen;
+        let pc: u128 = self.pc.to_u128();
+        let privilege: RvPrivilege = self.privilege;
+        let csr: &mut csr::Csr = self.csr.as_mut().unwrap();
+        let mut vsstatus: u128 = csr.read(csr::VSSTATUS).unwrap();
+        let tvec: u128 = csr.read(csr::VSTVEC).unwrap();
+        let mut target: u128 = tvec & !0x3;
+        let mut vscause: u128 = cause as u128;
+
+        if interrupt {
+            vscause -= 1;
+            if tvec & 0x3 == csr::MTVEC_MODE_VECTORED {
+                target += 4 * vscause;
+            }
+            vscause |= 1 << (xlen - 1);
+        }
+
+        if vsstatus & csr::MSTATUS_SIE != 0 {
+            vsstatus |= csr::MSTATUS_SPIE;
+        } else {
+            vsstatus &= !csr::MSTATUS_SPIE;
+        }
+        vsstatus &= !(csr::MSTATUS_SIE | csr::MSTATUS_SPP);
+        if privilege == RvPrivilege::Supervisor {
+            vsstatus |= csr::MSTATUS_SPP;
+        }
+        self.privilege = RvPrivilege::Supervisor;
+
+        csr.write(csr::VSEPC, pc);
+        csr.write(csr::VSCAUSE, vscause);
+        csr.write(csr::VSTVAL, tval);
+        csr.write(csr::VSSTATUS, vsstatus);
+        csr.count(csr::HPMEVENT_TRAP);
+
+        self.set_pc(target);
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::vsoc::arch::riscv::{
+        csr, exception::RvException, hart::Rv, interrupt::RvInterrupt, privilege::RvPrivilege,
+        xlen::Xlen,
+    };
+    use crate::vsoc::arch::types::Uint;
+
+    fn csr_get<X: Xlen>(hart: &Rv<X>, addr: usize) -> u128 {
+        hart.csr.as_ref().unwrap().read(addr).unwrap()
+    }
+
+    #[test]
+    fn test_trap_direct() {
+        let mut hart: Rv<u32> = Rv::new("rv32i_zicsr");
+
+        hart.set_pc(0x8000_0010);
+        hart.csr
+            .as_mut()
+            .unwrap()
+            .set(csr::MTVEC, &Uint::from(0x8000_0101u32));
+        hart.csr
+            .as_mut()
+            .unwrap()
+            .set(csr::MSTATUS, &Uint::from(csr::MSTATUS_MIE as u32));
+
+        assert!(hart.trap(RvException::InstructionIllegal as usize, false, 0x1234));
+        assert_eq!(hart.pc, 0x8000_0100);
+        assert_eq!(csr_get(&hart, csr::MEPC), 0x8000_0010);
+        assert_eq!(csr_get(&hart, csr::MCAUSE), 0x2);
+        assert_eq!(csr_get(&hart, csr::MTVAL), 0x1234);
+        assert_eq!(
+            csr_get(&hart, csr::MSTATUS),
+            csr::MSTATUS_MPIE | csr::MSTATUS_MPP
+        );
+    }
+
+    #[test]
+    fn test_trap_vectored() {
+        let mut hart: Rv<u64> = Rv::new("rv64i_zicsr");
+
+        hart.set_pc(0x8000_0010);
+        hart.csr
+            .as_mut()
+            .unwrap()
+            .set(csr::MTVEC, &Uint::from(0x8000_0101u64));
+
+        assert!(hart.trap(7, true, 0));
+        assert_eq!(hart.pc, 0x8000_011c);
+        assert_eq!(csr_get(&hart, csr::MCAUSE), (1 << 63) | 7);
+    }
+
+    #[test]
+    fn test_trap_delegated() {
+        let mut hart: Rv<u64> = Rv::new("rv64isu_zicsr");
+
+        hart.privilege = RvPrivilege::User;
+        hart.set_pc(0x8000_0010);
+        hart.csr
+            .as_mut()
+            .unwrap()
+            .set(csr::STVEC, &Uint::from(0x8000_0200u64));
+        hart.csr.as_mut().unwrap().set(
+            csr::MEDELEG,
+            &Uint::from(1u64 << RvException::EnvironmentCallUMode as usize),
+        );
+
+        assert!(hart.trap(RvException::EnvironmentCallUMode as usize, false, 0));
+        assert_eq!(hart.privilege, RvPrivilege::Supervisor);
+        assert_eq!(hart.pc, 0x8000_0200);
+        assert_eq!(csr_get(&hart, csr::SEPC), 0x8000_0010);
+        assert_eq!(csr_get(&hart, csr::SCAUSE), 0x8);
+        assert_eq!(csr_get(&hart, csr::MSTATUS) & csr::MSTATUS_SPP, 0);
+
+        // Traps taken from M-mode are never delegated
+        hart.privilege = RvPrivilege::Machine;
+        hart.csr
+            .as_mut()
+            .unwrap()
+            .set(csr::MTVEC, &Uint::from(0x8000_0100u64));
+        assert!(hart.trap(RvException::EnvironmentCallUMode as usize, false, 0));
+        assert_eq!(hart.pc, 0x8000_0100);
+    }
+
+    #[test]
+    fn test_pending_interrupt() {
+        let mut hart: Rv<u64> = Rv::new("rv64isu_zicsr");
+        let mie: u64 = (1 << RvInterrupt::MachineTimerInt as usize)
+            | (1 << RvInterrupt::MachineSwInt as usize)
+            | csr::MIP_STIP as u64;
+
+        hart.csr.as_mut().unwrap().set(csr::MIE, &Uint::from(mie));
+        hart.set_pending(RvInterrupt::MachineTimerInt, true);
+
+        // M-mode interrupts are masked by mstatus.MIE in M-mode only
+        assert_eq!(hart.pending_interrupt(), None);
+        hart.privilege = RvPrivilege::User;
+        assert_eq!(hart.pending_interrupt(), Some(RvInterrupt::MachineTimerInt));
+
+        hart.privilege = RvPrivilege::Machine;
+        hart.csr
+            .as_mut()
+            .unwrap()
+            .set(csr::MSTATUS, &Uint::from(csr::MSTATUS_MIE as u64));
+        hart.set_pending(RvInterrupt::MachineSwInt, true);
+        assert_eq!(hart.pending_interrupt(), Some(RvInterrupt::MachineSwInt));
+
+        // Software cannot clear the timer interrupt through mip
+        hart.set_pending(RvInterrupt::MachineSwInt, false);
+        hart.csr.as_mut().unwrap().set(csr::MIP, &Uint::from(0u64));
+        assert_eq!(hart.pending_interrupt(), Some(RvInterrupt::MachineTimerInt));
+
+        // Delegated interrupts are not taken in M-mode
+        hart.set_pending(RvInterrupt::MachineTimerInt, false);
+        hart.csr
+            .as_mut()
+            .unwrap()
+            .set(csr::MIDELEG, &Uint::from(csr::MIP_STIP as u64));
+        hart.csr
+            .as_mut()
+            .unwrap()
+            .set(csr::MIP, &Uint::from(csr::MIP_STIP as u64));
+        assert_eq!(hart.pending_interrupt(), None);
+        hart.privilege = RvPrivilege::User;
+        assert_eq!(hart.pending_interrupt(), Some(RvInterrupt::SupervisorTimerInt));
+    }
+
+    #[test]
+    fn test_trap_virtual() {
+        let mut hart: Rv<u64> = Rv::new("rv64ihsu_zicsr");
+        let ecall: u64 = 1 << RvException::EnvironmentCallUMode as usize;
+        let c: &mut csr::Csr = hart.csr.as_mut().unwrap();
+
+        c.set(csr::STVEC, &Uint::from(0x8000_0200u64));
+        c.set(csr::VSTVEC, &Uint::from(0x8000_0300u64));
+        c.set(csr::MEDELEG, &Uint::from(ecall | (1 << 10)));
+        c.set(csr::HEDELEG, &Uint::from(ecall));
+        c.set_virt(true);
+        hart.privilege = RvPrivilege::User;
+        hart.set_pc(0x8000_0010);
+
+        // Delegated through hedeleg, the trap stays in VS-mode
+        assert!(hart.trap(RvException::EnvironmentCallUMode as usize, false, 0));
+        assert_eq!(hart.privilege, RvPrivilege::Supervisor);
+        assert!(hart.csr.as_ref().unwrap().virt());
+        assert_eq!(hart.pc, 0x8000_0300);
+        assert_eq!(csr_get(&hart, csr::VSEPC), 0x8000_0010);
+        assert_eq!(csr_get(&hart, csr::VSCAUSE), 0x8);
+        assert_eq!(csr_get(&hart, csr::SCAUSE), 0x0);
+
+        // HS-mode gets the VS-mode ecall and records where it came from
+        assert!(hart.trap(RvException::EnvironmentCallVSMode as usize, false, 0));
+        assert_eq!(hart.privilege, RvPrivilege::Supervisor);
+        assert!(!hart.csr.as_ref().unwrap().virt());
+        assert_eq!(hart.pc, 0x8000_0200);
+        assert_eq!(csr_get(&hart, csr::SCAUSE), 0xa);
+        assert_eq!(
+            csr_get(&hart, csr::HSTATUS) & (csr::HSTATUS_SPV | csr::HSTATUS_SPVP),
+            csr::HSTATUS_SPV | csr::HSTATUS_SPVP
+        );
+
+        // Guest page faults go to M-mode with the guest physical address
+        hart.csr.as_mut().unwrap().set_virt(true);
+        assert!(hart.trap(RvException::LoadGuestPageFault as usize, false, 0x4000_0000));
+        assert_eq!(hart.privilege, RvPrivilege::Machine);
+        assert_eq!(csr_get(&hart, csr::MCAUSE), 0x15);
+        assert_eq!(csr_get(&hart, csr::MTVAL), 0x4000_0000);
+        assert_eq!(
+            csr_get(&hart, csr::MSTATUS) & csr::MSTATUS_MPV,
+            csr::MSTATUS_MPV
+        );
+    }
+
+    #[test]
+    fn test_pending_virtual_interrupt() {
+        let mut hart: Rv<u64> = Rv::new("rv64ihsu_zicsr");
+        let c: &mut csr::Csr = hart.csr.as_mut().unwrap();
+
+        c.set(csr::HIDELEG, &Uint::from(csr::MIP_VSTIP as u64));
+        c.set(csr::HIE, &Uint::from(csr::MIP_VSTIP as u64));
+        c.set(csr::HVIP, &Uint::from(csr::MIP_VSTIP as u64));
+
+        // VS-level interrupts are only taken in VS/VU-mode
+        hart.privilege = RvPrivilege::User;
+        assert_eq!(hart.pending_interrupt(), None);
+        hart.csr.as_mut().unwrap().set_virt(true);
+        assert_eq!(
+            hart.pending_interrupt(),
+            Some(RvInterrupt::VirtualSupervisorTimerInt)
+        );
+
+        // VS-mode masks them through vsstatus.SIE
+        hart.privilege = RvPrivilege::Supervisor;
+        assert_eq!(hart.pending_interrupt(), None);
+        hart.csr
+            .as_mut()
+            .unwrap()
+            .set(csr::VSSTATUS, &Uint::from(csr::MSTATUS_SIE as u64));
+        assert_eq!(
+            hart.pending_interrupt(),
+            Some(RvInterrupt::VirtualSupervisorTimerInt)
+        );
+        assert_eq!(csr_get(&hart, csr::VSIP), csr::MIP_STIP);
+
+        hart.set_pc(0x8000_0010);
+        hart.csr
+            .as_mut()
+            .unwrap()
+            .set(csr::VSTVEC, &Uint::from(0x8000_0301u64));
+        assert!(hart.trap(RvInterrupt::VirtualSupervisorTimerInt as usize, true, 0));
+        assert_eq!(hart.pc, 0x8000_0314);
+        assert_eq!(csr_get(&hart, csr::VSCAUSE), (1 << 63) | 5);
+    }
+
+    #[test]
+    fn test_trap_without_csr() {
+        let mut hart: Rv<u32> = Rv::new("rv32i");
+
+        assert!(!hart.trap(RvException::Breakpoint as usize, false, 0));
+    }
+}
@@ -0,0 +1,89 @@
+use super::instr::Decoded;
+use super::xlen::Xlen;
+
+// Entries of the direct-mapped cache, one per halfword
+const ENTRIES: usize = 4096;
+
+// Decoded instructions keyed by the physical address they were fetched from,
+// so that address translation changes do not have to flush it
+#[derive(Debug)]
+pub struct ICache<X: Xlen> {
+    entries: Vec<Option<(u64, Decoded<X>)>>,
+}
+
+impl<X: Xlen> Default for ICache<X> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<X: Xlen> ICache<X> {
+    pub fn new() -> ICache<X> {
+        ICache {
+            entries: vec![None; ENTRIES],
+        }
+    }
+
+    fn index(addr: u64) -> usize {
+        (addr >> 1) as usize & (ENTRIES - 1)
+    }
+
+    pub fn get(&self, addr: u64) -> Option<Decoded<X>> {
+        match self.entries[Self::index(addr)] {
+            Some((a, decoded)) if a == addr => Some(decoded),
+            _ => None,
+        }
+    }
+
+    pub fn insert(&mut self, addr: u64, decoded: Decoded<X>) {
+        self.entries[Self::index(addr)] = Some((addr, decoded));
+    }
+
+    // Drop the instructions overlapping a store, a 32-bit one may start a
+    // halfword before it
+    pub fn invalidate(&mut self, addr: u64, width: usize) {
+        let mut a: u64 = (addr & !1).saturating_sub(2);
+
+        while a < addr + width as u64 {
+            let i: usize = Self::index(a);
+
+            if matches!(self.entries[i], Some((cached, _)) if cached == a) {
+                self.entries[i] = None;
+            }
+            a += 2;
+        }
+    }
+
+    pub fn flush(&mut self) {
+        self.entries.fill(None);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ICache;
+    use crate::vsoc::arch::riscv::instr::Instr;
+
+    #[test]
+    fn test_invalidate() {
+        let mut cache: ICache<u64> = ICache::new();
+
+        // addi a0, a0, 1 and c.addi a0, 1
+        cache.insert(0x8000_0002, Instr::new(0x0015_0513).decode(64, true));
+        cache.insert(0x8000_0006, Instr::new(0x0505).decode(64, true));
+        assert!(cache.get(0x8000_0002).is_some());
+        assert!(cache.get(0x8000_2002).is_none());
+
+        // The upper half of the 32-bit instruction
+        cache.invalidate(0x8000_0004, 1);
+        assert!(cache.get(0x8000_0002).is_none());
+        assert!(cache.get(0x8000_0006).is_some());
+
+        cache.invalidate(0x8000_0000, 8);
+        assert!(cache.get(0x8000_0006).is_none());
+
+        cache.insert(0x8000_0006, Instr::new(0x0505).decode(64, true));
+        cache.flush();
+        assert!(cache.get(0x8000_0006).is_none());
+    }
+}
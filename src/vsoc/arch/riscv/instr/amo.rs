@@ -1,20 +1,19 @@
 use crate::vsoc::arch::riscv::atomic::AtomicCtx;
 use crate::vsoc::arch::riscv::registers::RvRegisters;
-use crate::vsoc::{
-    arch::{riscv::exception::RvException, types::Uint},
-    bus::Bus,
-};
+use crate::vsoc::arch::riscv::xlen::Xlen;
+use crate::vsoc::arch::riscv::mmu::AddressSpace;
+use crate::vsoc::arch::{riscv::exception::RvException, types::Uint};
 
-pub fn lr(
+pub fn lr<X: Xlen>(
     ctx: &mut AtomicCtx,
     _aq: bool,
     _rl: bool,
     funct3: usize,
-    x: &mut RvRegisters,
+    x: &mut RvRegisters<X>,
     rd: usize,
     rs1: usize,
     rs2: usize,
-    bus: &mut Bus,
+    mem: &mut AddressSpace,
 ) -> Option<RvException> {
     let width: usize = match funct3 {
         2 => 32,
@@ -29,36 +28,28 @@ pub fn lr(
     let addr: u64 = u64::from(x.get(rs1));
     ctx.reserve(addr);
 
-    let mut value: Uint = match bus.fetch(width / 8, addr) {
+    let mut value: Uint = match mem.fetch(width / 8, addr) {
         Ok(v) => Uint::new(v),
-        Err(e) => return Some(RvException::from(e)),
+        Err(e) => return Some(e),
     };
     if (width == 32) && x.len() > 32 {
         value.sextend(x.len(), width);
     }
     x.set(rd, &value);
 
-    println!(
-        "lr.{}\t{},({})\t# {:0x}",
-        if width == 32 { "w" } else { "d" },
-        x.name(rd),
-        x.name(rs1),
-        addr
-    );
-
     None
 }
 
-pub fn sc(
+pub fn sc<X: Xlen>(
     ctx: &mut AtomicCtx,
     _aq: bool,
     _rl: bool,
     funct3: usize,
-    x: &mut RvRegisters,
+    x: &mut RvRegisters<X>,
     rd: usize,
     rs1: usize,
     rs2: usize,
-    bus: &mut Bus,
+    mem: &mut AddressSpace,
 ) -> Option<RvException> {
     let width: usize = match funct3 {
         2 => 32,
@@ -69,9 +60,9 @@ pub fn sc(
     let value: Uint = x.get(rs2);
 
     if ctx.check(addr) {
-        match bus.store(width / 8, addr, &Vec::<u8>::from(value.clone())) {
+        match mem.store(width / 8, addr, &Vec::<u8>::from(value.clone())) {
             None => (),
-            Some(e) => return Some(RvException::from(e)),
+            Some(e) => return Some(e),
         }
 
         x.set(rd, &Uint::zero(x.len()));
@@ -81,27 +72,18 @@ pub fn sc(
 
     ctx.release();
 
-    println!(
-        "sc.{}\t{},{},({})\t# {:0x}",
-        if width == 32 { "w" } else { "d" },
-        x.name(rd),
-        x.name(rs2),
-        x.name(rs1),
-        addr
-    );
-
     None
 }
 
-pub fn swap(
+pub fn swap<X: Xlen>(
     _aq: bool,
     _rl: bool,
     funct3: usize,
-    x: &mut RvRegisters,
+    x: &mut RvRegisters<X>,
     rd: usize,
     rs1: usize,
     rs2: usize,
-    bus: &mut Bus,
+    mem: &mut AddressSpace,
 ) -> Option<RvException> {
     let width: usize = match funct3 {
         2 => 32,
@@ -110,39 +92,30 @@ pub fn swap(
     };
     let addr: u64 = u64::from(x.get(rs1));
     let rs2val: Uint = x.get(rs2);
-    let value = match bus.fetch(width / 8, addr) {
+    let value = match mem.fetch(width / 8, addr) {
         Ok(v) => Uint::new(v).sextend(x.len(), width),
-        Err(e) => return Some(RvException::from(e)),
+        Err(e) => return Some(e),
     };
 
     x.set(rd, &value);
 
-    match bus.store(width / 8, addr, &Vec::<u8>::from(rs2val.clone())) {
+    match mem.store(width / 8, addr, &Vec::<u8>::from(rs2val.clone())) {
         None => (),
-        Some(e) => return Some(RvException::from(e)),
+        Some(e) => return Some(e),
     }
 
-    println!(
-        "amoswap.{}\t{},{},({})\t# {:0x}",
-        if width == 32 { "w" } else { "d" },
-        x.name(rd),
-        x.name(rs2),
-        x.name(rs1),
-        addr
-    );
-
     None
 }
 
-pub fn add(
+pub fn add<X: Xlen>(
     _aq: bool,
     _rl: bool,
     funct3: usize,
-    x: &mut RvRegisters,
+    x: &mut RvRegisters<X>,
     rd: usize,
     rs1: usize,
     rs2: usize,
-    bus: &mut Bus,
+    mem: &mut AddressSpace,
 ) -> Option<RvException> {
     let width: usize = match funct3 {
         2 => 32,
@@ -150,41 +123,31 @@ pub fn add(
         _ => unreachable!(),
     };
     let addr: u64 = u64::from(x.get(rs1));
-    let value = match bus.fetch(width / 8, addr) {
+    let value = match mem.fetch(width / 8, addr) {
         Ok(v) => Uint::new(v).sextend(x.len(), width),
-        Err(e) => return Some(RvException::from(e)),
+        Err(e) => return Some(e),
     };
 
     x.set(rd, &value);
-    let mut result = value + x.get(rs2);
+    let result = value + x.get(rs2);
 
-    match bus.store(width / 8, addr, &Vec::<u8>::from(result.clone())) {
+    match mem.store(width / 8, addr, &Vec::<u8>::from(result.clone())) {
         None => (),
-        Some(e) => return Some(RvException::from(e)),
+        Some(e) => return Some(e),
     }
 
-    println!(
-        "amoadd.{}\t{},{},({})\t# @{:0x}={}",
-        if width == 32 { "w" } else { "d" },
-        x.name(rd),
-        x.name(rs2),
-        x.name(rs1),
-        addr,
-        result.truncate(width)
-    );
-
     None
 }
 
-pub fn xor(
+pub fn xor<X: Xlen>(
     _aq: bool,
     _rl: bool,
     funct3: usize,
-    x: &mut RvRegisters,
+    x: &mut RvRegisters<X>,
     rd: usize,
     rs1: usize,
     rs2: usize,
-    bus: &mut Bus,
+    mem: &mut AddressSpace,
 ) -> Option<RvException> {
     let width: usize = match funct3 {
         2 => 32,
@@ -192,41 +155,31 @@ pub fn xor(
         _ => unreachable!(),
     };
     let addr: u64 = u64::from(x.get(rs1));
-    let value = match bus.fetch(width / 8, addr) {
+    let value = match mem.fetch(width / 8, addr) {
         Ok(v) => Uint::new(v).sextend(x.len(), width),
-        Err(e) => return Some(RvException::from(e)),
+        Err(e) => return Some(e),
     };
 
     x.set(rd, &value);
-    let mut result = value ^ x.get(rs2);
+    let result = value ^ x.get(rs2);
 
-    match bus.store(width / 8, addr, &Vec::<u8>::from(result.clone())) {
+    match mem.store(width / 8, addr, &Vec::<u8>::from(result.clone())) {
         None => (),
-        Some(e) => return Some(RvException::from(e)),
+        Some(e) => return Some(e),
     }
 
-    println!(
-        "amoxor.{}\t{},{},({})\t# @{:0x}={}",
-        if width == 32 { "w" } else { "d" },
-        x.name(rd),
-        x.name(rs2),
-        x.name(rs1),
-        addr,
-        result.truncate(width)
-    );
-
     None
 }
 
-pub fn and(
+pub fn and<X: Xlen>(
     _aq: bool,
     _rl: bool,
     funct3: usize,
-    x: &mut RvRegisters,
+    x: &mut RvRegisters<X>,
     rd: usize,
     rs1: usize,
     rs2: usize,
-    bus: &mut Bus,
+    mem: &mut AddressSpace,
 ) -> Option<RvException> {
     let width: usize = match funct3 {
         2 => 32,
@@ -234,41 +187,31 @@ pub fn and(
         _ => unreachable!(),
     };
     let addr: u64 = u64::from(x.get(rs1));
-    let value = match bus.fetch(width / 8, addr) {
+    let value = match mem.fetch(width / 8, addr) {
         Ok(v) => Uint::new(v).sextend(x.len(), width),
-        Err(e) => return Some(RvException::from(e)),
+        Err(e) => return Some(e),
     };
 
     x.set(rd, &value);
-    let mut result = value & x.get(rs2);
+    let result = value & x.get(rs2);
 
-    match bus.store(width / 8, addr, &Vec::<u8>::from(result.clone())) {
+    match mem.store(width / 8, addr, &Vec::<u8>::from(result.clone())) {
         None => (),
-        Some(e) => return Some(RvException::from(e)),
+        Some(e) => return Some(e),
     }
 
-    println!(
-        "amoand.{}\t{},{},({})\t# @{:0x}={}",
-        if width == 32 { "w" } else { "d" },
-        x.name(rd),
-        x.name(rs2),
-        x.name(rs1),
-        addr,
-        result.truncate(width)
-    );
-
     None
 }
 
-pub fn or(
+pub fn or<X: Xlen>(
     _aq: bool,
     _rl: bool,
     funct3: usize,
-    x: &mut RvRegisters,
+    x: &mut RvRegisters<X>,
     rd: usize,
     rs1: usize,
     rs2: usize,
-    bus: &mut Bus,
+    mem: &mut AddressSpace,
 ) -> Option<RvException> {
     let width: usize = match funct3 {
         2 => 32,
@@ -276,41 +219,31 @@ pub fn or(
         _ => unreachable!(),
     };
     let addr: u64 = u64::from(x.get(rs1));
-    let value = match bus.fetch(width / 8, addr) {
+    let value = match mem.fetch(width / 8, addr) {
         Ok(v) => Uint::new(v).sextend(x.len(), width),
-        Err(e) => return Some(RvException::from(e)),
+        Err(e) => return Some(e),
     };
 
     x.set(rd, &value);
-    let mut result = value | x.get(rs2);
+    let result = value | x.get(rs2);
 
-    match bus.store(width / 8, addr, &Vec::<u8>::from(result.clone())) {
+    match mem.store(width / 8, addr, &Vec::<u8>::from(result.clone())) {
         None => (),
-        Some(e) => return Some(RvException::from(e)),
+        Some(e) => return Some(e),
     }
 
-    println!(
-        "amoor.{}\t{},{},({})\t# @{:0x}={}",
-        if width == 32 { "w" } else { "d" },
-        x.name(rd),
-        x.name(rs2),
-        x.name(rs1),
-        addr,
-        result.truncate(width)
-    );
-
     None
 }
 
-pub fn min(
+pub fn min<X: Xlen>(
     _aq: bool,
     _rl: bool,
     funct3: usize,
-    x: &mut RvRegisters,
+    x: &mut RvRegisters<X>,
     rd: usize,
     rs1: usize,
     rs2: usize,
-    bus: &mut Bus,
+    mem: &mut AddressSpace,
 ) -> Option<RvException> {
     let width: usize = match funct3 {
         2 => 32,
@@ -318,76 +251,53 @@ pub fn min(
         _ => unreachable!(),
     };
     let addr: u64 = u64::from(x.get(rs1));
-    let value = match bus.fetch(width / 8, addr) {
+    let value = match mem.fetch(width / 8, addr) {
         Ok(v) => Uint::new(v).sextend(x.len(), width),
-        Err(e) => return Some(RvException::from(e)),
+        Err(e) => return Some(e),
     };
-    let result: Uint;
-
-    x.set(rd, &value.clone());
-    match width {
+    let result: Uint = match width {
         32 => {
             let rd_val: i32 = i32::from(value.clone());
             let rs2_val: i32 = i32::from(x.get(rs2));
-            result = if rd_val < rs2_val {
+
+            if rd_val < rs2_val {
                 value.clone()
             } else {
                 x.get(rs2)
-            };
-
-            print!(
-                "amomin.{}\t{},{},({})\t# min({}, {}) => @{:0x}",
-                if width == 32 { "w" } else { "d" },
-                x.name(rd),
-                x.name(rs2),
-                x.name(rs1),
-                rd_val,
-                rs2_val,
-                addr
-            );
+            }
         }
         64 => {
             let rd_val: i64 = i64::from(value.clone());
             let rs2_val: i64 = i64::from(x.get(rs2));
-            result = if rd_val < rs2_val {
+
+            if rd_val < rs2_val {
                 value.clone()
             } else {
                 x.get(rs2)
-            };
-
-            print!(
-                "amomin.{}\t{},{},({})\t# min({}, {}) => @{:0x}",
-                if width == 32 { "w" } else { "d" },
-                x.name(rd),
-                x.name(rs2),
-                x.name(rs1),
-                rd_val,
-                rs2_val,
-                addr
-            );
+            }
         }
         _ => unreachable!(),
     };
 
-    match bus.store(width / 8, addr, &Vec::<u8>::from(result.clone())) {
+    x.set(rd, &value);
+
+    match mem.store(width / 8, addr, &Vec::<u8>::from(result.clone())) {
         None => (),
-        Some(e) => return Some(RvException::from(e)),
+        Some(e) => return Some(e),
     }
 
-    println!("{}", result);
-
     None
 }
 
-pub fn minu(
+pub fn minu<X: Xlen>(
     _aq: bool,
     _rl: bool,
     funct3: usize,
-    x: &mut RvRegisters,
+    x: &mut RvRegisters<X>,
     rd: usize,
     rs1: usize,
     rs2: usize,
-    bus: &mut Bus,
+    mem: &mut AddressSpace,
 ) -> Option<RvException> {
     let width: usize = match funct3 {
         2 => 32,
@@ -395,9 +305,9 @@ pub fn minu(
         _ => unreachable!(),
     };
     let addr: u64 = u64::from(x.get(rs1));
-    let value = match bus.fetch(width / 8, addr) {
+    let value = match mem.fetch(width / 8, addr) {
         Ok(v) => Uint::new(v).sextend(x.len(), width),
-        Err(e) => return Some(RvException::from(e)),
+        Err(e) => return Some(e),
     };
 
     x.set(rd, &value);
@@ -407,35 +317,23 @@ pub fn minu(
         x.get(rs2)
     };
 
-    match bus.store(width / 8, addr, &Vec::<u8>::from(result.clone())) {
+    match mem.store(width / 8, addr, &Vec::<u8>::from(result.clone())) {
         None => (),
-        Some(e) => return Some(RvException::from(e)),
+        Some(e) => return Some(e),
     }
 
-    println!(
-        "amominu.{}\t{},{},({})\t# minu({}, {}) => @{:0x}={}",
-        if width == 32 { "w" } else { "d" },
-        x.name(rd),
-        x.name(rs2),
-        x.name(rs1),
-        value,
-        x.get(rs2),
-        addr,
-        result
-    );
-
     None
 }
 
-pub fn max(
+pub fn max<X: Xlen>(
     _aq: bool,
     _rl: bool,
     funct3: usize,
-    x: &mut RvRegisters,
+    x: &mut RvRegisters<X>,
     rd: usize,
     rs1: usize,
     rs2: usize,
-    bus: &mut Bus,
+    mem: &mut AddressSpace,
 ) -> Option<RvException> {
     let width: usize = match funct3 {
         2 => 32,
@@ -443,76 +341,53 @@ pub fn max(
         _ => unreachable!(),
     };
     let addr: u64 = u64::from(x.get(rs1));
-    let value = match bus.fetch(width / 8, addr) {
+    let value = match mem.fetch(width / 8, addr) {
         Ok(v) => Uint::new(v).sextend(x.len(), width),
-        Err(e) => return Some(RvException::from(e)),
+        Err(e) => return Some(e),
     };
-    let result: Uint;
-
-    x.set(rd, &value);
-    match width {
+    let result: Uint = match width {
         32 => {
             let rd_val: i32 = i32::from(value.clone());
             let rs2_val: i32 = i32::from(x.get(rs2));
-            result = if rd_val > rs2_val {
+
+            if rd_val > rs2_val {
                 value.clone()
             } else {
                 x.get(rs2)
-            };
-
-            print!(
-                "amomax.{}\t{},{},({})\t# max({}, {}) => @{:0x}=",
-                if width == 32 { "w" } else { "d" },
-                x.name(rd),
-                x.name(rs2),
-                x.name(rs1),
-                rd_val,
-                rs2_val,
-                addr
-            );
+            }
         }
         64 => {
             let rd_val: i64 = i64::from(value.clone());
             let rs2_val: i64 = i64::from(x.get(rs2));
-            result = if rd_val > rs2_val {
+
+            if rd_val > rs2_val {
                 value.clone()
             } else {
                 x.get(rs2)
-            };
-
-            print!(
-                "amomax.{}\t{},{},({})\t# max({}, {}) => @{:0x}=",
-                if width == 32 { "w" } else { "d" },
-                x.name(rd),
-                x.name(rs2),
-                x.name(rs1),
-                rd_val,
-                rs2_val,
-                addr
-            );
+            }
         }
         _ => unreachable!(),
     };
 
-    match bus.store(width / 8, addr, &Vec::<u8>::from(result.clone())) {
+    x.set(rd, &value);
+
+    match mem.store(width / 8, addr, &Vec::<u8>::from(result.clone())) {
         None => (),
-        Some(e) => return Some(RvException::from(e)),
+        Some(e) => return Some(e),
     }
 
-    println!("{}", result);
-
     None
 }
 
-pub fn maxu(
+pub fn maxu<X: Xlen>(
     _aq: bool,
     _rl: bool,
     funct3: usize,
-    x: &mut RvRegisters,
+    x: &mut RvRegisters<X>,
     rd: usize,
     rs1: usize,
     rs2: usize,
-    bus: &mut Bus,
+    mem: &mut AddressSpace,
 ) -> Option<RvException> {
     let width: usize = match funct3 {
         2 => 32,
@@ -520,9 +395,9 @@ pub fn maxu(
         _ => unreachable!(),
     };
     let addr: u64 = u64::from(x.get(rs1));
-    let value = match bus.fetch(width / 8, addr) {
+    let value = match mem.fetch(width / 8, addr) {
         Ok(v) => Uint::new(v).sextend(x.len(), width),
-        Err(e) => return Some(RvException::from(e)),
+        Err(e) => return Some(e),
     };
 
     x.set(rd, &value);
@@ -532,35 +407,23 @@ pub fn maxu(
         x.get(rs2)
     };
 
-    match bus.store(width / 8, addr, &Vec::<u8>::from(result.clone())) {
+    match mem.store(width / 8, addr, &Vec::<u8>::from(result.clone())) {
         None => (),
-        Some(e) => return Some(RvException::from(e)),
+        Some(e) => return Some(e),
     }
 
-    println!(
-        "amomaxu.{}\t{},{},({})\t# maxu({}, {}) => @{:0x}={}",
-        if width == 32 { "w" } else { "d" },
-        x.name(rd),
-        x.name(rs2),
-        x.name(rs1),
-        value,
-        x.get(rs2),
-        addr,
-        result
-    );
-
     None
 }
 
-pub fn cas(
+pub fn cas<X: Xlen>(
     _aq: bool,
     _rl: bool,
     funct3: usize,
-    x: &mut RvRegisters,
+    x: &mut RvRegisters<X>,
     rd: usize,
     rs1: usize,
     rs2: usize,
-    bus: &mut Bus,
+    mem: &mut AddressSpace,
 ) -> Option<RvException> {
     let width: usize = match funct3 {
         2 => 32,
@@ -575,9 +438,9 @@ pub fn cas(
         return Some(RvException::InstructionIllegal);
     }
 
-    let temp0 = match bus.fetch(width / 8, addr) {
+    let temp0 = match mem.fetch(width / 8, addr) {
         Ok(v) => Uint::new(v).sextend(x.len(), width),
-        Err(e) => return Some(RvException::from(e)),
+        Err(e) => return Some(e),
     };
     let comp0 = x.get(rd);
     let swap0 = x.get(rs2);
@@ -587,28 +450,28 @@ pub fn cas(
     let mut swap1: Uint = Uint::zero(x.len());
 
     if width == 2 * x.len() {
-        temp1 = match bus.fetch(width / 8, addr + (x.len() / 8) as u64) {
+        temp1 = match mem.fetch(width / 8, addr + (x.len() / 8) as u64) {
             Ok(v) => Uint::new(v).sextend(x.len(), width),
-            Err(e) => return Some(RvException::from(e)),
+            Err(e) => return Some(e),
         };
         comp1 = x.get(rd + 1);
         swap1 = x.get(rs2 + 1);
     };
 
     if temp0 == comp0 && temp1 == comp1 {
-        match bus.store(x.len() / 8, addr, &Vec::<u8>::from(swap0)) {
+        match mem.store(x.len() / 8, addr, &Vec::<u8>::from(swap0)) {
             None => (),
-            Some(e) => return Some(RvException::from(e)),
+            Some(e) => return Some(e),
         }
 
         if width == 2 * x.len() {
-            match bus.store(
+            match mem.store(
                 x.len() / 8,
                 addr + (x.len() / 8) as u64,
                 &Vec::<u8>::from(swap1),
             ) {
                 None => (),
-                Some(e) => return Some(RvException::from(e)),
+                Some(e) => return Some(e),
             }
         }
     }
@@ -620,19 +483,5 @@ pub fn cas(
         }
     }
 
-    println!(
-        "amocas.{}\t{},{},({})",
-        if width == 32 {
-            "w"
-        } else if width == 64 {
-            "d"
-        } else {
-            "q"
-        },
-        x.name(rd),
-        x.name(rs2),
-        x.name(rs1)
-    );
-
     None
 }
@@ -0,0 +1,442 @@
+use super::super::registers::RvRegisters;
+use super::super::xlen::Xlen;
+
+// Operands are handled as u128 holding the xlen bits of the register
+fn get<X: Xlen>(x: &RvRegisters<X>, rs: usize) -> u128 {
+    x.read(rs).to_u128()
+}
+
+fn set<X: Xlen>(x: &mut RvRegisters<X>, rd: usize, value: u128) {
+    x.write(rd, X::from_u128(value));
+}
+
+fn mask(xlen: usize) -> u128 {
+    u128::MAX >> (128 - xlen)
+}
+
+// Sign-extend the low `bits` bits of the value
+fn sext(value: u128, bits: usize) -> u128 {
+    (((value << (128 - bits)) as i128) >> (128 - bits)) as u128
+}
+
+fn rotate_right(value: u128, shamt: usize, xlen: usize) -> u128 {
+    let shamt: usize = shamt % xlen;
+
+    if shamt == 0 {
+        return value;
+    }
+
+    ((value >> shamt) | (value << (xlen - shamt))) & mask(xlen)
+}
+
+// Zba
+
+pub fn sh_add<X: Xlen>(x: &mut RvRegisters<X>, rd: usize, rs1: usize, rs2: usize, shift: usize) {
+    let result: u128 = get(x, rs2).wrapping_add(get(x, rs1) << shift);
+
+    set(x, rd, result);
+}
+
+// add.uw is sh_add_uw with no shift
+pub fn sh_add_uw<X: Xlen>(x: &mut RvRegisters<X>, rd: usize, rs1: usize, rs2: usize, shift: usize) {
+    let result: u128 = get(x, rs2).wrapping_add((get(x, rs1) & 0xffff_ffff) << shift);
+
+    set(x, rd, result);
+}
+
+pub fn slli_uw<X: Xlen>(x: &mut RvRegisters<X>, rd: usize, rs1: usize, shamt: usize) {
+    let result: u128 = (get(x, rs1) & 0xffff_ffff) << shamt;
+
+    set(x, rd, result);
+}
+
+// Zbb
+
+pub fn andn<X: Xlen>(x: &mut RvRegisters<X>, rd: usize, rs1: usize, rs2: usize) {
+    let result: u128 = get(x, rs1) & !get(x, rs2);
+
+    set(x, rd, result);
+}
+
+pub fn orn<X: Xlen>(x: &mut RvRegisters<X>, rd: usize, rs1: usize, rs2: usize) {
+    let result: u128 = get(x, rs1) | !get(x, rs2);
+
+    set(x, rd, result);
+}
+
+pub fn xnor<X: Xlen>(x: &mut RvRegisters<X>, rd: usize, rs1: usize, rs2: usize) {
+    let result: u128 = !(get(x, rs1) ^ get(x, rs2));
+
+    set(x, rd, result);
+}
+
+pub fn clz<X: Xlen>(x: &mut RvRegisters<X>, rd: usize, rs1: usize) {
+    let xlen: usize = x.len();
+    let value: u128 = get(x, rs1);
+    let result: u128 = if value == 0 {
+        xlen as u128
+    } else {
+        (value.leading_zeros() as usize - (128 - xlen)) as u128
+    };
+
+    set(x, rd, result);
+}
+
+pub fn clzw<X: Xlen>(x: &mut RvRegisters<X>, rd: usize, rs1: usize) {
+    let result: u32 = (get(x, rs1) as u32).leading_zeros();
+
+    set(x, rd, result as u128);
+}
+
+pub fn ctz<X: Xlen>(x: &mut RvRegisters<X>, rd: usize, rs1: usize) {
+    let xlen: usize = x.len();
+    let value: u128 = get(x, rs1);
+    let result: u128 = if value == 0 {
+        xlen as u128
+    } else {
+        value.trailing_zeros() as u128
+    };
+
+    set(x, rd, result);
+}
+
+pub fn ctzw<X: Xlen>(x: &mut RvRegisters<X>, rd: usize, rs1: usize) {
+    let result: u32 = (get(x, rs1) as u32).trailing_zeros();
+
+    set(x, rd, result as u128);
+}
+
+pub fn cpop<X: Xlen>(x: &mut RvRegisters<X>, rd: usize, rs1: usize) {
+    let result: u32 = get(x, rs1).count_ones();
+
+    set(x, rd, result as u128);
+}
+
+pub fn cpopw<X: Xlen>(x: &mut RvRegisters<X>, rd: usize, rs1: usize) {
+    let result: u32 = (get(x, rs1) as u32).count_ones();
+
+    set(x, rd, result as u128);
+}
+
+pub fn max<X: Xlen>(x: &mut RvRegisters<X>, rd: usize, rs1: usize, rs2: usize) {
+    let xlen: usize = x.len();
+    let rs1value: i128 = sext(get(x, rs1), xlen) as i128;
+    let rs2value: i128 = sext(get(x, rs2), xlen) as i128;
+
+    set(x, rd, rs1value.max(rs2value) as u128);
+}
+
+pub fn maxu<X: Xlen>(x: &mut RvRegisters<X>, rd: usize, rs1: usize, rs2: usize) {
+    let result: u128 = get(x, rs1).max(get(x, rs2));
+
+    set(x, rd, result);
+}
+
+pub fn min<X: Xlen>(x: &mut RvRegisters<X>, rd: usize, rs1: usize, rs2: usize) {
+    let xlen: usize = x.len();
+    let rs1value: i128 = sext(get(x, rs1), xlen) as i128;
+    let rs2value: i128 = sext(get(x, rs2), xlen) as i128;
+
+    set(x, rd, rs1value.min(rs2value) as u128);
+}
+
+pub fn minu<X: Xlen>(x: &mut RvRegisters<X>, rd: usize, rs1: usize, rs2: usize) {
+    let result: u128 = get(x, rs1).min(get(x, rs2));
+
+    set(x, rd, result);
+}
+
+pub fn sext_b<X: Xlen>(x: &mut RvRegisters<X>, rd: usize, rs1: usize) {
+    let result: u128 = sext(get(x, rs1), 8);
+
+    set(x, rd, result);
+}
+
+pub fn sext_h<X: Xlen>(x: &mut RvRegisters<X>, rd: usize, rs1: usize) {
+    let result: u128 = sext(get(x, rs1), 16);
+
+    set(x, rd, result);
+}
+
+pub fn zext_h<X: Xlen>(x: &mut RvRegisters<X>, rd: usize, rs1: usize) {
+    let result: u128 = get(x, rs1) & 0xffff;
+
+    set(x, rd, result);
+}
+
+pub fn rol<X: Xlen>(x: &mut RvRegisters<X>, rd: usize, rs1: usize, rs2: usize) {
+    let xlen: usize = x.len();
+    let shamt: usize = get(x, rs2) as usize % xlen;
+    let result: u128 = rotate_right(get(x, rs1), xlen - shamt, xlen);
+
+    set(x, rd, result);
+}
+
+pub fn ror<X: Xlen>(x: &mut RvRegisters<X>, rd: usize, rs1: usize, rs2: usize) {
+    let xlen: usize = x.len();
+    let result: u128 = rotate_right(get(x, rs1), get(x, rs2) as usize, xlen);
+
+    set(x, rd, result);
+}
+
+pub fn rori<X: Xlen>(x: &mut RvRegisters<X>, rd: usize, rs1: usize, shamt: usize) {
+    let xlen: usize = x.len();
+    let result: u128 = rotate_right(get(x, rs1), shamt, xlen);
+
+    set(x, rd, result);
+}
+
+pub fn rolw<X: Xlen>(x: &mut RvRegisters<X>, rd: usize, rs1: usize, rs2: usize) {
+    let result: u32 = (get(x, rs1) as u32).rotate_left(get(x, rs2) as u32 & 0x1f);
+
+    set(x, rd, sext(result as u128, 32));
+}
+
+pub fn rorw<X: Xlen>(x: &mut RvRegisters<X>, rd: usize, rs1: usize, rs2: usize) {
+    let result: u32 = (get(x, rs1) as u32).rotate_right(get(x, rs2) as u32 & 0x1f);
+
+    set(x, rd, sext(result as u128, 32));
+}
+
+pub fn roriw<X: Xlen>(x: &mut RvRegisters<X>, rd: usize, rs1: usize, shamt: usize) {
+    let result: u32 = (get(x, rs1) as u32).rotate_right(shamt as u32 & 0x1f);
+
+    set(x, rd, sext(result as u128, 32));
+}
+
+pub fn orc_b<X: Xlen>(x: &mut RvRegisters<X>, rd: usize, rs1: usize) {
+    let value: u128 = get(x, rs1);
+    let result: u128 = (0..16)
+        .filter(|i| (value >> (8 * i)) & 0xff != 0)
+        .fold(0, |acc, i| acc | 0xff << (8 * i));
+
+    set(x, rd, result);
+}
+
+pub fn rev8<X: Xlen>(x: &mut RvRegisters<X>, rd: usize, rs1: usize) {
+    let xlen: usize = x.len();
+    let result: u128 = get(x, rs1).swap_bytes() >> (128 - xlen);
+
+    set(x, rd, result);
+}
+
+// Zbc
+
+pub fn clmul<X: Xlen>(x: &mut RvRegisters<X>, rd: usize, rs1: usize, rs2: usize) {
+    let xlen: usize = x.len();
+    let (rs1value, rs2value): (u128, u128) = (get(x, rs1), get(x, rs2));
+    let result: u128 = (0..xlen)
+        .filter(|i| (rs2value >> i) & 0x1 != 0)
+        .fold(0, |acc, i| acc ^ (rs1value << i));
+
+    set(x, rd, result);
+}
+
+pub fn clmulh<X: Xlen>(x: &mut RvRegisters<X>, rd: usize, rs1: usize, rs2: usize) {
+    let xlen: usize = x.len();
+    let (rs1value, rs2value): (u128, u128) = (get(x, rs1), get(x, rs2));
+    let result: u128 = (1..xlen)
+        .filter(|i| (rs2value >> i) & 0x1 != 0)
+        .fold(0, |acc, i| acc ^ (rs1value >> (xlen - i)));
+
+    set(x, rd, result);
+}
+
+pub fn clmulr<X: Xlen>(x: &mut RvRegisters<X>, rd: usize, rs1: usize, rs2: usize) {
+    let xlen: usize = x.len();
+    let (rs1value, rs2value): (u128, u128) = (get(x, rs1), get(x, rs2));
+    let result: u128 = (0..xlen)
+        .filter(|i| (rs2value >> i) & 0x1 != 0)
+        .fold(0, |acc, i| acc ^ (rs1value >> (xlen - i - 1)));
+
+    set(x, rd, result);
+}
+
+// Zbs, the register forms take the bit index from rs2
+
+pub fn bset<X: Xlen>(x: &mut RvRegisters<X>, rd: usize, rs1: usize, rs2: usize) {
+    let shamt: usize = get(x, rs2) as usize;
+
+    bseti(x, rd, rs1, shamt);
+}
+
+pub fn bseti<X: Xlen>(x: &mut RvRegisters<X>, rd: usize, rs1: usize, shamt: usize) {
+    let result: u128 = get(x, rs1) | 1 << (shamt % x.len());
+
+    set(x, rd, result);
+}
+
+pub fn bclr<X: Xlen>(x: &mut RvRegisters<X>, rd: usize, rs1: usize, rs2: usize) {
+    let shamt: usize = get(x, rs2) as usize;
+
+    bclri(x, rd, rs1, shamt);
+}
+
+pub fn bclri<X: Xlen>(x: &mut RvRegisters<X>, rd: usize, rs1: usize, shamt: usize) {
+    let result: u128 = get(x, rs1) & !(1 << (shamt % x.len()));
+
+    set(x, rd, result);
+}
+
+pub fn binv<X: Xlen>(x: &mut RvRegisters<X>, rd: usize, rs1: usize, rs2: usize) {
+    let shamt: usize = get(x, rs2) as usize;
+
+    binvi(x, rd, rs1, shamt);
+}
+
+pub fn binvi<X: Xlen>(x: &mut RvRegisters<X>, rd: usize, rs1: usize, shamt: usize) {
+    let result: u128 = get(x, rs1) ^ 1 << (shamt % x.len());
+
+    set(x, rd, result);
+}
+
+pub fn bext<X: Xlen>(x: &mut RvRegisters<X>, rd: usize, rs1: usize, rs2: usize) {
+    let shamt: usize = get(x, rs2) as usize;
+
+    bexti(x, rd, rs1, shamt);
+}
+
+pub fn bexti<X: Xlen>(x: &mut RvRegisters<X>, rd: usize, rs1: usize, shamt: usize) {
+    let result: u128 = (get(x, rs1) >> (shamt % x.len())) & 0x1;
+
+    set(x, rd, result);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::super::registers::RvRegisters;
+    use crate::vsoc::Vsoc;
+
+    #[test]
+    fn test_rv32() {
+        let mut x: RvRegisters<u32> = RvRegisters::new(32);
+        let get = |x: &RvRegisters<u32>, r: usize| x.read(r);
+
+        x.write(1, 0x8000_00f0);
+        x.write(2, 36);
+        super::rev8(&mut x, 3, 1);
+        assert_eq!(get(&x, 3), 0xf000_0080);
+        super::ror(&mut x, 3, 1, 2);
+        assert_eq!(get(&x, 3), 0x0800_000f);
+        super::rol(&mut x, 3, 1, 2);
+        assert_eq!(get(&x, 3), 0x0000_0f08);
+        super::clz(&mut x, 3, 0);
+        assert_eq!(get(&x, 3), 32);
+        super::min(&mut x, 3, 1, 2);
+        assert_eq!(get(&x, 3), 0x8000_00f0);
+        super::clmulh(&mut x, 3, 1, 1);
+        assert_eq!(get(&x, 3), 0x4000_0000);
+        super::clmulr(&mut x, 3, 1, 1);
+        assert_eq!(get(&x, 3), 0x8000_0000);
+        super::binv(&mut x, 3, 1, 2);
+        assert_eq!(get(&x, 3), 0x8000_00e0);
+    }
+
+    #[test]
+    fn test_decode() {
+        let arch: String = String::from("rv64i_zba_zbb_zbc_zbs");
+        let mut vsoc: Vsoc = Vsoc::new(&arch);
+        // li a1, -2; li a2, 0xf0, then one instruction per register from a3
+        let program: [u32; 22] = [
+            0xffe0_0593,
+            0x0f00_0613,
+            0x20b6_46b3,
+            0x0805_873b,
+            0x40b6_77b3,
+            0x6006_1813,
+            0x6016_1893,
+            0x6025_9913,
+            0x6b86_5993,
+            0x2876_5a13,
+            0x0ac5_eab3,
+            0x0ac5_db33,
+            0x60c5_9bb3,
+            0x6046_5c1b,
+            0x0ac6_1cb3,
+            0x28c0_1d33,
+            0x4846_5d93,
+            0x0805_ce3b,
+            0x6046_1e93,
+            0x0815_9f1b,
+            0x6006_1f9b,
+            0x0010_0073,
+        ];
+        let binary: Vec<u8> = program.iter().flat_map(|i| i.to_le_bytes()).collect();
+        let expected: [(usize, u64); 19] = [
+            (13, 0x3be),                 // sh2add
+            (14, 0xffff_fffe),           // add.uw
+            (15, 0),                     // andn
+            (16, 56),                    // clz
+            (17, 4),                     // ctz
+            (18, 63),                    // cpop
+            (19, 0xf000_0000_0000_0000), // rev8
+            (20, 0xff),                  // orc.b
+            (21, 0xf0),                  // max
+            (22, 0xf0),                  // minu
+            (23, 0xfffe_ffff_ffff_ffff), // rol
+            (24, 0xf),                   // roriw
+            (25, 0x5500),                // clmul
+            (26, 1 << 48),               // bset
+            (27, 1),                     // bexti
+            (28, 0xfffe),                // zext.h
+            (29, 0xffff_ffff_ffff_fff0), // sext.b
+            (30, 0x1_ffff_fffc),         // slli.uw
+            (31, 24),                    // clzw
+        ];
+
+        vsoc.load(&binary).unwrap();
+        for _ in 0..21 {
+            assert!(vsoc.step().is_none());
+        }
+        for (reg, value) in expected {
+            let v: Vec<u8> = vsoc.read_register(reg).unwrap();
+
+            assert_eq!(u64::from_le_bytes(v.try_into().unwrap()), value, "x{}", reg);
+        }
+
+        // The same encodings are illegal without the extensions, and without
+        // CSRs to trap to
+        let arch: String = String::from("rv64i");
+        let mut vsoc: Vsoc = Vsoc::new(&arch);
+
+        vsoc.load(&binary).unwrap();
+        vsoc.step();
+        vsoc.step();
+        assert!(vsoc.step().is_some());
+    }
+
+    #[test]
+    fn test_shamt() {
+        // rori, bseti, bclri, binvi and bexti by 32 are reserved on RV32
+        for instr in [
+            0x6205_5513u32,
+            0x2a05_1513,
+            0x4a05_1513,
+            0x6a05_1513,
+            0x4a05_5513,
+        ] {
+            let arch: String = String::from("rv32i_zbb_zbs");
+            let mut vsoc: Vsoc = Vsoc::new(&arch);
+
+            vsoc.load(&instr.to_le_bytes()).unwrap();
+            assert!(vsoc.step().is_some(), "{:#x}", instr);
+        }
+
+        // RV128 takes them up to 127: bseti a1, zero, 100; rori a2, a1, 100
+        let arch: String = String::from("rv128i_zbb_zbs");
+        let mut vsoc: Vsoc = Vsoc::new(&arch);
+        let binary: Vec<u8> = [0x2e40_1593u32, 0x6645_d613]
+            .iter()
+            .flat_map(|i| i.to_le_bytes())
+            .collect();
+        let x = |vsoc: &Vsoc, reg: usize| -> u128 {
+            u128::from_le_bytes(vsoc.read_register(reg).unwrap().try_into().unwrap())
+        };
+
+        vsoc.load(&binary).unwrap();
+        assert!(vsoc.step().is_none());
+        assert!(vsoc.step().is_none());
+        assert_eq!(x(&vsoc, 11), 1 << 100);
+        assert_eq!(x(&vsoc, 12), 1);
+    }
+}
@@ -1,41 +1,33 @@
 use super::super::registers::RvRegisters;
-use crate::vsoc::{
-    arch::{riscv::{exception::RvException, registers::RvFpuRegisters}, types::Uint},
-    bus::Bus,
+use super::super::xlen::Xlen;
+use super::load::address;
+use crate::vsoc::arch::{
+    riscv::{
+        csr::{Csr, FFLAGS, FRM},
+        exception::RvException,
+        fpu::{self, FpFormat, RoundingMode},
+        mmu::AddressSpace,
+        registers::RvFpuRegisters,
+    },
+    types::Uint,
 };
 
-pub fn load(
-    x: &mut RvRegisters,
+pub fn load<X: Xlen>(
+    x: &mut RvRegisters<X>,
     f: &mut RvFpuRegisters,
     width: usize,
     rd: usize,
     rs1: usize,
     imm: i32,
-    bus: &mut Bus,
+    mem: &mut AddressSpace,
 ) -> Result<Uint, RvException> {
-    let mut value: Uint = if imm < 0 {
-        let addr: u64 = u64::from(x.get(rs1)) - imm.unsigned_abs() as u64;
-
-        match bus.fetch(width, addr) {
-            Ok(v) => Uint::new(v),
-            Err(e) => return Err(RvException::from(e)),
-        }
-    } else {
-        let addr: u64 = u64::from(x.get(rs1)) + imm.unsigned_abs() as u64;
-
-        match bus.fetch(width, addr) {
-            Ok(v) => Uint::new(v),
-            Err(e) => return Err(RvException::from(e)),
-        }
-    };
-
-    match width {
-        4 => print!("flw\t{},{}({})\t# ", f.name(rd), imm, x.name(rs1)),
-        8 => print!("fld\t{},{}({})\t# ", f.name(rd), imm, x.name(rs1)),
-        16 => print!("flq\t{},{}({})\t# ", f.name(rd), imm, x.name(rs1)),
-        _ => return Err(RvException::InstructionIllegal),
+    if !matches!(width, 4 | 8 | 16) {
+        return Err(RvException::InstructionIllegal);
     }
 
+    let addr: u64 = address(x, rs1, imm).ok_or(RvException::LoadAccessFault)?;
+    let mut value: Uint = Uint::new(mem.fetch(width, addr)?);
+
     if width * 8 < f.len() {
         value.extend_with(f.len(), 0xff);
     }
@@ -45,32 +37,467 @@ pub fn load(
     Ok(value)
 }
 
-pub fn store(
-    x: &mut RvRegisters,
+pub fn store<X: Xlen>(
+    x: &mut RvRegisters<X>,
     f: &mut RvFpuRegisters,
     width: usize,
     rs1: usize,
     rs2: usize,
     imm: i32,
-    bus: &mut Bus,
+    mem: &mut AddressSpace,
 ) -> Result<Uint, RvException> {
-    let addr: u64 = if imm < 0 {
-        u64::from(x.get(rs1)) - imm.unsigned_abs() as u64
+    if !matches!(width, 4 | 8 | 16) {
+        return Err(RvException::InstructionIllegal);
+    }
+
+    let addr: u64 = address(x, rs1, imm).ok_or(RvException::StoreAccessFault)?;
+    let value: Uint = f.get(rs2);
+
+    match mem.store(width, addr, &Vec::<u8>::from(value.clone())) {
+        None => Ok(value),
+        Some(e) => Err(e),
+    }
+}
+
+fn get(f: &RvFpuRegisters, fmt: FpFormat, r: usize) -> u64 {
+    let value: u128 = u128::from(f.get(r));
+    let width: usize = fmt.width();
+
+    // Narrower values must be NaN-boxed, otherwise they read as the canonical NaN
+    if f.len() > width && (value >> width) != u128::MAX >> (128 - (f.len() - width)) {
+        return fmt.canonical_nan();
+    }
+
+    (value & ((1u128 << width) - 1)) as u64
+}
+
+fn set(f: &mut RvFpuRegisters, fmt: FpFormat, r: usize, bits: u64) {
+    let mut value: Uint = Uint::from(bits);
+
+    value.truncate(fmt.width() / 8);
+    value.extend_with(f.len(), 0xff);
+    f.set(r, &value);
+}
+
+fn set_x<X: Xlen>(x: &mut RvRegisters<X>, rd: usize, value: i64) {
+    match x.len() {
+        32 => x.set(rd, &Uint::from(value as i32)),
+        64 => x.set(rd, &Uint::from(value)),
+        128 => x.set(rd, &Uint::from(value as i128)),
+        _ => unreachable!(),
+    }
+}
+
+pub fn rounding_mode(csr: &Csr, rm: usize) -> Result<RoundingMode, RvException> {
+    let rm: usize = if rm == 0x7 {
+        match csr.get(FRM) {
+            Some(v) => u8::from(v) as usize,
+            None => return Err(RvException::InstructionIllegal),
+        }
     } else {
-        u64::from(x.get(rs1)) + imm as u64
+        rm
     };
-    let value: Uint = f.get(rs2);
 
-    match width {
-        4 => println!("fsw\t{},{}({})\t# {}", f.name(rs2), imm, x.name(rs1), value),
-        8 => println!("fsd\t{},{}({})\t# {}", f.name(rs2), imm, x.name(rs1), value),
-        16 => println!("fsq\t{},{}({})\t# {}", f.name(rs2), imm, x.name(rs1), value),
+    match RoundingMode::from_rm(rm) {
+        Some(mode) => Ok(mode),
+        None => Err(RvException::InstructionIllegal),
+    }
+}
+
+pub fn raise_flags(csr: &mut Csr, xlen: usize, flags: u8) {
+    if flags == 0 {
+        return;
+    }
+
+    let fflags: u8 = match csr.get(FFLAGS) {
+        Some(v) => u8::from(v),
+        None => 0,
+    };
+
+    csr.set(FFLAGS, Uint::from(fflags | flags).extend(xlen));
+}
+
+pub fn fadd(
+    f: &mut RvFpuRegisters,
+    fmt: FpFormat,
+    rm: RoundingMode,
+    rd: usize,
+    rs1: usize,
+    rs2: usize,
+) -> u8 {
+    let (value, flags) = fpu::add(fmt, rm, get(f, fmt, rs1), get(f, fmt, rs2));
+
+    set(f, fmt, rd, value);
+
+    flags
+}
+
+pub fn fsub(
+    f: &mut RvFpuRegisters,
+    fmt: FpFormat,
+    rm: RoundingMode,
+    rd: usize,
+    rs1: usize,
+    rs2: usize,
+) -> u8 {
+    let (value, flags) = fpu::sub(fmt, rm, get(f, fmt, rs1), get(f, fmt, rs2));
+
+    set(f, fmt, rd, value);
+
+    flags
+}
+
+pub fn fmul(
+    f: &mut RvFpuRegisters,
+    fmt: FpFormat,
+    rm: RoundingMode,
+    rd: usize,
+    rs1: usize,
+    rs2: usize,
+) -> u8 {
+    let (value, flags) = fpu::mul(fmt, rm, get(f, fmt, rs1), get(f, fmt, rs2));
+
+    set(f, fmt, rd, value);
+
+    flags
+}
+
+pub fn fdiv(
+    f: &mut RvFpuRegisters,
+    fmt: FpFormat,
+    rm: RoundingMode,
+    rd: usize,
+    rs1: usize,
+    rs2: usize,
+) -> u8 {
+    let (value, flags) = fpu::div(fmt, rm, get(f, fmt, rs1), get(f, fmt, rs2));
+
+    set(f, fmt, rd, value);
+
+    flags
+}
+
+pub fn fsqrt(f: &mut RvFpuRegisters, fmt: FpFormat, rm: RoundingMode, rd: usize, rs1: usize) -> u8 {
+    let (value, flags) = fpu::sqrt(fmt, rm, get(f, fmt, rs1));
+
+    set(f, fmt, rd, value);
+
+    flags
+}
+
+pub fn fsgnj(
+    f: &mut RvFpuRegisters,
+    fmt: FpFormat,
+    funct3: usize,
+    rd: usize,
+    rs1: usize,
+    rs2: usize,
+) -> Result<u8, RvException> {
+    let sign: u64 = 1u64 << (fmt.width() - 1);
+    let a: u64 = get(f, fmt, rs1);
+    let b: u64 = get(f, fmt, rs2);
+    let value: u64 = match funct3 {
+        0x0 => {
+            (a & !sign) | (b & sign)
+        }
+        0x1 => {
+            (a & !sign) | (!b & sign)
+        }
+        0x2 => {
+            a ^ (b & sign)
+        }
+        _ => return Err(RvException::InstructionIllegal),
+    };
+
+    set(f, fmt, rd, value);
+
+    Ok(0)
+}
+
+pub fn fminmax(
+    f: &mut RvFpuRegisters,
+    fmt: FpFormat,
+    funct3: usize,
+    rd: usize,
+    rs1: usize,
+    rs2: usize,
+) -> Result<u8, RvException> {
+    let a: u64 = get(f, fmt, rs1);
+    let b: u64 = get(f, fmt, rs2);
+    let (value, flags) = match funct3 {
+        0x0 => {
+            fpu::min(fmt, a, b)
+        }
+        0x1 => {
+            fpu::max(fmt, a, b)
+        }
+        _ => return Err(RvException::InstructionIllegal),
+    };
+
+    set(f, fmt, rd, value);
+
+    Ok(flags)
+}
+
+pub fn fcvt_f_f(
+    f: &mut RvFpuRegisters,
+    to: FpFormat,
+    from: FpFormat,
+    rm: RoundingMode,
+    rd: usize,
+    rs1: usize,
+) -> u8 {
+    let (value, flags) = fpu::convert(from, to, rm, get(f, from, rs1));
+
+    set(f, to, rd, value);
+
+    flags
+}
+
+pub fn fcmp<X: Xlen>(
+    x: &mut RvRegisters<X>,
+    f: &RvFpuRegisters,
+    fmt: FpFormat,
+    funct3: usize,
+    rd: usize,
+    rs1: usize,
+    rs2: usize,
+) -> Result<u8, RvException> {
+    let a: u64 = get(f, fmt, rs1);
+    let b: u64 = get(f, fmt, rs2);
+    let (value, flags) = match funct3 {
+        0x0 => {
+            fpu::le(fmt, a, b)
+        }
+        0x1 => {
+            fpu::lt(fmt, a, b)
+        }
+        0x2 => {
+            fpu::eq(fmt, a, b)
+        }
+        _ => return Err(RvException::InstructionIllegal),
+    };
+
+    set_x(x, rd, value as i64);
+
+    Ok(flags)
+}
+
+// fcvt.{w,wu,l,lu}.fmt
+pub fn fcvt_x_f<X: Xlen>(
+    x: &mut RvRegisters<X>,
+    f: &RvFpuRegisters,
+    fmt: FpFormat,
+    rm: RoundingMode,
+    kind: usize,
+    rd: usize,
+    rs1: usize,
+) -> Result<u8, RvException> {
+    let (signed, width): (bool, u32) = match kind {
+        0x0 => (true, 32),
+        0x1 => (false, 32),
+        0x2 if x.len() > 32 => (true, 64),
+        0x3 if x.len() > 32 => (false, 64),
         _ => return Err(RvException::InstructionIllegal),
+    };
+    let (value, flags) = fpu::to_int(fmt, rm, get(f, fmt, rs1), signed, width);
+
+    // 32-bit results are sign-extended, even the unsigned ones
+    if width == 32 {
+        set_x(x, rd, value as i32 as i64);
+    } else {
+        set_x(x, rd, value as i64);
     }
 
-    match bus.store(width, addr, &Vec::<u8>::from(value.clone())) {
-        None => Ok(value),
-        Some(e) => Err(RvException::from(e)),
+    Ok(flags)
+}
+
+// fcvt.fmt.{w,wu,l,lu}
+pub fn fcvt_f_x<X: Xlen>(
+    x: &RvRegisters<X>,
+    f: &mut RvFpuRegisters,
+    fmt: FpFormat,
+    rm: RoundingMode,
+    kind: usize,
+    rd: usize,
+    rs1: usize,
+) -> Result<u8, RvException> {
+    let (signed, width): (bool, u32) = match kind {
+        0x0 => (true, 32),
+        0x1 => (false, 32),
+        0x2 if x.len() > 32 => (true, 64),
+        0x3 if x.len() > 32 => (false, 64),
+        _ => return Err(RvException::InstructionIllegal),
+    };
+    let (value, flags) = fpu::from_int(fmt, rm, u64::from(x.get(rs1)), signed, width);
+
+    set(f, fmt, rd, value);
+
+    Ok(flags)
+}
+
+// fmv.x.fmt and fclass.fmt
+pub fn fmv_x_f<X: Xlen>(
+    x: &mut RvRegisters<X>,
+    f: &RvFpuRegisters,
+    fmt: FpFormat,
+    funct3: usize,
+    rd: usize,
+    rs1: usize,
+) -> Result<u8, RvException> {
+    match funct3 {
+        0x0 => {
+            if fmt.width() > x.len() {
+                return Err(RvException::InstructionIllegal);
+            }
+
+            // Moves the raw bits, without checking the NaN-boxing
+            let raw: u64 = u128::from(f.get(rs1)) as u64;
+            let value: i64 = match fmt {
+                FpFormat::Single => raw as i32 as i64,
+                FpFormat::Double => raw as i64,
+            };
+
+            set_x(x, rd, value);
+        }
+        0x1 => {
+            let value: u64 = fpu::class(fmt, get(f, fmt, rs1));
+
+            set_x(x, rd, value as i64);
+        }
+        _ => return Err(RvException::InstructionIllegal),
     }
+
+    Ok(0)
+}
+
+// fmv.fmt.x
+pub fn fmv_f_x<X: Xlen>(
+    x: &RvRegisters<X>,
+    f: &mut RvFpuRegisters,
+    fmt: FpFormat,
+    rd: usize,
+    rs1: usize,
+) -> Result<u8, RvException> {
+    if fmt.width() > x.len() {
+        return Err(RvException::InstructionIllegal);
+    }
+
+    let value: u64 = u64::from(x.get(rs1));
+
+    set(f, fmt, rd, value);
+
+    Ok(0)
 }
 
+// fmadd, fmsub, fnmsub and fnmadd, selected by the low bits of the opcode
+pub fn fmadd(
+    f: &mut RvFpuRegisters,
+    fmt: FpFormat,
+    rm: RoundingMode,
+    opcode: usize,
+    rd: usize,
+    rs: (usize, usize, usize),
+) -> u8 {
+    let (negate_product, negate_addend): (bool, bool) = match opcode & 0x3 {
+        0x0 => (false, false),
+        0x1 => (false, true),
+        0x2 => (true, false),
+        _ => (true, true),
+    };
+    let (value, flags) = fpu::fma(
+        fmt,
+        rm,
+        (get(f, fmt, rs.0), get(f, fmt, rs.1), get(f, fmt, rs.2)),
+        negate_product,
+        negate_addend,
+    );
+
+    set(f, fmt, rd, value);
+
+    flags
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::vsoc::Vsoc;
+
+    // Run a faulting FP access after a load of 1.0, and return the loaded
+    // value, mcause and mtval
+    fn fault(access: u32) -> (u32, u32, u32) {
+        let arch: String = String::from("rv32if_zicsr");
+        let mut vsoc: Vsoc = Vsoc::new(&arch);
+        // auipc t2, 0; addi t2, t2, 0x20; csrw mtvec, t2; flw ft1, 16(t2)
+        // fmv.x.w a0, ft1; <access>; nop; nop
+        // 0x20: csrr s1, mcause; csrr a2, mtval; j .; nop; 1.0
+        let program: [u32; 13] = [
+            0x0000_0397,
+            0x0203_8393,
+            0x3053_9073,
+            0x0103_a087,
+            0xe000_8553,
+            access,
+            0x0000_0013,
+            0x0000_0013,
+            0x3420_24f3,
+            0x3430_2673,
+            0x0000_006f,
+            0x0000_0013,
+            0x3f80_0000,
+        ];
+        let binary: Vec<u8> = program.iter().flat_map(|i| i.to_le_bytes()).collect();
+
+        vsoc.load(&binary).unwrap();
+        for _ in 0..8 {
+            assert!(vsoc.step().is_none());
+        }
+
+        let x =
+            |reg: usize| u32::from_le_bytes(vsoc.read_register(reg).unwrap().try_into().unwrap());
+
+        (x(10), x(9), x(12))
+    }
+
+    #[test]
+    fn test_wrapping_address() {
+        // flw ft0, -4(zero) and fsw ft1, -4(zero) wrap to the top of the space
+        assert_eq!(fault(0xffc0_2007), (0x3f80_0000, 5, 0xffff_fffc));
+        assert_eq!(fault(0xfe10_2e27), (0x3f80_0000, 7, 0xffff_fffc));
+    }
+
+    #[test]
+    fn test_fs() {
+        let arch: String = String::from("rv32if_zicsr");
+        let mut vsoc: Vsoc = Vsoc::new(&arch);
+        // auipc t2, 0; addi t2, t2, 0x20; csrw mtvec, t2; fmv.w.x ft0, zero
+        // csrr a0, mstatus; lui t0, 6; csrc mstatus, t0; fadd.s ft0, ft0, ft0
+        // 0x20: csrr s1, mcause; csrr a2, mstatus; j .
+        let program: [u32; 11] = [
+            0x0000_0397,
+            0x0203_8393,
+            0x3053_9073,
+            0xf000_0053,
+            0x3000_2573,
+            0x0000_62b7,
+            0x3002_b073,
+            0x0000_7053,
+            0x3420_24f3,
+            0x3000_2673,
+            0x0000_006f,
+        ];
+        let binary: Vec<u8> = program.iter().flat_map(|i| i.to_le_bytes()).collect();
+
+        vsoc.load(&binary).unwrap();
+        for _ in 0..10 {
+            assert!(vsoc.step().is_none());
+        }
+
+        let x =
+            |reg: usize| u32::from_le_bytes(vsoc.read_register(reg).unwrap().try_into().unwrap());
+
+        // Writing an FP register makes FS Dirty, and sets SD
+        assert_eq!(x(10) & 0x8000_6000, 0x8000_6000);
+        // With FS Off the FP instructions are illegal
+        assert_eq!((x(9), x(12) & 0x6000), (2, 0));
+    }
+}
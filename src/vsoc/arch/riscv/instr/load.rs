@@ -1,217 +1,128 @@
 use super::super::registers::RvRegisters;
-use crate::vsoc::{
-    arch::{riscv::exception::RvException, types::Uint},
-    bus::Bus,
-};
+use super::super::xlen::Xlen;
+use crate::vsoc::arch::riscv::{exception::RvException, mmu::AddressSpace};
+
+// Effective address of a load or store, None when an RV128 address is beyond
+// the 64-bit bus
+pub fn address<X: Xlen>(x: &RvRegisters<X>, rs1: usize, imm: i32) -> Option<u64> {
+    let addr: X = x.read(rs1).wrapping_add(X::from_i128(imm as i128));
+
+    match X::BITS {
+        128 => u64::try_from(addr.to_u128()).ok(),
+        _ => Some(addr.to_u64()),
+    }
+}
 
-pub fn lb(
-    x: &mut RvRegisters,
+// Load width bytes into rd, sign-extended when signed and zero-extended
+// otherwise
+fn load<X: Xlen>(
+    x: &mut RvRegisters<X>,
     rd: usize,
     rs1: usize,
     imm: i32,
-    bus: &mut Bus,
-) -> Result<Uint, RvException> {
-    let addr: u64;
-    let value: Uint = if imm < 0 {
-        addr = u64::from(x.get(rs1)) - imm.unsigned_abs() as u64;
-
-        match bus.fetch(1, addr) {
-            Ok(v) => Uint::new(v),
-            Err(e) => return Err(RvException::from(e)),
-        }
-    } else {
-        addr = u64::from(x.get(rs1)) + imm.unsigned_abs() as u64;
-
-        match bus.fetch(1, addr) {
-            Ok(v) => Uint::new(v),
-            Err(e) => return Err(RvException::from(e)),
-        }
-    };
+    mem: &mut AddressSpace,
+    width: usize,
+    signed: bool,
+) -> Result<X, RvException> {
+    let addr: u64 = address(x, rs1, imm).ok_or(RvException::LoadAccessFault)?;
+    let mut value: X = X::from_u128(mem.load(width, addr)?);
+    let shamt: u32 = (X::BITS - width * 8) as u32;
 
-    println!("lb\t{},{}({})\t# @{:0x}={}", x.name(rd), imm, x.name(rs1), addr, value);
+    if signed && shamt > 0 {
+        value = value.shl(shamt).sra(shamt);
+    }
 
-    x.set(rd, &value);
+    x.write(rd, value);
 
     Ok(value)
 }
 
-pub fn lh(
-    x: &mut RvRegisters,
+pub fn lb<X: Xlen>(
+    x: &mut RvRegisters<X>,
     rd: usize,
     rs1: usize,
     imm: i32,
-    bus: &mut Bus,
-) -> Result<Uint, RvException> {
-    let value: Uint = if imm < 0 {
-        let addr: u64 = u64::from(x.get(rs1)) - imm.unsigned_abs() as u64;
-
-        match bus.fetch(2, addr) {
-            Ok(v) => Uint::new(v),
-            Err(e) => return Err(RvException::from(e)),
-        }
-    } else {
-        let addr: u64 = u64::from(x.get(rs1)) + imm.unsigned_abs() as u64;
-
-        match bus.fetch(2, addr) {
-            Ok(v) => Uint::new(v),
-            Err(e) => return Err(RvException::from(e)),
-        }
-    };
-
-    println!("lh\t{},{}({})\t# {}", x.name(rd), imm, x.name(rs1), value);
-
-    x.set(rd, &value);
-
-    Ok(value)
+    mem: &mut AddressSpace,
+) -> Result<X, RvException> {
+    load(x, rd, rs1, imm, mem, 1, true)
 }
 
-pub fn lw(
-    x: &mut RvRegisters,
+pub fn lh<X: Xlen>(
+    x: &mut RvRegisters<X>,
     rd: usize,
     rs1: usize,
     imm: i32,
-    bus: &mut Bus,
-) -> Result<Uint, RvException> {
-    let addr: u64;
-    let value: Uint = if imm < 0 {
-        addr = u64::from(x.get(rs1)) - imm.unsigned_abs() as u64;
-
-        match bus.fetch(4, addr) {
-            Ok(v) => Uint::new(v),
-            Err(e) => return Err(RvException::from(e)),
-        }
-    } else {
-        addr = u64::from(x.get(rs1)) + imm.unsigned_abs() as u64;
-
-        match bus.fetch(4, addr) {
-            Ok(v) => Uint::new(v),
-            Err(e) => return Err(RvException::from(e)),
-        }
-    };
-
-    println!("lw\t{},{}({})\t# @{:0x}={}", x.name(rd), imm, x.name(rs1), addr, value);
-
-    x.set(rd, &value);
-
-    Ok(value)
+    mem: &mut AddressSpace,
+) -> Result<X, RvException> {
+    load(x, rd, rs1, imm, mem, 2, true)
 }
 
-pub fn ld(
-    x: &mut RvRegisters,
+pub fn lw<X: Xlen>(
+    x: &mut RvRegisters<X>,
     rd: usize,
     rs1: usize,
     imm: i32,
-    bus: &mut Bus,
-) -> Result<Uint, RvException> {
-    let value: Uint = if imm < 0 {
-        let addr: u64 = u64::from(x.get(rs1)) - imm.unsigned_abs() as u64;
-
-        match bus.fetch(8, addr) {
-            Ok(v) => Uint::new(v),
-            Err(e) => return Err(RvException::from(e)),
-        }
-    } else {
-        let addr: u64 = u64::from(x.get(rs1)) + imm.unsigned_abs() as u64;
-
-        match bus.fetch(8, addr) {
-            Ok(v) => Uint::new(v),
-            Err(e) => return Err(RvException::from(e)),
-        }
-    };
-
-    println!("ld\t{},{}({})\t# {}", x.name(rd), imm, x.name(rs1), value);
-
-    x.set(rd, &value);
-
-    Ok(value)
+    mem: &mut AddressSpace,
+) -> Result<X, RvException> {
+    load(x, rd, rs1, imm, mem, 4, true)
 }
 
-pub fn lbu(
-    x: &mut RvRegisters,
+pub fn ld<X: Xlen>(
+    x: &mut RvRegisters<X>,
     rd: usize,
     rs1: usize,
     imm: i32,
-    bus: &mut Bus,
-) -> Result<Uint, RvException> {
-    let value: Uint = if imm < 0 {
-        let addr: u64 = u64::from(x.get(rs1)) - imm.unsigned_abs() as u64;
-
-        match bus.fetch(1, addr) {
-            Ok(v) => Uint::new(v),
-            Err(e) => return Err(RvException::from(e)),
-        }
-    } else {
-        let addr: u64 = u64::from(x.get(rs1)) + imm.unsigned_abs() as u64;
-
-        match bus.fetch(1, addr) {
-            Ok(v) => Uint::new(v),
-            Err(e) => return Err(RvException::from(e)),
-        }
-    };
-
-    println!("lbu\t{},{}({})\t# {}", x.name(rd), imm, x.name(rs1), value);
-
-    x.set(rd, &value);
-
-    Ok(value)
+    mem: &mut AddressSpace,
+) -> Result<X, RvException> {
+    load(x, rd, rs1, imm, mem, 8, true)
 }
 
-pub fn lhu(
-    x: &mut RvRegisters,
+pub fn lbu<X: Xlen>(
+    x: &mut RvRegisters<X>,
     rd: usize,
     rs1: usize,
     imm: i32,
-    bus: &mut Bus,
-) -> Result<Uint, RvException> {
-    let value: Uint = if imm < 0 {
-        let addr: u64 = u64::from(x.get(rs1)) - imm.unsigned_abs() as u64;
-
-        match bus.fetch(2, addr) {
-            Ok(v) => Uint::new(v),
-            Err(e) => return Err(RvException::from(e)),
-        }
-    } else {
-        let addr: u64 = u64::from(x.get(rs1)) + imm.unsigned_abs() as u64;
-
-        match bus.fetch(2, addr) {
-            Ok(v) => Uint::new(v),
-            Err(e) => return Err(RvException::from(e)),
-        }
-    };
-
-    println!("lhu\t{},{}({})\t# {}", x.name(rd), imm, x.name(rs1), value);
-
-    x.set(rd, &value);
-
-    Ok(value)
+    mem: &mut AddressSpace,
+) -> Result<X, RvException> {
+    load(x, rd, rs1, imm, mem, 1, false)
 }
 
-pub fn lwu(
-    x: &mut RvRegisters,
+pub fn lhu<X: Xlen>(
+    x: &mut RvRegisters<X>,
     rd: usize,
     rs1: usize,
     imm: i32,
-    bus: &mut Bus,
-) -> Result<Uint, RvException> {
-    let value: Uint = if imm < 0 {
-        let addr: u64 = u64::from(x.get(rs1)) - imm.unsigned_abs() as u64;
-
-        match bus.fetch(4, addr) {
-            Ok(v) => Uint::new(v),
-            Err(e) => return Err(RvException::from(e)),
-        }
-    } else {
-        let addr: u64 = u64::from(x.get(rs1)) + imm.unsigned_abs() as u64;
-
-        match bus.fetch(4, addr) {
-            Ok(v) => Uint::new(v),
-            Err(e) => return Err(RvException::from(e)),
-        }
-    };
+    mem: &mut AddressSpace,
+) -> Result<X, RvException> {
+    load(x, rd, rs1, imm, mem, 2, false)
+}
 
-    println!("lwu\t{},{}({})\t# {}", x.name(rd), imm, x.name(rs1), value);
+pub fn lwu<X: Xlen>(
+    x: &mut RvRegisters<X>,
+    rd: usize,
+    rs1: usize,
+    imm: i32,
+    mem: &mut AddressSpace,
+) -> Result<X, RvException> {
+    load(x, rd, rs1, imm, mem, 4, false)
+}
 
-    x.set(rd, &value);
+pub fn ldu<X: Xlen>(
+    x: &mut RvRegisters<X>,
+    rd: usize,
+    rs1: usize,
+    imm: i32,
+    mem: &mut AddressSpace,
+) -> Result<X, RvException> {
+    load(x, rd, rs1, imm, mem, 8, false)
+}
 
-    Ok(value)
+pub fn lq<X: Xlen>(
+    x: &mut RvRegisters<X>,
+    rd: usize,
+    rs1: usize,
+    imm: i32,
+    mem: &mut AddressSpace,
+) -> Result<X, RvException> {
+    load(x, rd, rs1, imm, mem, 16, true)
 }
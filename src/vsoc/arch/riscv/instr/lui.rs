@@ -1,9 +1,5 @@
-use crate::vsoc::arch::{riscv::registers::RvRegisters, types::Uint};
+use crate::vsoc::arch::riscv::{registers::RvRegisters, xlen::Xlen};
 
-pub fn lui(x: &mut RvRegisters, rd: usize, imm: i32) {
-    print!("lui\t");
-
-    x.set(rd, &Uint::from(imm).sextend(x.len(), 32));
-
-    println!("{},{:#0x}", x.name(rd), imm as u32 >> 12);
+pub fn lui<X: Xlen>(x: &mut RvRegisters<X>, rd: usize, imm: i32) {
+    x.write(rd, X::from_i128(imm as i128));
 }
@@ -1,23 +1,33 @@
 mod amo;
 mod auipc;
+mod bitmanip;
 mod branch;
 mod fp;
 mod load;
 mod lui;
 mod op;
 mod opimm;
+mod rvc;
 mod store;
 mod system;
+mod vector;
+mod vmem;
 
 use super::atomic::AtomicCtx;
-use super::csr::Csr;
+use super::csr::{self, Csr};
 use super::exception;
 use super::ext::RvExtensions;
+use super::fpu::FpFormat;
 use super::hart::Rv;
-use super::registers::{RvRegisters, RvFpuRegisters};
+use super::icache::ICache;
+use super::mmu::{AddressSpace, Mmu};
+use super::privilege::RvPrivilege;
+use super::registers::{RvRegisters, RvFpuRegisters, RvVectorRegisters};
+use super::xlen::Xlen;
 use crate::vsoc::arch::types::Uint;
 use crate::vsoc::bus::Bus;
 
+#[derive(Debug, Clone, Copy)]
 pub enum Instr {
     Invalid,
     InstrC0(u16),
@@ -26,13 +36,73 @@ pub enum Instr {
     Instr32(u32),
 }
 
+// Runs an instruction given its length, and returns the offset to the next one
+type Handler<X> = fn(&Instr, &mut Rv<X>, &mut Bus, i128) -> Result<i128, exception::RvException>;
+
+// An instruction along with the 32-bit form it executes as, None when it is
+// illegal, and its handler, what the decoded-instruction cache holds
+#[derive(Debug, Clone, Copy)]
+pub struct Decoded<X: Xlen> {
+    pub instr: Instr,
+    pub expanded: Option<u32>,
+    handler: Handler<X>,
+}
+
+impl<X: Xlen> Decoded<X> {
+    pub fn len(&self) -> i128 {
+        match self.instr {
+            Instr::Instr32(_) => 4,
+            _ => 2,
+        }
+    }
+
+    pub fn process(&self, hart: &mut Rv<X>, bus: &mut Bus) -> Result<i128, exception::RvException> {
+        let raw: u32 = self.expanded.unwrap_or(0);
+
+        (self.handler)(&Instr::Instr32(raw), hart, bus, self.len())
+    }
+}
+
+fn illegal<X: Xlen>(
+    _: &Instr,
+    _: &mut Rv<X>,
+    _: &mut Bus,
+    _: i128,
+) -> Result<i128, exception::RvException> {
+    Err(exception::RvException::InstructionIllegal)
+}
+
+// Offset to the next instruction of a handler that does not jump
+fn next(e: Option<exception::RvException>, ilen: i128) -> Result<i128, exception::RvException> {
+    match e {
+        None => Ok(ilen),
+        Some(e) => Err(e),
+    }
+}
+
+// Harts without CSRs have no FS to turn the FP state off
+fn fs_enabled(csr: &Option<Csr>) -> bool {
+    csr.as_ref().is_none_or(|c| c.fs_enabled())
+}
+
+fn dirty_fs(csr: &mut Option<Csr>) {
+    if let Some(c) = csr.as_mut() {
+        c.dirty_fs();
+    }
+}
+
+// Without C, jumps and taken branches must land on a 4-byte boundary
+fn misaligned<X: Xlen>(target: X, c: bool) -> bool {
+    !c && target & X::from_u128(0x3) != X::ZERO
+}
+
 impl From<Vec<u8>> for Instr {
     fn from(v: Vec<u8>) -> Self {
-        if v.len() != 4 {
-            return Instr::Invalid;
+        match v.len() {
+            2 => Instr::new(u16::from_le_bytes(v.try_into().unwrap()) as u32),
+            4 => Instr::new(u32::from_le_bytes(v.try_into().unwrap())),
+            _ => Instr::Invalid,
         }
-
-        Instr::Instr32(u32::from_le_bytes(v.try_into().unwrap()))
     }
 }
 
@@ -63,20 +133,48 @@ impl Instr {
         }
     }
 
-    fn load(&self, x: &mut RvRegisters, bus: &mut Bus) -> Option<exception::RvException> {
+    // Compressed instructions are illegal without the C extension
+    pub fn decode<X: Xlen>(self, xlen: usize, c: bool) -> Decoded<X> {
+        let expanded: Option<u32> = match self {
+            Instr::InstrC0(_) | Instr::InstrC1(_) | Instr::InstrC2(_) if !c => None,
+            _ => self.expand(xlen),
+        };
+        let handler: Handler<X> = match expanded {
+            Some(raw) => Instr::Instr32(raw).handler(xlen),
+            None => illegal,
+        };
+
+        Decoded {
+            instr: self,
+            expanded,
+            handler,
+        }
+    }
+
+    // 32-bit equivalent of the instruction, compressed ones being expanded
+    pub fn expand(&self, xlen: usize) -> Option<u32> {
+        match self {
+            Instr::Instr32(i) => Some(*i),
+            Instr::InstrC0(_) | Instr::InstrC1(_) | Instr::InstrC2(_) => rvc::expand(self, xlen),
+            Instr::Invalid => None,
+        }
+    }
+
+    fn load<X: Xlen>(&self, x: &mut RvRegisters<X>, mem: &mut AddressSpace) -> Option<exception::RvException> {
         let funct3: usize = self.get_funct3();
         let rd: usize = self.get_rd();
         let rs1: usize = self.get_rs1();
         let imm: i32 = self.get_i_imm();
 
         let result = match funct3 {
-            0x0 => load::lb(x, rd, rs1, imm, bus),  // load byte
-            0x1 => load::lh(x, rd, rs1, imm, bus),  // load half
-            0x2 => load::lw(x, rd, rs1, imm, bus),  // load word
-            0x3 => load::ld(x, rd, rs1, imm, bus),  // load double
-            0x4 => load::lbu(x, rd, rs1, imm, bus), // load byte unsigned
-            0x5 => load::lhu(x, rd, rs1, imm, bus), // load half unsigned
-            0x6 => load::lwu(x, rd, rs1, imm, bus), // load word unsigned
+            0x0 => load::lb(x, rd, rs1, imm, mem),  // load byte
+            0x1 => load::lh(x, rd, rs1, imm, mem),  // load half
+            0x2 => load::lw(x, rd, rs1, imm, mem),  // load word
+            0x3 if x.len() > 32 => load::ld(x, rd, rs1, imm, mem), // load double
+            0x4 => load::lbu(x, rd, rs1, imm, mem), // load byte unsigned
+            0x5 => load::lhu(x, rd, rs1, imm, mem), // load half unsigned
+            0x6 if x.len() > 32 => load::lwu(x, rd, rs1, imm, mem), // load word unsigned
+            0x7 if x.len() == 128 => load::ldu(x, rd, rs1, imm, mem), // load double unsigned
             _ => return Some(exception::RvException::InstructionIllegal),
         };
 
@@ -86,7 +184,7 @@ impl Instr {
         }
     }
 
-    fn load_fp(&self, x: &mut RvRegisters, f: &mut RvFpuRegisters, extensions: &RvExtensions, bus: &mut Bus) -> Option<exception::RvException> {
+    fn load_fp<X: Xlen>(&self, x: &mut RvRegisters<X>, f: &mut RvFpuRegisters, extensions: &RvExtensions, mem: &mut AddressSpace) -> Option<exception::RvException> {
         let funct3: usize = self.get_funct3();
         let rd: usize = self.get_rd();
         let rs1: usize = self.get_rs1();
@@ -99,17 +197,17 @@ impl Instr {
 
         match funct3 {
             0x2 =>  if extensions.f || extensions.d || extensions.q {
-                result = fp::load(x, f, 4, rd, rs1, imm, bus); // load word
+                result = fp::load(x, f, 4, rd, rs1, imm, mem); // load word
             } else {
                 return Some(exception::RvException::InstructionIllegal);
             },
             0x3 => if extensions.d || extensions.q {
-                result = fp::load(x, f, 8, rd, rs1, imm, bus); // load double
+                result = fp::load(x, f, 8, rd, rs1, imm, mem); // load double
             } else {
                 return Some(exception::RvException::InstructionIllegal);
             },
             0x4 => if extensions.q { 
-                result = fp::load(x, f,  16, rd, rs1, imm, bus); // load quad
+                result = fp::load(x, f,  16, rd, rs1, imm, mem); // load quad
             } else {
                 return Some(exception::RvException::InstructionIllegal);
             },
@@ -122,7 +220,7 @@ impl Instr {
         }
     }
 
-    fn op(&self, ext: &RvExtensions, x: &mut RvRegisters) -> Option<exception::RvException> {
+    fn op<X: Xlen>(&self, ext: &RvExtensions, x: &mut RvRegisters<X>) -> Option<exception::RvException> {
         let funct3: usize = self.get_funct3();
         let funct7: usize = self.get_funct7();
         let rd: usize = self.get_rd();
@@ -189,6 +287,8 @@ impl Instr {
                 }
                 _ => return Some(exception::RvException::InstructionIllegal),
             },
+            0x04 | 0x05 | 0x10 | 0x14 | 0x24 | 0x30 | 0x34 => return self.op_bitmanip(ext, x),
+            0x20 if matches!(funct3, 0x4 | 0x6 | 0x7) => return self.op_bitmanip(ext, x),
             _ => match funct3 {
                 0x0 => match self.get_funct7() {
                     0x00 => op::add(x, rd, rs1, rs2), // add
@@ -215,7 +315,102 @@ impl Instr {
         None
     }
 
-    fn op32(&self, ext: &RvExtensions, x: &mut RvRegisters) -> Option<exception::RvException> {
+    // Zba, Zbb, Zbc and Zbs instructions of the OP and OP-32 opcodes
+    fn op_bitmanip<X: Xlen>(&self, ext: &RvExtensions, x: &mut RvRegisters<X>) -> Option<exception::RvException> {
+        let funct3: usize = self.get_funct3();
+        let rd: usize = self.get_rd();
+        let rs1: usize = self.get_rs1();
+        let rs2: usize = self.get_rs2();
+        let word: bool = self.get_opcode() == 0x0e;
+
+        match (word, self.get_funct7(), funct3) {
+            (false, 0x10, 0x2) if ext.zba => bitmanip::sh_add(x, rd, rs1, rs2, 1), // sh1add
+            (false, 0x10, 0x4) if ext.zba => bitmanip::sh_add(x, rd, rs1, rs2, 2), // sh2add
+            (false, 0x10, 0x6) if ext.zba => bitmanip::sh_add(x, rd, rs1, rs2, 3), // sh3add
+            (true, 0x04, 0x0) if ext.zba => bitmanip::sh_add_uw(x, rd, rs1, rs2, 0), // add.uw
+            (true, 0x10, 0x2) if ext.zba => bitmanip::sh_add_uw(x, rd, rs1, rs2, 1), // sh1add.uw
+            (true, 0x10, 0x4) if ext.zba => bitmanip::sh_add_uw(x, rd, rs1, rs2, 2), // sh2add.uw
+            (true, 0x10, 0x6) if ext.zba => bitmanip::sh_add_uw(x, rd, rs1, rs2, 3), // sh3add.uw
+            (false, 0x20, 0x7) if ext.zbb => bitmanip::andn(x, rd, rs1, rs2),  // andn
+            (false, 0x20, 0x6) if ext.zbb => bitmanip::orn(x, rd, rs1, rs2),   // orn
+            (false, 0x20, 0x4) if ext.zbb => bitmanip::xnor(x, rd, rs1, rs2),  // xnor
+            (false, 0x05, 0x6) if ext.zbb => bitmanip::max(x, rd, rs1, rs2),   // max
+            (false, 0x05, 0x7) if ext.zbb => bitmanip::maxu(x, rd, rs1, rs2),  // maxu
+            (false, 0x05, 0x4) if ext.zbb => bitmanip::min(x, rd, rs1, rs2),   // min
+            (false, 0x05, 0x5) if ext.zbb => bitmanip::minu(x, rd, rs1, rs2),  // minu
+            (false, 0x30, 0x1) if ext.zbb => bitmanip::rol(x, rd, rs1, rs2),   // rol
+            (false, 0x30, 0x5) if ext.zbb => bitmanip::ror(x, rd, rs1, rs2),   // ror
+            (true, 0x30, 0x1) if ext.zbb => bitmanip::rolw(x, rd, rs1, rs2),   // rolw
+            (true, 0x30, 0x5) if ext.zbb => bitmanip::rorw(x, rd, rs1, rs2),   // rorw
+            // zext.h is OP on RV32 and OP-32 on RV64
+            (w, 0x04, 0x4) if ext.zbb && rs2 == 0 && w == (x.len() != 32) => bitmanip::zext_h(x, rd, rs1),
+            (false, 0x05, 0x1) if ext.zbc => bitmanip::clmul(x, rd, rs1, rs2),  // clmul
+            (false, 0x05, 0x2) if ext.zbc => bitmanip::clmulr(x, rd, rs1, rs2), // clmulr
+            (false, 0x05, 0x3) if ext.zbc => bitmanip::clmulh(x, rd, rs1, rs2), // clmulh
+            (false, 0x14, 0x1) if ext.zbs => bitmanip::bset(x, rd, rs1, rs2), // bset
+            (false, 0x24, 0x1) if ext.zbs => bitmanip::bclr(x, rd, rs1, rs2), // bclr
+            (false, 0x34, 0x1) if ext.zbs => bitmanip::binv(x, rd, rs1, rs2), // binv
+            (false, 0x24, 0x5) if ext.zbs => bitmanip::bext(x, rd, rs1, rs2), // bext
+            _ => return Some(exception::RvException::InstructionIllegal),
+        };
+
+        None
+    }
+
+    // Zbb and Zbs instructions of the OP-IMM and OP-IMM-32 opcodes, told
+    // apart from the shifts by imm[11:6]
+    fn opimm_bitmanip<X: Xlen>(&self, ext: &RvExtensions, x: &mut RvRegisters<X>) -> Option<exception::RvException> {
+        let funct3: usize = self.get_funct3();
+        let rd: usize = self.get_rd();
+        let rs1: usize = self.get_rs1();
+        let funct12: usize = self.get_funct12();
+        let shamt: usize = self.get_shamt();
+        let word: bool = self.get_opcode() == 0x06;
+        let rev8: usize = 0x680 | (x.len() - 8);
+        // RV128 shift amounts take imm[6] too, as in opimm
+        let funct6: usize = if x.len() == 128 && !word {
+            funct12 >> 7 << 1
+        } else {
+            funct12 >> 6
+        };
+
+        // The unary ones hold less than xlen in their shamt field, roriw and
+        // slli.uw are checked by their funct6
+        if !word && shamt >= x.len() {
+            return Some(exception::RvException::InstructionIllegal);
+        }
+
+        match (word, funct6, funct3) {
+            (true, 0x02, 0x1) if ext.zba => bitmanip::slli_uw(x, rd, rs1, shamt), // slli.uw
+            (false, 0x18, 0x1) if ext.zbb => match funct12 {
+                0x600 => bitmanip::clz(x, rd, rs1),    // clz
+                0x601 => bitmanip::ctz(x, rd, rs1),    // ctz
+                0x602 => bitmanip::cpop(x, rd, rs1),   // cpop
+                0x604 => bitmanip::sext_b(x, rd, rs1), // sext.b
+                0x605 => bitmanip::sext_h(x, rd, rs1), // sext.h
+                _ => return Some(exception::RvException::InstructionIllegal),
+            },
+            (true, 0x18, 0x1) if ext.zbb => match funct12 {
+                0x600 => bitmanip::clzw(x, rd, rs1),  // clzw
+                0x601 => bitmanip::ctzw(x, rd, rs1),  // ctzw
+                0x602 => bitmanip::cpopw(x, rd, rs1), // cpopw
+                _ => return Some(exception::RvException::InstructionIllegal),
+            },
+            (false, 0x18, 0x5) if ext.zbb => bitmanip::rori(x, rd, rs1, shamt), // rori
+            (true, 0x18, 0x5) if ext.zbb && shamt < 32 => bitmanip::roriw(x, rd, rs1, shamt), // roriw
+            (false, _, 0x5) if ext.zbb && funct12 == 0x287 => bitmanip::orc_b(x, rd, rs1), // orc.b
+            (false, _, 0x5) if ext.zbb && funct12 == rev8 => bitmanip::rev8(x, rd, rs1),   // rev8
+            (false, 0x0a, 0x1) if ext.zbs => bitmanip::bseti(x, rd, rs1, shamt), // bseti
+            (false, 0x12, 0x1) if ext.zbs => bitmanip::bclri(x, rd, rs1, shamt), // bclri
+            (false, 0x1a, 0x1) if ext.zbs => bitmanip::binvi(x, rd, rs1, shamt), // binvi
+            (false, 0x12, 0x5) if ext.zbs => bitmanip::bexti(x, rd, rs1, shamt), // bexti
+            _ => return Some(exception::RvException::InstructionIllegal),
+        };
+
+        None
+    }
+
+    fn op32<X: Xlen>(&self, ext: &RvExtensions, x: &mut RvRegisters<X>) -> Option<exception::RvException> {
         let funct3: usize = self.get_funct3();
         let rd: usize = self.get_rd();
         let rs1: usize = self.get_rs1();
@@ -254,6 +449,7 @@ impl Instr {
                 },
                 _ => return Some(exception::RvException::InstructionIllegal),
             },
+            0x04 | 0x10 | 0x30 => return self.op_bitmanip(ext, x),
             _ => match funct3 {
                 0x0 => match self.get_funct7() {
                     0x00 => op::addw(x, rd, rs1, rs2), // addw
@@ -273,36 +469,42 @@ impl Instr {
         None
     }
 
-    fn mem(&self, zifencei: bool) -> Option<exception::RvException> {
+    fn mem<X: Xlen>(&self, zifencei: bool, icache: &mut ICache<X>) -> Option<exception::RvException> {
         let funct3: usize = self.get_funct3();
         let fm: usize = self.get_imm(31, 28) as usize >> 28;
 
         match funct3 {
-            0x0 => match fm {
-                0x0 => println!("fence"),
-                0x8 => println!("fence.tso"),
-                _ => return Some(exception::RvException::InstructionIllegal),
-            },
-            0x1 => {
-                if zifencei {
-                    println!("fence.i");
-                } else {
-                    return Some(exception::RvException::InstructionIllegal);
-                }
-            }
+            0x0 if fm == 0x0 || fm == 0x8 => (), // fence, fence.tso
+            0x1 if zifencei => icache.flush(),   // fence.i
             _ => return Some(exception::RvException::InstructionIllegal),
         };
 
         None
     }
 
-    fn opimm(&self, x: &mut RvRegisters) -> Option<exception::RvException> {
+    fn opimm<X: Xlen>(&self, ext: &RvExtensions, x: &mut RvRegisters<X>) -> Option<exception::RvException> {
         let funct3: usize = self.get_funct3();
         let funct7: usize = self.get_funct7();
         let rd: usize = self.get_rd();
         let rs1: usize = self.get_rs1();
         let imm: i32 = self.get_i_imm();
         let shamt: usize = self.get_shamt();
+        // RV128 shift amounts take imm[6] too, imm[11:7] tells the shifts apart
+        let funct6: usize = if x.len() == 128 {
+            self.get_funct12() >> 7 << 1
+        } else {
+            self.get_funct12() >> 6
+        };
+
+        match (funct3, funct6) {
+            (0x1, 0x00) | (0x5, 0x00) | (0x5, 0x10) => (),
+            (0x1, _) | (0x5, _) => return self.opimm_bitmanip(ext, x),
+            _ => (),
+        }
+
+        if matches!(funct3, 0x1 | 0x5) && shamt >= x.len() {
+            return Some(exception::RvException::InstructionIllegal);
+        }
 
         match funct3 {
             0x0 => opimm::addi(x, rd, rs1, imm),   // addi
@@ -325,7 +527,7 @@ impl Instr {
         None
     }
 
-    fn opimm32(&self, x: &mut RvRegisters) -> Option<exception::RvException> {
+    fn opimm32<X: Xlen>(&self, ext: &RvExtensions, x: &mut RvRegisters<X>) -> Option<exception::RvException> {
         let funct3: usize = self.get_funct3();
         let funct7: usize = self.get_funct7();
         let rd: usize = self.get_rd();
@@ -337,6 +539,12 @@ impl Instr {
             return Some(exception::RvException::InstructionIllegal);
         }
 
+        match (funct3, funct7) {
+            (0x1, 0x00) | (0x5, 0x00) | (0x5, 0x20) => (),
+            (0x1, _) | (0x5, _) => return self.opimm_bitmanip(ext, x),
+            _ => (),
+        }
+
         match funct3 {
             0x0 => opimm::addiw(x, rd, rs1, imm), // addiw
             0x1 => opimm::slliw(x, rd, rs1, rs2), // slliw
@@ -353,101 +561,93 @@ impl Instr {
         None
     }
 
-    fn auipc(&self, x: &mut RvRegisters, pc: &Uint) -> Option<exception::RvException> {
+    // OP-IMM-64 of RV128, the 64-bit word counterpart of OP-IMM-32
+    fn opimm64<X: Xlen>(&self, x: &mut RvRegisters<X>) -> Option<exception::RvException> {
+        let funct3: usize = self.get_funct3();
         let rd: usize = self.get_rd();
-        let imm: i32 = self.get_u_imm();
+        let rs1: usize = self.get_rs1();
+        let imm: i32 = self.get_i_imm();
+        let shamt: usize = self.get_shamt() & 0x3f;
 
-        auipc::auipc(x, rd, pc, imm);
+        match (funct3, self.get_funct12() >> 6) {
+            (0x0, _) => opimm::addid(x, rd, rs1, imm),     // addid
+            (0x1, 0x00) => opimm::sllid(x, rd, rs1, shamt), // sllid
+            (0x5, 0x00) => opimm::srlid(x, rd, rs1, shamt), // srlid
+            (0x5, 0x10) => opimm::sraid(x, rd, rs1, shamt), // sraid
+            _ => return Some(exception::RvException::InstructionIllegal),
+        };
 
         None
     }
 
-    fn lui(&self, x: &mut RvRegisters) -> Option<exception::RvException> {
+    // OP-64 of RV128, the 64-bit word counterpart of OP-32
+    fn op64<X: Xlen>(&self, ext: &RvExtensions, x: &mut RvRegisters<X>) -> Option<exception::RvException> {
         let rd: usize = self.get_rd();
-        let imm: i32 = self.get_u_imm();
+        let rs1: usize = self.get_rs1();
+        let rs2: usize = self.get_rs2();
 
-        lui::lui(x, rd, imm);
+        match (self.get_funct7(), self.get_funct3()) {
+            (0x00, 0x0) => op::addd(x, rd, rs1, rs2), // addd
+            (0x20, 0x0) => op::subd(x, rd, rs1, rs2), // subd
+            (0x00, 0x1) => op::slld(x, rd, rs1, rs2), // slld
+            (0x00, 0x5) => op::srld(x, rd, rs1, rs2), // srld
+            (0x20, 0x5) => op::srad(x, rd, rs1, rs2), // srad
+            (0x01, 0x0) if ext.m || ext.zmmul => op::muld(x, rd, rs1, rs2), // muld
+            (0x01, 0x4) if ext.m => op::divd(x, rd, rs1, rs2),  // divd
+            (0x01, 0x5) if ext.m => op::divud(x, rd, rs1, rs2), // divud
+            (0x01, 0x6) if ext.m => op::remd(x, rd, rs1, rs2),  // remd
+            (0x01, 0x7) if ext.m => op::remud(x, rd, rs1, rs2), // remud
+            _ => return Some(exception::RvException::InstructionIllegal),
+        };
 
         None
     }
 
-    fn jalr(&self, x: &mut RvRegisters, pc: &Uint) -> Result<i128, exception::RvException> {
+    fn auipc<X: Xlen>(&self, x: &mut RvRegisters<X>, pc: X) -> Option<exception::RvException> {
         let rd: usize = self.get_rd();
-        let rs1: usize = self.get_rs1();
-        let imm: i32 = self.get_i_imm();
-        let v: u128;
-        let offset: i128;
-
-        match x.len() {
-            32 => {
-                let base: i32 = i32::from(x.get(rs1));
-                let value: i32 = i32::from(pc.clone());
+        let imm: i32 = self.get_u_imm();
 
-                v = (base as u128 + 4) & 0xffffffff;
-                x.set(rd, &Uint::from(value + 4));
-                offset = (base - value) as i128 + imm as i128;
-            }
-            64 => {
-                let base: i64 = i64::from(x.get(rs1));
-                let value: i64 = i64::from(pc.clone());
+        auipc::auipc(x, rd, pc, imm);
 
-                v = (base as u128 + 4) & 0xffffffffffffffff;
-                x.set(rd, &Uint::from(value + 4));
-                offset = (base - value) as i128 + imm as i128;
-            }
-            128 => {
-                let base: i128 = i128::from(x.get(rs1));
-                let value: i128 = i128::from(pc.clone());
+        None
+    }
 
-                v = base as u128 + 4;
-                x.set(rd, &Uint::from(value + 4));
-                offset = base - value + imm as i128;
-            }
-            _ => unreachable!(),
-        }
+    fn lui<X: Xlen>(&self, x: &mut RvRegisters<X>) -> Option<exception::RvException> {
+        let rd: usize = self.get_rd();
+        let imm: i32 = self.get_u_imm();
 
-        println!("jalr\t{},{:0x}", x.name(rd), v);
+        lui::lui(x, rd, imm);
 
-        Ok(offset)
+        None
     }
 
-    fn jal(&self, x: &mut RvRegisters, pc: &Uint) -> Result<i128, exception::RvException> {
+    fn jalr<X: Xlen>(&self, x: &mut RvRegisters<X>, pc: X, ilen: i128, c: bool) -> Result<i128, exception::RvException> {
         let rd: usize = self.get_rd();
-        let imm: i32 = self.get_j_imm();
-        let v: u128;
+        let target: X = X::from_u128(self.jump_target(x, pc));
 
-        match x.len() {
-            32 => {
-                let value: u32 = u32::from(pc.clone()) + 4;
+        if misaligned(target, c) {
+            return Err(exception::RvException::InstructionAddressMisaligned);
+        }
 
-                v = value as u128;
-                x.set(rd, &Uint::from(value));
-            }
-            64 => {
-                let value: u64 = u64::from(pc.clone()) + 4;
+        x.write(rd, pc.wrapping_add(X::from_i128(ilen)));
 
-                v = value as u128;
-                x.set(rd, &Uint::from(value));
-            }
-            128 => {
-                let value: u128 = u128::from(pc.clone()) + 4;
+        Ok(target.wrapping_sub(pc).to_i128())
+    }
 
-                v = value;
-                x.set(rd, &Uint::from(value));
-            }
-            _ => unreachable!(),
-        };
+    fn jal<X: Xlen>(&self, x: &mut RvRegisters<X>, pc: X, ilen: i128, c: bool) -> Result<i128, exception::RvException> {
+        let rd: usize = self.get_rd();
+        let imm: i32 = self.get_j_imm();
 
-        if rd == 0 {
-            println!("j\t{:0x}", v);
-        } else {
-            println!("jal\t{},{:0x}", x.name(rd), v);
+        if misaligned(pc.wrapping_add(X::from_i128(imm as i128)), c) {
+            return Err(exception::RvException::InstructionAddressMisaligned);
         }
 
+        x.write(rd, pc.wrapping_add(X::from_i128(ilen)));
+
         Ok(imm as i128)
     }
 
-    fn branch(&self, x: &mut RvRegisters, pc: &Uint) -> Result<i128, exception::RvException> {
+    fn branch<X: Xlen>(&self, x: &mut RvRegisters<X>, pc: X, ilen: i128, c: bool) -> Result<i128, exception::RvException> {
         let funct3: usize = self.get_funct3();
         let rs1: usize = self.get_rs1();
         let rs2: usize = self.get_rs2();
@@ -462,43 +662,29 @@ impl Instr {
             _ => return Err(exception::RvException::InstructionIllegal),
         };
 
-        match x.len() {
-            32 => {
-                let pcvalue: i32 = i32::from(pc.clone()) + offset;
-
-                println!("{:0x}\t# {} {}", pcvalue, x.get(rs1), x.get(rs2))
-            }
-            64 => {
-                let pcvalue: i64 = i64::from(pc.clone()) + offset as i64;
-
-                println!("{:0x}\t# {} {}", pcvalue, x.get(rs1), x.get(rs2))
-            }
-            128 => {
-                let pcvalue: i128 = i128::from(pc.clone()) + offset as i128;
-
-                println!("{:0x}\t# {} {}", pcvalue, x.get(rs1), x.get(rs2))
-            }
-            _ => return Err(exception::RvException::InstructionIllegal),
+        if branched && misaligned(pc.wrapping_add(X::from_i128(offset as i128)), c) {
+            return Err(exception::RvException::InstructionAddressMisaligned);
         }
 
         if branched {
             Ok(offset as i128)
         } else {
-            Ok(4)
+            Ok(ilen)
         }
     }
 
-    fn store(&self, x: &mut RvRegisters, bus: &mut Bus) -> Option<exception::RvException> {
+    fn store<X: Xlen>(&self, x: &mut RvRegisters<X>, mem: &mut AddressSpace) -> Option<exception::RvException> {
         let funct3: usize = self.get_funct3();
         let rs1: usize = self.get_rs1();
         let rs2: usize = self.get_rs2();
         let imm: i32 = self.get_s_imm();
 
         let result = match funct3 {
-            0x0 => store::sb(x, rs1, rs2, imm, bus), // store byte
-            0x1 => store::sh(x, rs1, rs2, imm, bus), // store half
-            0x2 => store::sw(x, rs1, rs2, imm, bus), // store word
-            0x3 => store::sd(x, rs1, rs2, imm, bus), // store double
+            0x0 => store::sb(x, rs1, rs2, imm, mem), // store byte
+            0x1 => store::sh(x, rs1, rs2, imm, mem), // store half
+            0x2 => store::sw(x, rs1, rs2, imm, mem), // store word
+            0x3 if x.len() > 32 => store::sd(x, rs1, rs2, imm, mem), // store double
+            0x4 if x.len() == 128 => store::sq(x, rs1, rs2, imm, mem), // store quad
             _ => return Some(exception::RvException::InstructionIllegal),
         };
 
@@ -508,7 +694,7 @@ impl Instr {
         }
     }
 
-    fn store_fp(&self, x: &mut RvRegisters, f: &mut RvFpuRegisters, extensions: &RvExtensions, bus: &mut Bus) -> Option<exception::RvException> {
+    fn store_fp<X: Xlen>(&self, x: &mut RvRegisters<X>, f: &mut RvFpuRegisters, extensions: &RvExtensions, mem: &mut AddressSpace) -> Option<exception::RvException> {
         let funct3: usize = self.get_funct3();
         let rs1: usize = self.get_rs1();
         let rs2: usize = self.get_rs2();
@@ -517,17 +703,17 @@ impl Instr {
 
         match funct3 {
             0x2 => if extensions.f || extensions.d || extensions.q {
-                result = fp::store(x, f, 4, rs1, rs2, imm, bus); // store word
+                result = fp::store(x, f, 4, rs1, rs2, imm, mem); // store word
             } else {
                 return Some(exception::RvException::InstructionIllegal);
             },
             0x3 => if extensions.d || extensions.q {
-                result = fp::store(x, f, 8, rs1, rs2, imm, bus); // store double
+                result = fp::store(x, f, 8, rs1, rs2, imm, mem); // store double
             } else {
                 return Some(exception::RvException::InstructionIllegal);
             },
             0x4 => if extensions.q { 
-                result = fp::store(x, f, 16, rs1, rs2, imm, bus); // store quad
+                result = fp::store(x, f, 16, rs1, rs2, imm, mem); // store quad
             } else {
                 return Some(exception::RvException::InstructionIllegal);
             },
@@ -540,7 +726,7 @@ impl Instr {
         }
     }
 
-    fn amo(&self, x: &mut RvRegisters, atomic_ctx: &mut AtomicCtx, extensions: &RvExtensions, bus: &mut Bus) -> Option<exception::RvException> {
+    fn amo<X: Xlen>(&self, x: &mut RvRegisters<X>, atomic_ctx: &mut AtomicCtx, extensions: &RvExtensions, mem: &mut AddressSpace) -> Option<exception::RvException> {
         let funct3: usize = self.get_funct3();
         let rd: usize = self.get_rd();
         let rs1: usize = self.get_rs1();
@@ -556,27 +742,27 @@ impl Instr {
 
         match funct7 >> 2 {
             0x00 => if extensions.zamo {
-                result = amo::add(aq, rl, funct3, x, rd, rs1, rs2, bus);
+                result = amo::add(aq, rl, funct3, x, rd, rs1, rs2, mem);
             } else {
                 return Some(exception::RvException::InstructionIllegal);
             },
             0x01 => if extensions.zamo {
-                result = amo::swap(aq, rl, funct3, x, rd, rs1, rs2, bus);
+                result = amo::swap(aq, rl, funct3, x, rd, rs1, rs2, mem);
             } else {
                 return Some(exception::RvException::InstructionIllegal);
             },
             0x02 => if extensions.zalrsc {
-                result = amo::lr(atomic_ctx, aq, rl, funct3, x, rd, rs1, rs2, bus);
+                result = amo::lr(atomic_ctx, aq, rl, funct3, x, rd, rs1, rs2, mem);
             } else {
                 return Some(exception::RvException::InstructionIllegal);
             },
             0x03 => if extensions.zalrsc {
-                result = amo::sc(atomic_ctx, aq, rl, funct3, x, rd, rs1, rs2, bus);
+                result = amo::sc(atomic_ctx, aq, rl, funct3, x, rd, rs1, rs2, mem);
             } else {
                 return Some(exception::RvException::InstructionIllegal);
             },
             0x04 =>  if extensions.zamo {
-                result = amo::xor(aq, rl, funct3, x, rd, rs1, rs2, bus);
+                result = amo::xor(aq, rl, funct3, x, rd, rs1, rs2, mem);
             } else {
                 return Some(exception::RvException::InstructionIllegal);
             },
@@ -584,37 +770,37 @@ impl Instr {
                 if funct3 == 0x4 && x.len() < 64 {
                     return Some(exception::RvException::InstructionIllegal);
                 }
-                result = amo::cas(aq, rl, funct3, x, rd, rs1, rs2, bus);
+                result = amo::cas(aq, rl, funct3, x, rd, rs1, rs2, mem);
             } else {
                 return Some(exception::RvException::InstructionIllegal);
             },
             0x08 =>  if extensions.zamo {
-                result = amo::or(aq, rl, funct3, x, rd, rs1, rs2, bus);
+                result = amo::or(aq, rl, funct3, x, rd, rs1, rs2, mem);
             } else {
                 return Some(exception::RvException::InstructionIllegal);
             },
             0x0c =>  if extensions.zamo {
-                result = amo::and(aq, rl, funct3, x, rd, rs1, rs2, bus);
+                result = amo::and(aq, rl, funct3, x, rd, rs1, rs2, mem);
             } else {
                 return Some(exception::RvException::InstructionIllegal);
             },
             0x10 =>  if extensions.zamo {
-                result = amo::min(aq, rl, funct3, x, rd, rs1, rs2, bus);
+                result = amo::min(aq, rl, funct3, x, rd, rs1, rs2, mem);
             } else {
                 return Some(exception::RvException::InstructionIllegal);
             },
             0x14 =>  if extensions.zamo {
-                result = amo::max(aq, rl, funct3, x, rd, rs1, rs2, bus);
+                result = amo::max(aq, rl, funct3, x, rd, rs1, rs2, mem);
             } else {
                 return Some(exception::RvException::InstructionIllegal);
             },
             0x18 =>  if extensions.zamo {
-                result = amo::minu(aq, rl, funct3, x, rd, rs1, rs2, bus);
+                result = amo::minu(aq, rl, funct3, x, rd, rs1, rs2, mem);
             } else {
                 return Some(exception::RvException::InstructionIllegal);
             },
             0x1c =>  if extensions.zamo {
-                result = amo::maxu(aq, rl, funct3, x, rd, rs1, rs2, bus);
+                result = amo::maxu(aq, rl, funct3, x, rd, rs1, rs2, mem);
             } else {
                 return Some(exception::RvException::InstructionIllegal);
             },
@@ -624,227 +810,495 @@ impl Instr {
         result
     }
 
-    fn system(
+    fn fp_format(&self, fmt: usize, extensions: &RvExtensions) -> Result<FpFormat, exception::RvException> {
+        match FpFormat::from_fmt(fmt) {
+            Some(FpFormat::Single) if extensions.f => Ok(FpFormat::Single),
+            Some(FpFormat::Double) if extensions.d => Ok(FpFormat::Double),
+            _ => Err(exception::RvException::InstructionIllegal),
+        }
+    }
+
+    fn op_fp<X: Xlen>(
         &self,
-        x: &mut RvRegisters,
-        csr: &mut Option<Csr>,
+        x: &mut RvRegisters<X>,
+        f: &mut RvFpuRegisters,
+        csr: &mut Csr,
+        extensions: &RvExtensions,
+    ) -> Option<exception::RvException> {
+        let funct3: usize = self.get_funct3();
+        let funct7: usize = self.get_funct7();
+        let rd: usize = self.get_rd();
+        let rs1: usize = self.get_rs1();
+        let rs2: usize = self.get_rs2();
+        let fmt: FpFormat = match self.fp_format(funct7 & 0x3, extensions) {
+            Ok(fmt) => fmt,
+            Err(e) => return Some(e),
+        };
+
+        let result: Result<u8, exception::RvException> = match funct7 >> 2 {
+            0x00 => fp::rounding_mode(csr, funct3).map(|rm| fp::fadd(f, fmt, rm, rd, rs1, rs2)),
+            0x01 => fp::rounding_mode(csr, funct3).map(|rm| fp::fsub(f, fmt, rm, rd, rs1, rs2)),
+            0x02 => fp::rounding_mode(csr, funct3).map(|rm| fp::fmul(f, fmt, rm, rd, rs1, rs2)),
+            0x03 => fp::rounding_mode(csr, funct3).map(|rm| fp::fdiv(f, fmt, rm, rd, rs1, rs2)),
+            0x0b => if rs2 == 0 {
+                fp::rounding_mode(csr, funct3).map(|rm| fp::fsqrt(f, fmt, rm, rd, rs1))
+            } else {
+                Err(exception::RvException::InstructionIllegal)
+            },
+            0x04 => fp::fsgnj(f, fmt, funct3, rd, rs1, rs2),
+            0x05 => fp::fminmax(f, fmt, funct3, rd, rs1, rs2),
+            0x08 => match self.fp_format(rs2, extensions) {
+                Ok(from) if from != fmt => {
+                    fp::rounding_mode(csr, funct3).map(|rm| fp::fcvt_f_f(f, fmt, from, rm, rd, rs1))
+                }
+                _ => Err(exception::RvException::InstructionIllegal),
+            },
+            0x14 => fp::fcmp(x, f, fmt, funct3, rd, rs1, rs2),
+            0x18 => match fp::rounding_mode(csr, funct3) {
+                Ok(rm) => fp::fcvt_x_f(x, f, fmt, rm, rs2, rd, rs1),
+                Err(e) => Err(e),
+            },
+            0x1a => match fp::rounding_mode(csr, funct3) {
+                Ok(rm) => fp::fcvt_f_x(x, f, fmt, rm, rs2, rd, rs1),
+                Err(e) => Err(e),
+            },
+            0x1c if rs2 == 0 => fp::fmv_x_f(x, f, fmt, funct3, rd, rs1),
+            0x1e if rs2 == 0 && funct3 == 0 => fp::fmv_f_x(x, f, fmt, rd, rs1),
+            _ => Err(exception::RvException::InstructionIllegal),
+        };
+
+        match result {
+            Ok(flags) => {
+                fp::raise_flags(csr, x.len(), flags);
+                None
+            }
+            Err(e) => Some(e),
+        }
+    }
+
+    fn fmadd<X: Xlen>(
+        &self,
+        x: &RvRegisters<X>,
+        f: &mut RvFpuRegisters,
+        csr: &mut Csr,
+        extensions: &RvExtensions,
     ) -> Option<exception::RvException> {
+        let funct3: usize = self.get_funct3();
+        let rd: usize = self.get_rd();
+        let rs1: usize = self.get_rs1();
+        let rs2: usize = self.get_rs2();
+        let rs3: usize = self.get_funct5();
+        let fmt: FpFormat = match self.fp_format(self.get_funct7() & 0x3, extensions) {
+            Ok(fmt) => fmt,
+            Err(e) => return Some(e),
+        };
+
+        match fp::rounding_mode(csr, funct3) {
+            Ok(rm) => {
+                let flags: u8 = fp::fmadd(f, fmt, rm, self.get_opcode(), rd, (rs1, rs2, rs3));
+
+                fp::raise_flags(csr, x.len(), flags);
+                None
+            }
+            Err(e) => Some(e),
+        }
+    }
+
+    // OP-V: vector arithmetic and the vsetvl family
+    fn op_v<X: Xlen>(&self, x: &mut RvRegisters<X>, v: &mut RvVectorRegisters, csr: &mut Csr) -> Option<exception::RvException> {
+        let raw: u32 = self.get_raw();
+        let rd: usize = self.get_rd();
+        let rs1: usize = self.get_rs1();
+
+        if self.get_funct3() != 0x7 {
+            let f: vector::Fields = vector::Fields {
+                funct6: self.get_funct6(),
+                funct3: self.get_funct3(),
+                vm: raw & (1 << 25) != 0,
+                vd: rd,
+                rs1,
+                vs2: self.get_rs2(),
+            };
+
+            return vector::op(v, csr, x, &f);
+        }
+
+        match raw >> 30 {
+            0x0 | 0x1 => vector::vsetvl(x, csr, v.len(), rd, rs1, None, (raw >> 20 & 0x7ff) as u128), // vsetvli
+            0x3 => vector::vsetvl(x, csr, v.len(), rd, rs1, Some(rs1 as u64), (raw >> 20 & 0x3ff) as u128), // vsetivli
+            _ if self.get_funct7() == 0x40 => {
+                // vsetvl
+                let vtype: u128 = u128::from(x.get(self.get_rs2()));
+                vector::vsetvl(x, csr, v.len(), rd, rs1, None, vtype)
+            }
+            _ => Some(exception::RvException::InstructionIllegal),
+        }
+    }
+
+    // Vector loads and stores, in the LOAD-FP and STORE-FP opcodes
+    fn vector_mem<X: Xlen>(&self, x: &RvRegisters<X>, v: &mut RvVectorRegisters, csr: &Csr, mem: &mut AddressSpace, store: bool) -> vmem::Outcome {
+        let raw: u32 = self.get_raw();
+        let f: vmem::Fields = vmem::Fields {
+            nf: (raw >> 29) as usize,
+            mew: raw & (1 << 28) != 0,
+            mop: (raw >> 26 & 0x3) as usize,
+            vm: raw & (1 << 25) != 0,
+            rs2: self.get_rs2(),
+            rs1: self.get_rs1(),
+            eew: vmem::eew(self.get_funct3()).unwrap(),
+            vd: self.get_rd(),
+        };
+
+        vmem::access(v, csr, x, mem, &f, store)
+    }
+
+    fn system<X: Xlen>(
+        &self,
+        x: &mut RvRegisters<X>,
+        pc: X,
+        csr: &mut Option<Csr>,
+        privilege: &mut RvPrivilege,
+        mmu: &mut Mmu,
+        ilen: i128,
+    ) -> Result<i128, exception::RvException> {
         let funct3: usize = self.get_funct3();
         let rd: usize = self.get_rd();
         let rs1: usize = self.get_rs1();
         let funct12: usize = self.get_funct12();
 
-        match funct3 {
-            0x0 => match rs1 {
-                0x0 => system::ecall(x),  // ecall
-                0x1 => system::ebreak(x), // ebreak
-                0x2 => {
+        let result: Option<exception::RvException> = match funct3 {
+            0x0 if rd != 0 || (rs1 != 0 && !matches!(funct12 >> 5, 0x09 | 0x11 | 0x31)) => Some(exception::RvException::InstructionIllegal),
+            0x0 => match funct12 {
+                0x000 => system::ecall(x, *privilege, csr), // ecall
+                0x001 => system::ebreak(x),                 // ebreak
+                0x105 => system::wfi(*privilege, csr),      // wfi
+                _ if funct12 >> 5 == 0x09 => system::sfence_vma(x, rs1, self.get_rs2(), *privilege, csr, mmu),
+                _ if funct12 >> 5 == 0x11 => system::hfence(false, *privilege, csr, mmu), // hfence.vvma
+                _ if funct12 >> 5 == 0x31 => system::hfence(true, *privilege, csr, mmu),  // hfence.gvma
+                _ => {
                     if let Some(c) = csr {
-                        system::xret(x, rd, rs1, funct12, c) // xret: sret, mret, mnret
-                    } else {
-                        Some(exception::RvException::InstructionIllegal)
+                        return system::xret(pc, funct12, c, privilege); // xret: sret, mret
                     }
+
+                    Some(exception::RvException::InstructionIllegal)
                 }
-                _ => Some(exception::RvException::InstructionIllegal),
             },
             0x1 | 0x5 => {
                 if let Some(c) = csr {
-                    system::csrrw(x, rd, rs1, funct3, funct12, c) // csrrw, csrrwi
+                    system::csrrw(x, rd, rs1, funct3, funct12, c, *privilege) // csrrw, csrrwi
                 } else {
                     Some(exception::RvException::InstructionIllegal)
                 }
             }
             0x2 | 0x6 => {
                 if let Some(c) = csr {
-                    system::csrrs(x, rd, rs1, funct3, funct12, c) // csrrs, csrrsi
+                    system::csrrs(x, rd, rs1, funct3, funct12, c, *privilege) // csrrs, csrrsi
                 } else {
                     Some(exception::RvException::InstructionIllegal)
                 }
             }
             0x3 | 0x7 => {
                 if let Some(c) = csr {
-                    system::csrrc(x, rd, rs1, funct3, funct12, c) // csrrc, csrrci
+                    system::csrrc(x, rd, rs1, funct3, funct12, c, *privilege) // csrrc, csrrci
                 } else {
                     Some(exception::RvException::InstructionIllegal)
                 }
             }
             _ => Some(exception::RvException::InstructionIllegal),
+        };
+
+        match result {
+            None => Ok(ilen),
+            Some(e) => Err(e),
         }
     }
 
-    fn process_32(&self, hart: &mut Rv, bus: &mut Bus) -> Result<i128, exception::RvException> {
-        let mut offset: i128 = 4;
+    // Handler of the instruction, selected once when it is decoded so that
+    // running it from the cache skips the dispatch on its opcode
+    fn handler<X: Xlen>(&self, xlen: usize) -> Handler<X> {
         match self.get_opcode() {
-            0x00 => match self.load(&mut hart.x, bus) {
-                None => (),
-                Some(e) => {
-                    println!("<error>");
-                    return Err(e);
-                }
+            0x00 => |i, hart, bus, ilen| {
+                let xlen: usize = hart.x.len();
+                let mut mem =
+                    AddressSpace::new(bus, &mut hart.mmu, hart.csr.as_ref(), hart.privilege, xlen);
+                next(i.load(&mut hart.x, &mut mem), ilen)
             },
-            0x01 => {
-                if hart.f.is_none() {
-                    return Err(exception::RvException::InstructionIllegal);
-                }
-                match self.load_fp(&mut hart.x, hart.f.as_mut().unwrap(), &hart.extensions, bus) {
-                    None => (),
-                    Some(e) => {
-                        println!("<error>");
-                        return Err(e);
+            0x01 if vmem::eew(self.get_funct3()).is_some() => {
+                |i, hart, bus, ilen| match (hart.v.as_mut(), hart.csr.as_mut()) {
+                    (Some(v), Some(c)) if c.vs_enabled() => {
+                        let mut mem = AddressSpace::new(
+                            bus,
+                            &mut hart.mmu,
+                            Some(&*c),
+                            hart.privilege,
+                            hart.x.len(),
+                        );
+                        let outcome = i.vector_mem(&hart.x, v, c, &mut mem, false);
+
+                        if let Some(e) = vmem::commit(c, outcome) {
+                            return Err(e);
+                        }
+                        c.dirty_vs();
+                        Ok(ilen)
                     }
+                    _ => Err(exception::RvException::InstructionIllegal),
                 }
-            },
-            //            0x02 => rc = self.custom_0(),
-            0x03 => match self.mem(hart.extensions.zifencei) {
-                None => (),
-                Some(e) => {
-                    println!("<error>");
-                    return Err(e);
-                }
-            },
-            0x04 => match self.opimm(&mut hart.x) {
-                None => (),
-                Some(e) => {
-                    println!("<error>");
-                    return Err(e);
+            }
+            0x01 => |i, hart, bus, ilen| {
+                if hart.f.is_none() || !fs_enabled(&hart.csr) {
+                    return Err(exception::RvException::InstructionIllegal);
                 }
-            },
-            0x05 => match self.auipc(&mut hart.x, &hart.pc) {
-                None => (),
-                Some(e) => {
-                    println!("<error>");
-                    return Err(e);
+                let mut mem = AddressSpace::new(
+                    bus,
+                    &mut hart.mmu,
+                    hart.csr.as_ref(),
+                    hart.privilege,
+                    hart.x.len(),
+                );
+                match i.load_fp(
+                    &mut hart.x,
+                    hart.f.as_mut().unwrap(),
+                    &hart.extensions,
+                    &mut mem,
+                ) {
+                    None => dirty_fs(&mut hart.csr),
+                    Some(e) => return Err(e),
                 }
+                Ok(ilen)
             },
-            0x06 => match self.opimm32(&mut hart.x) {
-                None => (),
-                Some(e) => {
-                    println!("<error>");
-                    return Err(e);
-                }
+            //            0x02 => rc = self.custom_0(),
+            // lq takes the MISC-MEM opcode
+            0x03 if self.get_funct3() == 0x2 && xlen == 128 => |i, hart, bus, ilen| {
+                let mut mem = AddressSpace::new(
+                    bus,
+                    &mut hart.mmu,
+                    hart.csr.as_ref(),
+                    hart.privilege,
+                    hart.x.len(),
+                );
+                load::lq(
+                    &mut hart.x,
+                    i.get_rd(),
+                    i.get_rs1(),
+                    i.get_i_imm(),
+                    &mut mem,
+                )?;
+                Ok(ilen)
             },
+            0x03 => {
+                |i, hart, _, ilen| next(i.mem(hart.extensions.zifencei, &mut hart.icache), ilen)
+            }
+            0x04 => |i, hart, _, ilen| next(i.opimm(&hart.extensions, &mut hart.x), ilen),
+            0x05 => |i, hart, _, ilen| next(i.auipc(&mut hart.x, hart.pc), ilen),
+            0x06 => |i, hart, _, ilen| next(i.opimm32(&hart.extensions, &mut hart.x), ilen),
             //
-            0x08 => match self.store(&mut hart.x, bus) {
-                None => (),
-                Some(e) => {
-                    println!("<error>");
-                    return Err(e);
-                }
+            0x08 => |i, hart, bus, ilen| {
+                let xlen: usize = hart.x.len();
+                let mut mem =
+                    AddressSpace::new(bus, &mut hart.mmu, hart.csr.as_ref(), hart.privilege, xlen);
+                next(i.store(&mut hart.x, &mut mem), ilen)
             },
-            0x09 => {
-                if hart.f.is_none() {
-                    return Err(exception::RvException::InstructionIllegal);
-                }
-                match self.store_fp(&mut hart.x, hart.f.as_mut().unwrap(), &hart.extensions, bus) {
-                    None => (),
-                    Some(e) => {
-                        println!("<error>");
-                        return Err(e);
+            0x09 if vmem::eew(self.get_funct3()).is_some() => {
+                |i, hart, bus, ilen| match (hart.v.as_mut(), hart.csr.as_mut()) {
+                    (Some(v), Some(c)) if c.vs_enabled() => {
+                        let mut mem = AddressSpace::new(
+                            bus,
+                            &mut hart.mmu,
+                            Some(&*c),
+                            hart.privilege,
+                            hart.x.len(),
+                        );
+                        let outcome = i.vector_mem(&hart.x, v, c, &mut mem, true);
+
+                        match vmem::commit(c, outcome) {
+                            None => Ok(ilen),
+                            Some(e) => Err(e),
+                        }
                     }
+                    _ => Err(exception::RvException::InstructionIllegal),
+                }
+            }
+            0x09 => |i, hart, bus, ilen| {
+                if hart.f.is_none() || !fs_enabled(&hart.csr) {
+                    return Err(exception::RvException::InstructionIllegal);
                 }
+                let mut mem = AddressSpace::new(
+                    bus,
+                    &mut hart.mmu,
+                    hart.csr.as_ref(),
+                    hart.privilege,
+                    hart.x.len(),
+                );
+                next(
+                    i.store_fp(
+                        &mut hart.x,
+                        hart.f.as_mut().unwrap(),
+                        &hart.extensions,
+                        &mut mem,
+                    ),
+                    ilen,
+                )
             },
             //            0x0a => rc = self.custom_1(),
-            0x0b => {
+            0x0b => |i, hart, bus, ilen| {
                 if hart.atomic_ctx.is_none() {
                     return Err(exception::RvException::InstructionIllegal);
                 }
-                match self.amo(&mut hart.x, hart.atomic_ctx.as_mut().unwrap(), &hart.extensions, bus) {
-                    None => (),
-                    Some(e) => {
-                        println!("<error>");
-                        return Err(e);
-                    }
-                }
-            },
-            0x0c => match self.op(&hart.extensions, &mut hart.x) {
-                None => (),
-                Some(e) => {
-                    println!("<error>");
-                    return Err(e);
-                }
-            },
-            0x0d => match self.lui(&mut hart.x) {
-                None => (),
-                Some(e) => {
-                    println!("<error>");
-                    return Err(e);
-                }
-            },
-            0x0e => match self.op32(&hart.extensions, &mut hart.x) {
-                None => (),
-                Some(e) => {
-                    println!("<error>");
-                    return Err(e);
+                let mut mem = AddressSpace::new(
+                    bus,
+                    &mut hart.mmu,
+                    hart.csr.as_ref(),
+                    hart.privilege,
+                    hart.x.len(),
+                );
+                if i.get_funct7() >> 2 != 0x02 {
+                    mem = mem.amo();
                 }
+                next(
+                    i.amo(
+                        &mut hart.x,
+                        hart.atomic_ctx.as_mut().unwrap(),
+                        &hart.extensions,
+                        &mut mem,
+                    ),
+                    ilen,
+                )
             },
+            0x0c => |i, hart, _, ilen| next(i.op(&hart.extensions, &mut hart.x), ilen),
+            0x0d => |i, hart, _, ilen| next(i.lui(&mut hart.x), ilen),
+            0x0e => |i, hart, _, ilen| next(i.op32(&hart.extensions, &mut hart.x), ilen),
             //
-            //            0x10 => rc = self.madd(),
-            //            0x11 => rc = self.msub(),
-            //            0x12 => rc = self.nmsub(),
-            //            0x13 => rc = self.nmadd(),
-            //            0x14 => rc = self.op_fp(),
-            //            0x16 => rc = self.custom_2(),
-            //
-            0x18 => match self.branch(&mut hart.x, &hart.pc) {
-                Ok(o) => match hart.x.len() {
-                    32 | 64 | 128 => offset = o,
-                    _ => return Err(exception::RvException::InstructionIllegal),
-                },
-                Err(e) => {
-                    println!("<error>");
-                    return Err(e);
+            0x10..=0x13 => |i, hart, _, ilen| match (hart.f.as_mut(), hart.csr.as_mut()) {
+                (Some(f), Some(c)) if c.fs_enabled() => {
+                    match i.fmadd(&hart.x, f, c, &hart.extensions) {
+                        None => {
+                            c.dirty_fs();
+                            Ok(ilen)
+                        }
+                        Some(e) => Err(e),
+                    }
                 }
+                _ => Err(exception::RvException::InstructionIllegal),
             },
-            0x19 => match self.jalr(&mut hart.x, &hart.pc) {
-                Ok(o) => match hart.x.len() {
-                    32 | 64 | 128 => offset = o,
-                    _ => return Err(exception::RvException::InstructionIllegal),
-                },
-                Err(e) => {
-                    println!("<error>");
-                    return Err(e);
+            0x14 => |i, hart, _, ilen| match (hart.f.as_mut(), hart.csr.as_mut()) {
+                (Some(f), Some(c)) if c.fs_enabled() => {
+                    match i.op_fp(&mut hart.x, f, c, &hart.extensions) {
+                        None => {
+                            c.dirty_fs();
+                            Ok(ilen)
+                        }
+                        Some(e) => Err(e),
+                    }
                 }
+                _ => Err(exception::RvException::InstructionIllegal),
             },
-            0x1b => match self.jal(&mut hart.x, &hart.pc) {
-                Ok(o) => match hart.x.len() {
-                    32 | 64 | 128 => offset = o,
-                    _ => return Err(exception::RvException::InstructionIllegal),
+            0x15 => |i, hart, _, ilen| match (hart.v.as_mut(), hart.csr.as_mut()) {
+                (Some(v), Some(c)) if c.vs_enabled() => match i.op_v(&mut hart.x, v, c) {
+                    None => {
+                        c.dirty_vs();
+                        Ok(ilen)
+                    }
+                    Some(e) => Err(e),
                 },
-                Err(e) => {
-                    println!("<error>");
-                    return Err(e);
-                }
+                _ => Err(exception::RvException::InstructionIllegal),
             },
-            0x1c => match self.system(&mut hart.x, &mut hart.csr) {
-                None => (),
-                Some(e) => {
-                    println!("<error>");
-                    return Err(e);
+            0x16 if xlen == 128 => |i, hart, _, ilen| next(i.opimm64(&mut hart.x), ilen),
+            //
+            0x18 => |i, hart, _, ilen| i.branch(&mut hart.x, hart.pc, ilen, hart.extensions.c),
+            0x19 => |i, hart, _, ilen| i.jalr(&mut hart.x, hart.pc, ilen, hart.extensions.c),
+            0x1b => |i, hart, _, ilen| i.jal(&mut hart.x, hart.pc, ilen, hart.extensions.c),
+            // hlv, hlvx, hsv
+            0x1c if self.get_funct3() == 0x4 => |i, hart, bus, ilen| match hart.csr.as_ref() {
+                Some(c) if c.exists(csr::HSTATUS) => {
+                    let privilege: RvPrivilege = system::guest_privilege(c, hart.privilege)?;
+                    let mut mem =
+                        AddressSpace::new(bus, &mut hart.mmu, Some(c), privilege, hart.x.len())
+                            .guest();
+
+                    if i.get_funct7() & 0x1 == 0 && i.get_rs2() == 0x3 {
+                        mem = mem.execute();
+                    }
+                    next(
+                        system::hlv_hsv(
+                            &mut hart.x,
+                            i.get_rd(),
+                            i.get_rs1(),
+                            i.get_rs2(),
+                            i.get_funct7(),
+                            &mut mem,
+                        ),
+                        ilen,
+                    )
                 }
+                _ => Err(exception::RvException::InstructionIllegal),
             },
-            //            0x1e => rc = self.custom_3(),
-            //
-            _ => {
-                println!("<invalid>");
-                return Err(exception::RvException::InstructionIllegal);
+            0x1c => |i, hart, _, ilen| {
+                i.system(
+                    &mut hart.x,
+                    hart.pc,
+                    &mut hart.csr,
+                    &mut hart.privilege,
+                    &mut hart.mmu,
+                    ilen,
+                )
+            },
+            0x1e if xlen == 128 => {
+                |i, hart, _, ilen| next(i.op64(&hart.extensions, &mut hart.x), ilen)
             }
+            //
+            _ => illegal,
         }
-
-        Ok(offset)
     }
 
-    pub fn process(&self, hart: &mut Rv, bus: &mut Bus) -> Result<i128, exception::RvException> {
-        print!("asm: \t{}:\t{:08x}\t\t", hart.pc, self.get_raw());
-
-        match self {
-            Instr::Instr32(_) => match self.process_32(hart, bus) {
-                Ok(offset) => Ok(offset),
-                Err(e) => Err(e),
+    // Value written to xtval when this instruction raises the exception `e`
+    pub fn trap_value<X: Xlen>(&self, e: exception::RvException, x: &RvRegisters<X>, pc: X, v: Option<&RvVectorRegisters>) -> u128 {
+        match e {
+            exception::RvException::InstructionIllegal | exception::RvException::VirtualInstruction => self.get_raw() as u128,
+            exception::RvException::LoadAddressMisaligned
+            | exception::RvException::LoadAccessFault
+            | exception::RvException::LoadPageFault
+            | exception::RvException::LoadGuestPageFault
+            | exception::RvException::StoreAddressMisaligned
+            | exception::RvException::StoreAccessFault
+            | exception::RvException::StorePageFault
+            | exception::RvException::StoreGuestPageFault => match self {
+                // Vector accesses fault on one of their elements
+                Instr::Instr32(_) if matches!(self.get_opcode(), 0x01 | 0x09) && vmem::eew(self.get_funct3()).is_some() => {
+                    v.map_or(0, |v| v.fault as u128) & (u128::MAX >> (128 - x.len()))
+                }
+                Instr::Instr32(_) => self.effective_address(x),
+                _ => match rvc::expand(self, x.len()) {
+                    Some(raw) => Instr::Instr32(raw).effective_address(x),
+                    None => 0,
+                },
             },
-            _ => Err(exception::RvException::InstructionIllegal),
+            exception::RvException::InstructionAddressMisaligned => self.jump_target(x, pc),
+            exception::RvException::Breakpoint => pc.to_u128(),
+            _ => 0,
         }
     }
 
+    // Target of a jump or a taken branch, rd is not written yet
+    fn jump_target<X: Xlen>(&self, x: &RvRegisters<X>, pc: X) -> u128 {
+        let target: X = match self.get_opcode() {
+            0x18 => pc.wrapping_add(X::from_i128(self.get_b_imm() as i128)),
+            0x19 => x.read(self.get_rs1()).wrapping_add(X::from_i128(self.get_i_imm() as i128)) & !X::from_u128(1),
+            0x1b => pc.wrapping_add(X::from_i128(self.get_j_imm() as i128)),
+            _ => return 0,
+        };
+
+        target.to_u128()
+    }
+
+    fn effective_address<X: Xlen>(&self, x: &RvRegisters<X>) -> u128 {
+        let imm: i32 = match self.get_opcode() {
+            0x00 | 0x01 | 0x03 => self.get_i_imm(), // load, load fp, lq
+            0x08 | 0x09 => self.get_s_imm(),        // store, store fp
+            _ => 0,                                 // amo
+        };
+        x.read(self.get_rs1()).wrapping_add(X::from_i128(imm as i128)).to_u128()
+    }
+
     fn get_opcode(&self) -> usize {
         match self {
             Instr::Invalid => unreachable!(),
@@ -887,9 +1341,9 @@ impl Instr {
 
     fn get_rd_prime(&self) -> usize {
         match self {
-            Instr::InstrC0(i) => ((i >> 2) & 0x03) as usize,
-            Instr::InstrC1(i) => ((i >> 2) & 0x03) as usize,
-            Instr::InstrC2(i) => ((i >> 2) & 0x03) as usize,
+            Instr::InstrC0(i) => ((i >> 2) & 0x07) as usize + 8,
+            Instr::InstrC1(i) => ((i >> 2) & 0x07) as usize + 8,
+            Instr::InstrC2(i) => ((i >> 2) & 0x07) as usize + 8,
             Instr::Instr32(_) => todo!(),
             Instr::Invalid => unreachable!(),
         }
@@ -897,9 +1351,9 @@ impl Instr {
 
     fn get_rs1_prime(&self) -> usize {
         match self {
-            Instr::InstrC0(i) => ((i >> 7) & 0x03) as usize,
-            Instr::InstrC1(i) => ((i >> 7) & 0x03) as usize,
-            Instr::InstrC2(i) => ((i >> 7) & 0x03) as usize,
+            Instr::InstrC0(i) => ((i >> 7) & 0x07) as usize + 8,
+            Instr::InstrC1(i) => ((i >> 7) & 0x07) as usize + 8,
+            Instr::InstrC2(i) => ((i >> 7) & 0x07) as usize + 8,
             Instr::Instr32(_) => todo!(),
             Instr::Invalid => unreachable!(),
         }
@@ -907,9 +1361,9 @@ impl Instr {
 
     fn get_rs2_prime(&self) -> usize {
         match self {
-            Instr::InstrC0(i) => ((i >> 2) & 0x03) as usize,
-            Instr::InstrC1(i) => ((i >> 2) & 0x03) as usize,
-            Instr::InstrC2(i) => ((i >> 2) & 0x03) as usize,
+            Instr::InstrC0(i) => ((i >> 2) & 0x07) as usize + 8,
+            Instr::InstrC1(i) => ((i >> 2) & 0x07) as usize + 8,
+            Instr::InstrC2(i) => ((i >> 2) & 0x07) as usize + 8,
             Instr::Instr32(_) => todo!(),
             Instr::Invalid => unreachable!(),
         }
@@ -917,9 +1371,9 @@ impl Instr {
 
     fn get_funct3(&self) -> usize {
         match self {
-            Instr::InstrC0(i) => ((i >> 13) & 0x02) as usize,
-            Instr::InstrC1(i) => ((i >> 13) & 0x02) as usize,
-            Instr::InstrC2(i) => ((i >> 13) & 0x02) as usize,
+            Instr::InstrC0(i) => ((i >> 13) & 0x07) as usize,
+            Instr::InstrC1(i) => ((i >> 13) & 0x07) as usize,
+            Instr::InstrC2(i) => ((i >> 13) & 0x07) as usize,
             Instr::Instr32(i) => ((i >> 12) & 0x07) as usize,
             Instr::Invalid => unreachable!(),
         }
@@ -950,7 +1404,7 @@ impl Instr {
             Instr::InstrC0(i) => ((i >> 10) & 0x3f) as usize,
             Instr::InstrC1(i) => ((i >> 10) & 0x3f) as usize,
             Instr::InstrC2(i) => ((i >> 10) & 0x3f) as usize,
-            Instr::Instr32(_) => todo!(),
+            Instr::Instr32(i) => ((i >> 26) & 0x3f) as usize,
             Instr::Invalid => unreachable!(),
         }
     }
@@ -980,7 +1434,7 @@ impl Instr {
             Instr::InstrC0(_) => todo!(),
             Instr::InstrC1(_) => todo!(),
             Instr::InstrC2(_) => todo!(),
-            Instr::Instr32(i) => ((i >> 20) & 0x3f) as usize,
+            Instr::Instr32(i) => ((i >> 20) & 0x7f) as usize,
             Instr::Invalid => unreachable!(),
         }
     }
@@ -1063,3 +1517,33 @@ impl Instr {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::vsoc::Vsoc;
+
+    // The extensions are checked when the cached instruction runs
+    #[test]
+    fn test_m() {
+        // li a1, 6; li a2, 7; mul a0, a1, a2
+        let binary: Vec<u8> = [0x0060_0593u32, 0x0070_0613, 0x02c5_8533]
+            .iter()
+            .flat_map(|i| i.to_le_bytes())
+            .collect();
+
+        for (arch, product) in [("rv32im", true), ("rv32i", false)] {
+            let arch: String = String::from(arch);
+            let mut vsoc: Vsoc = Vsoc::new(&arch);
+
+            vsoc.load(&binary).unwrap();
+            for _ in 0..2 {
+                assert!(vsoc.step().is_none());
+            }
+            assert_eq!(vsoc.step().is_none(), product);
+            assert_eq!(
+                vsoc.read_register(10) == Some(42u32.to_le_bytes().to_vec()),
+                product
+            );
+        }
+    }
+}
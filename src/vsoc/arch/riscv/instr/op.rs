@@ -1,839 +1,202 @@
-use crate::vsoc::arch::types::Uint;
-
 use super::super::registers::RvRegisters;
+use super::super::xlen::Xlen;
 
-pub fn add(x: &mut RvRegisters, rd: usize, rs1: usize, rs2: usize) {
-    let rs1v: Uint = x.get(rs1);
-    let rs2v: Uint = x.get(rs2);
-
-    println!("add\t{},{},{}", x.name(rd), x.name(rs1), x.name(rs2));
-
-    match x.len() {
-        32 => {
-            let rs1value: i32 = i32::from(rs1v);
-            let rs2value: i32 = i32::from(rs2v);
-            let result: i32 = rs1value.wrapping_add(rs2value);
-
-            x.set(rd, &Uint::from(result));
-        }
-        64 => {
-            let rs1value: i64 = i64::from(rs1v);
-            let rs2value: i64 = i64::from(rs2v);
-            let result: i64 = rs1value.wrapping_add(rs2value);
-
-            x.set(rd, &Uint::from(result));
-        }
-        128 => {
-            let rs1value: i128 = i128::from(rs1v);
-            let rs2value: i128 = i128::from(rs2v);
-            let result: i128 = rs1value.wrapping_add(rs2value);
-
-            x.set(rd, &Uint::from(result));
-        }
-        _ => unreachable!(),
-    }
+// The word instructions compute on the low 32 bits of the registers, the
+// double ones of RV128 on the low 64 bits, and sign-extend the result
+pub(super) fn word<X: Xlen>(x: &RvRegisters<X>, rs: usize) -> u32 {
+    x.read(rs).to_u128() as u32
 }
 
-pub fn addw(x: &mut RvRegisters, rd: usize, rs1: usize, rs2: usize) {
-    let rs1v: Uint = x.get(rs1);
-    let rs2v: Uint = x.get(rs2);
-
-    println!("addw\t{},{},{}", x.name(rd), x.name(rs1), x.name(rs2));
-
-    match x.len() {
-        64 => {
-            let rs1value: i64 = i64::from(rs1v);
-            let rs2value: i64 = i64::from(rs2v);
-            let result: i64 = (rs1value as i32).wrapping_add(rs2value as i32) as i64;
-
-            x.set(rd, &Uint::from(result));
-        }
-        128 => {
-            let rs1value: i128 = i128::from(rs1v);
-            let rs2value: i128 = i128::from(rs2v);
-            let result: i128 = (rs1value as i32).wrapping_add(rs2value as i32) as i128;
-
-            x.set(rd, &Uint::from(result));
-        }
-        _ => unreachable!(),
-    }
+pub(super) fn double<X: Xlen>(x: &RvRegisters<X>, rs: usize) -> u64 {
+    x.read(rs).to_u64()
 }
 
-pub fn sub(x: &mut RvRegisters, rd: usize, rs1: usize, rs2: usize) {
-    let rs1v: Uint = x.get(rs1);
-    let rs2v: Uint = x.get(rs2);
-
-    println!("sub\t{},{},{}", x.name(rd), x.name(rs1), x.name(rs2));
-
-    match x.len() {
-        32 => {
-            let rs1value: i32 = i32::from(rs1v);
-            let rs2value: i32 = i32::from(rs2v);
-            let result: i32 = rs1value.wrapping_sub(rs2value);
-
-            x.set(rd, &Uint::from(result));
-        }
-        64 => {
-            let rs1value: i64 = i64::from(rs1v);
-            let rs2value: i64 = i64::from(rs2v);
-            let result: i64 = rs1value.wrapping_sub(rs2value);
-
-            x.set(rd, &Uint::from(result));
-        }
-        128 => {
-            let rs1value: i128 = i128::from(rs1v);
-            let rs2value: i128 = i128::from(rs2v);
-            let result: i128 = rs1value.wrapping_sub(rs2value);
-
-            x.set(rd, &Uint::from(result));
-        }
-        _ => unreachable!(),
-    }
+pub(super) fn set_word<X: Xlen>(x: &mut RvRegisters<X>, rd: usize, value: u32) {
+    x.write(rd, X::from_i128(value.to_i128()));
 }
 
-pub fn subw(x: &mut RvRegisters, rd: usize, rs1: usize, rs2: usize) {
-    let rs1v: Uint = x.get(rs1);
-    let rs2v: Uint = x.get(rs2);
-
-    println!("subw\t{},{},{}", x.name(rd), x.name(rs1), x.name(rs2));
-
-    match x.len() {
-        64 => {
-            let rs1value: i64 = i64::from(rs1v);
-            let rs2value: i64 = i64::from(rs2v);
-            let result: i64 = (rs1value as i32).wrapping_sub(rs2value as i32) as i64;
-
-            x.set(rd, &Uint::from(result));
-        }
-        128 => {
-            let rs1value: i128 = i128::from(rs1v);
-            let rs2value: i128 = i128::from(rs2v);
-            let result: i128 = (rs1value as i32).wrapping_sub(rs2value as i32) as i128;
-
-            x.set(rd, &Uint::from(result));
-        }
-        _ => unreachable!(),
-    }
+pub(super) fn set_double<X: Xlen>(x: &mut RvRegisters<X>, rd: usize, value: u64) {
+    x.write(rd, X::from_i128(value.to_i128()));
 }
 
-pub fn slt(x: &mut RvRegisters, rd: usize, rs1: usize, rs2: usize) {
-    let rs1v: Uint = x.get(rs1);
-    let rs2v: Uint = x.get(rs2);
-
-    println!("slt\t{},{},{}", x.name(rd), x.name(rs1), x.name(rs2));
-
-    match x.len() {
-        32 => {
-            let rs1value: i32 = i32::from(rs1v);
-            let rs2value: i32 = i32::from(rs2v);
-            let result: i32 = if rs1value < rs2value { 1 } else { 0 };
-
-            x.set(rd, &Uint::from(result));
-        }
-        64 => {
-            let rs1value: i64 = i64::from(rs1v);
-            let rs2value: i64 = i64::from(rs2v);
-            let result: i64 = if rs1value < rs2value { 1 } else { 0 };
-
-            x.set(rd, &Uint::from(result));
-        }
-        128 => {
-            let rs1value: i128 = i128::from(rs1v);
-            let rs2value: i128 = i128::from(rs2v);
-            let result: i128 = if rs1value < rs2value { 1 } else { 0 };
-
-            x.set(rd, &Uint::from(result));
-        }
-        _ => unreachable!(),
-    }
+pub fn add<X: Xlen>(x: &mut RvRegisters<X>, rd: usize, rs1: usize, rs2: usize) {
+    x.write(rd, x.read(rs1).wrapping_add(x.read(rs2)));
 }
 
-pub fn sltu(x: &mut RvRegisters, rd: usize, rs1: usize, rs2: usize) {
-    let rs1v: Uint = x.get(rs1);
-    let rs2v: Uint = x.get(rs2);
-
-    println!("sltu\t{},{},{}", x.name(rd), x.name(rs1), x.name(rs2));
-
-    match x.len() {
-        32 => {
-            let result: u32 = if rs1v < rs2v { 1 } else { 0 };
-
-            x.set(rd, &Uint::from(result));
-        }
-        64 => {
-            let result: u64 = if rs1v < rs2v { 1 } else { 0 };
-
-            x.set(rd, &Uint::from(result));
-        }
-        128 => {
-            let result: u128 = if rs1v < rs2v { 1 } else { 0 };
-
-            x.set(rd, &Uint::from(result));
-        }
-        _ => unreachable!(),
-    }
+pub fn addw<X: Xlen>(x: &mut RvRegisters<X>, rd: usize, rs1: usize, rs2: usize) {
+    set_word(x, rd, word(x, rs1).wrapping_add(word(x, rs2)));
 }
 
-pub fn and(x: &mut RvRegisters, rd: usize, rs1: usize, rs2: usize) {
-    let rs1v: Uint = x.get(rs1);
-    let rs2v: Uint = x.get(rs2);
-    let result: Uint = rs1v & rs2v;
-
-    println!("and\t{},{},{}", x.name(rd), x.name(rs1), x.name(rs2));
-
-    x.set(rd, &result);
+pub fn sub<X: Xlen>(x: &mut RvRegisters<X>, rd: usize, rs1: usize, rs2: usize) {
+    x.write(rd, x.read(rs1).wrapping_sub(x.read(rs2)));
 }
 
-pub fn or(x: &mut RvRegisters, rd: usize, rs1: usize, rs2: usize) {
-    let rs1v: Uint = x.get(rs1);
-    let rs2v: Uint = x.get(rs2);
-    let result: Uint = rs1v | rs2v;
-
-    println!("or\t{},{},{}", x.name(rd), x.name(rs1), x.name(rs2));
-
-    x.set(rd, &result);
+pub fn subw<X: Xlen>(x: &mut RvRegisters<X>, rd: usize, rs1: usize, rs2: usize) {
+    set_word(x, rd, word(x, rs1).wrapping_sub(word(x, rs2)));
 }
 
-pub fn xor(x: &mut RvRegisters, rd: usize, rs1: usize, rs2: usize) {
-    let rs1v: Uint = x.get(rs1);
-    let rs2v: Uint = x.get(rs2);
-    let result: Uint = rs1v ^ rs2v;
-
-    println!("xor\t{},{},{}", x.name(rd), x.name(rs1), x.name(rs2));
-
-    x.set(rd, &result);
+pub fn slt<X: Xlen>(x: &mut RvRegisters<X>, rd: usize, rs1: usize, rs2: usize) {
+    x.write(rd, X::from_u128(x.read(rs1).signed_lt(x.read(rs2)) as u128));
 }
 
-pub fn sll(x: &mut RvRegisters, rd: usize, rs1: usize, rs2: usize) {
-    let rs1v: Uint = x.get(rs1);
-    let shamt: u32 = u32::from(x.get(rs2)) & 0x1f;
-
-    println!("sll\t{},{},{}", x.name(rd), x.name(rs1), shamt);
-
-    match x.len() {
-        32 => {
-            let rs1value: u32 = u32::from(rs1v);
-            let result: u32 = rs1value.wrapping_shl(shamt);
-
-            x.set(rd, &Uint::from(result));
-        }
-        64 => {
-            let rs1value: u64 = u64::from(rs1v);
-            let result: u64 = rs1value.wrapping_shl(shamt);
-
-            x.set(rd, &Uint::from(result));
-        }
-        128 => {
-            let rs1value: u128 = u128::from(rs1v);
-            let result: u128 = rs1value.wrapping_shl(shamt);
-
-            x.set(rd, &Uint::from(result));
-        }
-        _ => unreachable!(),
-    }
+pub fn sltu<X: Xlen>(x: &mut RvRegisters<X>, rd: usize, rs1: usize, rs2: usize) {
+    x.write(rd, X::from_u128((x.read(rs1) < x.read(rs2)) as u128));
 }
 
-pub fn sllw(x: &mut RvRegisters, rd: usize, rs1: usize, rs2: usize) {
-    let rs1v: Uint = x.get(rs1);
-    let shamt: u32 = u32::from(x.get(rs2)) & 0x1f;
-
-    println!("sllw\t{},{},{}", x.name(rd), x.name(rs1), x.name(rs2));
-
-    match x.len() {
-        64 => {
-            let rs1value: u64 = u64::from(rs1v);
-            let result: i64 = rs1value.wrapping_shl(shamt) as i32 as i64;
-
-            x.set(rd, &Uint::from(result));
-        }
-        128 => {
-            let rs1value: u128 = u128::from(rs1v);
-            let result: i128 = rs1value.wrapping_shl(shamt) as i32 as i128;
-
-            x.set(rd, &Uint::from(result));
-        }
-        _ => unreachable!(),
-    }
+pub fn and<X: Xlen>(x: &mut RvRegisters<X>, rd: usize, rs1: usize, rs2: usize) {
+    x.write(rd, x.read(rs1) & x.read(rs2));
 }
 
-pub fn srl(x: &mut RvRegisters, rd: usize, rs1: usize, rs2: usize) {
-    let rs1v: Uint = x.get(rs1);
-    let shamt: u32 = u32::from(x.get(rs2)) & 0x1f;
-
-    println!("srl\t{},{},{}", x.name(rd), x.name(rs1), shamt);
-
-    match x.len() {
-        32 => {
-            let rs1value: u32 = u32::from(rs1v);
-            let result: u32 = rs1value.wrapping_shr(shamt);
-
-            x.set(rd, &Uint::from(result));
-        }
-        64 => {
-            let rs1value: u64 = u64::from(rs1v);
-            let result: u64 = rs1value >> shamt;
-            x.set(rd, &Uint::from(result));
-        }
-        128 => {
-            let rs1value: u128 = u128::from(rs1v);
-            let result: u128 = rs1value >> shamt;
-
-            x.set(rd, &Uint::from(result));
-        }
-        _ => unreachable!(),
-    }
+pub fn or<X: Xlen>(x: &mut RvRegisters<X>, rd: usize, rs1: usize, rs2: usize) {
+    x.write(rd, x.read(rs1) | x.read(rs2));
 }
 
-pub fn srlw(x: &mut RvRegisters, rd: usize, rs1: usize, rs2: usize) {
-    let rs1v: Uint = x.get(rs1);
-    let shamt: u32 = u32::from(x.get(rs2)) & 0x1f;
-
-    println!("srlw\t{},{},{}", x.name(rd), x.name(rs1), shamt);
-
-    match x.len() {
-        64 => {
-            let rs1value: i64 = i64::from(rs1v);
-            let result: i64 = (rs1value as u32).wrapping_shr(shamt) as i32 as i64;
-
-            x.set(rd, &Uint::from(result));
-        }
-        128 => {
-            let rs1value: i128 = i128::from(rs1v);
-            let result: i128 = (rs1value as u32).wrapping_shr(shamt) as i32 as i128;
-
-            x.set(rd, &Uint::from(result));
-        }
-        _ => unreachable!(),
-    }
+pub fn xor<X: Xlen>(x: &mut RvRegisters<X>, rd: usize, rs1: usize, rs2: usize) {
+    x.write(rd, x.read(rs1) ^ x.read(rs2));
 }
 
-pub fn sra(x: &mut RvRegisters, rd: usize, rs1: usize, rs2: usize) {
-    let rs1v: Uint = x.get(rs1);
-    let shamt: u32 = u32::from(x.get(rs2)) & 0x1f;
-
-    println!("sra\t{},{},{}", x.name(rd), x.name(rs1), shamt);
-
-    match x.len() {
-        32 => {
-            let rs1value: i32 = i32::from(rs1v);
-            let result: i32 = rs1value.wrapping_shr(shamt);
-
-            x.set(rd, &Uint::from(result));
-        }
-        64 => {
-            let rs1value: i64 = i64::from(rs1v);
-            let result: i64 = rs1value.wrapping_shr(shamt);
-
-            x.set(rd, &Uint::from(result));
-        }
-        128 => {
-            let rs1value: i128 = i128::from(rs1v);
-            let result: i128 = rs1value.wrapping_shr(shamt);
-
-            x.set(rd, &Uint::from(result));
-        }
-        _ => unreachable!(),
-    }
+pub fn sll<X: Xlen>(x: &mut RvRegisters<X>, rd: usize, rs1: usize, rs2: usize) {
+    x.write(rd, x.read(rs1).shl(word(x, rs2)));
 }
 
-pub fn sraw(x: &mut RvRegisters, rd: usize, rs1: usize, rs2: usize) {
-    let rs1v: Uint = x.get(rs1);
-    let shamt: u32 = u32::from(x.get(rs2)) & 0x1f;
-
-    println!("sraw\t{},{},{}", x.name(rd), x.name(rs1), shamt);
-
-    match x.len() {
-        64 => {
-            let rs1value: i64 = i64::from(rs1v);
-            let result: i64 = (rs1value as i32 >> shamt) as i64;
-
-            x.set(rd, &Uint::from(result));
-        }
-        128 => {
-            let rs1value: i128 = i128::from(rs1v);
-            let result: i128 = (rs1value as i32 >> shamt) as i128;
-
-            x.set(rd, &Uint::from(result));
-        }
-        _ => unreachable!(),
-    }
+pub fn sllw<X: Xlen>(x: &mut RvRegisters<X>, rd: usize, rs1: usize, rs2: usize) {
+    set_word(x, rd, word(x, rs1).wrapping_shl(word(x, rs2)));
 }
 
-pub fn mul(x: &mut RvRegisters, rd: usize, rs1: usize, rs2: usize) {
-    let rs1v: Uint = x.get(rs1);
-    let rs2v: Uint = x.get(rs2);
-
-    println!("mul\t{},{},{}", x.name(rd), x.name(rs1), x.name(rs2));
-
-    match x.len() {
-        32 => {
-            let rs1value: i32 = i32::from(rs1v);
-            let rs2value: i32 = i32::from(rs2v);
-            let result: i32 = rs1value.wrapping_mul(rs2value);
-
-            x.set(rd, &Uint::from(result));
-        }
-        64 => {
-            let rs1value: i64 = i64::from(rs1v);
-            let rs2value: i64 = i64::from(rs2v);
-            let result: i64 = rs1value.wrapping_mul(rs2value);
-
-            x.set(rd, &Uint::from(result));
-        }
-        128 => {
-            let rs1value: i128 = i128::from(rs1v);
-            let rs2value: i128 = i128::from(rs2v);
-            let result: i128 = rs1value.wrapping_mul(rs2value);
-
-            x.set(rd, &Uint::from(result));
-        }
-        _ => unreachable!(),
-    }
+pub fn srl<X: Xlen>(x: &mut RvRegisters<X>, rd: usize, rs1: usize, rs2: usize) {
+    x.write(rd, x.read(rs1).shr(word(x, rs2)));
 }
 
-pub fn mulh(x: &mut RvRegisters, rd: usize, rs1: usize, rs2: usize) {
-    let rs1v: Uint = x.get(rs1);
-    let rs2v: Uint = x.get(rs2);
-
-    println!("mulh\t{},{},{}", x.name(rd), x.name(rs1), x.name(rs2));
-
-    match x.len() {
-        32 => {
-            let rs1value: i64 = i32::from(rs1v) as i64;
-            let rs2value: i64 = i32::from(rs2v) as i64;
-            let result: i32 = (rs1value.wrapping_mul(rs2value) >> x.len()) as i32;
-
-            x.set(rd, &Uint::from(result));
-        }
-        64 => {
-            let rs1value: i128 = i64::from(rs1v) as i128;
-            let rs2value: i128 = i64::from(rs2v) as i128;
-            let result: i64 = (rs1value.wrapping_mul(rs2value) >> x.len()) as i64;
-
-            x.set(rd, &Uint::from(result));
-        }
-        128 => {
-            todo!();
-        }
-        _ => unreachable!(),
-    }
+pub fn srlw<X: Xlen>(x: &mut RvRegisters<X>, rd: usize, rs1: usize, rs2: usize) {
+    set_word(x, rd, word(x, rs1).wrapping_shr(word(x, rs2)));
 }
 
-pub fn mulhu(x: &mut RvRegisters, rd: usize, rs1: usize, rs2: usize) {
-    let rs1v: Uint = x.get(rs1);
-    let rs2v: Uint = x.get(rs2);
-
-    println!(
-        "mulhu\t{},{},{}",
-        x.name(rd),
-        x.name(rs1),
-        x.name(rs2)
-    );
-
-    match x.len() {
-        32 => {
-            let rs1value: u64 = u32::from(rs1v) as u64;
-            let rs2value: u64 = u32::from(rs2v) as u64;
-            let result: u32 = (rs1value.wrapping_mul(rs2value) >> x.len()) as u32;
-
-            x.set(rd, &Uint::from(result));
-        }
-        64 => {
-            let rs1value: u128 = u64::from(rs1v) as u128;
-            let rs2value: u128 = u64::from(rs2v) as u128;
-            let result: u64 = (rs1value.wrapping_mul(rs2value) >> x.len()) as u64;
-
-            x.set(rd, &Uint::from(result));
-        }
-        128 => {
-            todo!();
-        }
-        _ => unreachable!(),
-    }
+pub fn sra<X: Xlen>(x: &mut RvRegisters<X>, rd: usize, rs1: usize, rs2: usize) {
+    x.write(rd, x.read(rs1).sra(word(x, rs2)));
 }
 
-pub fn mulhsu(x: &mut RvRegisters, rd: usize, rs1: usize, rs2: usize) {
-    let rs1v: Uint = x.get(rs1);
-    let rs2v: Uint = x.get(rs2);
-
-    println!(
-        "mulhsu\t{},{},{}",
-        x.name(rd),
-        x.name(rs1),
-        x.name(rs2)
-    );
-
-    match x.len() {
-        32 => {
-            let rs1value: i64 = i32::from(rs1v) as i64;
-            let rs2value: i64 = u32::from(rs2v) as u64 as i64;
-            let result: i32 = (rs1value.wrapping_mul(rs2value) >> x.len()) as i32;
-
-            x.set(rd, &Uint::from(result));
-        }
-        64 => {
-            let rs1value: i128 = i64::from(rs1v) as i128;
-            let rs2value: i128 = u64::from(rs2v) as u128 as i128;
-            let result: i64 = (rs1value.wrapping_mul(rs2value) >> x.len()) as i64;
-
-            x.set(rd, &Uint::from(result));
-        }
-        128 => {
-            todo!();
-        }
-        _ => unreachable!(),
-    }
+pub fn sraw<X: Xlen>(x: &mut RvRegisters<X>, rd: usize, rs1: usize, rs2: usize) {
+    set_word(x, rd, word(x, rs1).sra(word(x, rs2)));
 }
 
-pub fn mulw(x: &mut RvRegisters, rd: usize, rs1: usize, rs2: usize) {
-    let rs1v: Uint = x.get(rs1);
-    let rs2v: Uint = x.get(rs2);
-
-    println!("mulw\t{},{},{}", x.name(rd), x.name(rs1), x.name(rs2));
-
-    match x.len() {
-        64 => {
-            let rs1value: i64 = i64::from(rs1v);
-            let rs2value: i64 = i64::from(rs2v);
-            let result: i64 = (rs1value as i32).wrapping_mul(rs2value as i32) as i64;
-
-            x.set(rd, &Uint::from(result));
-        }
-        128 => {
-            let rs1value: i128 = i128::from(rs1v);
-            let rs2value: i128 = i128::from(rs2v);
-            let result: i128 = rs1value.wrapping_mul(rs2value);
-
-            x.set(rd, &Uint::from(result));
-        }
-        _ => unreachable!(),
-    }
+pub fn mul<X: Xlen>(x: &mut RvRegisters<X>, rd: usize, rs1: usize, rs2: usize) {
+    x.write(rd, x.read(rs1).wrapping_mul(x.read(rs2)));
 }
 
-pub fn div(x: &mut RvRegisters, rd: usize, rs1: usize, rs2: usize) {
-    let rs1v: Uint = x.get(rs1);
-    let rs2v: Uint = x.get(rs2);
-
-    println!("div\t{},{},{}", x.name(rd), x.name(rs1), x.name(rs2));
-
-    if rs2v == Uint::zero(x.len()) {
-        x.set(rd, &Uint::ff_ff(x.len()));
-        return;
-    }
-
-    match x.len() {
-        32 => {
-            let rs1value: i32 = i32::from(rs1v.clone());
-            let rs2value: i32 = i32::from(rs2v);
-
-            if rs1value == (-1 << 31) && rs2value == -1 {
-                x.set(rd, &rs1v);
-            } else {
-                let result: i32 = rs1value.wrapping_div(rs2value);
-
-                x.set(rd, &Uint::from(result));
-            }
-        }
-        64 => {
-            let rs1value: i64 = i64::from(rs1v.clone());
-            let rs2value: i64 = i64::from(rs2v);
-
-            if rs1value == (-1 << 63) && rs2value == -1 {
-                x.set(rd, &rs1v);
-            } else {
-                let result: i64 = rs1value.wrapping_div(rs2value);
-                x.set(rd, &Uint::from(result));
-            }
-        }
-        128 => {
-            let rs1value: i128 = i128::from(rs1v.clone());
-            let rs2value: i128 = i128::from(rs2v);
-            
-            if rs1value == (-1 << 127) && rs2value == -1 {
-                x.set(rd, &rs1v);
-            } else {
-                let result: i128 = rs1value.wrapping_div(rs2value);
-
-                x.set(rd, &Uint::from(result));
-            }
-        }
-        _ => unreachable!(),
-    }
+pub fn mulh<X: Xlen>(x: &mut RvRegisters<X>, rd: usize, rs1: usize, rs2: usize) {
+    x.write(rd, x.read(rs1).mulh(x.read(rs2), true, true));
 }
 
-pub fn divu(x: &mut RvRegisters, rd: usize, rs1: usize, rs2: usize) {
-    let rs1v: Uint = x.get(rs1);
-    let rs2v: Uint = x.get(rs2);
-
-    println!("divu\t{},{},{}", x.name(rd), x.name(rs1), x.name(rs2));
-
-    if rs2v == Uint::zero(x.len()) {
-        x.set(rd, &Uint::ff_ff(x.len()));
-        return;
-    }
-
-    match x.len() {
-        32 => {
-            let rs1value: u32 = u32::from(rs1v);
-            let rs2value: u32 = u32::from(rs2v);
-            let result: u32 = rs1value.wrapping_div(rs2value);
-
-            x.set(rd, &Uint::from(result));
-        }
-        64 => {
-            let rs1value: u64 = u64::from(rs1v);
-            let rs2value: u64 = u64::from(rs2v);
-
-            let result: u64 = rs1value.wrapping_div(rs2value);
-
-            x.set(rd, &Uint::from(result));
-        }
-        128 => {
-            let rs1value: u128 = u128::from(rs1v);
-            let rs2value: u128 = u128::from(rs2v);
-
-            let result: u128 = rs1value.wrapping_div(rs2value);
-
-            x.set(rd, &Uint::from(result));
-        }
-        _ => unreachable!(),
-    }
+pub fn mulhu<X: Xlen>(x: &mut RvRegisters<X>, rd: usize, rs1: usize, rs2: usize) {
+    x.write(rd, x.read(rs1).mulh(x.read(rs2), false, false));
 }
 
-pub fn divw(x: &mut RvRegisters, rd: usize, rs1: usize, rs2: usize) {
-    let rs1v: Uint = x.get(rs1);
-    let rs2v: Uint = x.get(rs2);
+pub fn mulhsu<X: Xlen>(x: &mut RvRegisters<X>, rd: usize, rs1: usize, rs2: usize) {
+    x.write(rd, x.read(rs1).mulh(x.read(rs2), true, false));
+}
 
-    println!("divw\t{},{},{}", x.name(rd), x.name(rs1), x.name(rs2));
+pub fn mulw<X: Xlen>(x: &mut RvRegisters<X>, rd: usize, rs1: usize, rs2: usize) {
+    set_word(x, rd, word(x, rs1).wrapping_mul(word(x, rs2)));
+}
 
-    if rs2v == Uint::zero(x.len()) {
-        x.set(rd, &Uint::ff_ff(x.len()));
-        return;
-    }
+pub fn div<X: Xlen>(x: &mut RvRegisters<X>, rd: usize, rs1: usize, rs2: usize) {
+    x.write(rd, x.read(rs1).div(x.read(rs2)));
+}
 
-    match x.len() {
-        64 => {
-            let rs1value: i64 = i64::from(rs1v.clone());
-            let rs2value: i64 = i64::from(rs2v);
-
-            if rs1value == (-1 << 63) && rs2value == -1 {
-                x.set(rd, &rs1v);
-            } else {
-                let result: i64 = (rs1value as i32).wrapping_div(rs2value as i32) as i64;
-
-                x.set(rd, &Uint::from(result));
-            }
-        }
-        128 => {
-            let rs1value: i128 = i128::from(rs1v.clone());
-            let rs2value: i128 = i128::from(rs2v);
-
-            if rs1value == (-1 << 127) && rs2value == -1 {
-                x.set(rd, &rs1v);
-            } else {
-                let result: i128 = (rs1value as i32).wrapping_div(rs2value as i32) as i128;
-
-                x.set(rd, &Uint::from(result));
-            }
-        }
-        _ => unreachable!(),
-    }
+pub fn divu<X: Xlen>(x: &mut RvRegisters<X>, rd: usize, rs1: usize, rs2: usize) {
+    x.write(rd, x.read(rs1).divu(x.read(rs2)));
 }
 
-pub fn divuw(x: &mut RvRegisters, rd: usize, rs1: usize, rs2: usize) {
-    let rs1v: Uint = x.get(rs1);
-    let rs2v: Uint = x.get(rs2);
+pub fn divw<X: Xlen>(x: &mut RvRegisters<X>, rd: usize, rs1: usize, rs2: usize) {
+    set_word(x, rd, Xlen::div(word(x, rs1), word(x, rs2)));
+}
 
-    println!(
-        "divuw\t{},{},{}",
-        x.name(rd),
-        x.name(rs1),
-        x.name(rs2)
-    );
+pub fn divuw<X: Xlen>(x: &mut RvRegisters<X>, rd: usize, rs1: usize, rs2: usize) {
+    set_word(x, rd, word(x, rs1).divu(word(x, rs2)));
+}
 
-    if rs2v == Uint::zero(x.len()) {
-        x.set(rd, &Uint::ff_ff(x.len()));
-        return;
-    }
+pub fn rem<X: Xlen>(x: &mut RvRegisters<X>, rd: usize, rs1: usize, rs2: usize) {
+    x.write(rd, x.read(rs1).rem(x.read(rs2)));
+}
 
-    match x.len() {
-        64 => {
-            let rs1value: u64 = u64::from(rs1v);
-            let rs2value: u64 = u64::from(rs2v);
-            let result: i64 = (rs1value as u32).wrapping_div(rs2value as u32) as i32 as i64;
-
-            x.set(rd, &Uint::from(result));
-        }
-        128 => {
-            let rs1value: u128 = u128::from(rs1v);
-            let rs2value: u128 = u128::from(rs2v);
-            let result: i128 = (rs1value as u32).wrapping_div(rs2value as u32) as i32 as i128;
-
-            x.set(rd, &Uint::from(result));
-        }
-        _ => unreachable!(),
-    }
+pub fn remu<X: Xlen>(x: &mut RvRegisters<X>, rd: usize, rs1: usize, rs2: usize) {
+    x.write(rd, x.read(rs1).remu(x.read(rs2)));
 }
 
-pub fn rem(x: &mut RvRegisters, rd: usize, rs1: usize, rs2: usize) {
-    let rs1v: Uint = x.get(rs1);
-    let rs2v: Uint = x.get(rs2);
+pub fn remw<X: Xlen>(x: &mut RvRegisters<X>, rd: usize, rs1: usize, rs2: usize) {
+    set_word(x, rd, Xlen::rem(word(x, rs1), word(x, rs2)));
+}
 
-    println!("rem\t{},{},{}", x.name(rd), x.name(rs1), x.name(rs2));
+pub fn remuw<X: Xlen>(x: &mut RvRegisters<X>, rd: usize, rs1: usize, rs2: usize) {
+    set_word(x, rd, word(x, rs1).remu(word(x, rs2)));
+}
 
-    if rs2v == Uint::zero(x.len()) {
-        x.set(rd, &rs1v);
-        return;
-    }
+pub fn addd<X: Xlen>(x: &mut RvRegisters<X>, rd: usize, rs1: usize, rs2: usize) {
+    set_double(x, rd, double(x, rs1).wrapping_add(double(x, rs2)));
+}
 
-    match x.len() {
-        32 => {
-            let rs1value: i32 = i32::from(rs1v.clone());
-            let rs2value: i32 = i32::from(rs2v);
-
-            if rs1value == (-1 << 31) && rs2value == -1 {
-                x.set(rd, &Uint::from(0u32));
-            } else {
-                let result: i32 = rs1value.wrapping_rem(rs2value);
-
-                x.set(rd, &Uint::from(result));
-            }
-        }
-        64 => {
-            let rs1value: i64 = i64::from(rs1v.clone());
-            let rs2value: i64 = i64::from(rs2v);
-
-            if rs1value == (-1 << 63) && rs2value == -1 {
-                x.set(rd, &Uint::from(0u64));
-            } else {
-                let result: i64 = rs1value.wrapping_rem(rs2value);
-                x.set(rd, &Uint::from(result));
-            }
-        }
-        128 => {
-            let rs1value: i128 = i128::from(rs1v.clone());
-            let rs2value: i128 = i128::from(rs2v);
-
-            if rs1value == (-1 << 127) && rs2value == -1 {
-                x.set(rd, &Uint::from(0u128));
-            } else {
-                let result: i128 = rs1value.wrapping_rem(rs2value);
-
-                x.set(rd, &Uint::from(result));
-            }
-        }
-        _ => unreachable!(),
-    }
+pub fn subd<X: Xlen>(x: &mut RvRegisters<X>, rd: usize, rs1: usize, rs2: usize) {
+    set_double(x, rd, double(x, rs1).wrapping_sub(double(x, rs2)));
 }
 
-pub fn remu(x: &mut RvRegisters, rd: usize, rs1: usize, rs2: usize) {
-    let rs1v: Uint = x.get(rs1);
-    let rs2v: Uint = x.get(rs2);
+pub fn slld<X: Xlen>(x: &mut RvRegisters<X>, rd: usize, rs1: usize, rs2: usize) {
+    set_double(x, rd, double(x, rs1).wrapping_shl(word(x, rs2)));
+}
 
-    println!("remu\t{},{},{}", x.name(rd), x.name(rs1), x.name(rs2));
+pub fn srld<X: Xlen>(x: &mut RvRegisters<X>, rd: usize, rs1: usize, rs2: usize) {
+    set_double(x, rd, double(x, rs1).wrapping_shr(word(x, rs2)));
+}
 
-    if rs2v == Uint::zero(x.len()) {
-        x.set(rd, &rs1v);
-        return;
-    }
+pub fn srad<X: Xlen>(x: &mut RvRegisters<X>, rd: usize, rs1: usize, rs2: usize) {
+    set_double(x, rd, double(x, rs1).sra(word(x, rs2)));
+}
 
-    match x.len() {
-        32 => {
-            let rs2value: u32 = u32::from(rs2v);
-            let rs1value: u32 = u32::from(rs1v);
-            let result: u32 = rs1value.wrapping_rem(rs2value);
-
-            x.set(rd, &Uint::from(result));
-        }
-        64 => {
-            let rs2value: u64 = u64::from(rs2v);
-            let rs1value: u64 = u64::from(rs1v);
-            let result: u64 = rs1value.wrapping_rem(rs2value);
-
-            x.set(rd, &Uint::from(result));
-        }
-        128 => {
-            let rs2value: u128 = u128::from(rs2v);
-            let rs1value: u128 = u128::from(rs1v);
-            let result: u128 = rs1value.wrapping_rem(rs2value);
-
-            x.set(rd, &Uint::from(result));
-        }
-        _ => unreachable!(),
-    }
+pub fn muld<X: Xlen>(x: &mut RvRegisters<X>, rd: usize, rs1: usize, rs2: usize) {
+    set_double(x, rd, double(x, rs1).wrapping_mul(double(x, rs2)));
 }
 
-pub fn remw(x: &mut RvRegisters, rd: usize, rs1: usize, rs2: usize) {
-    let rs1v: Uint = x.get(rs1);
-    let rs2v: Uint = x.get(rs2);
+pub fn divd<X: Xlen>(x: &mut RvRegisters<X>, rd: usize, rs1: usize, rs2: usize) {
+    set_double(x, rd, Xlen::div(double(x, rs1), double(x, rs2)));
+}
 
-    println!("remw\t{},{},{}", x.name(rd), x.name(rs1), x.name(rs2));
+pub fn divud<X: Xlen>(x: &mut RvRegisters<X>, rd: usize, rs1: usize, rs2: usize) {
+    set_double(x, rd, double(x, rs1).divu(double(x, rs2)));
+}
 
-    if rs2v == Uint::zero(x.len()) {
-        x.set(rd, &rs1v);
-        return;
-    }
+pub fn remd<X: Xlen>(x: &mut RvRegisters<X>, rd: usize, rs1: usize, rs2: usize) {
+    set_double(x, rd, Xlen::rem(double(x, rs1), double(x, rs2)));
+}
 
-    match x.len() {
-        64 => {
-            let rs1value: i64 = i64::from(rs1v.clone());
-            let rs2value: i64 = i64::from(rs2v);
-
-            if rs1value == (-1 << 63) && rs2value == -1 {
-                x.set(rd, &Uint::from(0u64));
-            } else {
-                let result: i64 = (rs1value as i32).wrapping_rem(rs2value as i32) as i64;
-
-                x.set(rd, &Uint::from(result));
-            }
-        }
-        128 => {
-            let rs1value: i128 = i128::from(rs1v.clone());
-            let rs2value: i128 = i128::from(rs2v);
-
-            if rs1value == (-1 << 127) && rs2value == -1 {
-                x.set(rd, &Uint::from(0u128));
-            } else {
-                let result: i128 = (rs1value as i32).wrapping_rem(rs2value as i32) as i128;
-
-                x.set(rd, &Uint::from(result));
-            }
-        }
-        _ => unreachable!(),
-    }
+pub fn remud<X: Xlen>(x: &mut RvRegisters<X>, rd: usize, rs1: usize, rs2: usize) {
+    set_double(x, rd, double(x, rs1).remu(double(x, rs2)));
 }
 
-pub fn remuw(x: &mut RvRegisters, rd: usize, rs1: usize, rs2: usize) {
-    let rs1v: Uint = x.get(rs1);
-    let rs2v: Uint = x.get(rs2);
+#[cfg(test)]
+mod tests {
+    use super::super::super::registers::RvRegisters;
 
-    println!(
-        "remuw\t{},{},{}",
-        x.name(rd),
-        x.name(rs1),
-        x.name(rs2)
-    );
+    #[test]
+    fn test_word() {
+        let mut x: RvRegisters<u64> = RvRegisters::new(32);
 
-    if rs2v == Uint::zero(x.len()) {
-        x.set(rd, &rs1v);
-        return;
-    }
+        x.write(1, 0x1_8000_0000);
+        x.write(2, 0x1_0000_0000);
+        super::addw(&mut x, 3, 1, 0);
+        assert_eq!(x.read(3), 0xffff_ffff_8000_0000);
+        // Only the low word of the divisor counts
+        super::divw(&mut x, 3, 1, 2);
+        assert_eq!(x.read(3), u64::MAX);
+        super::remuw(&mut x, 3, 1, 2);
+        assert_eq!(x.read(3), 0xffff_ffff_8000_0000);
+        super::mulh(&mut x, 3, 1, 1);
+        assert_eq!(x.read(3), 2);
+
+        let mut x: RvRegisters<u128> = RvRegisters::new(32);
 
-    match x.len() {
-        64 => {
-            let rs2value: u64 = u64::from(rs2v);
-            let rs1value: u64 = u64::from(rs1v);
-            let result: i64 = (rs1value as u32).wrapping_rem(rs2value as u32) as i32 as i64;
-
-            x.set(rd, &Uint::from(result));
-        }
-        128 => {
-            let rs2value: u128 = u128::from(rs2v);
-            let rs1value: u128 = u128::from(rs1v);
-            let result: i128 = (rs1value as u32).wrapping_rem(rs2value as u32) as i32 as i128;
-
-            x.set(rd, &Uint::from(result));
-        }
-        _ => unreachable!(),
+        x.write(1, 0x8000_0000_0000_0000);
+        super::srad(&mut x, 2, 1, 0);
+        assert_eq!(x.read(2), 0xffff_ffff_ffff_ffff_8000_0000_0000_0000);
+        super::divd(&mut x, 2, 1, 0);
+        assert_eq!(x.read(2), u128::MAX);
     }
 }
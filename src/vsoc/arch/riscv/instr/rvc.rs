@@ -0,0 +1,339 @@
+use super::Instr;
+
+const OPCODE_LOAD: u32 = 0x03;
+const OPCODE_LOAD_FP: u32 = 0x07;
+const OPCODE_MISC_MEM: u32 = 0x0f;
+const OPCODE_OP_IMM: u32 = 0x13;
+const OPCODE_OP_IMM_32: u32 = 0x1b;
+const OPCODE_STORE: u32 = 0x23;
+const OPCODE_STORE_FP: u32 = 0x27;
+const OPCODE_OP: u32 = 0x33;
+const OPCODE_LUI: u32 = 0x37;
+const OPCODE_OP_32: u32 = 0x3b;
+const OPCODE_BRANCH: u32 = 0x63;
+const OPCODE_JALR: u32 = 0x67;
+const OPCODE_JAL: u32 = 0x6f;
+
+const EBREAK: u32 = 0x0010_0073;
+
+fn bits(raw: u32, last: u32, first: u32) -> u32 {
+    (raw >> first) & ((1 << (last - first + 1)) - 1)
+}
+
+fn sext(value: u32, width: u32) -> i32 {
+    ((value << (32 - width)) as i32) >> (32 - width)
+}
+
+fn i_type(opcode: u32, rd: u32, funct3: u32, rs1: u32, imm: i32) -> u32 {
+    ((imm as u32 & 0xfff) << 20) | (rs1 << 15) | (funct3 << 12) | (rd << 7) | opcode
+}
+
+fn s_type(opcode: u32, funct3: u32, rs1: u32, rs2: u32, imm: i32) -> u32 {
+    let imm: u32 = imm as u32;
+
+    (((imm >> 5) & 0x7f) << 25)
+        | (rs2 << 20)
+        | (rs1 << 15)
+        | (funct3 << 12)
+        | ((imm & 0x1f) << 7)
+        | opcode
+}
+
+fn r_type(opcode: u32, funct7: u32, rd: u32, funct3: u32, rs1: u32, rs2: u32) -> u32 {
+    (funct7 << 25) | (rs2 << 20) | (rs1 << 15) | (funct3 << 12) | (rd << 7) | opcode
+}
+
+fn b_type(funct3: u32, rs1: u32, rs2: u32, imm: i32) -> u32 {
+    let imm: u32 = imm as u32;
+
+    (((imm >> 12) & 0x1) << 31)
+        | (((imm >> 5) & 0x3f) << 25)
+        | (rs2 << 20)
+        | (rs1 << 15)
+        | (funct3 << 12)
+        | (((imm >> 1) & 0xf) << 8)
+        | (((imm >> 11) & 0x1) << 7)
+        | OPCODE_BRANCH
+}
+
+fn u_type(opcode: u32, rd: u32, imm: i32) -> u32 {
+    (imm as u32 & 0xffff_f000) | (rd << 7) | opcode
+}
+
+fn j_type(rd: u32, imm: i32) -> u32 {
+    let imm: u32 = imm as u32;
+
+    (((imm >> 20) & 0x1) << 31)
+        | (((imm >> 1) & 0x3ff) << 21)
+        | (((imm >> 11) & 0x1) << 20)
+        | (((imm >> 12) & 0xff) << 12)
+        | (rd << 7)
+        | OPCODE_JAL
+}
+
+// offset[11|4|9:8|10|6|7|3:1|5] of c.j and c.jal
+fn cj_imm(raw: u32) -> i32 {
+    sext(
+        (bits(raw, 12, 12) << 11)
+            | (bits(raw, 11, 11) << 4)
+            | (bits(raw, 10, 9) << 8)
+            | (bits(raw, 8, 8) << 10)
+            | (bits(raw, 7, 7) << 6)
+            | (bits(raw, 6, 6) << 7)
+            | (bits(raw, 5, 3) << 1)
+            | (bits(raw, 2, 2) << 5),
+        12,
+    )
+}
+
+// offset[8|4:3|7:6|2:1|5] of c.beqz and c.bnez
+fn cb_imm(raw: u32) -> i32 {
+    sext(
+        (bits(raw, 12, 12) << 8)
+            | (bits(raw, 11, 10) << 3)
+            | (bits(raw, 6, 5) << 6)
+            | (bits(raw, 4, 3) << 1)
+            | (bits(raw, 2, 2) << 5),
+        9,
+    )
+}
+
+fn quadrant0(instr: &Instr, raw: u32, xlen: usize) -> Option<u32> {
+    let rd: u32 = instr.get_rd_prime() as u32;
+    let rs1: u32 = instr.get_rs1_prime() as u32;
+    let rs2: u32 = instr.get_rs2_prime() as u32;
+    let uimm_w: i32 =
+        ((bits(raw, 12, 10) << 3) | (bits(raw, 6, 6) << 2) | (bits(raw, 5, 5) << 6)) as i32;
+    let uimm_d: i32 = ((bits(raw, 12, 10) << 3) | (bits(raw, 6, 5) << 6)) as i32;
+    let uimm_q: i32 =
+        ((bits(raw, 12, 11) << 4) | (bits(raw, 10, 10) << 8) | (bits(raw, 6, 5) << 6)) as i32;
+
+    match instr.get_funct3() {
+        0x0 => {
+            // c.addi4spn
+            let nzuimm: u32 = (bits(raw, 12, 11) << 4)
+                | (bits(raw, 10, 7) << 6)
+                | (bits(raw, 6, 6) << 2)
+                | (bits(raw, 5, 5) << 3);
+
+            if nzuimm == 0 {
+                return None;
+            }
+
+            Some(i_type(OPCODE_OP_IMM, rd, 0x0, 2, nzuimm as i32))
+        }
+        0x1 if xlen < 128 => Some(i_type(OPCODE_LOAD_FP, rd, 0x3, rs1, uimm_d)), // c.fld
+        0x1 => Some(i_type(OPCODE_MISC_MEM, rd, 0x2, rs1, uimm_q)),              // c.lq
+        0x2 => Some(i_type(OPCODE_LOAD, rd, 0x2, rs1, uimm_w)),                  // c.lw
+        0x3 if xlen == 32 => Some(i_type(OPCODE_LOAD_FP, rd, 0x2, rs1, uimm_w)), // c.flw
+        0x3 => Some(i_type(OPCODE_LOAD, rd, 0x3, rs1, uimm_d)),                  // c.ld
+        0x5 if xlen < 128 => Some(s_type(OPCODE_STORE_FP, 0x3, rs1, rs2, uimm_d)), // c.fsd
+        0x5 => Some(s_type(OPCODE_STORE, 0x4, rs1, rs2, uimm_q)),                // c.sq
+        0x6 => Some(s_type(OPCODE_STORE, 0x2, rs1, rs2, uimm_w)),                // c.sw
+        0x7 if xlen == 32 => Some(s_type(OPCODE_STORE_FP, 0x2, rs1, rs2, uimm_w)), // c.fsw
+        0x7 => Some(s_type(OPCODE_STORE, 0x3, rs1, rs2, uimm_d)),                // c.sd
+        _ => None,
+    }
+}
+
+fn quadrant1(instr: &Instr, raw: u32, xlen: usize) -> Option<u32> {
+    let rd: u32 = instr.get_rd() as u32;
+    let imm: i32 = sext((bits(raw, 12, 12) << 5) | bits(raw, 6, 2), 6);
+
+    match instr.get_funct3() {
+        0x0 => Some(i_type(OPCODE_OP_IMM, rd, 0x0, rd, imm)), // c.addi, c.nop
+        0x1 if xlen == 32 => Some(j_type(1, cj_imm(raw))),    // c.jal
+        0x1 => {
+            // c.addiw
+            if rd == 0 {
+                return None;
+            }
+
+            Some(i_type(OPCODE_OP_IMM_32, rd, 0x0, rd, imm))
+        }
+        0x2 => Some(i_type(OPCODE_OP_IMM, rd, 0x0, 0, imm)), // c.li
+        0x3 => {
+            if rd == 2 {
+                // c.addi16sp
+                let nzimm: i32 = sext(
+                    (bits(raw, 12, 12) << 9)
+                        | (bits(raw, 6, 6) << 4)
+                        | (bits(raw, 5, 5) << 6)
+                        | (bits(raw, 4, 3) << 7)
+                        | (bits(raw, 2, 2) << 5),
+                    10,
+                );
+
+                if nzimm == 0 {
+                    return None;
+                }
+
+                Some(i_type(OPCODE_OP_IMM, 2, 0x0, 2, nzimm))
+            } else {
+                // c.lui
+                let nzimm: i32 = sext((bits(raw, 12, 12) << 17) | (bits(raw, 6, 2) << 12), 18);
+
+                if nzimm == 0 {
+                    return None;
+                }
+
+                Some(u_type(OPCODE_LUI, rd, nzimm))
+            }
+        }
+        0x4 => {
+            let rd: u32 = instr.get_rs1_prime() as u32;
+            let rs2: u32 = instr.get_rs2_prime() as u32;
+            let mut shamt: u32 = (bits(raw, 12, 12) << 5) | bits(raw, 6, 2);
+
+            // RV128 sign-extends the shift amount, 0 meaning 64
+            if xlen == 128 {
+                shamt = match shamt {
+                    0 => 64,
+                    s if s >= 32 => s + 64,
+                    s => s,
+                };
+            }
+
+            match bits(raw, 11, 10) {
+                0x0 if xlen > 32 || shamt < 32 => {
+                    Some(i_type(OPCODE_OP_IMM, rd, 0x5, rd, shamt as i32)) // c.srli
+                }
+                0x1 if xlen > 32 || shamt < 32 => {
+                    Some(i_type(OPCODE_OP_IMM, rd, 0x5, rd, (0x400 | shamt) as i32)) // c.srai
+                }
+                0x2 => Some(i_type(OPCODE_OP_IMM, rd, 0x7, rd, imm)), // c.andi
+                0x3 => match (bits(raw, 12, 12), bits(raw, 6, 5)) {
+                    (0, 0x0) => Some(r_type(OPCODE_OP, 0x20, rd, 0x0, rd, rs2)), // c.sub
+                    (0, 0x1) => Some(r_type(OPCODE_OP, 0x00, rd, 0x4, rd, rs2)), // c.xor
+                    (0, 0x2) => Some(r_type(OPCODE_OP, 0x00, rd, 0x6, rd, rs2)), // c.or
+                    (0, 0x3) => Some(r_type(OPCODE_OP, 0x00, rd, 0x7, rd, rs2)), // c.and
+                    (1, 0x0) if xlen > 32 => Some(r_type(OPCODE_OP_32, 0x20, rd, 0x0, rd, rs2)), // c.subw
+                    (1, 0x1) if xlen > 32 => Some(r_type(OPCODE_OP_32, 0x00, rd, 0x0, rd, rs2)), // c.addw
+                    _ => None,
+                },
+                _ => None,
+            }
+        }
+        0x5 => Some(j_type(0, cj_imm(raw))), // c.j
+        0x6 => Some(b_type(0x0, instr.get_rs1_prime() as u32, 0, cb_imm(raw))), // c.beqz
+        0x7 => Some(b_type(0x1, instr.get_rs1_prime() as u32, 0, cb_imm(raw))), // c.bnez
+        _ => None,
+    }
+}
+
+fn quadrant2(instr: &Instr, raw: u32, xlen: usize) -> Option<u32> {
+    let rd: u32 = instr.get_rd() as u32;
+    let rs2: u32 = instr.get_rs2() as u32;
+    let uimm_w: i32 =
+        ((bits(raw, 12, 12) << 5) | (bits(raw, 6, 4) << 2) | (bits(raw, 3, 2) << 6)) as i32;
+    let uimm_d: i32 =
+        ((bits(raw, 12, 12) << 5) | (bits(raw, 6, 5) << 3) | (bits(raw, 4, 2) << 6)) as i32;
+    let uimm_sw: i32 = ((bits(raw, 12, 9) << 2) | (bits(raw, 8, 7) << 6)) as i32;
+    let uimm_sd: i32 = ((bits(raw, 12, 10) << 3) | (bits(raw, 9, 7) << 6)) as i32;
+    let uimm_q: i32 =
+        ((bits(raw, 12, 12) << 5) | (bits(raw, 6, 6) << 4) | (bits(raw, 5, 2) << 6)) as i32;
+    let uimm_sq: i32 = ((bits(raw, 12, 11) << 4) | (bits(raw, 10, 7) << 6)) as i32;
+
+    match instr.get_funct3() {
+        0x0 => {
+            // c.slli
+            let mut shamt: u32 = (bits(raw, 12, 12) << 5) | bits(raw, 6, 2);
+
+            if xlen == 32 && shamt >= 32 {
+                return None;
+            }
+
+            if xlen == 128 && shamt == 0 {
+                shamt = 64;
+            }
+
+            Some(i_type(OPCODE_OP_IMM, rd, 0x1, rd, shamt as i32))
+        }
+        0x1 if xlen < 128 => Some(i_type(OPCODE_LOAD_FP, rd, 0x3, 2, uimm_d)), // c.fldsp
+        0x1 if rd != 0 => Some(i_type(OPCODE_MISC_MEM, rd, 0x2, 2, uimm_q)),   // c.lqsp
+        0x2 if rd != 0 => Some(i_type(OPCODE_LOAD, rd, 0x2, 2, uimm_w)),       // c.lwsp
+        0x3 if xlen == 32 => Some(i_type(OPCODE_LOAD_FP, rd, 0x2, 2, uimm_w)), // c.flwsp
+        0x3 if rd != 0 => Some(i_type(OPCODE_LOAD, rd, 0x3, 2, uimm_d)),       // c.ldsp
+        0x4 => match (bits(raw, 12, 12), rd, rs2) {
+            (0, 0, 0) => None,
+            (0, _, 0) => Some(i_type(OPCODE_JALR, 0, 0x0, rd, 0)), // c.jr
+            (0, _, _) => Some(r_type(OPCODE_OP, 0x00, rd, 0x0, 0, rs2)), // c.mv
+            (_, 0, 0) => Some(EBREAK),                             // c.ebreak
+            (_, _, 0) => Some(i_type(OPCODE_JALR, 1, 0x0, rd, 0)), // c.jalr
+            (_, _, _) => Some(r_type(OPCODE_OP, 0x00, rd, 0x0, rd, rs2)), // c.add
+        },
+        0x5 if xlen < 128 => Some(s_type(OPCODE_STORE_FP, 0x3, 2, rs2, uimm_sd)), // c.fsdsp
+        0x5 => Some(s_type(OPCODE_STORE, 0x4, 2, rs2, uimm_sq)),                  // c.sqsp
+        0x6 => Some(s_type(OPCODE_STORE, 0x2, 2, rs2, uimm_sw)),                  // c.swsp
+        0x7 if xlen == 32 => Some(s_type(OPCODE_STORE_FP, 0x2, 2, rs2, uimm_sw)), // c.fswsp
+        0x7 => Some(s_type(OPCODE_STORE, 0x3, 2, rs2, uimm_sd)),                  // c.sdsp
+        _ => None,
+    }
+}
+
+// Expand a compressed instruction into its 32-bit equivalent
+pub fn expand(instr: &Instr, xlen: usize) -> Option<u32> {
+    let raw: u32 = instr.get_raw();
+
+    match instr {
+        Instr::InstrC0(_) => quadrant0(instr, raw, xlen),
+        Instr::InstrC1(_) => quadrant1(instr, raw, xlen),
+        Instr::InstrC2(_) => quadrant2(instr, raw, xlen),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::expand;
+    use crate::vsoc::arch::riscv::instr::Instr;
+
+    fn expand64(raw: u32) -> Option<u32> {
+        expand(&Instr::new(raw), 64)
+    }
+
+    #[test]
+    fn test_expand_quadrant0() {
+        assert_eq!(expand64(0x0000), None); // illegal
+        assert_eq!(expand64(0x411c), Some(0x0005_2783)); // lw a5,0(a0)
+        assert_eq!(expand64(0xc188), Some(0x00a5_a023)); // sw a0,0(a1)
+    }
+
+    #[test]
+    fn test_expand_quadrant1() {
+        assert_eq!(expand64(0x0001), Some(0x0000_0013)); // nop
+        assert_eq!(expand64(0x4505), Some(0x0010_0513)); // li a0,1
+        assert_eq!(expand64(0x1141), Some(0xff01_0113)); // addi sp,sp,-16
+        assert_eq!(expand64(0x7179), Some(0xfd01_0113)); // addi sp,sp,-48
+        assert_eq!(expand64(0x6505), Some(0x0000_1537)); // lui a0,0x1
+        assert_eq!(expand64(0x2505), Some(0x0015_051b)); // addiw a0,a0,1
+        assert_eq!(expand64(0x8105), Some(0x0015_5513)); // srli a0,a0,1
+        assert_eq!(expand64(0x8d6d), Some(0x00b5_7533)); // and a0,a0,a1
+        assert_eq!(expand64(0x8d0d), Some(0x40b5_0533)); // sub a0,a0,a1
+        assert_eq!(expand64(0xc501), Some(0x0005_0463)); // beqz a0,8
+    }
+
+    #[test]
+    fn test_expand_quadrant2() {
+        assert_eq!(expand64(0x8082), Some(0x0000_8067)); // ret
+        assert_eq!(expand64(0x852e), Some(0x00b0_0533)); // mv a0,a1
+        assert_eq!(expand64(0x9002), Some(0x0010_0073)); // ebreak
+        assert_eq!(expand64(0xe406), Some(0x0011_3423)); // sd ra,8(sp)
+        assert_eq!(expand64(0x60a2), Some(0x0081_3083)); // ld ra,8(sp)
+    }
+
+    #[test]
+    fn test_expand_xlen() {
+        // c.addiw on RV64 is c.jal on RV32
+        assert_eq!(expand(&Instr::new(0x2001), 32), Some(0x0000_00ef));
+        // c.ld on RV64 is c.flw on RV32
+        assert_eq!(expand(&Instr::new(0x6108), 32), Some(0x0005_2507));
+        // c.fld and c.fsdsp on RV64 are c.lq and c.sqsp on RV128
+        assert_eq!(expand(&Instr::new(0x2908), 128), Some(0x0105_250f));
+        assert_eq!(expand(&Instr::new(0xb02a), 128), Some(0x02a1_4023));
+        // c.srli and c.srai shift amounts are sign-extended on RV128
+        assert_eq!(expand(&Instr::new(0x8105), 128), Some(0x0015_5513));
+        assert_eq!(expand(&Instr::new(0x9101), 128), Some(0x0605_5513));
+        assert_eq!(expand(&Instr::new(0x8501), 128), Some(0x4405_5513));
+    }
+}
@@ -1,120 +1,72 @@
 use super::super::registers::RvRegisters;
-use crate::vsoc::{
-    arch::{riscv::exception::RvException, types::Uint},
-    bus::Bus,
-};
+use super::super::xlen::Xlen;
+use super::load::address;
+use crate::vsoc::arch::riscv::{exception::RvException, mmu::AddressSpace};
 
-pub fn sb(
-    x: &mut RvRegisters,
+// Store the low width bytes of rs2
+fn store<X: Xlen>(
+    x: &mut RvRegisters<X>,
     rs1: usize,
     rs2: usize,
     imm: i32,
-    bus: &mut Bus,
-) -> Result<Uint, RvException> {
-    let addr: u64 = if imm < 0 {
-        u64::from(x.get(rs1)) - imm.unsigned_abs() as u64
-    } else {
-        u64::from(x.get(rs1)) + imm as u64
-    };
-    let mut value: Uint = x.get(rs2);
+    mem: &mut AddressSpace,
+    width: usize,
+) -> Result<X, RvException> {
+    let addr: u64 = address(x, rs1, imm).ok_or(RvException::StoreAccessFault)?;
+    let value: X = x.read(rs2);
 
-    value.truncate(1);
-    println!(
-        "sb\t{},{}({})",
-        x.name(rs2),
-        imm,
-        x.name(rs1)
-    );
-
-    match bus.store(1, addr, &Vec::<u8>::from(value.clone())) {
+    match mem.store(width, addr, &value.to_u128().to_le_bytes()[..width]) {
         None => Ok(value),
-        Some(e) => Err(RvException::from(e)),
+        Some(e) => Err(e),
     }
 }
 
-pub fn sh(
-    x: &mut RvRegisters,
+pub fn sb<X: Xlen>(
+    x: &mut RvRegisters<X>,
     rs1: usize,
     rs2: usize,
     imm: i32,
-    bus: &mut Bus,
-) -> Result<Uint, RvException> {
-    let addr: u64 = if imm < 0 {
-        u64::from(x.get(rs1)) - imm.unsigned_abs() as u64
-    } else {
-        u64::from(x.get(rs1)) + imm as u64
-    };
-    let mut value: Uint = x.get(rs2);
-
-    value.truncate(2);
-    println!(
-        "sh\t{},{}({})\t# {}",
-        x.name(rs2),
-        imm,
-        x.name(rs1),
-        value
-    );
-
-    match bus.store(2, addr, &Vec::<u8>::from(value.clone())) {
-        None => Ok(value),
-        Some(e) => Err(RvException::from(e)),
-    }
+    mem: &mut AddressSpace,
+) -> Result<X, RvException> {
+    store(x, rs1, rs2, imm, mem, 1)
 }
 
-pub fn sw(
-    x: &mut RvRegisters,
+pub fn sh<X: Xlen>(
+    x: &mut RvRegisters<X>,
     rs1: usize,
     rs2: usize,
     imm: i32,
-    bus: &mut Bus,
-) -> Result<Uint, RvException> {
-    let addr: u64 = if imm < 0 {
-        u64::from(x.get(rs1)) - imm.unsigned_abs() as u64
-    } else {
-        u64::from(x.get(rs1)) + imm as u64
-    };
-    let mut value: Uint = x.get(rs2);
-
-    value.truncate(4);
-    println!(
-        "sw\t{},{}({})\t# {}",
-        x.name(rs2),
-        imm,
-        x.name(rs1),
-        value
-    );
-
-    match bus.store(4, addr, &Vec::<u8>::from(value.clone())) {
-        None => Ok(value),
-        Some(e) => Err(RvException::from(e)),
-    }
+    mem: &mut AddressSpace,
+) -> Result<X, RvException> {
+    store(x, rs1, rs2, imm, mem, 2)
 }
 
-pub fn sd(
-    x: &mut RvRegisters,
+pub fn sw<X: Xlen>(
+    x: &mut RvRegisters<X>,
     rs1: usize,
     rs2: usize,
     imm: i32,
-    bus: &mut Bus,
-) -> Result<Uint, RvException> {
-    let addr: u64 = if imm < 0 {
-        u64::from(x.get(rs1)) - imm.unsigned_abs() as u64
-    } else {
-        u64::from(x.get(rs1)) + imm as u64
-    };
-    let mut value: Uint = x.get(rs2);
+    mem: &mut AddressSpace,
+) -> Result<X, RvException> {
+    store(x, rs1, rs2, imm, mem, 4)
+}
 
-    value.truncate(8);
-    println!(
-        "sd\t{},{}({})\t# {}",
-        x.name(rs2),
-        imm,
-        x.name(rs1),
-        value
-    );
+pub fn sd<X: Xlen>(
+    x: &mut RvRegisters<X>,
+    rs1: usize,
+    rs2: usize,
+    imm: i32,
+    mem: &mut AddressSpace,
+) -> Result<X, RvException> {
+    store(x, rs1, rs2, imm, mem, 8)
+}
 
-    match bus.store(8, addr, &Vec::<u8>::from(value.clone())) {
-        None => Ok(value),
-        Some(e) => Err(RvException::from(e)),
-    }
+pub fn sq<X: Xlen>(
+    x: &mut RvRegisters<X>,
+    rs1: usize,
+    rs2: usize,
+    imm: i32,
+    mem: &mut AddressSpace,
+) -> Result<X, RvException> {
+    store(x, rs1, rs2, imm, mem, 16)
 }
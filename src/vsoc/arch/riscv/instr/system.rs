@@ -1,110 +1,356 @@
 use crate::vsoc::arch::{
     riscv::{
-        csr::Csr,
+        csr::{self, Csr},
         exception::RvException,
+        mmu::{AddressSpace, Mmu},
+        privilege::RvPrivilege,
     },
     types::Uint,
 };
 
 use super::super::registers::RvRegisters;
+use super::super::xlen::Xlen;
 
-pub fn ecall(_x: &mut RvRegisters) -> Option<RvException> {
-    println!("ecall\ttodo\ttodo\ttodo");
+pub fn ecall<X: Xlen>(
+    _x: &mut RvRegisters<X>,
+    privilege: RvPrivilege,
+    csr: &Option<Csr>,
+) -> Option<RvException> {
+    let virt: bool = csr.as_ref().is_some_and(|c| c.virt());
+
+    match privilege {
+        RvPrivilege::User => Some(RvException::EnvironmentCallUMode),
+        RvPrivilege::Supervisor if virt => Some(RvException::EnvironmentCallVSMode),
+        RvPrivilege::Supervisor => Some(RvException::EnvironmentCallSMode),
+        RvPrivilege::Machine => Some(RvException::EnvironmentCallMMode),
+    }
+}
+
+pub fn ebreak<X: Xlen>(_x: &mut RvRegisters<X>) -> Option<RvException> {
+    Some(RvException::Breakpoint)
+}
+
+pub fn wfi(privilege: RvPrivilege, csr: &Option<Csr>) -> Option<RvException> {
+    if let Some(c) = csr {
+        let mstatus: u128 = c.read(csr::MSTATUS).unwrap();
+
+        if privilege < RvPrivilege::Machine && mstatus & csr::MSTATUS_TW != 0 {
+            return Some(RvException::InstructionIllegal);
+        }
+
+        let hstatus: u128 = c.read(csr::HSTATUS).unwrap();
+
+        if c.virt() && privilege == RvPrivilege::Supervisor && hstatus & csr::HSTATUS_VTW != 0 {
+            return Some(RvException::VirtualInstruction);
+        }
+    }
 
     None
 }
 
-pub fn ebreak(_x: &mut RvRegisters) -> Option<RvException> {
-    println!("ebreak\ttodo\ttodo\ttodo");
+pub fn sfence_vma<X: Xlen>(
+    x: &RvRegisters<X>,
+    rs1: usize,
+    rs2: usize,
+    privilege: RvPrivilege,
+    csr: &Option<Csr>,
+    mmu: &mut Mmu,
+) -> Option<RvException> {
+    let (mstatus, c) = match csr {
+        Some(c) if c.exists(csr::SATP) => (c.read(csr::MSTATUS).unwrap(), c),
+        _ => return Some(RvException::InstructionIllegal),
+    };
+
+    // In VS-mode it flushes the guest translations, unless hstatus.VTVM traps it
+    if c.virt() {
+        let hstatus: u128 = c.read(csr::HSTATUS).unwrap();
 
-    Some(RvException::Breakpoint)
+        if privilege == RvPrivilege::User || hstatus & csr::HSTATUS_VTVM != 0 {
+            return Some(RvException::VirtualInstruction);
+        }
+
+        mmu.flush_guest();
+        return None;
+    }
+
+    if privilege == RvPrivilege::User
+        || (privilege == RvPrivilege::Supervisor && mstatus & csr::MSTATUS_TVM != 0)
+    {
+        return Some(RvException::InstructionIllegal);
+    }
+
+    let vaddr: Option<u64> = if rs1 != 0 {
+        Some(u64::from(x.get(rs1)))
+    } else {
+        None
+    };
+    let asid: Option<u64> = if rs2 != 0 {
+        Some(u64::from(x.get(rs2)))
+    } else {
+        None
+    };
+
+    mmu.flush(vaddr, asid);
+
+    None
 }
 
-pub fn xret(
-    _x: &mut RvRegisters,
-    _rd: usize,
-    _rs1: usize,
-    _funct12: usize,
-    _csr: &mut Csr,
+// hfence.vvma and hfence.gvma, from HS-mode or M-mode only. hgatp being
+// trapped by mstatus.TVM, so is hfence.gvma
+pub fn hfence(
+    gvma: bool,
+    privilege: RvPrivilege,
+    csr: &Option<Csr>,
+    mmu: &mut Mmu,
 ) -> Option<RvException> {
-    println!("xret\ttodo\ttodo\ttodo");
+    let c: &Csr = match csr {
+        Some(c) if c.exists(csr::HGATP) => c,
+        _ => return Some(RvException::InstructionIllegal),
+    };
+    let mstatus: u128 = c.read(csr::MSTATUS).unwrap();
+
+    if c.virt() {
+        return Some(RvException::VirtualInstruction);
+    }
+
+    if privilege == RvPrivilege::User
+        || (gvma && privilege == RvPrivilege::Supervisor && mstatus & csr::MSTATUS_TVM != 0)
+    {
+        return Some(RvException::InstructionIllegal);
+    }
+
+    mmu.flush_guest();
 
     None
 }
 
-pub fn csrrw(
-    x: &mut RvRegisters,
+// Privilege of the hlv/hlvx/hsv accesses, hstatus.SPVP selects VS-mode or
+// VU-mode. U-mode may use them when hstatus.HU is set
+pub fn guest_privilege(csr: &Csr, privilege: RvPrivilege) -> Result<RvPrivilege, RvException> {
+    let hstatus: u128 = csr.read(csr::HSTATUS).unwrap();
+
+    if csr.virt() {
+        return Err(RvException::VirtualInstruction);
+    }
+
+    if privilege == RvPrivilege::User && hstatus & csr::HSTATUS_HU == 0 {
+        return Err(RvException::InstructionIllegal);
+    }
+
+    if hstatus & csr::HSTATUS_SPVP != 0 {
+        Ok(RvPrivilege::Supervisor)
+    } else {
+        Ok(RvPrivilege::User)
+    }
+}
+
+// hlv/hlvx load a byte/half/word/double sign-extended (rs2 = 0), zero-extended
+// (rs2 = 1) or with execute permission (rs2 = 3), hsv store them
+pub fn hlv_hsv<X: Xlen>(
+    x: &mut RvRegisters<X>,
     rd: usize,
     rs1: usize,
-    funct3: usize,
-    funct12: usize,
-    csr: &mut Csr,
+    rs2: usize,
+    funct7: usize,
+    mem: &mut AddressSpace,
 ) -> Option<RvException> {
-    let value: Uint;
-    let dest: Uint = match csr.get(funct12) {
-        Some(d) => d,
-        None => return Some(RvException::InstructionIllegal),
+    let xlen: usize = x.len();
+    let width: usize = 1 << ((funct7 >> 1) & 0x3);
+    let addr: u64 = u64::from(x.get(rs1));
+
+    if width == 8 && xlen == 32 {
+        return Some(RvException::InstructionIllegal);
+    }
+
+    if funct7 & 0x1 != 0 {
+        if rd != 0 {
+            return Some(RvException::InstructionIllegal);
+        }
+
+        let value: Vec<u8> = u128::from(x.get(rs2)).to_le_bytes()[..width].to_vec();
+
+        return mem.store(width, addr, &value);
+    }
+
+    let signed: bool = match (rs2, width) {
+        (0x0, _) => true,
+        (0x1, 1 | 2) => false,
+        (0x1, 4) if xlen > 32 => false,
+        (0x3, 2 | 4) => false,
+        _ => return Some(RvException::InstructionIllegal),
+    };
+    let value: Vec<u8> = match mem.fetch(width, addr) {
+        Ok(v) => v,
+        Err(e) => return Some(e),
     };
+    let mut bytes: [u8; 16] = [0; 16];
+
+    bytes[..width].copy_from_slice(&value);
+
+    let mut value: u128 = u128::from_le_bytes(bytes);
+    let shift: usize = 128 - 8 * width;
 
-    match funct3 {
-        0x1 => {
-            // csrrw
-            value = match csr.get(funct12) {
-                Some(d) => d,
-                None => return Some(RvException::InstructionIllegal),
+    if signed {
+        value = (((value << shift) as i128) >> shift) as u128;
+    }
+    x.write(rd, X::from_u128(value));
+
+    None
+}
+
+pub fn xret<X: Xlen>(
+    pc: X,
+    funct12: usize,
+    csr: &mut Csr,
+    privilege: &mut RvPrivilege,
+) -> Result<i128, RvException> {
+    let mut mstatus: u128 = csr.read(csr::MSTATUS).unwrap();
+    let hstatus: u128 = csr.read(csr::HSTATUS).unwrap();
+    let hypervisor: bool = csr.exists(csr::HSTATUS);
+    // Virtualization mode to return to
+    let mut virt: bool = csr.virt();
+    let epc: u128;
+
+    match funct12 {
+        0x302 if *privilege == RvPrivilege::Machine => {
+            // mret
+            epc = csr.read(csr::MEPC).unwrap() & !0x1;
+            *privilege =
+                RvPrivilege::from_mpp((mstatus & csr::MSTATUS_MPP) >> csr::MSTATUS_MPP_SHIFT)
+                    .unwrap_or(RvPrivilege::Machine);
+            virt = *privilege != RvPrivilege::Machine && csr.machine_virt().1;
+
+            if mstatus & csr::MSTATUS_MPIE != 0 {
+                mstatus |= csr::MSTATUS_MIE;
+            } else {
+                mstatus &= !csr::MSTATUS_MIE;
+            }
+            mstatus |= csr::MSTATUS_MPIE;
+            // MPP is WARL, it stays M when U-mode is not supported
+            mstatus &= !csr::MSTATUS_MPP;
+        }
+        0x102 if virt => {
+            // sret in VS-mode returns through the VS CSRs
+            if *privilege == RvPrivilege::User || hstatus & csr::HSTATUS_VTSR != 0 {
+                return Err(RvException::VirtualInstruction);
+            }
+
+            let mut vsstatus: u128 = csr.read(csr::VSSTATUS).unwrap();
+
+            epc = csr.read(csr::VSEPC).unwrap() & !0x1;
+            *privilege = if vsstatus & csr::MSTATUS_SPP != 0 {
+                RvPrivilege::Supervisor
+            } else {
+                RvPrivilege::User
             };
 
-            if rd == 0 {
-                println!("csrw\t{},{}", csr.name(funct12), x.name(rs1));
+            if vsstatus & csr::MSTATUS_SPIE != 0 {
+                vsstatus |= csr::MSTATUS_SIE;
             } else {
-                println!(
-                    "csrrw\t{},{},{}",
-                    csr.name(funct12),
-                    x.name(rd),
-                    x.name(rs1)
-                );
+                vsstatus &= !csr::MSTATUS_SIE;
             }
+            vsstatus |= csr::MSTATUS_SPIE;
+            vsstatus &= !csr::MSTATUS_SPP;
+            csr.write(csr::VSSTATUS, vsstatus);
         }
-        0x5 => {
-            // csrrwi
-            value = match x.len() {
-                32 => Uint::from((((rs1 << 26) as i32) >> 26) as u32),
-                64 => Uint::from((((rs1 << 26) as i32) >> 26) as i64 as u64),
-                128 => Uint::from((((rs1 << 26) as i32) >> 26) as i128 as u128),
-                _ => return Some(RvException::InstructionIllegal),
+        0x102 if *privilege >= RvPrivilege::Supervisor && csr.exists(csr::SEPC) => {
+            // sret
+            if *privilege == RvPrivilege::Supervisor && mstatus & csr::MSTATUS_TSR != 0 {
+                return Err(RvException::InstructionIllegal);
+            }
+
+            epc = csr.read(csr::SEPC).unwrap() & !0x1;
+            virt = hypervisor && hstatus & csr::HSTATUS_SPV != 0;
+            *privilege = if mstatus & csr::MSTATUS_SPP != 0 {
+                RvPrivilege::Supervisor
+            } else {
+                RvPrivilege::User
             };
 
-            if rd == 0 {
-                println!("csrwi\t{},{}", csr.name(funct12), rs1);
+            if mstatus & csr::MSTATUS_SPIE != 0 {
+                mstatus |= csr::MSTATUS_SIE;
             } else {
-                println!("csrrwi\t{},{}", csr.name(funct12), rs1);
+                mstatus &= !csr::MSTATUS_SIE;
             }
+            mstatus |= csr::MSTATUS_SPIE;
+            mstatus &= !csr::MSTATUS_SPP;
         }
-        _ => return Some(RvException::InstructionIllegal),
+        _ => return Err(RvException::InstructionIllegal),
+    }
+
+    if *privilege != RvPrivilege::Machine {
+        mstatus &= !csr::MSTATUS_MPRV;
+    }
+    csr.write(csr::MSTATUS, mstatus);
+    if hypervisor {
+        if funct12 == 0x302 {
+            let (gva, _) = csr.machine_virt();
+
+            csr.set_machine_virt(gva, false);
+        }
+        csr.set_virt(virt);
+    }
+
+    Ok(epc as i128 - pc.to_u128() as i128)
+}
+
+pub fn csrrw<X: Xlen>(
+    x: &mut RvRegisters<X>,
+    rd: usize,
+    rs1: usize,
+    funct3: usize,
+    funct12: usize,
+    csr: &mut Csr,
+    privilege: RvPrivilege,
+) -> Option<RvException> {
+    let write: bool = funct3 & 0x3 == 0x1 || rs1 != 0;
+
+    if let Some(e) = csr.check(funct12, privilege, write) {
+        return Some(e);
     }
 
+    let funct12: usize = csr.alias(funct12);
+
+    let dest: Uint = match csr.get(funct12) {
+        Some(d) => d,
+        None => return Some(RvException::InstructionIllegal),
+    };
+    let value: Uint = match funct3 {
+        0x1 => x.get(rs1),                         // csrrw
+        0x5 => csr::to_xlen(x.len(), rs1 as u128), // csrrwi
+        _ => return Some(RvException::InstructionIllegal),
+    };
+
     csr.set(funct12, &value);
     x.set(rd, &dest);
 
     None
 }
 
-pub fn csrrs(
-    x: &mut RvRegisters,
+pub fn csrrs<X: Xlen>(
+    x: &mut RvRegisters<X>,
     rd: usize,
     rs1: usize,
     funct3: usize,
     funct12: usize,
     csr: &mut Csr,
+    privilege: RvPrivilege,
 ) -> Option<RvException> {
     let value: Uint;
+    let write: bool = funct3 & 0x3 == 0x1 || rs1 != 0;
+
+    if let Some(e) = csr.check(funct12, privilege, write) {
+        return Some(e);
+    }
+
+    let funct12: usize = csr.alias(funct12);
+
     let dest: Uint = match csr.get(funct12) {
         Some(d) => d,
         None => return Some(RvException::InstructionIllegal),
     };
 
     if rs1 == 0 {
-        println!("csrr\t{},{}", x.name(rd), csr.name(funct12));
     } else {
         match funct3 {
             0x2 => {
@@ -115,9 +361,7 @@ pub fn csrrs(
                     128 => Uint::from(u128::from(dest.clone()) | u128::from(x.get(rs1))),
                     _ => return Some(RvException::InstructionIllegal),
                 };
-
-                println!("csrs\t{},{:0x},{:0x}", x.name(rd), funct12, rs1);
-            },
+            }
             0x6 => {
                 // csrrsi
                 value = match x.len() {
@@ -126,9 +370,7 @@ pub fn csrrs(
                     128 => Uint::from(u128::from(dest.clone()) | rs1 as u128),
                     _ => return Some(RvException::InstructionIllegal),
                 };
-
-                println!("csrrs\t{},{},{:0x}", x.name(rd), csr.name(funct12), rs1);
-            },
+            }
             _ => return Some(RvException::InstructionIllegal),
         }
 
@@ -140,22 +382,30 @@ pub fn csrrs(
     None
 }
 
-pub fn csrrc(
-    x: &mut RvRegisters,
+pub fn csrrc<X: Xlen>(
+    x: &mut RvRegisters<X>,
     rd: usize,
     rs1: usize,
     funct3: usize,
     funct12: usize,
     csr: &mut Csr,
+    privilege: RvPrivilege,
 ) -> Option<RvException> {
     let value: Uint;
+    let write: bool = funct3 & 0x3 == 0x1 || rs1 != 0;
+
+    if let Some(e) = csr.check(funct12, privilege, write) {
+        return Some(e);
+    }
+
+    let funct12: usize = csr.alias(funct12);
+
     let dest: Uint = match csr.get(funct12) {
         Some(d) => d,
         None => return Some(RvException::InstructionIllegal),
     };
 
     if rs1 == 0 {
-        println!("csrr\t{},{}", x.name(rd), csr.name(funct12));
     } else {
         match funct3 {
             0x3 => {
@@ -166,9 +416,7 @@ pub fn csrrc(
                     128 => Uint::from(u128::from(dest.clone()) & !u128::from(x.get(rs1))),
                     _ => return Some(RvException::InstructionIllegal),
                 };
-
-                println!("csrc\t{},{},{:0x}", x.name(rd), csr.name(funct12), rs1);
-            },
+            }
             0x7 => {
                 // csrrci
                 value = match x.len() {
@@ -177,9 +425,7 @@ pub fn csrrc(
                     128 => Uint::from(u128::from(dest.clone()) & !(rs1 as u128)),
                     _ => return Some(RvException::InstructionIllegal),
                 };
-
-                println!("csrrc\t{},{},{:0x}", x.name(rd), csr.name(funct12), rs1);
-            },
+            }
             _ => return Some(RvException::InstructionIllegal),
         }
 
@@ -190,3 +436,64 @@ pub fn csrrc(
 
     None
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::vsoc::Vsoc;
+
+    #[test]
+    fn test_guest() {
+        let arch: String = String::from("rv64ihsu_zicsr");
+        let mut vsoc: Vsoc = Vsoc::new(&arch);
+        // Open PMP then mret to VS-mode with vsscratch set, the guest reads sscratch then
+        // traps on hstatus and ecall, the M-mode handler skips both
+        let program: [u32; 28] = [
+            0xfff0_0293, // li t0, -1
+            0x3b02_9073, // csrw pmpaddr0, t0
+            0x00f0_0293, // li t0, 0xf
+            0x3a02_9073, // csrw pmpcfg0, t0
+            0x0000_0297, // la t0, guest
+            0x03c2_8293,
+            0x3412_9073, // csrw mepc, t0
+            0x0000_0297, // la t0, handler
+            0x03c2_8293,
+            0x3052_9073, // csrw mtvec, t0
+            0x0000_12b7, // li t0, MPP_S | MPV
+            0x8002_829b,
+            0x0010_0313,
+            0x0273_1313,
+            0x0062_e2b3,
+            0x3002_a073, // csrs mstatus, t0
+            0x02a0_0293, // li t0, 42
+            0x2402_9073, // csrw vsscratch, t0
+            0x3020_0073, // mret
+            0x1400_2573, // guest: csrr a0, sscratch
+            0x6000_25f3, // csrr a1, hstatus
+            0x0000_0073, // ecall
+            0x3420_2673, // handler: csrr a2, mcause
+            0x3000_26f3, // csrr a3, mstatus
+            0x3410_22f3, // csrr t0, mepc
+            0x0042_8293, // addi t0, t0, 4
+            0x3412_9073, // csrw mepc, t0
+            0x3020_0073, // mret
+        ];
+        let binary: Vec<u8> = program.iter().flat_map(|i| i.to_le_bytes()).collect();
+        let get = |vsoc: &Vsoc, reg: usize| {
+            u64::from_le_bytes(vsoc.read_register(reg).unwrap().try_into().unwrap())
+        };
+
+        vsoc.load(&binary).unwrap();
+        for _ in 0..27 {
+            assert!(vsoc.step().is_none());
+        }
+        assert_eq!(get(&vsoc, 10), 42);
+        assert_eq!(get(&vsoc, 12), 0x16);
+        assert_eq!(get(&vsoc, 13) & (1 << 39), 1 << 39);
+
+        for _ in 0..3 {
+            assert!(vsoc.step().is_none());
+        }
+        assert_eq!(get(&vsoc, 12), 0xa);
+        assert_eq!(get(&vsoc, 13) & (1 << 39), 1 << 39);
+    }
+}
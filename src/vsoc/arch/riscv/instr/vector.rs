@@ -0,0 +1,1078 @@
+use crate::vsoc::arch::riscv::{
+    csr::{self, Csr},
+    exception::RvException,
+    registers::{RvRegisters, RvVectorRegisters},
+    xlen::Xlen,
+};
+
+// Widest element supported, vector floating-point aside
+pub const ELEN: usize = 64;
+
+// Vector configuration set by vsetvl, SEW in bytes and LMUL as a power of 2
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Vtype {
+    pub vill: bool,
+    pub sew: usize,
+    pub lmul: i32,
+    pub ta: bool,
+    pub ma: bool,
+}
+
+impl Vtype {
+    pub fn decode(value: u128, xlen: usize) -> Vtype {
+        let vlmul: u128 = value & 0x7;
+        let vsew: u128 = (value >> 3) & 0x7;
+        let lmul: i32 = if vlmul < 4 {
+            vlmul as i32
+        } else {
+            vlmul as i32 - 8
+        };
+        let sew: usize = 1 << vsew;
+        let reserved: u128 = (value & (u128::MAX >> (128 - xlen))) >> 8;
+        // A fractional LMUL must still hold one element of ELEN bits
+        let vill: bool =
+            reserved != 0 || vlmul == 4 || vsew > 3 || (lmul < 0 && sew * 8 > ELEN >> -lmul);
+
+        Vtype {
+            vill,
+            sew,
+            lmul,
+            ta: value & 0x40 != 0,
+            ma: value & 0x80 != 0,
+        }
+    }
+
+    pub fn encode(&self, xlen: usize) -> u128 {
+        if self.vill {
+            return 1 << (xlen - 1);
+        }
+
+        (self.lmul & 0x7) as u128
+            | (self.sew.trailing_zeros() as u128) << 3
+            | (self.ta as u128) << 6
+            | (self.ma as u128) << 7
+    }
+
+    pub fn vlmax(&self, vlen: usize) -> usize {
+        if self.vill {
+            0
+        } else if self.lmul < 0 {
+            (vlen >> -self.lmul) / (self.sew * 8)
+        } else {
+            (vlen << self.lmul) / (self.sew * 8)
+        }
+    }
+}
+
+// State an instruction executes under
+pub struct Config {
+    pub vl: usize,
+    pub vstart: usize,
+    pub vtype: Vtype,
+    pub vlmax: usize,
+}
+
+impl Config {
+    pub fn read(csr: &Csr, vlen: usize) -> Config {
+        let get = |addr: usize| csr.read(addr).unwrap();
+        let vtype: Vtype = Vtype::decode(get(csr::VTYPE), csr.xlen());
+
+        Config {
+            vl: get(csr::VL) as usize,
+            vstart: get(csr::VSTART) as usize,
+            vtype,
+            vlmax: vtype.vlmax(vlen),
+        }
+    }
+
+    // EMUL of a register group of `eew` byte elements, if it is a legal one
+    fn emul(&self, eew: usize) -> Option<i32> {
+        let emul: i32 =
+            self.vtype.lmul + eew.trailing_zeros() as i32 - self.vtype.sew.trailing_zeros() as i32;
+
+        if eew == 0 || eew > ELEN / 8 || !(-3..=3).contains(&emul) {
+            return None;
+        }
+
+        Some(emul)
+    }
+
+    // Register groups must start on a multiple of their size
+    fn aligned(&self, reg: usize, eew: usize) -> bool {
+        match self.emul(eew) {
+            Some(emul) => emul <= 0 || reg.is_multiple_of(1 << emul),
+            None => false,
+        }
+    }
+}
+
+// Fields of an OP-V instruction
+pub struct Fields {
+    pub funct6: usize,
+    pub funct3: usize,
+    pub vm: bool,
+    pub vd: usize,
+    pub rs1: usize,
+    pub vs2: usize,
+}
+
+// Second operand of the .vv, .vx and .vi forms
+#[derive(Clone, Copy)]
+enum Operand {
+    Vector(usize),
+    Scalar(u64),
+}
+
+impl Operand {
+    fn get(&self, v: &RvVectorRegisters, eew: usize, i: usize) -> u64 {
+        match self {
+            Operand::Vector(r) => v.get(*r, eew, i),
+            Operand::Scalar(value) => value & ones(eew),
+        }
+    }
+}
+
+fn ones(eew: usize) -> u64 {
+    u64::MAX >> (64 - 8 * eew)
+}
+
+// Sign-extend an element of `eew` bytes
+fn sext(value: u64, eew: usize) -> i64 {
+    ((value << (64 - 8 * eew)) as i64) >> (64 - 8 * eew)
+}
+
+// Scalar operand, sign-extended on RV32 and truncated on RV128
+pub(super) fn xreg<X: Xlen>(x: &RvRegisters<X>, r: usize) -> u64 {
+    let value: u128 = u128::from(x.get(r));
+
+    match x.len() {
+        32 => value as u32 as i32 as i64 as u64,
+        _ => value as u64,
+    }
+}
+
+fn set_xreg<X: Xlen>(x: &mut RvRegisters<X>, rd: usize, value: i64) {
+    x.write(rd, X::from_i128(value as i128));
+}
+
+// Fixed-point rounding of `value` shifted right by `d` bits, as selected by vxrm
+fn roundoff(value: i128, d: usize, vxrm: u64) -> i128 {
+    if d == 0 {
+        return value;
+    }
+
+    let bit = |n: usize| (value >> n) & 1;
+    let below = |n: usize| value & ((1i128 << n) - 1) != 0;
+    let r: i128 = match vxrm {
+        0 => bit(d - 1),
+        1 => bit(d - 1) & (below(d - 1) as i128 | bit(d)),
+        2 => 0,
+        _ => (bit(d) == 0 && below(d)) as i128,
+    };
+
+    (value >> d) + r
+}
+
+// Signed saturation to `eew` bytes
+fn clamp(value: i128, eew: usize, sat: &mut bool) -> u64 {
+    let max: i128 = (ones(eew) >> 1) as i128;
+    let min: i128 = -max - 1;
+
+    if value > max {
+        *sat = true;
+        max as u64
+    } else if value < min {
+        *sat = true;
+        min as u64
+    } else {
+        value as u64
+    }
+}
+
+fn clamp_unsigned(value: i128, eew: usize, sat: &mut bool) -> u64 {
+    if value > ones(eew) as i128 {
+        *sat = true;
+        ones(eew)
+    } else if value < 0 {
+        *sat = true;
+        0
+    } else {
+        value as u64
+    }
+}
+
+// vtype.ma and vtype.ta are honoured by leaving the elements undisturbed
+
+// vd[i] = op(vs2[i], operand[i], vd[i]) for the active body elements, with
+// the element width of vd, vs2 and the operand
+fn elementwise(
+    v: &mut RvVectorRegisters,
+    cfg: &Config,
+    f: &Fields,
+    b: Operand,
+    eew: [usize; 3],
+    mut op: impl FnMut(u64, u64, u64) -> u64,
+) -> Option<RvException> {
+    let [ed, e2, e1] = eew;
+
+    if !cfg.aligned(f.vd, ed)
+        || !cfg.aligned(f.vs2, e2)
+        || matches!(b, Operand::Vector(r) if !cfg.aligned(r, e1))
+        || (!f.vm && f.vd == 0)
+    {
+        return Some(RvException::InstructionIllegal);
+    }
+
+    let src: RvVectorRegisters = v.clone();
+
+    for i in cfg.vstart..cfg.vl {
+        if f.vm || src.mask(0, i) {
+            let result: u64 = op(
+                src.get(f.vs2, e2, i),
+                b.get(&src, e1, i),
+                src.get(f.vd, ed, i),
+            );
+
+            v.set(f.vd, ed, i, result & ones(ed));
+        }
+    }
+
+    None
+}
+
+// Mask bit vd[i] = op(vs2[i], operand[i], carry), v0 being a carry-in rather
+// than a mask when `carry` is set
+fn compare(
+    v: &mut RvVectorRegisters,
+    cfg: &Config,
+    f: &Fields,
+    b: Operand,
+    carry: bool,
+    op: impl Fn(u64, u64, bool) -> bool,
+) -> Option<RvException> {
+    let sew: usize = cfg.vtype.sew;
+
+    if !cfg.aligned(f.vs2, sew) || matches!(b, Operand::Vector(r) if !cfg.aligned(r, sew)) {
+        return Some(RvException::InstructionIllegal);
+    }
+
+    let src: RvVectorRegisters = v.clone();
+
+    for i in cfg.vstart..cfg.vl {
+        let c: bool = !f.vm && src.mask(0, i);
+
+        if carry || f.vm || c {
+            let result: bool = op(src.get(f.vs2, sew, i), b.get(&src, sew, i), carry && c);
+
+            v.set_mask(f.vd, i, result);
+        }
+    }
+
+    None
+}
+
+// vadc and vsbc take v0 as carry-in, and vmerge as selector, on all body elements
+fn select(
+    v: &mut RvVectorRegisters,
+    cfg: &Config,
+    f: &Fields,
+    b: Operand,
+    op: impl Fn(u64, u64, bool) -> u64,
+) -> Option<RvException> {
+    let sew: usize = cfg.vtype.sew;
+
+    if f.vd == 0
+        || !cfg.aligned(f.vd, sew)
+        || !cfg.aligned(f.vs2, sew)
+        || matches!(b, Operand::Vector(r) if !cfg.aligned(r, sew))
+    {
+        return Some(RvException::InstructionIllegal);
+    }
+
+    let src: RvVectorRegisters = v.clone();
+
+    for i in cfg.vstart..cfg.vl {
+        let result: u64 = op(src.get(f.vs2, sew, i), b.get(&src, sew, i), src.mask(0, i));
+
+        v.set(f.vd, sew, i, result & ones(sew));
+    }
+
+    None
+}
+
+// vd[0] = fold of vs1[0] with the active elements of vs2, which are widened
+// to 2*SEW by `op` for the widening reductions
+fn reduce(
+    v: &mut RvVectorRegisters,
+    cfg: &Config,
+    f: &Fields,
+    widen: bool,
+    op: impl Fn(u64, u64) -> u64,
+) -> Option<RvException> {
+    let sew: usize = cfg.vtype.sew;
+    let ed: usize = if widen { 2 * sew } else { sew };
+
+    if cfg.vstart != 0 || !cfg.aligned(f.vs2, sew) || ed > ELEN / 8 {
+        return Some(RvException::InstructionIllegal);
+    }
+
+    if cfg.vl == 0 {
+        return None;
+    }
+
+    let mut acc: u64 = v.get(f.rs1, ed, 0);
+
+    for i in 0..cfg.vl {
+        if f.vm || v.mask(0, i) {
+            acc = op(acc, v.get(f.vs2, sew, i)) & ones(ed);
+        }
+    }
+    v.set(f.vd, ed, 0, acc);
+
+    None
+}
+
+// vd[i] = vs2[index(i)], or 0 past VLMAX
+fn gather(
+    v: &mut RvVectorRegisters,
+    cfg: &Config,
+    f: &Fields,
+    index: impl Fn(&RvVectorRegisters, usize) -> u64,
+) -> Option<RvException> {
+    let sew: usize = cfg.vtype.sew;
+
+    if !cfg.aligned(f.vd, sew) || !cfg.aligned(f.vs2, sew) || (!f.vm && f.vd == 0) {
+        return Some(RvException::InstructionIllegal);
+    }
+
+    let src: RvVectorRegisters = v.clone();
+
+    for i in cfg.vstart..cfg.vl {
+        if f.vm || src.mask(0, i) {
+            let j: u64 = index(&src, i);
+            let value: u64 = if j < cfg.vlmax as u64 {
+                src.get(f.vs2, sew, j as usize)
+            } else {
+                0
+            };
+
+            v.set(f.vd, sew, i, value);
+        }
+    }
+
+    None
+}
+
+// Mask register logical operations, always unmasked
+fn mask_logical(
+    v: &mut RvVectorRegisters,
+    cfg: &Config,
+    f: &Fields,
+    op: impl Fn(bool, bool) -> bool,
+) -> Option<RvException> {
+    if !f.vm {
+        return Some(RvException::InstructionIllegal);
+    }
+
+    for i in cfg.vstart..cfg.vl {
+        let result: bool = op(v.mask(f.vs2, i), v.mask(f.rs1, i));
+
+        v.set_mask(f.vd, i, result);
+    }
+
+    None
+}
+
+pub fn vsetvl<X: Xlen>(
+    x: &mut RvRegisters<X>,
+    csr: &mut Csr,
+    vlen: usize,
+    rd: usize,
+    rs1: usize,
+    avl: Option<u64>,
+    value: u128,
+) -> Option<RvException> {
+    let xlen: usize = x.len();
+    let vtype: Vtype = Vtype::decode(value, xlen);
+    let vlmax: u128 = vtype.vlmax(vlen) as u128;
+    let vl: u128 = match avl {
+        Some(avl) => (avl as u128).min(vlmax),
+        None if rs1 != 0 => u128::from(x.get(rs1)).min(vlmax),
+        None if rd != 0 => vlmax,
+        // Keep vl, the new configuration having the same VLMAX
+        None => csr.read(csr::VL).unwrap().min(vlmax),
+    };
+
+    csr.write(csr::VTYPE, vtype.encode(xlen));
+    csr.write(csr::VL, vl);
+    csr.write(csr::VSTART, 0);
+    x.write(rd, X::from_u128(vl));
+
+    None
+}
+
+// OPIVV, OPIVX, OPIVI, OPMVV and OPMVX instructions
+pub fn op<X: Xlen>(
+    v: &mut RvVectorRegisters,
+    csr: &mut Csr,
+    x: &mut RvRegisters<X>,
+    f: &Fields,
+) -> Option<RvException> {
+    let cfg: Config = Config::read(csr, v.len());
+    let mut sat: bool = false;
+
+    // Whole register moves do not depend on vtype
+    let result: Option<RvException> = if f.funct3 == 0x3 && f.funct6 == 0x27 {
+        vmv_nr(v, &cfg, f)
+    } else if cfg.vtype.vill {
+        Some(RvException::InstructionIllegal)
+    } else {
+        match f.funct3 {
+            0x0 | 0x3 | 0x4 => opi(v, csr, x, f, &cfg, &mut sat),
+            0x2 | 0x6 => opm(v, csr, x, f, &cfg),
+            _ => Some(RvException::InstructionIllegal),
+        }
+    };
+
+    if result.is_none() {
+        if sat {
+            csr.write(csr::VXSAT, 1);
+        }
+        csr.write(csr::VSTART, 0);
+    }
+
+    result
+}
+
+fn vmv_nr(v: &mut RvVectorRegisters, cfg: &Config, f: &Fields) -> Option<RvException> {
+    let nreg: usize = f.rs1 + 1;
+    let eew: usize = if cfg.vtype.vill { 1 } else { cfg.vtype.sew };
+
+    if !f.vm
+        || !matches!(nreg, 1 | 2 | 4 | 8)
+        || !f.vd.is_multiple_of(nreg)
+        || !f.vs2.is_multiple_of(nreg)
+    {
+        return Some(RvException::InstructionIllegal);
+    }
+
+    for i in cfg.vstart..nreg * v.len() / 8 / eew {
+        let value: u64 = v.get(f.vs2, eew, i);
+
+        v.set(f.vd, eew, i, value);
+    }
+
+    None
+}
+
+fn opi<X: Xlen>(
+    v: &mut RvVectorRegisters,
+    csr: &Csr,
+    x: &RvRegisters<X>,
+    f: &Fields,
+    cfg: &Config,
+    sat: &mut bool,
+) -> Option<RvException> {
+    let sew: usize = cfg.vtype.sew;
+    let bits: u64 = 8 * sew as u64;
+    let vxrm: u64 = csr.read(csr::VXRM).unwrap() as u64;
+    let (vv, vi) = (f.funct3 == 0x0, f.funct3 == 0x3);
+    let b: Operand = match f.funct3 {
+        0x0 => Operand::Vector(f.rs1),
+        0x3 => Operand::Scalar(((f.rs1 as i64) << 59 >> 59) as u64),
+        _ => Operand::Scalar(xreg(x, f.rs1)),
+    };
+    // Shifts, slides and gathers take an unsigned immediate
+    let u: Operand = if vi { Operand::Scalar(f.rs1 as u64) } else { b };
+    let imm: u64 = match u {
+        Operand::Scalar(value) => value,
+        Operand::Vector(_) => 0,
+    };
+    let s = |value: u64| sext(value, sew) as i128;
+    let w = |value: u64| sext(value, 2 * sew) as i128;
+    let same: [usize; 3] = [sew, sew, sew];
+    let narrow: [usize; 3] = [sew, 2 * sew, sew];
+
+    match f.funct6 {
+        0x00 => elementwise(v, cfg, f, b, same, |a, b, _| a.wrapping_add(b)), // vadd
+        0x02 if !vi => elementwise(v, cfg, f, b, same, |a, b, _| a.wrapping_sub(b)), // vsub
+        0x03 if !vv => elementwise(v, cfg, f, b, same, |a, b, _| b.wrapping_sub(a)), // vrsub
+        0x04 if !vi => elementwise(v, cfg, f, b, same, |a, b, _| a.min(b)),   // vminu
+        0x05 if !vi => elementwise(v, cfg, f, b, same, |a, b, _| s(a).min(s(b)) as u64), // vmin
+        0x06 if !vi => elementwise(v, cfg, f, b, same, |a, b, _| a.max(b)),   // vmaxu
+        0x07 if !vi => elementwise(v, cfg, f, b, same, |a, b, _| s(a).max(s(b)) as u64), // vmax
+        0x09 => elementwise(v, cfg, f, b, same, |a, b, _| a & b),             // vand
+        0x0a => elementwise(v, cfg, f, b, same, |a, b, _| a | b),             // vor
+        0x0b => elementwise(v, cfg, f, b, same, |a, b, _| a ^ b),             // vxor
+        0x0c => match u {
+            // vrgather
+            Operand::Vector(r) if cfg.aligned(r, sew) => {
+                gather(v, cfg, f, |src, i| src.get(r, sew, i))
+            }
+            Operand::Vector(_) => Some(RvException::InstructionIllegal),
+            Operand::Scalar(_) => gather(v, cfg, f, |_, _| imm),
+        },
+        0x0e if vv => {
+            // vrgatherei16
+            if !cfg.aligned(f.rs1, 2) {
+                return Some(RvException::InstructionIllegal);
+            }
+            gather(v, cfg, f, |src, i| src.get(f.rs1, 2, i))
+        }
+        0x0e => slide(v, cfg, f, imm.min(cfg.vlmax as u64) as usize, true), // vslideup
+        0x0f if !vv => slide(v, cfg, f, imm.min(cfg.vlmax as u64) as usize, false), // vslidedown
+        0x10 if !f.vm => select(v, cfg, f, b, |a, b, c| {
+            a.wrapping_add(b).wrapping_add(c as u64)
+        }), // vadc
+        0x11 => compare(v, cfg, f, b, true, |a, b, c| {
+            // vmadc
+            a as u128 + b as u128 + c as u128 > ones(sew) as u128
+        }),
+        0x12 if !vi && !f.vm => select(v, cfg, f, b, |a, b, c| {
+            a.wrapping_sub(b).wrapping_sub(c as u64)
+        }), // vsbc
+        0x13 if !vi => compare(v, cfg, f, b, true, |a, b, c| {
+            (a as u128) < b as u128 + c as u128
+        }), // vmsbc
+        0x17 if !f.vm => select(v, cfg, f, b, |a, b, c| if c { b } else { a }), // vmerge
+        0x17 if f.vs2 == 0 => elementwise(v, cfg, f, b, same, |_, b, _| b),     // vmv.v
+        0x18 => compare(v, cfg, f, b, false, |a, b, _| a == b),                 // vmseq
+        0x19 => compare(v, cfg, f, b, false, |a, b, _| a != b),                 // vmsne
+        0x1a if !vi => compare(v, cfg, f, b, false, |a, b, _| a < b),           // vmsltu
+        0x1b if !vi => compare(v, cfg, f, b, false, |a, b, _| s(a) < s(b)),     // vmslt
+        0x1c => compare(v, cfg, f, b, false, |a, b, _| a <= b),                 // vmsleu
+        0x1d => compare(v, cfg, f, b, false, |a, b, _| s(a) <= s(b)),           // vmsle
+        0x1e if !vv => compare(v, cfg, f, b, false, |a, b, _| a > b),           // vmsgtu
+        0x1f if !vv => compare(v, cfg, f, b, false, |a, b, _| s(a) > s(b)),     // vmsgt
+        0x20 => elementwise(v, cfg, f, b, same, |a, b, _| {
+            // vsaddu
+            clamp_unsigned(a as i128 + b as i128, sew, sat)
+        }),
+        0x21 => elementwise(v, cfg, f, b, same, |a, b, _| clamp(s(a) + s(b), sew, sat)), // vsadd
+        0x22 if !vi => elementwise(v, cfg, f, b, same, |a, b, _| {
+            // vssubu
+            clamp_unsigned(a as i128 - b as i128, sew, sat)
+        }),
+        0x23 if !vi => elementwise(v, cfg, f, b, same, |a, b, _| clamp(s(a) - s(b), sew, sat)), // vssub
+        0x25 => elementwise(v, cfg, f, u, same, |a, b, _| a << (b % bits)), // vsll
+        0x27 if !vi => elementwise(v, cfg, f, b, same, |a, b, _| {
+            // vsmul
+            clamp(roundoff(s(a) * s(b), 8 * sew - 1, vxrm), sew, sat)
+        }),
+        0x28 => elementwise(v, cfg, f, u, same, |a, b, _| a >> (b % bits)), // vsrl
+        0x29 => elementwise(v, cfg, f, u, same, |a, b, _| (s(a) >> (b % bits)) as u64), // vsra
+        0x2a => elementwise(v, cfg, f, u, same, |a, b, _| {
+            // vssrl
+            roundoff(a as i128, (b % bits) as usize, vxrm) as u64
+        }),
+        0x2b => elementwise(v, cfg, f, u, same, |a, b, _| {
+            // vssra
+            roundoff(s(a), (b % bits) as usize, vxrm) as u64
+        }),
+        0x2c => elementwise(v, cfg, f, u, narrow, |a, b, _| a >> (b % (2 * bits))), // vnsrl
+        0x2d => elementwise(v, cfg, f, u, narrow, |a, b, _| {
+            (w(a) >> (b % (2 * bits))) as u64
+        }), // vnsra
+        0x2e => elementwise(v, cfg, f, u, narrow, |a, b, _| {
+            // vnclipu
+            clamp_unsigned(
+                roundoff(a as i128, (b % (2 * bits)) as usize, vxrm),
+                sew,
+                sat,
+            )
+        }),
+        0x2f => elementwise(v, cfg, f, u, narrow, |a, b, _| {
+            // vnclip
+            clamp(roundoff(w(a), (b % (2 * bits)) as usize, vxrm), sew, sat)
+        }),
+        0x30 if vv => reduce(v, cfg, f, true, |acc, a| acc.wrapping_add(a)), // vwredsumu
+        0x31 if vv => reduce(v, cfg, f, true, |acc, a| acc.wrapping_add(s(a) as u64)), // vwredsum
+        _ => Some(RvException::InstructionIllegal),
+    }
+}
+
+// vslideup and vslidedown by `offset` elements
+fn slide(
+    v: &mut RvVectorRegisters,
+    cfg: &Config,
+    f: &Fields,
+    offset: usize,
+    up: bool,
+) -> Option<RvException> {
+    let sew: usize = cfg.vtype.sew;
+
+    if !cfg.aligned(f.vd, sew) || !cfg.aligned(f.vs2, sew) || (!f.vm && f.vd == 0) {
+        return Some(RvException::InstructionIllegal);
+    }
+
+    let src: RvVectorRegisters = v.clone();
+    let first: usize = if up {
+        cfg.vstart.max(offset)
+    } else {
+        cfg.vstart
+    };
+
+    for i in first..cfg.vl {
+        if f.vm || src.mask(0, i) {
+            let value: u64 = if up {
+                src.get(f.vs2, sew, i - offset)
+            } else if i + offset < cfg.vlmax {
+                src.get(f.vs2, sew, i + offset)
+            } else {
+                0
+            };
+
+            v.set(f.vd, sew, i, value);
+        }
+    }
+
+    None
+}
+
+fn opm<X: Xlen>(
+    v: &mut RvVectorRegisters,
+    csr: &Csr,
+    x: &mut RvRegisters<X>,
+    f: &Fields,
+    cfg: &Config,
+) -> Option<RvException> {
+    let sew: usize = cfg.vtype.sew;
+    let bits: u32 = 8 * sew as u32;
+    let vxrm: u64 = csr.read(csr::VXRM).unwrap() as u64;
+    let vv: bool = f.funct3 == 0x2;
+    let b: Operand = if vv {
+        Operand::Vector(f.rs1)
+    } else {
+        Operand::Scalar(xreg(x, f.rs1))
+    };
+    let s = |value: u64| sext(value, sew) as i128;
+    let same: [usize; 3] = [sew, sew, sew];
+    let wide: [usize; 3] = [2 * sew, sew, sew];
+    let wide_w: [usize; 3] = [2 * sew, 2 * sew, sew];
+
+    match f.funct6 {
+        0x00 if vv => reduce(v, cfg, f, false, |acc, a| acc.wrapping_add(a)), // vredsum
+        0x01 if vv => reduce(v, cfg, f, false, |acc, a| acc & a),             // vredand
+        0x02 if vv => reduce(v, cfg, f, false, |acc, a| acc | a),             // vredor
+        0x03 if vv => reduce(v, cfg, f, false, |acc, a| acc ^ a),             // vredxor
+        0x04 if vv => reduce(v, cfg, f, false, |acc, a| acc.min(a)),          // vredminu
+        0x05 if vv => reduce(v, cfg, f, false, |acc, a| s(acc).min(s(a)) as u64), // vredmin
+        0x06 if vv => reduce(v, cfg, f, false, |acc, a| acc.max(a)),          // vredmaxu
+        0x07 if vv => reduce(v, cfg, f, false, |acc, a| s(acc).max(s(a)) as u64), // vredmax
+        0x08 => elementwise(v, cfg, f, b, same, |a, b, _| {
+            // vaaddu
+            roundoff(a as i128 + b as i128, 1, vxrm) as u64
+        }),
+        0x09 => elementwise(v, cfg, f, b, same, |a, b, _| {
+            roundoff(s(a) + s(b), 1, vxrm) as u64
+        }), // vaadd
+        0x0a => elementwise(v, cfg, f, b, same, |a, b, _| {
+            // vasubu
+            roundoff(a as i128 - b as i128, 1, vxrm) as u64
+        }),
+        0x0b => elementwise(v, cfg, f, b, same, |a, b, _| {
+            roundoff(s(a) - s(b), 1, vxrm) as u64
+        }), // vasub
+        0x0e if !vv => slide1(v, cfg, f, xreg(x, f.rs1), true), // vslide1up
+        0x0f if !vv => slide1(v, cfg, f, xreg(x, f.rs1), false), // vslide1down
+        0x10 if vv => wxunary0(v, x, f, cfg),
+        0x10 if f.vs2 == 0 => {
+            // vmv.s.x
+            if cfg.vstart < cfg.vl {
+                v.set(f.vd, sew, 0, xreg(x, f.rs1) & ones(sew));
+            }
+            None
+        }
+        0x12 if vv => {
+            // vzext and vsext, by a factor of 8, 4 or 2
+            let (factor, signed) = match f.rs1 {
+                0x02..=0x07 => (1 << (4 - f.rs1 / 2), f.rs1 & 1 != 0),
+                _ => return Some(RvException::InstructionIllegal),
+            };
+            let e2: usize = sew / factor;
+
+            if e2 == 0 || cfg.emul(e2).is_none() {
+                return Some(RvException::InstructionIllegal);
+            }
+            elementwise(v, cfg, f, Operand::Scalar(0), [sew, e2, sew], |a, _, _| {
+                if signed {
+                    sext(a, e2) as u64
+                } else {
+                    a
+                }
+            })
+        }
+        0x14 if vv => munary0(v, f, cfg),
+        0x17 if vv => compress(v, f, cfg), // vcompress
+        0x18 if vv => mask_logical(v, cfg, f, |a, b| a & !b), // vmandn
+        0x19 if vv => mask_logical(v, cfg, f, |a, b| a & b), // vmand
+        0x1a if vv => mask_logical(v, cfg, f, |a, b| a | b), // vmor
+        0x1b if vv => mask_logical(v, cfg, f, |a, b| a ^ b), // vmxor
+        0x1c if vv => mask_logical(v, cfg, f, |a, b| a | !b), // vmorn
+        0x1d if vv => mask_logical(v, cfg, f, |a, b| !(a & b)), // vmnand
+        0x1e if vv => mask_logical(v, cfg, f, |a, b| !(a | b)), // vmnor
+        0x1f if vv => mask_logical(v, cfg, f, |a, b| !(a ^ b)), // vmxnor
+        0x20 => elementwise(v, cfg, f, b, same, |a, b, _| {
+            a.checked_div(b).unwrap_or(ones(sew))
+        }), // vdivu
+        0x21 => elementwise(v, cfg, f, b, same, |a, b, _| {
+            // vdiv
+            match s(b) {
+                0 => u64::MAX,
+                d => (s(a) as i64).wrapping_div(d as i64) as u64,
+            }
+        }),
+        0x22 => elementwise(v, cfg, f, b, same, |a, b, _| a.checked_rem(b).unwrap_or(a)), // vremu
+        0x23 => elementwise(v, cfg, f, b, same, |a, b, _| {
+            // vrem
+            match s(b) {
+                0 => a,
+                d => (s(a) as i64).wrapping_rem(d as i64) as u64,
+            }
+        }),
+        0x24 => elementwise(v, cfg, f, b, same, |a, b, _| {
+            ((a as u128 * b as u128) >> bits) as u64
+        }), // vmulhu
+        0x25 => elementwise(v, cfg, f, b, same, |a, b, _| a.wrapping_mul(b)), // vmul
+        0x26 => elementwise(v, cfg, f, b, same, |a, b, _| {
+            ((s(a) * b as i128) >> bits) as u64
+        }), // vmulhsu
+        0x27 => elementwise(v, cfg, f, b, same, |a, b, _| ((s(a) * s(b)) >> bits) as u64), // vmulh
+        0x29 => elementwise(v, cfg, f, b, same, |a, b, d| {
+            d.wrapping_mul(b).wrapping_add(a)
+        }), // vmadd
+        0x2b => elementwise(v, cfg, f, b, same, |a, b, d| {
+            a.wrapping_sub(d.wrapping_mul(b))
+        }), // vnmsub
+        0x2d => elementwise(v, cfg, f, b, same, |a, b, d| {
+            d.wrapping_add(a.wrapping_mul(b))
+        }), // vmacc
+        0x2f => elementwise(v, cfg, f, b, same, |a, b, d| {
+            d.wrapping_sub(a.wrapping_mul(b))
+        }), // vnmsac
+        0x30 => elementwise(v, cfg, f, b, wide, |a, b, _| a.wrapping_add(b)), // vwaddu
+        0x31 => elementwise(v, cfg, f, b, wide, |a, b, _| (s(a) + s(b)) as u64), // vwadd
+        0x32 => elementwise(v, cfg, f, b, wide, |a, b, _| a.wrapping_sub(b)), // vwsubu
+        0x33 => elementwise(v, cfg, f, b, wide, |a, b, _| (s(a) - s(b)) as u64), // vwsub
+        0x34 => elementwise(v, cfg, f, b, wide_w, |a, b, _| a.wrapping_add(b)), // vwaddu.w
+        0x35 => elementwise(v, cfg, f, b, wide_w, |a, b, _| a.wrapping_add(s(b) as u64)), // vwadd.w
+        0x36 => elementwise(v, cfg, f, b, wide_w, |a, b, _| a.wrapping_sub(b)), // vwsubu.w
+        0x37 => elementwise(v, cfg, f, b, wide_w, |a, b, _| a.wrapping_sub(s(b) as u64)), // vwsub.w
+        0x38 => elementwise(v, cfg, f, b, wide, |a, b, _| a.wrapping_mul(b)), // vwmulu
+        0x3a => elementwise(v, cfg, f, b, wide, |a, b, _| (s(a) * b as i128) as u64), // vwmulsu
+        0x3b => elementwise(v, cfg, f, b, wide, |a, b, _| (s(a) * s(b)) as u64), // vwmul
+        0x3c => elementwise(v, cfg, f, b, wide, |a, b, d| {
+            d.wrapping_add(a.wrapping_mul(b))
+        }), // vwmaccu
+        0x3d => elementwise(v, cfg, f, b, wide, |a, b, d| {
+            d.wrapping_add((s(a) * s(b)) as u64)
+        }), // vwmacc
+        0x3e if !vv => elementwise(v, cfg, f, b, wide, |a, b, d| {
+            d.wrapping_add((s(a) * b as i128) as u64)
+        }), // vwmaccus
+        0x3f => elementwise(v, cfg, f, b, wide, |a, b, d| {
+            d.wrapping_add((a as i128 * s(b)) as u64)
+        }), // vwmaccsu
+        _ => Some(RvException::InstructionIllegal),
+    }
+}
+
+fn slide1(
+    v: &mut RvVectorRegisters,
+    cfg: &Config,
+    f: &Fields,
+    value: u64,
+    up: bool,
+) -> Option<RvException> {
+    let sew: usize = cfg.vtype.sew;
+
+    if !cfg.aligned(f.vd, sew) || !cfg.aligned(f.vs2, sew) || (!f.vm && f.vd == 0) {
+        return Some(RvException::InstructionIllegal);
+    }
+
+    let src: RvVectorRegisters = v.clone();
+
+    for i in cfg.vstart..cfg.vl {
+        if f.vm || src.mask(0, i) {
+            let element: u64 = match (up, i) {
+                (true, 0) => value,
+                (true, _) => src.get(f.vs2, sew, i - 1),
+                (false, _) if i + 1 == cfg.vl => value,
+                (false, _) => src.get(f.vs2, sew, i + 1),
+            };
+
+            v.set(f.vd, sew, i, element & ones(sew));
+        }
+    }
+
+    None
+}
+
+// vmv.x.s, vcpop.m and vfirst.m
+fn wxunary0<X: Xlen>(
+    v: &RvVectorRegisters,
+    x: &mut RvRegisters<X>,
+    f: &Fields,
+    cfg: &Config,
+) -> Option<RvException> {
+    let sew: usize = cfg.vtype.sew;
+    let active = |i: &usize| v.mask(f.vs2, *i) && (f.vm || v.mask(0, *i));
+
+    match f.rs1 {
+        0x00 if f.vm => set_xreg(x, f.vd, sext(v.get(f.vs2, sew, 0), sew)), // vmv.x.s
+        0x10 => set_xreg(x, f.vd, (cfg.vstart..cfg.vl).filter(active).count() as i64), // vcpop.m
+        0x11 => {
+            // vfirst.m
+            let first: Option<usize> = (cfg.vstart..cfg.vl).find(active);
+
+            set_xreg(x, f.vd, first.map_or(-1, |i| i as i64))
+        }
+        _ => return Some(RvException::InstructionIllegal),
+    }
+
+    None
+}
+
+// vmsbf.m, vmsof.m, vmsif.m, viota.m and vid.v
+fn munary0(v: &mut RvVectorRegisters, f: &Fields, cfg: &Config) -> Option<RvException> {
+    let sew: usize = cfg.vtype.sew;
+    let src: RvVectorRegisters = v.clone();
+    let active = |i: usize| f.vm || src.mask(0, i);
+
+    if cfg.vstart != 0 && f.rs1 != 0x11 {
+        return Some(RvException::InstructionIllegal);
+    }
+
+    match f.rs1 {
+        0x01..=0x03 => {
+            if f.vd == f.vs2 || (!f.vm && f.vd == 0) {
+                return Some(RvException::InstructionIllegal);
+            }
+
+            let mut found: bool = false;
+
+            for i in (0..cfg.vl).filter(|i| active(*i)) {
+                let set: bool = src.mask(f.vs2, i);
+                let bit: bool = match f.rs1 {
+                    0x01 => !found && !set, // vmsbf
+                    0x02 => !found && set,  // vmsof
+                    _ => !found,            // vmsif
+                };
+
+                v.set_mask(f.vd, i, bit);
+                found |= set;
+            }
+        }
+        0x10 => {
+            // viota
+            if !cfg.aligned(f.vd, sew) || (!f.vm && f.vd == 0) {
+                return Some(RvException::InstructionIllegal);
+            }
+
+            let mut count: u64 = 0;
+
+            for i in (0..cfg.vl).filter(|i| active(*i)) {
+                v.set(f.vd, sew, i, count & ones(sew));
+                count += src.mask(f.vs2, i) as u64;
+            }
+        }
+        0x11 if f.vs2 == 0 => {
+            // vid
+            if !cfg.aligned(f.vd, sew) || (!f.vm && f.vd == 0) {
+                return Some(RvException::InstructionIllegal);
+            }
+
+            for i in (cfg.vstart..cfg.vl).filter(|i| active(*i)) {
+                v.set(f.vd, sew, i, i as u64 & ones(sew));
+            }
+        }
+        _ => return Some(RvException::InstructionIllegal),
+    }
+
+    None
+}
+
+fn compress(v: &mut RvVectorRegisters, f: &Fields, cfg: &Config) -> Option<RvException> {
+    let sew: usize = cfg.vtype.sew;
+
+    if !f.vm || cfg.vstart != 0 || !cfg.aligned(f.vd, sew) || !cfg.aligned(f.vs2, sew) {
+        return Some(RvException::InstructionIllegal);
+    }
+
+    let src: RvVectorRegisters = v.clone();
+
+    for (j, i) in (0..cfg.vl).filter(|i| src.mask(f.rs1, *i)).enumerate() {
+        v.set(f.vd, sew, j, src.get(f.vs2, sew, i));
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{roundoff, Vtype};
+    use crate::vsoc::Vsoc;
+
+    #[test]
+    fn test_vtype() {
+        // e32, m2, ta, ma
+        let vtype: Vtype = Vtype::decode(0xd1, 64);
+
+        assert!(!vtype.vill);
+        assert_eq!(
+            (vtype.sew, vtype.lmul, vtype.ta, vtype.ma),
+            (4, 1, true, true)
+        );
+        assert_eq!(vtype.vlmax(128), 8);
+        assert_eq!(vtype.encode(64), 0xd1);
+
+        // e16, mf2
+        assert_eq!(Vtype::decode(0x0f, 32).vlmax(128), 4);
+        // e64, mf8 cannot hold an element, lmul 4 and e128 are reserved
+        assert!(Vtype::decode(0x1d, 64).vill);
+        assert!(Vtype::decode(0x04, 64).vill);
+        assert!(Vtype::decode(0x20, 64).vill);
+        assert!(Vtype::decode(0x100, 64).vill);
+        assert_eq!(Vtype::decode(0x100, 32).encode(32), 0x8000_0000);
+    }
+
+    #[test]
+    fn test_roundoff() {
+        // 0b1011 >> 2 for rnu, rne, rdn and rod
+        assert_eq!(roundoff(11, 2, 0), 3);
+        assert_eq!(roundoff(11, 2, 1), 3);
+        assert_eq!(roundoff(11, 2, 2), 2);
+        assert_eq!(roundoff(11, 2, 3), 3);
+        // Ties: 0b1010 >> 2
+        assert_eq!(roundoff(10, 2, 0), 3);
+        assert_eq!(roundoff(10, 2, 1), 2);
+        assert_eq!(roundoff(-3, 1, 0), -1);
+        assert_eq!(roundoff(8, 2, 3), 2);
+    }
+
+    #[test]
+    fn test_program() {
+        let arch: String = String::from("rv64iv_zicsr");
+        let mut vsoc: Vsoc = Vsoc::new(&arch);
+        // t0 = buffer; e32 v3 = (id + 5)^2 stored then read back with ld,
+        // vredsum, vmerge, vslidedown, vcpop, strided and indexed loads;
+        // e8 saturating and averaging adds, vwmulu, vsetvl with VLMAX and a
+        // reserved LMUL; ebreak
+        let program: [u32; 49] = [
+            0x0000_0297,
+            0x4002_8293,
+            0xcd02_7357,
+            0x5208_a0d7,
+            0x0212_b157,
+            0x9621_21d7,
+            0x0202_e1a7,
+            0x0082_b503,
+            0x0230_22d7,
+            0x4250_25d7,
+            0x6211_3057,
+            0x5c1f_b357,
+            0x4260_2657,
+            0x3e61_3357,
+            0x4260_26d7,
+            0x4208_2757,
+            0x0080_0393,
+            0xcd01_7057,
+            0x0a72_e507,
+            0x3ea0_b5d7,
+            0x42b0_27d7,
+            0x5208_a657,
+            0x96c1_b657,
+            0x06c2_e687,
+            0x5e00_3757,
+            0x02d7_2757,
+            0x42e0_2857,
+            0xcc02_7057,
+            0x5e07_b457,
+            0x0780_0e13,
+            0x868e_44d7,
+            0x4290_28d7,
+            0x0090_2973,
+            0x5e01_b7d7,
+            0x0040_0e93,
+            0x26fe_e857,
+            0x4300_29d7,
+            0x00a1_5073,
+            0x26fe_e857,
+            0x4300_2a57,
+            0xe28e_6957,
+            0xcc92_7057,
+            0x4320_2ad7,
+            0x0db0_7b57,
+            0x0040_0f13,
+            0x81e0_7bd7,
+            0xc210_2c73,
+            0xc220_2cf3,
+            0x0010_0073,
+        ];
+        let binary: Vec<u8> = program.iter().flat_map(|i| i.to_le_bytes()).collect();
+        let expected: [(usize, u64); 16] = [
+            (10, 0x40_0000_0031),        // ld
+            (11, 174),                   // vredsum
+            (12, 0),                     // vmerge
+            (13, u64::MAX),              // vslidedown
+            (14, 1),                     // vcpop
+            (15, 49),                    // vlse32
+            (16, 74),                    // vluxei32
+            (17, 127),                   // vsadd
+            (18, 1),                     // vxsat
+            (19, 4),                     // vaadd, rnu
+            (20, 3),                     // vaadd, rdn
+            (21, 1800),                  // vwmulu
+            (22, 16),                    // vsetvli e64 m8
+            (23, 0),                     // vsetvl
+            (24, 0x8000_0000_0000_0000), // vtype.vill
+            (25, 16),                    // vlenb
+        ];
+
+        vsoc.load(&binary).unwrap();
+        for _ in 0..program.len() - 1 {
+            assert!(vsoc.step().is_none());
+        }
+        for (reg, value) in expected {
+            let v: Vec<u8> = vsoc.read_register(reg).unwrap();
+
+            assert_eq!(u64::from_le_bytes(v.try_into().unwrap()), value, "x{}", reg);
+        }
+    }
+
+    #[test]
+    fn test_vs() {
+        let arch: String = String::from("rv64iv_zicsr");
+        let mut vsoc: Vsoc = Vsoc::new(&arch);
+        // auipc t2, 0; addi t2, t2, 0x20; csrw mtvec, t2; vsetivli zero, 4, e32
+        // csrr a0, mstatus; li t0, 0x600; csrc mstatus, t0; vadd.vv v1, v1, v1
+        // 0x20: csrr s1, mcause; csrr a2, mstatus; j .
+        let program: [u32; 11] = [
+            0x0000_0397,
+            0x0203_8393,
+            0x3053_9073,
+            0xcd02_7057,
+            0x3000_2573,
+            0x6000_0293,
+            0x3002_b073,
+            0x0210_80d7,
+            0x3420_24f3,
+            0x3000_2673,
+            0x0000_006f,
+        ];
+        let binary: Vec<u8> = program.iter().flat_map(|i| i.to_le_bytes()).collect();
+
+        vsoc.load(&binary).unwrap();
+        for _ in 0..10 {
+            assert!(vsoc.step().is_none());
+        }
+
+        let x =
+            |reg: usize| u64::from_le_bytes(vsoc.read_register(reg).unwrap().try_into().unwrap());
+
+        // vsetvli makes VS Dirty, and sets SD
+        assert_eq!(x(10) & 0x8000_0000_0000_0600, 0x8000_0000_0000_0600);
+        // With VS Off the vector instructions are illegal
+        assert_eq!((x(9), x(12) & 0x600), (2, 0));
+    }
+}
@@ -0,0 +1,302 @@
+use super::vector::{xreg, Config, ELEN};
+use crate::vsoc::arch::riscv::{
+    csr::{self, Csr},
+    exception::RvException,
+    mmu::AddressSpace,
+    registers::{RvRegisters, RvVectorRegisters},
+    xlen::Xlen,
+};
+
+// Fields of a vector load or store
+pub struct Fields {
+    pub nf: usize,
+    pub mew: bool,
+    pub mop: usize,
+    pub vm: bool,
+    pub rs2: usize,
+    pub rs1: usize,
+    pub eew: usize,
+    pub vd: usize,
+}
+
+#[derive(PartialEq)]
+enum Mode {
+    Unit,
+    FaultOnlyFirst,
+    Strided(u64),
+    Indexed,
+}
+
+// EEW of the width field, None for the scalar floating-point widths
+pub fn eew(funct3: usize) -> Option<usize> {
+    match funct3 {
+        0x0 => Some(1),
+        0x5 => Some(2),
+        0x6 => Some(4),
+        0x7 => Some(8),
+        _ => None,
+    }
+}
+
+// Number of registers of a group of `eew` byte elements
+fn group(cfg: &Config, eew: usize) -> Option<usize> {
+    let emul: i32 =
+        cfg.vtype.lmul + eew.trailing_zeros() as i32 - cfg.vtype.sew.trailing_zeros() as i32;
+
+    if eew > ELEN / 8 || !(-3..=3).contains(&emul) {
+        return None;
+    }
+
+    Some(1 << emul.max(0))
+}
+
+// Ok with the vl a fault-only-first load trimmed to, or the exception and
+// the element to resume from. The CSRs are updated by `commit` once the
+// address space is released
+pub type Outcome = Result<Option<usize>, (RvException, usize)>;
+
+pub fn access<X: Xlen>(
+    v: &mut RvVectorRegisters,
+    csr: &Csr,
+    x: &RvRegisters<X>,
+    mem: &mut AddressSpace,
+    f: &Fields,
+    store: bool,
+) -> Outcome {
+    let cfg: Config = Config::read(csr, v.len());
+    let illegal: Outcome = Err((RvException::InstructionIllegal, cfg.vstart));
+
+    match (f.mop, f.rs2) {
+        _ if f.mew => illegal,
+        (0x0, 0x08) => whole(v, &cfg, x, mem, f, store),
+        (0x0, 0x0b) => mask(v, &cfg, x, mem, f, store),
+        (0x0, 0x00) => strided(v, &cfg, x, mem, f, store, Mode::Unit),
+        (0x0, 0x10) if !store => strided(v, &cfg, x, mem, f, store, Mode::FaultOnlyFirst),
+        (0x0, _) => illegal,
+        (0x2, rs2) => strided(v, &cfg, x, mem, f, store, Mode::Strided(xreg(x, rs2))),
+        _ => strided(v, &cfg, x, mem, f, store, Mode::Indexed),
+    }
+}
+
+pub fn commit(csr: &mut Csr, outcome: Outcome) -> Option<RvException> {
+    match outcome {
+        Ok(vl) => {
+            if let Some(vl) = vl {
+                csr.write(csr::VL, vl as u128);
+            }
+            csr.write(csr::VSTART, 0);
+            None
+        }
+        Err((e, vstart)) => {
+            csr.write(csr::VSTART, vstart as u128);
+            Some(e)
+        }
+    }
+}
+
+// Transfer element `i` of `eew` bytes of register group `reg` at `addr`
+fn transfer(
+    v: &mut RvVectorRegisters,
+    mem: &mut AddressSpace,
+    reg: usize,
+    eew: usize,
+    i: usize,
+    addr: u64,
+    store: bool,
+) -> Option<RvException> {
+    let result: Option<RvException> = if store {
+        mem.store(eew, addr, &v.get(reg, eew, i).to_le_bytes()[..eew])
+    } else {
+        match mem.fetch(eew, addr) {
+            Ok(value) => {
+                let mut bytes: [u8; 8] = [0; 8];
+
+                bytes[..eew].copy_from_slice(&value);
+                v.set(reg, eew, i, u64::from_le_bytes(bytes));
+                None
+            }
+            Err(e) => Some(e),
+        }
+    };
+
+    if result.is_some() {
+        v.fault = addr;
+    }
+
+    result
+}
+
+// Unit-stride, strided and indexed accesses, with nf+1 fields per element
+fn strided<X: Xlen>(
+    v: &mut RvVectorRegisters,
+    cfg: &Config,
+    x: &RvRegisters<X>,
+    mem: &mut AddressSpace,
+    f: &Fields,
+    store: bool,
+    mode: Mode,
+) -> Outcome {
+    let fields: usize = f.nf + 1;
+    let base: u64 = xreg(x, f.rs1);
+    // Indexed accesses have data of SEW and offsets of EEW
+    let eew: usize = if mode == Mode::Indexed {
+        cfg.vtype.sew
+    } else {
+        f.eew
+    };
+    let regs: usize = match (group(cfg, eew), group(cfg, f.eew)) {
+        (Some(regs), Some(index)) if mode != Mode::Indexed || f.rs2.is_multiple_of(index) => regs,
+        _ => return Err((RvException::InstructionIllegal, cfg.vstart)),
+    };
+
+    if cfg.vtype.vill
+        || fields * regs > 8
+        || !f.vd.is_multiple_of(regs)
+        || f.vd + fields * regs > 32
+        || (!store && !f.vm && f.vd == 0)
+    {
+        return Err((RvException::InstructionIllegal, cfg.vstart));
+    }
+
+    let index: RvVectorRegisters = v.clone();
+
+    for i in cfg.vstart..cfg.vl {
+        if !f.vm && !v.mask(0, i) {
+            continue;
+        }
+
+        for field in 0..fields {
+            let offset: u64 = match mode {
+                Mode::Unit | Mode::FaultOnlyFirst => ((i * fields + field) * eew) as u64,
+                Mode::Strided(stride) => stride.wrapping_mul(i as u64) + (field * eew) as u64,
+                Mode::Indexed => index.get(f.rs2, f.eew, i) + (field * eew) as u64,
+            };
+
+            if let Some(e) = transfer(
+                v,
+                mem,
+                f.vd + field * regs,
+                eew,
+                i,
+                base.wrapping_add(offset),
+                store,
+            ) {
+                // Only the first element of a fault-only-first load traps, the
+                // others trim vl
+                if mode == Mode::FaultOnlyFirst && i > 0 {
+                    return Ok(Some(i));
+                }
+                return Err((e, i));
+            }
+        }
+    }
+
+    Ok(None)
+}
+
+// vlm.v and vsm.v move ceil(vl/8) bytes
+fn mask<X: Xlen>(
+    v: &mut RvVectorRegisters,
+    cfg: &Config,
+    x: &RvRegisters<X>,
+    mem: &mut AddressSpace,
+    f: &Fields,
+    store: bool,
+) -> Outcome {
+    let base: u64 = xreg(x, f.rs1);
+
+    if cfg.vtype.vill || f.eew != 1 || f.nf != 0 || !f.vm {
+        return Err((RvException::InstructionIllegal, cfg.vstart));
+    }
+
+    for i in cfg.vstart..cfg.vl.div_ceil(8) {
+        if let Some(e) = transfer(v, mem, f.vd, 1, i, base.wrapping_add(i as u64), store) {
+            return Err((e, i));
+        }
+    }
+
+    Ok(None)
+}
+
+// vl<nf>r and vs<nf>r ignore vtype and vl
+fn whole<X: Xlen>(
+    v: &mut RvVectorRegisters,
+    cfg: &Config,
+    x: &RvRegisters<X>,
+    mem: &mut AddressSpace,
+    f: &Fields,
+    store: bool,
+) -> Outcome {
+    let regs: usize = f.nf + 1;
+    let base: u64 = xreg(x, f.rs1);
+
+    if !f.vm
+        || !matches!(regs, 1 | 2 | 4 | 8)
+        || !f.vd.is_multiple_of(regs)
+        || (store && f.eew != 1)
+    {
+        return Err((RvException::InstructionIllegal, cfg.vstart));
+    }
+
+    for i in cfg.vstart..regs * v.len() / 8 / f.eew {
+        if let Some(e) = transfer(
+            v,
+            mem,
+            f.vd,
+            f.eew,
+            i,
+            base.wrapping_add((i * f.eew) as u64),
+            store,
+        ) {
+            return Err((e, i));
+        }
+    }
+
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::vsoc::Vsoc;
+
+    #[test]
+    fn test_fault() {
+        let arch: String = String::from("rv64iv_zicsr");
+        let mut vsoc: Vsoc = Vsoc::new(&arch);
+        // t0 = end of sram - 8; mtvec = 1f; vsetivli zero, 4, e32
+        // vle32ff.v v1, (t0); csrr a0, vl; vsetivli zero, 4, e32; vle32.v v2, (t0)
+        // 1: csrr a1..a3, vstart/mtval/mcause; j .
+        let program: [u32; 15] = [
+            0x0400_12b7,
+            0x0052_9293,
+            0xff82_8293,
+            0x0000_0317,
+            0x0203_0313,
+            0x3053_1073,
+            0xcd02_7057,
+            0x0302_e087,
+            0xc200_2573,
+            0xcd02_7057,
+            0x0202_e107,
+            0x0080_25f3,
+            0x3430_2673,
+            0x3420_26f3,
+            0x0000_006f,
+        ];
+        let binary: Vec<u8> = program.iter().flat_map(|i| i.to_le_bytes()).collect();
+        let x = |vsoc: &Vsoc, reg: usize| {
+            u64::from_le_bytes(vsoc.read_register(reg).unwrap().try_into().unwrap())
+        };
+
+        vsoc.load(&binary).unwrap();
+        for _ in 0..16 {
+            assert!(vsoc.step().is_none());
+        }
+        // The fault-only-first load trims vl, the other one traps on its
+        // third element
+        assert_eq!(x(&vsoc, 10), 2);
+        assert_eq!(x(&vsoc, 11), 2);
+        assert_eq!(x(&vsoc, 12), 0x8002_0000);
+        assert_eq!(x(&vsoc, 13), 5);
+    }
+}
@@ -1,11 +1,33 @@
 use std::fmt;
 
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum RvInterrupt {
     SupervisorSwInt = 0x1,
+    VirtualSupervisorSwInt = 0x2,
+    MachineSwInt = 0x3,
     SupervisorTimerInt = 0x5,
+    VirtualSupervisorTimerInt = 0x6,
+    MachineTimerInt = 0x7,
     SupervisorExternalInt = 0x9,
+    VirtualSupervisorExternalInt = 0xa,
+    MachineExternalInt = 0xb,
+    SupervisorGuestExternalInt = 0xc,
 }
 
+// Interrupts taken first when several are pending and enabled
+pub const PRIORITY: [RvInterrupt; 10] = [
+    RvInterrupt::MachineExternalInt,
+    RvInterrupt::MachineSwInt,
+    RvInterrupt::MachineTimerInt,
+    RvInterrupt::SupervisorExternalInt,
+    RvInterrupt::SupervisorSwInt,
+    RvInterrupt::SupervisorTimerInt,
+    RvInterrupt::SupervisorGuestExternalInt,
+    RvInterrupt::VirtualSupervisorExternalInt,
+    RvInterrupt::VirtualSupervisorSwInt,
+    RvInterrupt::VirtualSupervisorTimerInt,
+];
+
 impl fmt::Display for RvInterrupt {
     // This trait requires `fmt` with this exact signature.
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
@@ -16,8 +38,15 @@ impl fmt::Display for RvInterrupt {
         // is very similar to `println!`.
         match *self {
             Self::SupervisorSwInt => s = String::from("SupervisorSw"),
+            Self::MachineSwInt => s = String::from("MachineSw"),
             Self::SupervisorTimerInt => s = String::from("SupervisorTimer"),
+            Self::MachineTimerInt => s = String::from("MachineTimer"),
             Self::SupervisorExternalInt => s = String::from("SupervisorExternal"),
+            Self::MachineExternalInt => s = String::from("MachineExternal"),
+            Self::VirtualSupervisorSwInt => s = String::from("VirtualSupervisorSw"),
+            Self::VirtualSupervisorTimerInt => s = String::from("VirtualSupervisorTimer"),
+            Self::VirtualSupervisorExternalInt => s = String::from("VirtualSupervisorExternal"),
+            Self::SupervisorGuestExternalInt => s = String::from("SupervisorGuestExternal"),
         }
         write!(f, "RvInterrupt::{}", s)
     }
@@ -0,0 +1,1159 @@
+use std::collections::HashMap;
+
+use super::csr::{self, Csr};
+use super::exception::RvException;
+use super::pmp::Pmp;
+use super::privilege::RvPrivilege;
+use crate::vsoc::bus::Bus;
+use crate::vsoc::snapshot::{Reader, SnapshotError, Writer};
+
+const PAGE_SHIFT: usize = 12;
+pub const PAGE_MASK: u64 = (1 << PAGE_SHIFT) - 1;
+
+// Page table entry fields
+const PTE_V: u64 = 1 << 0;
+const PTE_R: u64 = 1 << 1;
+const PTE_W: u64 = 1 << 2;
+const PTE_X: u64 = 1 << 3;
+const PTE_U: u64 = 1 << 4;
+const PTE_G: u64 = 1 << 5;
+const PTE_A: u64 = 1 << 6;
+const PTE_D: u64 = 1 << 7;
+
+// satp.MODE values
+const SATP_MODE_SV32: u128 = 1;
+const SATP_MODE_SV39: u128 = 8;
+const SATP_MODE_SV48: u128 = 9;
+const SATP_MODE_SV57: u128 = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Access {
+    Fetch,
+    Load,
+    Store,
+    // hlvx loads, which need execute instead of read permission
+    Execute,
+}
+
+impl Access {
+    fn page_fault(&self) -> RvException {
+        match self {
+            Access::Fetch => RvException::InstructionPageFault,
+            Access::Load | Access::Execute => RvException::LoadPageFault,
+            Access::Store => RvException::StorePageFault,
+        }
+    }
+
+    fn guest_page_fault(&self) -> RvException {
+        match self {
+            Access::Fetch => RvException::InstructionGuestPageFault,
+            Access::Load | Access::Execute => RvException::LoadGuestPageFault,
+            Access::Store => RvException::StoreGuestPageFault,
+        }
+    }
+
+    fn access_fault(&self) -> RvException {
+        match self {
+            Access::Fetch => RvException::InstructionAccessFault,
+            Access::Load | Access::Execute => RvException::LoadAccessFault,
+            Access::Store => RvException::StoreAccessFault,
+        }
+    }
+}
+
+// Geometry of a translation scheme, the G-stage ones widen their root level
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Scheme {
+    levels: usize,
+    pte_size: usize,
+    vpn_bits: usize,
+    ppn_bits: usize,
+    root_bits: usize,
+}
+
+impl Scheme {
+    fn va_bits(&self) -> usize {
+        PAGE_SHIFT + self.levels * self.vpn_bits + self.root_bits
+    }
+}
+
+// Translation parameters decoded from satp/vsatp, or from hgatp for the
+// G-stage (Sv32x4/Sv39x4/Sv48x4/Sv57x4). None when the mode is Bare
+fn decode_atp(xlen: usize, atp: u128, g_stage: bool) -> Option<(Scheme, u64, u64)> {
+    let (mode, asid, ppn) = match xlen {
+        32 => (atp >> 31, (atp >> 22) & 0x1ff, atp & 0x3f_ffff),
+        64 => (atp >> 60, (atp >> 44) & 0xffff, atp & 0xfff_ffff_ffff),
+        _ => return None,
+    };
+    let root_bits: usize = if g_stage { 2 } else { 0 };
+    let sv = |levels: usize| Scheme {
+        levels,
+        pte_size: 8,
+        vpn_bits: 9,
+        ppn_bits: 44,
+        root_bits,
+    };
+    let scheme: Scheme = match (xlen, mode) {
+        (32, SATP_MODE_SV32) => Scheme {
+            levels: 2,
+            pte_size: 4,
+            vpn_bits: 10,
+            ppn_bits: 22,
+            root_bits,
+        },
+        (64, SATP_MODE_SV39) => sv(3),
+        (64, SATP_MODE_SV48) => sv(4),
+        (64, SATP_MODE_SV57) => sv(5),
+        _ => return None,
+    };
+
+    Some((scheme, asid as u64, ppn as u64))
+}
+
+// Check the leaf permissions against the access and the effective privilege
+fn permitted(pte: u64, access: Access, privilege: RvPrivilege, sum: bool, mxr: bool) -> bool {
+    let user: bool = pte & PTE_U != 0;
+
+    match privilege {
+        RvPrivilege::User if !user => return false,
+        RvPrivilege::Supervisor if user && (access == Access::Fetch || !sum) => return false,
+        _ => (),
+    }
+
+    match access {
+        Access::Fetch | Access::Execute => pte & PTE_X != 0,
+        Access::Load => pte & PTE_R != 0 || (mxr && pte & PTE_X != 0),
+        Access::Store => pte & PTE_W != 0,
+    }
+}
+
+// Guest translations also keep the guest physical page and the G-stage leaf
+#[derive(Debug, Clone, Copy)]
+struct TlbEntry {
+    asid: u64,
+    ppage: u64,
+    pte: u64,
+    gpage: u64,
+    gpte: u64,
+}
+
+#[derive(Debug, Default)]
+pub struct Mmu {
+    satp: u128,
+    tlb: HashMap<u64, TlbEntry>,
+    // Translations of VS/VU-mode, through vsatp then hgatp
+    vsatp: u128,
+    hgatp: u128,
+    vtlb: HashMap<u64, TlbEntry>,
+    // Whether the last translation was a guest one, and the guest physical
+    // address of the last guest page fault
+    gva: bool,
+    gpa: u64,
+}
+
+impl Mmu {
+    pub fn new() -> Mmu {
+        Mmu {
+            satp: 0,
+            tlb: HashMap::new(),
+            vsatp: 0,
+            hgatp: 0,
+            vtlb: HashMap::new(),
+            gva: false,
+            gpa: 0,
+        }
+    }
+
+    // The TLB is a cache, it refills from the restored page tables
+    pub fn save(&self, w: &mut Writer) {
+        w.u128(self.satp);
+    }
+
+    pub fn restore(&mut self, r: &mut Reader) -> Result<(), SnapshotError> {
+        self.satp = r.u128()?;
+        self.tlb.clear();
+        self.vtlb.clear();
+
+        Ok(())
+    }
+
+    // Guest virtual address flag and guest physical address reported by a
+    // trap caused by the last translation
+    pub fn fault(&self) -> (bool, u64) {
+        (self.gva, self.gpa)
+    }
+
+    // hfence.vvma and hfence.gvma, and sfence.vma in VS-mode: the guest TLB
+    // holds both stages, it is flushed as a whole
+    pub fn flush_guest(&mut self) {
+        self.vtlb.clear();
+    }
+
+    // sfence.vma: drop the matching translations, global ones survive an ASID flush
+    pub fn flush(&mut self, vaddr: Option<u64>, asid: Option<u64>) {
+        match (vaddr, asid) {
+            (None, None) => self.tlb.clear(),
+            (Some(va), None) => {
+                self.tlb.remove(&(va >> PAGE_SHIFT));
+            }
+            (None, Some(asid)) => self.tlb.retain(|_, e| e.pte & PTE_G != 0 || e.asid != asid),
+            (Some(va), Some(asid)) => {
+                if let Some(e) = self.tlb.get(&(va >> PAGE_SHIFT)) {
+                    if e.pte & PTE_G == 0 && e.asid == asid {
+                        self.tlb.remove(&(va >> PAGE_SHIFT));
+                    }
+                }
+            }
+        }
+    }
+
+    // Translate a virtual address, then check the PMP on the physical range
+    pub fn translate(
+        &mut self,
+        vaddr: u64,
+        width: usize,
+        access: Access,
+        privilege: RvPrivilege,
+        csr: Option<&Csr>,
+        bus: &mut Bus,
+    ) -> Result<u64, RvException> {
+        let csr: &Csr = match csr {
+            Some(c) => c,
+            None => return Ok(vaddr),
+        };
+        let mstatus: u128 = csr.read(csr::MSTATUS).unwrap();
+        // MPRV also selects the virtualization mode of mstatus.MPV
+        let (privilege, virt) = if access != Access::Fetch
+            && mstatus & csr::MSTATUS_MPRV != 0
+            && privilege == RvPrivilege::Machine
+        {
+            let mpp: RvPrivilege =
+                RvPrivilege::from_mpp((mstatus & csr::MSTATUS_MPP) >> csr::MSTATUS_MPP_SHIFT)
+                    .unwrap_or(RvPrivilege::Machine);
+
+            (mpp, mpp != RvPrivilege::Machine && csr.machine_virt().1)
+        } else {
+            (privilege, csr.virt())
+        };
+
+        self.translate_as(vaddr, width, access, privilege, virt, csr, bus)
+    }
+
+    // hlv, hlvx and hsv translate as in VS/VU-mode whatever the current mode
+    pub fn translate_guest(
+        &mut self,
+        vaddr: u64,
+        width: usize,
+        access: Access,
+        privilege: RvPrivilege,
+        csr: Option<&Csr>,
+        bus: &mut Bus,
+    ) -> Result<u64, RvException> {
+        match csr {
+            Some(c) => self.translate_as(vaddr, width, access, privilege, true, c, bus),
+            None => Ok(vaddr),
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn translate_as(
+        &mut self,
+        vaddr: u64,
+        width: usize,
+        access: Access,
+        privilege: RvPrivilege,
+        virt: bool,
+        csr: &Csr,
+        bus: &mut Bus,
+    ) -> Result<u64, RvException> {
+        let mstatus: u128 = csr.read(csr::MSTATUS).unwrap();
+
+        self.gva = virt;
+
+        let paddr: u64 = if virt {
+            self.translate_guest_page(vaddr, access, privilege, mstatus, csr, bus)?
+        } else if privilege == RvPrivilege::Machine || !csr.exists(csr::SATP) {
+            vaddr
+        } else {
+            self.translate_page(vaddr, access, privilege, mstatus, csr, bus)?
+        };
+
+        if !csr.pmp().check(paddr, width, access, privilege) {
+            return Err(access.access_fault());
+        }
+
+        Ok(paddr)
+    }
+
+    fn translate_page(
+        &mut self,
+        vaddr: u64,
+        access: Access,
+        privilege: RvPrivilege,
+        mstatus: u128,
+        csr: &Csr,
+        bus: &mut Bus,
+    ) -> Result<u64, RvException> {
+        let xlen: usize = csr.xlen();
+        let satp: u128 = csr.read(csr::SATP).unwrap();
+        let (scheme, asid, root) = match decode_atp(xlen, satp, false) {
+            Some(s) => s,
+            None => return Ok(vaddr),
+        };
+        let sum: bool = mstatus & csr::MSTATUS_SUM != 0;
+        let mxr: bool = mstatus & csr::MSTATUS_MXR != 0;
+
+        if satp != self.satp {
+            self.tlb.clear();
+            self.satp = satp;
+        }
+
+        // Upper bits of the virtual address must be a sign extension of the highest VA bit
+        if xlen == 64 {
+            let shift: usize = 64 - scheme.va_bits();
+
+            if (((vaddr << shift) as i64) >> shift) as u64 != vaddr {
+                return Err(access.page_fault());
+            }
+        }
+
+        let vpage: u64 = vaddr >> PAGE_SHIFT;
+
+        if let Some(e) = self.tlb.get(&vpage) {
+            let dirty: bool = access != Access::Store || e.pte & PTE_D != 0;
+
+            if (e.asid == asid || e.pte & PTE_G != 0) && dirty {
+                if !permitted(e.pte, access, privilege, sum, mxr) {
+                    return Err(access.page_fault());
+                }
+
+                return Ok((e.ppage << PAGE_SHIFT) | (vaddr & PAGE_MASK));
+            }
+        }
+
+        let (ppage, pte) = self.walk(
+            scheme,
+            root,
+            vaddr,
+            access,
+            privilege,
+            sum,
+            mxr,
+            None,
+            csr.pmp(),
+            bus,
+        )?;
+
+        self.tlb.insert(
+            vpage,
+            TlbEntry {
+                asid,
+                ppage,
+                pte,
+                gpage: 0,
+                gpte: 0,
+            },
+        );
+
+        Ok((ppage << PAGE_SHIFT) | (vaddr & PAGE_MASK))
+    }
+
+    // VS-stage through vsatp with vsstatus.SUM/MXR, then G-stage through
+    // hgatp where every access is a U-mode one. Either stage is skipped when
+    // Bare
+    fn translate_guest_page(
+        &mut self,
+        vaddr: u64,
+        access: Access,
+        privilege: RvPrivilege,
+        mstatus: u128,
+        csr: &Csr,
+        bus: &mut Bus,
+    ) -> Result<u64, RvException> {
+        let xlen: usize = csr.xlen();
+        let vsatp: u128 = csr.read(csr::VSATP).unwrap();
+        let hgatp: u128 = csr.read(csr::HGATP).unwrap();
+        let vsstatus: u128 = csr.read(csr::VSSTATUS).unwrap();
+        let vs: Option<(Scheme, u64, u64)> = decode_atp(xlen, vsatp, false);
+        let g: Option<(Scheme, u64)> = decode_atp(xlen, hgatp, true).map(|(s, _, root)| (s, root));
+        let sum: bool = vsstatus & csr::MSTATUS_SUM != 0;
+        let mxr: bool = (vsstatus | mstatus) & csr::MSTATUS_MXR != 0;
+        let g_mxr: bool = mstatus & csr::MSTATUS_MXR != 0;
+
+        if vsatp != self.vsatp || hgatp != self.hgatp {
+            self.vtlb.clear();
+            self.vsatp = vsatp;
+            self.hgatp = hgatp;
+        }
+
+        if let Some((scheme, _, _)) = vs {
+            if xlen == 64 {
+                let shift: usize = 64 - scheme.va_bits();
+
+                if (((vaddr << shift) as i64) >> shift) as u64 != vaddr {
+                    return Err(access.page_fault());
+                }
+            }
+        }
+
+        let vpage: u64 = vaddr >> PAGE_SHIFT;
+
+        if let Some(e) = self.vtlb.get(&vpage) {
+            let dirty: bool = access != Access::Store
+                || ((vs.is_none() || e.pte & PTE_D != 0) && (g.is_none() || e.gpte & PTE_D != 0));
+
+            if dirty {
+                if vs.is_some() && !permitted(e.pte, access, privilege, sum, mxr) {
+                    return Err(access.page_fault());
+                }
+                if g.is_some() && !permitted(e.gpte, access, RvPrivilege::User, false, g_mxr) {
+                    self.gpa = (e.gpage << PAGE_SHIFT) | (vaddr & PAGE_MASK);
+                    return Err(access.guest_page_fault());
+                }
+
+                return Ok((e.ppage << PAGE_SHIFT) | (vaddr & PAGE_MASK));
+            }
+        }
+
+        let (gpage, pte) = match vs {
+            Some((scheme, _, root)) => self.walk(
+                scheme,
+                root,
+                vaddr,
+                access,
+                privilege,
+                sum,
+                mxr,
+                g,
+                csr.pmp(),
+                bus,
+            )?,
+            None => (vpage, 0),
+        };
+        let gpa: u64 = (gpage << PAGE_SHIFT) | (vaddr & PAGE_MASK);
+        let (ppage, gpte) = match g {
+            Some(g) => self.g_stage(g, gpa, access, access, g_mxr, csr.pmp(), bus)?,
+            None => (gpage, 0),
+        };
+
+        self.vtlb.insert(
+            vpage,
+            TlbEntry {
+                asid: 0,
+                ppage,
+                pte,
+                gpage,
+                gpte,
+            },
+        );
+
+        Ok((ppage << PAGE_SHIFT) | (vaddr & PAGE_MASK))
+    }
+
+    // Translate a guest physical address, checking the `perm` permission and
+    // reporting faults as the `access` of the original access
+    #[allow(clippy::too_many_arguments)]
+    fn g_stage(
+        &mut self,
+        g: (Scheme, u64),
+        gpa: u64,
+        perm: Access,
+        access: Access,
+        mxr: bool,
+        pmp: &Pmp,
+        bus: &mut Bus,
+    ) -> Result<(u64, u64), RvException> {
+        let (scheme, root) = g;
+
+        // Guest physical addresses are zero-extended
+        if gpa >> scheme.va_bits() != 0 {
+            self.gpa = gpa;
+            return Err(access.guest_page_fault());
+        }
+
+        match self.walk(
+            scheme,
+            root,
+            gpa,
+            perm,
+            RvPrivilege::User,
+            false,
+            mxr,
+            None,
+            pmp,
+            bus,
+        ) {
+            Ok(r) => Ok(r),
+            Err(e) if e == perm.page_fault() => {
+                self.gpa = gpa;
+                Err(access.guest_page_fault())
+            }
+            Err(e) if e == perm.access_fault() => Err(access.access_fault()),
+            Err(e) => Err(e),
+        }
+    }
+
+    // Page table accesses are implicit S-mode accesses as far as the PMP is
+    // concerned. The tables of the VS-stage are at guest physical addresses
+    // translated by the G-stage `g`
+    #[allow(clippy::too_many_arguments)]
+    fn walk(
+        &mut self,
+        scheme: Scheme,
+        root: u64,
+        vaddr: u64,
+        access: Access,
+        privilege: RvPrivilege,
+        sum: bool,
+        mxr: bool,
+        g: Option<(Scheme, u64)>,
+        pmp: &Pmp,
+        bus: &mut Bus,
+    ) -> Result<(u64, u64), RvException> {
+        let ppn_mask: u64 = (1 << scheme.ppn_bits) - 1;
+        let mut a: u64 = root << PAGE_SHIFT;
+        let mut level: usize = scheme.levels - 1;
+        let mut gpte_addr: u64 = 0;
+        let mut pte_addr: u64;
+        let mut pte: u64;
+
+        loop {
+            let bits: usize = if level == scheme.levels - 1 {
+                scheme.vpn_bits + scheme.root_bits
+            } else {
+                scheme.vpn_bits
+            };
+            let vpn: u64 = (vaddr >> (PAGE_SHIFT + level * scheme.vpn_bits)) & ((1 << bits) - 1);
+
+            pte_addr = a + vpn * scheme.pte_size as u64;
+            if let Some(g) = g {
+                let (ppage, _) =
+                    self.g_stage(g, pte_addr, Access::Load, access, false, pmp, bus)?;
+
+                gpte_addr = pte_addr;
+                pte_addr = (ppage << PAGE_SHIFT) | (pte_addr & PAGE_MASK);
+            }
+            if !pmp.check(
+                pte_addr,
+                scheme.pte_size,
+                Access::Load,
+                RvPrivilege::Supervisor,
+            ) {
+                return Err(access.access_fault());
+            }
+            pte = match bus.load(scheme.pte_size, pte_addr) {
+                Ok(v) => v as u64,
+                Err(_) => return Err(access.access_fault()),
+            };
+
+            if pte & PTE_V == 0 || (pte & PTE_R == 0 && pte & PTE_W != 0) {
+                return Err(access.page_fault());
+            }
+
+            // Reserved, PBMT and NAPOT bits are not supported
+            if scheme.pte_size == 8 && pte >> 54 != 0 {
+                return Err(access.page_fault());
+            }
+
+            if pte & (PTE_R | PTE_X) != 0 {
+                break;
+            }
+
+            if level == 0 || pte & (PTE_D | PTE_A | PTE_U) != 0 {
+                return Err(access.page_fault());
+            }
+
+            level -= 1;
+            a = ((pte >> 10) & ppn_mask) << PAGE_SHIFT;
+        }
+
+        if !permitted(pte, access, privilege, sum, mxr) {
+            return Err(access.page_fault());
+        }
+
+        let ppn: u64 = (pte >> 10) & ppn_mask;
+        let superpage_mask: u64 = (1 << (level * scheme.vpn_bits)) - 1;
+
+        // Misaligned superpage
+        if ppn & superpage_mask != 0 {
+            return Err(access.page_fault());
+        }
+
+        if pte & PTE_A == 0 || (access == Access::Store && pte & PTE_D == 0) {
+            pte |= PTE_A;
+            if access == Access::Store {
+                pte |= PTE_D;
+            }
+
+            // Updating a VS-stage entry needs the G-stage write permission
+            if let Some(g) = g {
+                self.g_stage(g, gpte_addr, Access::Store, access, false, pmp, bus)?;
+            }
+
+            if !pmp.check(
+                pte_addr,
+                scheme.pte_size,
+                Access::Store,
+                RvPrivilege::Supervisor,
+            ) {
+                return Err(access.access_fault());
+            }
+
+            let bytes: Vec<u8> = pte.to_le_bytes()[..scheme.pte_size].to_vec();
+            if bus.store(scheme.pte_size, pte_addr, &bytes).is_some() {
+                return Err(access.access_fault());
+            }
+        }
+
+        Ok((ppn | ((vaddr >> PAGE_SHIFT) & superpage_mask), pte))
+    }
+}
+
+// Memory as seen by a load/store instruction: virtual addresses are translated
+// by the MMU before reaching the bus
+pub struct AddressSpace<'a> {
+    bus: &'a mut Bus,
+    mmu: &'a mut Mmu,
+    csr: Option<&'a Csr>,
+    privilege: RvPrivilege,
+    xlen: usize,
+    amo: bool,
+    guest: bool,
+    execute: bool,
+}
+
+impl<'a> AddressSpace<'a> {
+    pub fn new(
+        bus: &'a mut Bus,
+        mmu: &'a mut Mmu,
+        csr: Option<&'a Csr>,
+        privilege: RvPrivilege,
+        xlen: usize,
+    ) -> AddressSpace<'a> {
+        AddressSpace {
+            bus,
+            mmu,
+            csr,
+            privilege,
+            xlen,
+            amo: false,
+            guest: false,
+            execute: false,
+        }
+    }
+
+    // AMOs report store faults, even for their load part
+    pub fn amo(mut self) -> Self {
+        self.amo = true;
+        self
+    }
+
+    // hlv/hsv access the memory of the guest, `privilege` is then VS or VU
+    pub fn guest(mut self) -> Self {
+        self.guest = true;
+        self
+    }
+
+    // hlvx reads need execute permission
+    pub fn execute(mut self) -> Self {
+        self.execute = true;
+        self
+    }
+
+    fn translate(&mut self, addr: u64, width: usize, access: Access) -> Result<u64, RvException> {
+        let addr: u64 = if self.xlen == 32 {
+            addr & 0xffff_ffff
+        } else {
+            addr
+        };
+
+        if self.guest {
+            return self.mmu.translate_guest(
+                addr,
+                width,
+                access,
+                self.privilege,
+                self.csr,
+                self.bus,
+            );
+        }
+
+        self.mmu
+            .translate(addr, width, access, self.privilege, self.csr, self.bus)
+    }
+
+    pub fn fetch(&mut self, width: usize, addr: u64) -> Result<Vec<u8>, RvException> {
+        Ok(self.load(width, addr)?.to_le_bytes()[..width].to_vec())
+    }
+
+    // Up to 16 bytes, zero-extended
+    pub fn load(&mut self, width: usize, addr: u64) -> Result<u128, RvException> {
+        let access: Access = if self.amo {
+            Access::Store
+        } else if self.execute {
+            Access::Execute
+        } else {
+            Access::Load
+        };
+
+        // An access crossing a page boundary is split into bytes
+        if (addr & PAGE_MASK) + width as u64 > PAGE_MASK + 1 {
+            let mut value: u128 = 0;
+
+            for i in 0..width as u64 {
+                let paddr: u64 = self.translate(addr + i, 1, access)?;
+
+                value |= self.bus.load(1, paddr).map_err(RvException::from)? << (8 * i);
+            }
+
+            return Ok(value);
+        }
+
+        let paddr: u64 = self.translate(addr, width, access)?;
+
+        self.bus
+            .load(width, paddr)
+            .map_err(|e| match (self.amo, RvException::from(e)) {
+                (true, RvException::LoadAccessFault) => RvException::StoreAccessFault,
+                (_, e) => e,
+            })
+    }
+
+    pub fn store(&mut self, width: usize, addr: u64, value: &[u8]) -> Option<RvException> {
+        if (addr & PAGE_MASK) + width as u64 > PAGE_MASK + 1 {
+            for i in 0..width {
+                let paddr: u64 = match self.translate(addr + i as u64, 1, Access::Store) {
+                    Ok(a) => a,
+                    Err(e) => return Some(e),
+                };
+
+                if let Some(e) = self.bus.store(1, paddr, &value[i..i + 1]) {
+                    return Some(RvException::from(e));
+                }
+            }
+
+            return None;
+        }
+
+        let paddr: u64 = match self.translate(addr, width, Access::Store) {
+            Ok(a) => a,
+            Err(e) => return Some(e),
+        };
+
+        self.bus.store(width, paddr, value).map(RvException::from)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Access, Mmu, PTE_A, PTE_D, PTE_R, PTE_U, PTE_V, PTE_W, PTE_X};
+    use crate::vsoc::arch::riscv::{
+        csr, exception::RvException, ext::RvExtensions, privilege::RvPrivilege,
+    };
+    use crate::vsoc::arch::types::Uint;
+    use crate::vsoc::bus::Bus;
+    use crate::vsoc::dev::sram::Sram;
+    use crate::vsoc::peripheral::Peripheral;
+
+    const RAM: u64 = 0x8000_0000;
+
+    // Grant S/U-mode full access to the whole address space
+    fn pmp_allow_all(c: &mut csr::Csr) {
+        c.set(csr::PMPADDR0, &Uint::from(u64::MAX));
+        c.set(csr::PMPCFG0, &Uint::from(0x1fu64));
+    }
+
+    fn setup(satp: u64) -> (Bus, csr::Csr) {
+        let mut bus: Bus = Bus::new();
+        let sram: Box<Sram> = Box::new(Sram::new(64 * 1024));
+        let ext: RvExtensions = RvExtensions {
+            s: true,
+            u: true,
+            h: true,
+            ..Default::default()
+        };
+        let mut c: csr::Csr = csr::Csr::new(64, &ext);
+
+        bus.attach(
+            RAM,
+            Box::new(Peripheral::new(String::from("sram"), sram.size(), sram)),
+        )
+        .unwrap();
+        c.set(csr::SATP, &Uint::from(satp));
+        pmp_allow_all(&mut c);
+
+        (bus, c)
+    }
+
+    fn pte(bus: &mut Bus, addr: u64, ppn: u64, flags: u64) {
+        bus.store(8, addr, &((ppn << 10) | flags).to_le_bytes());
+    }
+
+    // Sv39 root table at RAM, a level-1 table at RAM + 0x1000 mapping
+    // va 0x4000_0000 -> pa RAM + 0x2000
+    fn sv39(flags: u64) -> (Bus, csr::Csr) {
+        let (mut bus, c) = setup((8 << 60) | (RAM >> 12));
+
+        pte(&mut bus, RAM + 8, (RAM + 0x1000) >> 12, PTE_V);
+        pte(&mut bus, RAM + 0x1000, (RAM + 0x3000) >> 12, PTE_V);
+        pte(&mut bus, RAM + 0x3000, (RAM + 0x2000) >> 12, flags);
+
+        (bus, c)
+    }
+
+    #[test]
+    fn test_bare() {
+        let (mut bus, c) = setup(0);
+        let mut mmu: Mmu = Mmu::new();
+
+        assert_eq!(
+            mmu.translate(
+                0x1234,
+                4,
+                Access::Load,
+                RvPrivilege::Supervisor,
+                Some(&c),
+                &mut bus
+            ),
+            Ok(0x1234)
+        );
+    }
+
+    #[test]
+    fn test_sv39_translate() {
+        let (mut bus, c) = sv39(PTE_V | PTE_R | PTE_W);
+        let mut mmu: Mmu = Mmu::new();
+
+        assert_eq!(
+            mmu.translate(
+                0x4000_0123,
+                4,
+                Access::Load,
+                RvPrivilege::Supervisor,
+                Some(&c),
+                &mut bus
+            ),
+            Ok(RAM + 0x2123)
+        );
+        assert_eq!(
+            mmu.translate(
+                0x4000_0123,
+                4,
+                Access::Fetch,
+                RvPrivilege::Supervisor,
+                Some(&c),
+                &mut bus
+            ),
+            Err(RvException::InstructionPageFault)
+        );
+        assert_eq!(
+            mmu.translate(
+                0x4000_0123,
+                4,
+                Access::Load,
+                RvPrivilege::User,
+                Some(&c),
+                &mut bus
+            ),
+            Err(RvException::LoadPageFault)
+        );
+        assert_eq!(
+            mmu.translate(
+                0x5000_0000,
+                4,
+                Access::Store,
+                RvPrivilege::Supervisor,
+                Some(&c),
+                &mut bus
+            ),
+            Err(RvException::StorePageFault)
+        );
+        // Machine mode is not translated
+        assert_eq!(
+            mmu.translate(
+                0x4000_0123,
+                4,
+                Access::Load,
+                RvPrivilege::Machine,
+                Some(&c),
+                &mut bus
+            ),
+            Ok(0x4000_0123)
+        );
+    }
+
+    #[test]
+    fn test_sv39_accessed_dirty() {
+        let (mut bus, c) = sv39(PTE_V | PTE_R | PTE_W);
+        let mut mmu: Mmu = Mmu::new();
+
+        mmu.translate(
+            0x4000_0000,
+            4,
+            Access::Load,
+            RvPrivilege::Supervisor,
+            Some(&c),
+            &mut bus,
+        )
+        .unwrap();
+        let v: u64 = u64::from_le_bytes(bus.fetch(8, RAM + 0x3000).unwrap().try_into().unwrap());
+        assert_eq!(v & (PTE_A | PTE_D), PTE_A);
+
+        mmu.translate(
+            0x4000_0000,
+            4,
+            Access::Store,
+            RvPrivilege::Supervisor,
+            Some(&c),
+            &mut bus,
+        )
+        .unwrap();
+        let v: u64 = u64::from_le_bytes(bus.fetch(8, RAM + 0x3000).unwrap().try_into().unwrap());
+        assert_eq!(v & (PTE_A | PTE_D), PTE_A | PTE_D);
+    }
+
+    #[test]
+    fn test_sum_mxr() {
+        let (mut bus, mut c) = sv39(PTE_V | PTE_X | PTE_U);
+        let mut mmu: Mmu = Mmu::new();
+
+        assert_eq!(
+            mmu.translate(
+                0x4000_0000,
+                4,
+                Access::Load,
+                RvPrivilege::Supervisor,
+                Some(&c),
+                &mut bus
+            ),
+            Err(RvException::LoadPageFault)
+        );
+
+        c.set(
+            csr::MSTATUS,
+            &Uint::from((csr::MSTATUS_SUM | csr::MSTATUS_MXR) as u64),
+        );
+        assert_eq!(
+            mmu.translate(
+                0x4000_0000,
+                4,
+                Access::Load,
+                RvPrivilege::Supervisor,
+                Some(&c),
+                &mut bus
+            ),
+            Ok(RAM + 0x2000)
+        );
+        assert_eq!(
+            mmu.translate(
+                0x4000_0000,
+                4,
+                Access::Fetch,
+                RvPrivilege::Supervisor,
+                Some(&c),
+                &mut bus
+            ),
+            Err(RvException::InstructionPageFault)
+        );
+    }
+
+    #[test]
+    fn test_tlb_flush() {
+        let (mut bus, c) = sv39(PTE_V | PTE_R);
+        let mut mmu: Mmu = Mmu::new();
+
+        mmu.translate(
+            0x4000_0000,
+            4,
+            Access::Load,
+            RvPrivilege::Supervisor,
+            Some(&c),
+            &mut bus,
+        )
+        .unwrap();
+
+        // The TLB still holds the translation until sfence.vma
+        pte(&mut bus, RAM + 0x3000, 0, 0);
+        assert!(mmu
+            .translate(
+                0x4000_0000,
+                4,
+                Access::Load,
+                RvPrivilege::Supervisor,
+                Some(&c),
+                &mut bus
+            )
+            .is_ok());
+
+        mmu.flush(Some(0x4000_0000), None);
+        assert_eq!(
+            mmu.translate(
+                0x4000_0000,
+                4,
+                Access::Load,
+                RvPrivilege::Supervisor,
+                Some(&c),
+                &mut bus
+            ),
+            Err(RvException::LoadPageFault)
+        );
+    }
+
+    // The Sv39x4 G-stage maps the first GiB of guest physical memory at RAM
+    // with a gigapage, the Sv39 VS-stage tables are at guest physical 0
+    #[test]
+    fn test_two_stage() {
+        let (mut bus, mut c) = setup(0);
+        let mut mmu: Mmu = Mmu::new();
+        let translate = |mmu: &mut Mmu, c: &csr::Csr, bus: &mut Bus, va: u64, access: Access| {
+            mmu.translate(va, 4, access, RvPrivilege::Supervisor, Some(c), bus)
+        };
+
+        c.set(
+            csr::HGATP,
+            &Uint::from((8u64 << 60) | ((RAM + 0x8000) >> 12)),
+        );
+        pte(
+            &mut bus,
+            RAM + 0x8000,
+            RAM >> 12,
+            PTE_V | PTE_R | PTE_W | PTE_X | PTE_U,
+        );
+        c.set_virt(true);
+
+        assert_eq!(
+            translate(&mut mmu, &c, &mut bus, 0x1234, Access::Load),
+            Ok(RAM + 0x1234)
+        );
+        assert_eq!(
+            translate(&mut mmu, &c, &mut bus, 0x4000_0000, Access::Load),
+            Err(RvException::LoadGuestPageFault)
+        );
+        assert_eq!(mmu.fault(), (true, 0x4000_0000));
+
+        c.set(csr::VSATP, &Uint::from(8u64 << 60));
+        pte(&mut bus, RAM + 8, 0x1000 >> 12, PTE_V);
+        pte(&mut bus, RAM + 0x1000, 0x3000 >> 12, PTE_V);
+        pte(&mut bus, RAM + 0x3000, 0x2000 >> 12, PTE_V | PTE_R);
+        assert_eq!(
+            translate(&mut mmu, &c, &mut bus, 0x4000_0123, Access::Load),
+            Ok(RAM + 0x2123)
+        );
+        assert_eq!(
+            translate(&mut mmu, &c, &mut bus, 0x4000_0123, Access::Store),
+            Err(RvException::StorePageFault)
+        );
+        assert_eq!(
+            translate(&mut mmu, &c, &mut bus, 0x4000_0123, Access::Execute),
+            Err(RvException::LoadPageFault)
+        );
+
+        // The VS-stage table accesses go through the G-stage as U-mode ones
+        pte(
+            &mut bus,
+            RAM + 0x8000,
+            RAM >> 12,
+            PTE_V | PTE_R | PTE_W | PTE_X,
+        );
+        mmu.flush_guest();
+        assert_eq!(
+            translate(&mut mmu, &c, &mut bus, 0x4000_0123, Access::Load),
+            Err(RvException::LoadGuestPageFault)
+        );
+        assert_eq!(mmu.fault(), (true, 0x8));
+
+        c.set_virt(false);
+        assert_eq!(
+            translate(&mut mmu, &c, &mut bus, 0x4000_0123, Access::Load),
+            Ok(0x4000_0123)
+        );
+        assert!(!mmu.fault().0);
+    }
+
+    #[test]
+    fn test_sv32_megapage() {
+        let mut bus: Bus = Bus::new();
+        let sram: Box<Sram> = Box::new(Sram::new(64 * 1024));
+        let ext: RvExtensions = RvExtensions {
+            s: true,
+            ..Default::default()
+        };
+        let mut c: csr::Csr = csr::Csr::new(32, &ext);
+        let mut mmu: Mmu = Mmu::new();
+
+        bus.attach(
+            RAM,
+            Box::new(Peripheral::new(String::from("sram"), sram.size(), sram)),
+        )
+        .unwrap();
+        c.set(csr::SATP, &Uint::from((1u32 << 31) | (RAM >> 12) as u32));
+        c.set(csr::PMPADDR0, &Uint::from(u32::MAX));
+        c.set(csr::PMPCFG0, &Uint::from(0x1fu32));
+        // va 0x0040_0000 (vpn[1] = 1) -> 4 MiB page at pa 0x8040_0000
+        bus.store(
+            4,
+            RAM + 4,
+            &((((0x8040_0000u64 >> 12) << 10) | PTE_V | PTE_R | PTE_A) as u32).to_le_bytes(),
+        );
+
+        assert_eq!(
+            mmu.translate(
+                0x0041_2345,
+                4,
+                Access::Load,
+                RvPrivilege::Supervisor,
+                Some(&c),
+                &mut bus
+            ),
+            Ok(0x8041_2345)
+        );
+    }
+
+    #[test]
+    fn test_pmp() {
+        let (mut bus, mut c) = setup(0);
+        let mut mmu: Mmu = Mmu::new();
+
+        // No matching entry: S-mode fails, M-mode succeeds
+        c.set(csr::PMPCFG0, &Uint::from(0u64));
+        assert_eq!(
+            mmu.translate(
+                RAM,
+                4,
+                Access::Load,
+                RvPrivilege::Supervisor,
+                Some(&c),
+                &mut bus
+            ),
+            Err(RvException::LoadAccessFault)
+        );
+        assert_eq!(
+            mmu.translate(
+                RAM,
+                4,
+                Access::Load,
+                RvPrivilege::Machine,
+                Some(&c),
+                &mut bus
+            ),
+            Ok(RAM)
+        );
+
+        // The page table walk itself is checked against the PMP
+        let (mut bus, mut c) = sv39(PTE_V | PTE_R | PTE_X);
+        c.set(csr::PMPADDR0, &Uint::from((RAM >> 2) | 0x3ff));
+        c.set(csr::PMPADDR0 + 1, &Uint::from(u64::MAX));
+        c.set(csr::PMPCFG0, &Uint::from(0x1f18u64));
+        assert_eq!(
+            mmu.translate(
+                0x4000_0000,
+                4,
+                Access::Fetch,
+                RvPrivilege::Supervisor,
+                Some(&c),
+                &mut bus
+            ),
+            Err(RvException::InstructionAccessFault)
+        );
+    }
+}
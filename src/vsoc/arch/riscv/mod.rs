@@ -1,8 +1,15 @@
 pub mod atomic;
 pub mod csr;
+pub mod disasm;
 pub mod exception;
 pub mod ext;
+pub mod fpu;
 pub mod hart;
+pub mod icache;
 pub mod instr;
 pub mod interrupt;
+pub mod mmu;
+pub mod pmp;
+pub mod privilege;
 pub mod registers;
+pub mod xlen;
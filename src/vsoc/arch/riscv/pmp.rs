@@ -0,0 +1,211 @@
+use super::mmu::Access;
+use super::privilege::RvPrivilege;
+use crate::vsoc::snapshot::{Reader, SnapshotError, Writer};
+
+pub const PMP_ENTRIES: usize = 64;
+
+// pmpcfg fields
+const PMP_R: u8 = 1 << 0;
+const PMP_W: u8 = 1 << 1;
+const PMP_X: u8 = 1 << 2;
+const PMP_A: u8 = 0x3 << 3;
+const PMP_L: u8 = 1 << 7;
+
+// pmpcfg.A values
+const PMP_A_TOR: u8 = 0x1 << 3;
+const PMP_A_NA4: u8 = 0x2 << 3;
+const PMP_A_NAPOT: u8 = 0x3 << 3;
+
+#[derive(Debug, Default)]
+pub struct Pmp {
+    cfg: Vec<u8>,
+    addr: Vec<u64>,
+    addr_mask: u64,
+}
+
+impl Pmp {
+    pub fn new(xlen: usize) -> Pmp {
+        Pmp {
+            cfg: vec![0; PMP_ENTRIES],
+            addr: vec![0; PMP_ENTRIES],
+            // pmpaddr holds bits 33:2 of the address on RV32, bits 55:2 otherwise
+            addr_mask: if xlen == 32 {
+                0xffff_ffff
+            } else {
+                0x003f_ffff_ffff_ffff
+            },
+        }
+    }
+
+    fn locked(&self, i: usize) -> bool {
+        self.cfg[i] & PMP_L != 0
+    }
+
+    pub fn cfg(&self, i: usize) -> u8 {
+        self.cfg[i]
+    }
+
+    pub fn addr(&self, i: usize) -> u64 {
+        self.addr[i]
+    }
+
+    pub fn save(&self, w: &mut Writer) {
+        w.bytes(&self.cfg);
+        for addr in self.addr.iter() {
+            w.u64(*addr);
+        }
+    }
+
+    // Raw restore, bypassing the lock rules of set_cfg/set_addr
+    pub fn restore(&mut self, r: &mut Reader) -> Result<(), SnapshotError> {
+        self.cfg = r.bytes_exact(PMP_ENTRIES, "pmp entries")?.to_vec();
+        for addr in self.addr.iter_mut() {
+            *addr = r.u64()?;
+        }
+
+        Ok(())
+    }
+
+    pub fn set_cfg(&mut self, i: usize, value: u8) {
+        // Locked entries ignore writes, R=0/W=1 is reserved
+        if self.locked(i) || (value & PMP_R == 0 && value & PMP_W != 0) {
+            return;
+        }
+
+        self.cfg[i] = value & (PMP_R | PMP_W | PMP_X | PMP_A | PMP_L);
+    }
+
+    pub fn set_addr(&mut self, i: usize, value: u64) {
+        // A locked TOR entry also locks the address of the previous entry
+        let tor_locked: bool =
+            i + 1 < PMP_ENTRIES && self.locked(i + 1) && self.cfg[i + 1] & PMP_A == PMP_A_TOR;
+
+        if self.locked(i) || tor_locked {
+            return;
+        }
+
+        self.addr[i] = value & self.addr_mask;
+    }
+
+    // Address range [lo, hi) matched by the entry, None when it is off
+    fn range(&self, i: usize) -> Option<(u128, u128)> {
+        let addr: u128 = self.addr[i] as u128;
+
+        match self.cfg[i] & PMP_A {
+            PMP_A_TOR => {
+                let lo: u128 = if i == 0 {
+                    0
+                } else {
+                    (self.addr[i - 1] as u128) << 2
+                };
+
+                Some((lo, addr << 2))
+            }
+            PMP_A_NA4 => Some((addr << 2, (addr << 2) + 4)),
+            PMP_A_NAPOT => {
+                let ones: u32 = addr.trailing_ones();
+                let lo: u128 = (addr & !((1 << ones) - 1)) << 2;
+
+                Some((lo, lo + (1 << (ones + 3))))
+            }
+            _ => None,
+        }
+    }
+
+    // The lowest-numbered entry matching any byte of the access decides; it
+    // must then cover the whole access. M-mode accesses only obey locked entries
+    pub fn check(&self, addr: u64, width: usize, access: Access, privilege: RvPrivilege) -> bool {
+        let start: u128 = addr as u128;
+        let end: u128 = start + width as u128;
+
+        for i in 0..PMP_ENTRIES {
+            let (lo, hi) = match self.range(i) {
+                Some(r) => r,
+                None => continue,
+            };
+
+            if end <= lo || start >= hi {
+                continue;
+            }
+
+            if start < lo || end > hi {
+                return false;
+            }
+
+            if privilege == RvPrivilege::Machine && !self.locked(i) {
+                return true;
+            }
+
+            return match access {
+                Access::Fetch => self.cfg[i] & PMP_X != 0,
+                Access::Load | Access::Execute => self.cfg[i] & PMP_R != 0,
+                Access::Store => self.cfg[i] & PMP_W != 0,
+            };
+        }
+
+        privilege == RvPrivilege::Machine
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Pmp, PMP_A_NA4, PMP_A_NAPOT, PMP_A_TOR, PMP_L, PMP_R, PMP_W, PMP_X};
+    use crate::vsoc::arch::riscv::{mmu::Access, privilege::RvPrivilege};
+
+    #[test]
+    fn test_no_entry() {
+        let pmp: Pmp = Pmp::new(64);
+
+        assert!(pmp.check(0x8000_0000, 4, Access::Load, RvPrivilege::Machine));
+        assert!(!pmp.check(0x8000_0000, 4, Access::Load, RvPrivilege::Supervisor));
+    }
+
+    #[test]
+    fn test_napot() {
+        let mut pmp: Pmp = Pmp::new(64);
+
+        // 4 KiB at 0x8000_0000, read-only
+        pmp.set_addr(0, (0x8000_0000 >> 2) | 0x1ff);
+        pmp.set_cfg(0, PMP_A_NAPOT | PMP_R);
+
+        assert!(pmp.check(0x8000_0ffc, 4, Access::Load, RvPrivilege::User));
+        assert!(!pmp.check(0x8000_0ffc, 4, Access::Store, RvPrivilege::User));
+        assert!(!pmp.check(0x8000_1000, 4, Access::Load, RvPrivilege::User));
+        // Partial match
+        assert!(!pmp.check(0x8000_0ffe, 4, Access::Load, RvPrivilege::Machine));
+        // Unlocked entries do not apply to M-mode
+        assert!(pmp.check(0x8000_0000, 4, Access::Store, RvPrivilege::Machine));
+    }
+
+    #[test]
+    fn test_tor_na4() {
+        let mut pmp: Pmp = Pmp::new(32);
+
+        pmp.set_addr(0, 0x1000 >> 2);
+        pmp.set_addr(1, 0x2000 >> 2);
+        pmp.set_cfg(1, PMP_A_TOR | PMP_R | PMP_X);
+        pmp.set_addr(2, 0x3000 >> 2);
+        pmp.set_cfg(2, PMP_A_NA4 | PMP_R | PMP_W);
+
+        assert!(!pmp.check(0x0ffc, 4, Access::Load, RvPrivilege::Supervisor));
+        assert!(pmp.check(0x1000, 4, Access::Fetch, RvPrivilege::Supervisor));
+        assert!(pmp.check(0x3000, 4, Access::Store, RvPrivilege::Supervisor));
+        assert!(!pmp.check(0x3004, 4, Access::Store, RvPrivilege::Supervisor));
+    }
+
+    #[test]
+    fn test_lock() {
+        let mut pmp: Pmp = Pmp::new(64);
+
+        pmp.set_addr(0, 0x1000 >> 2);
+        pmp.set_addr(1, 0x2000 >> 2);
+        pmp.set_cfg(1, PMP_A_TOR | PMP_R | PMP_L);
+
+        // Locked entries apply to M-mode and ignore writes
+        assert!(!pmp.check(0x1000, 4, Access::Store, RvPrivilege::Machine));
+        pmp.set_cfg(1, PMP_A_TOR | PMP_R | PMP_W);
+        pmp.set_addr(0, 0);
+        assert!(!pmp.check(0x1000, 4, Access::Store, RvPrivilege::Machine));
+        assert!(pmp.check(0x0ffc, 4, Access::Store, RvPrivilege::Machine));
+    }
+}
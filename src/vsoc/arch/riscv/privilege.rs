@@ -0,0 +1,30 @@
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub enum RvPrivilege {
+    User = 0x0,
+    Supervisor = 0x1,
+    Machine = 0x3,
+}
+
+impl RvPrivilege {
+    pub fn from_mpp(mpp: u128) -> Option<RvPrivilege> {
+        match mpp {
+            0x0 => Some(RvPrivilege::User),
+            0x1 => Some(RvPrivilege::Supervisor),
+            0x3 => Some(RvPrivilege::Machine),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for RvPrivilege {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s: &str = match *self {
+            Self::User => "U",
+            Self::Supervisor => "S",
+            Self::Machine => "M",
+        };
+        write!(f, "{}", s)
+    }
+}
@@ -1,13 +1,37 @@
 use std::fmt;
 
 use crate::vsoc::arch::registers::ArchRegister;
+use crate::vsoc::arch::riscv::xlen::Xlen;
 use crate::vsoc::arch::types::Uint;
+use crate::vsoc::snapshot::{Reader, SnapshotError, Writer};
 
+fn save(reg: &[ArchRegister], w: &mut Writer) {
+    for r in reg.iter() {
+        w.bytes(&Vec::<u8>::from(r.get()));
+    }
+}
+
+fn restore(reg: &mut [ArchRegister], r: &mut Reader) -> Result<(), SnapshotError> {
+    for x in reg.iter_mut() {
+        x.set(&Uint::new(r.bytes()?.to_vec()));
+    }
+
+    Ok(())
+}
+
+// ABI names of the integer registers
+const X_NAMES: [&str; 32] = [
+    "zero", "ra", "sp", "gp", "tp", "t0", "t1", "t2", "s0/fp", "s1", "a0", "a1", "a2", "a3", "a4",
+    "a5", "a6", "a7", "s2", "s3", "s4", "s5", "s6", "s7", "s8", "s9", "s10", "s11", "t3", "t4",
+    "t5", "t6",
+];
+
+// The integer registers hold their native xlen type, Uint being kept for the
+// accesses from outside the hart
 #[derive(Debug, Default)]
-pub struct RvRegisters {
-    xlen: usize,
+pub struct RvRegisters<X: Xlen> {
     count: usize,
-    reg: Vec<ArchRegister>,
+    reg: [X; 32],
 }
 
 #[derive(Debug, Default)]
@@ -17,74 +41,56 @@ pub struct RvFpuRegisters {
     reg: Vec<ArchRegister>,
 }
 
-impl RvRegisters {
-    pub fn new(xlen: usize, count: usize) -> RvRegisters {
-        let mut reg: Vec<ArchRegister>;
-
+impl<X: Xlen> RvRegisters<X> {
+    pub fn new(count: usize) -> RvRegisters<X> {
         println!("* Creating RISC-V registers");
-        reg = Vec::<ArchRegister>::with_capacity(count);
-        for i in 0..count {
-            reg.push(ArchRegister::new(format!("x{}", i), i, Uint::zero(xlen)));
-        }
 
-        println!("* Populating RISC-V registers");
-        reg[0x00] = ArchRegister::new(String::from("zero"), 0, Uint::zero(xlen));
-        reg[0x01] = ArchRegister::new(String::from("ra"), 0x01, Uint::zero(xlen));
-        reg[0x02] = ArchRegister::new(String::from("sp"), 0x02, Uint::zero(xlen));
-        reg[0x03] = ArchRegister::new(String::from("gp"), 0x03, Uint::zero(xlen));
-        reg[0x04] = ArchRegister::new(String::from("tp"), 0x04, Uint::zero(xlen));
-        reg[0x05] = ArchRegister::new(String::from("t0"), 0x05, Uint::zero(xlen));
-        reg[0x06] = ArchRegister::new(String::from("t1"), 0x06, Uint::zero(xlen));
-        reg[0x07] = ArchRegister::new(String::from("t2"), 0x07, Uint::zero(xlen));
-        reg[0x08] = ArchRegister::new(String::from("s0/fp"), 0x08, Uint::zero(xlen));
-        reg[0x09] = ArchRegister::new(String::from("s1"), 0x09, Uint::zero(xlen));
-        reg[0x0a] = ArchRegister::new(String::from("a0"), 0x0a, Uint::zero(xlen));
-        reg[0x0b] = ArchRegister::new(String::from("a1"), 0x0b, Uint::zero(xlen));
-        reg[0x0c] = ArchRegister::new(String::from("a2"), 0x0c, Uint::zero(xlen));
-        reg[0x0d] = ArchRegister::new(String::from("a3"), 0x0d, Uint::zero(xlen));
-        reg[0x0e] = ArchRegister::new(String::from("a4"), 0x0e, Uint::zero(xlen));
-        reg[0x0f] = ArchRegister::new(String::from("a5"), 0x0f, Uint::zero(xlen));
-
-        if count == 32 {
-            reg[0x10] = ArchRegister::new(String::from("a6"), 0x10, Uint::zero(xlen));
-            reg[0x11] = ArchRegister::new(String::from("a7"), 0x11, Uint::zero(xlen));
-            reg[0x12] = ArchRegister::new(String::from("s2"), 0x12, Uint::zero(xlen));
-            reg[0x13] = ArchRegister::new(String::from("s3"), 0x13, Uint::zero(xlen));
-            reg[0x14] = ArchRegister::new(String::from("s4"), 0x14, Uint::zero(xlen));
-            reg[0x15] = ArchRegister::new(String::from("s5"), 0x15, Uint::zero(xlen));
-            reg[0x16] = ArchRegister::new(String::from("s6"), 0x16, Uint::zero(xlen));
-            reg[0x17] = ArchRegister::new(String::from("s7"), 0x17, Uint::zero(xlen));
-            reg[0x18] = ArchRegister::new(String::from("s8"), 0x18, Uint::zero(xlen));
-            reg[0x19] = ArchRegister::new(String::from("s9"), 0x19, Uint::zero(xlen));
-            reg[0x1a] = ArchRegister::new(String::from("s10"), 0x1a, Uint::zero(xlen));
-            reg[0x1b] = ArchRegister::new(String::from("s11"), 0x1b, Uint::zero(xlen));
-            reg[0x1c] = ArchRegister::new(String::from("t3"), 0x1c, Uint::zero(xlen));
-            reg[0x1d] = ArchRegister::new(String::from("t4"), 0x1d, Uint::zero(xlen));
-            reg[0x1e] = ArchRegister::new(String::from("t5"), 0x1e, Uint::zero(xlen));
-            reg[0x1f] = ArchRegister::new(String::from("t6"), 0x1f, Uint::zero(xlen));
+        RvRegisters {
+            count,
+            reg: [X::ZERO; 32],
         }
-
-        RvRegisters { xlen, count, reg }
     }
 
     pub fn len(&self) -> usize {
-        self.xlen
+        X::BITS
     }
 
-    pub fn name(&self, regidx: usize) -> &str {
-        self.reg[regidx].name()
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    pub fn read(&self, regidx: usize) -> X {
+        self.reg[regidx]
+    }
+
+    pub fn write(&mut self, regidx: usize, value: X) {
+        self.reg[regidx] = value;
     }
 
     pub fn set(&mut self, regidx: usize, value: &Uint) {
-        self.reg[regidx].set(value);
+        self.reg[regidx] = X::from(value.clone());
     }
 
     pub fn get(&self, regidx: usize) -> Uint {
-        self.reg[regidx].get()
+        self.reg[regidx].into()
+    }
+
+    pub fn save(&self, w: &mut Writer) {
+        for r in self.reg[..self.count].iter() {
+            w.bytes(&r.to_le_vec());
+        }
+    }
+
+    pub fn restore(&mut self, r: &mut Reader) -> Result<(), SnapshotError> {
+        for x in self.reg[..self.count].iter_mut() {
+            *x = X::from_le_slice(r.bytes()?);
+        }
+
+        Ok(())
     }
 }
 
-impl fmt::Display for RvRegisters {
+impl<X: Xlen> fmt::Display for RvRegisters<X> {
     // This trait requires `fmt` with this exact signature.
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         // Write strictly the first element into the supplied output
@@ -96,8 +102,8 @@ impl fmt::Display for RvRegisters {
             Err(e) => return Err(e),
         }
 
-        for reg in self.reg.iter() {
-            match writeln!(f, "      {}", reg) {
+        for (i, reg) in self.reg[..self.count].iter().enumerate() {
+            match writeln!(f, "      (${}\t{}\t{:#x})", i, X_NAMES[i], reg) {
                 Ok(_) => (),
                 Err(e) => return Err(e),
             }
@@ -125,10 +131,6 @@ impl RvFpuRegisters {
         self.flen
     }
 
-    pub fn name(&self, regidx: usize) -> &str {
-        self.reg[regidx].name()
-    }
-
     pub fn set(&mut self, regidx: usize, value: &Uint) {
         self.reg[regidx].set(value);
     }
@@ -136,6 +138,14 @@ impl RvFpuRegisters {
     pub fn get(&self, regidx: usize) -> Uint {
         self.reg[regidx].get()
     }
+
+    pub fn save(&self, w: &mut Writer) {
+        save(&self.reg, w);
+    }
+
+    pub fn restore(&mut self, r: &mut Reader) -> Result<(), SnapshotError> {
+        restore(&mut self.reg, r)
+    }
 }
 
 impl fmt::Display for RvFpuRegisters {
@@ -160,3 +170,87 @@ impl fmt::Display for RvFpuRegisters {
         writeln!(f, "     )\n    )")
     }
 }
+
+// The 32 vector registers, VLEN bits each, stored back to back so that a
+// register group is a contiguous run of bytes
+#[derive(Debug, Default, Clone)]
+pub struct RvVectorRegisters {
+    vlen: usize,
+    data: Vec<u8>,
+    // Address of the element a vector load/store faulted on, for xtval
+    pub fault: u64,
+}
+
+impl RvVectorRegisters {
+    pub fn new(vlen: usize) -> RvVectorRegisters {
+        println!("* Creating RISC-V vector registers");
+
+        RvVectorRegisters {
+            vlen,
+            data: vec![0; 32 * vlen / 8],
+            fault: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.vlen
+    }
+
+    // Element `idx` of `eew` bytes of the group starting at register `reg`
+    pub fn get(&self, reg: usize, eew: usize, idx: usize) -> u64 {
+        let offset: usize = reg * self.vlen / 8 + idx * eew;
+        let mut value: [u8; 8] = [0; 8];
+
+        value[..eew].copy_from_slice(&self.data[offset..offset + eew]);
+        u64::from_le_bytes(value)
+    }
+
+    pub fn set(&mut self, reg: usize, eew: usize, idx: usize, value: u64) {
+        let offset: usize = reg * self.vlen / 8 + idx * eew;
+
+        self.data[offset..offset + eew].copy_from_slice(&value.to_le_bytes()[..eew]);
+    }
+
+    // Bit `idx` of a mask register
+    pub fn mask(&self, reg: usize, idx: usize) -> bool {
+        self.data[reg * self.vlen / 8 + idx / 8] & (1 << (idx % 8)) != 0
+    }
+
+    pub fn set_mask(&mut self, reg: usize, idx: usize, value: bool) {
+        let offset: usize = reg * self.vlen / 8 + idx / 8;
+
+        if value {
+            self.data[offset] |= 1 << (idx % 8);
+        } else {
+            self.data[offset] &= !(1 << (idx % 8));
+        }
+    }
+
+    pub fn save(&self, w: &mut Writer) {
+        w.bytes(&self.data);
+    }
+
+    pub fn restore(&mut self, r: &mut Reader) -> Result<(), SnapshotError> {
+        let len: usize = self.data.len();
+
+        self.data.copy_from_slice(r.bytes_exact(len, "vlen")?);
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for RvVectorRegisters {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "(vector registers\n     (#vlen {})\n     (", self.vlen)?;
+
+        for (i, reg) in self.data.chunks(self.vlen / 8).enumerate() {
+            write!(f, "      (v{}\t0x", i)?;
+            for b in reg.iter().rev() {
+                write!(f, "{:02x}", b)?;
+            }
+            writeln!(f, ")")?;
+        }
+
+        writeln!(f, "     )\n    )")
+    }
+}
@@ -0,0 +1,196 @@
+use std::fmt;
+use std::ops::{BitAnd, BitOr, BitXor, Not};
+
+use crate::vsoc::arch::types::{mul_high_u128, Uint};
+
+// Native type of the integer registers, the hart is monomorphized over it so
+// that the hot path does not go through the heap allocated Uint
+pub trait Xlen:
+    Copy
+    + Default
+    + fmt::Debug
+    + fmt::LowerHex
+    + Eq
+    + Ord
+    + BitAnd<Output = Self>
+    + BitOr<Output = Self>
+    + BitXor<Output = Self>
+    + Not<Output = Self>
+    + From<Uint>
+    + Into<Uint>
+    + 'static
+{
+    const BITS: usize;
+    const ZERO: Self;
+    const ONES: Self;
+
+    // Keep the low xlen bits
+    fn from_u128(value: u128) -> Self;
+    fn to_u128(self) -> u128;
+    fn to_i128(self) -> i128;
+    fn wrapping_add(self, rhs: Self) -> Self;
+    fn wrapping_sub(self, rhs: Self) -> Self;
+    fn wrapping_mul(self, rhs: Self) -> Self;
+    fn mulhu(self, rhs: Self) -> Self;
+    // The shift amount is taken modulo xlen
+    fn shl(self, shamt: u32) -> Self;
+    fn shr(self, shamt: u32) -> Self;
+    fn sra(self, shamt: u32) -> Self;
+
+    fn from_i128(value: i128) -> Self {
+        Self::from_u128(value as u128)
+    }
+
+    fn to_u64(self) -> u64 {
+        self.to_u128() as u64
+    }
+
+    // Little-endian bytes, zero-extended
+    fn from_le_slice(bytes: &[u8]) -> Self {
+        let mut value: [u8; 16] = [0; 16];
+        let len: usize = bytes.len().min(16);
+
+        value[..len].copy_from_slice(&bytes[..len]);
+        Self::from_u128(u128::from_le_bytes(value))
+    }
+
+    fn to_le_vec(self) -> Vec<u8> {
+        self.to_u128().to_le_bytes()[..Self::BITS / 8].to_vec()
+    }
+
+    fn signed_lt(self, rhs: Self) -> bool {
+        self.to_i128() < rhs.to_i128()
+    }
+
+    // Upper half of the double-width product, the unsigned one corrected for
+    // the negative operands
+    fn mulh(self, rhs: Self, signed: bool, rhs_signed: bool) -> Self {
+        let mut high: Self = self.mulhu(rhs);
+
+        if signed && self.to_i128() < 0 {
+            high = high.wrapping_sub(rhs);
+        }
+        if rhs_signed && rhs.to_i128() < 0 {
+            high = high.wrapping_sub(self);
+        }
+
+        high
+    }
+
+    // Division by zero gives all ones, the overflow gives the dividend
+    fn div(self, rhs: Self) -> Self {
+        if rhs == Self::ZERO {
+            return Self::ONES;
+        }
+
+        Self::from_i128(self.to_i128().wrapping_div(rhs.to_i128()))
+    }
+
+    fn divu(self, rhs: Self) -> Self {
+        if rhs == Self::ZERO {
+            return Self::ONES;
+        }
+
+        Self::from_u128(self.to_u128() / rhs.to_u128())
+    }
+
+    // The remainder by zero is the dividend, the one of the overflow is zero
+    fn rem(self, rhs: Self) -> Self {
+        if rhs == Self::ZERO {
+            return self;
+        }
+
+        Self::from_i128(self.to_i128().wrapping_rem(rhs.to_i128()))
+    }
+
+    fn remu(self, rhs: Self) -> Self {
+        if rhs == Self::ZERO {
+            return self;
+        }
+
+        Self::from_u128(self.to_u128() % rhs.to_u128())
+    }
+}
+
+macro_rules! xlen {
+    ($u:ty, $i:ty, $mulhu:expr) => {
+        impl Xlen for $u {
+            const BITS: usize = <$u>::BITS as usize;
+            const ZERO: Self = 0;
+            const ONES: Self = <$u>::MAX;
+
+            fn from_u128(value: u128) -> Self {
+                value as $u
+            }
+
+            fn to_u128(self) -> u128 {
+                self as u128
+            }
+
+            fn to_i128(self) -> i128 {
+                self as $i as i128
+            }
+
+            fn wrapping_add(self, rhs: Self) -> Self {
+                <$u>::wrapping_add(self, rhs)
+            }
+
+            fn wrapping_sub(self, rhs: Self) -> Self {
+                <$u>::wrapping_sub(self, rhs)
+            }
+
+            fn wrapping_mul(self, rhs: Self) -> Self {
+                <$u>::wrapping_mul(self, rhs)
+            }
+
+            fn mulhu(self, rhs: Self) -> Self {
+                $mulhu(self, rhs)
+            }
+
+            fn shl(self, shamt: u32) -> Self {
+                self.wrapping_shl(shamt)
+            }
+
+            fn shr(self, shamt: u32) -> Self {
+                self.wrapping_shr(shamt)
+            }
+
+            fn sra(self, shamt: u32) -> Self {
+                (self as $i).wrapping_shr(shamt) as $u
+            }
+        }
+    };
+}
+
+xlen!(u32, i32, |a: u32, b: u32| ((a as u64 * b as u64) >> 32) as u32);
+xlen!(u64, i64, |a: u64, b: u64| ((a as u128 * b as u128) >> 64) as u64);
+xlen!(u128, i128, mul_high_u128);
+
+#[cfg(test)]
+mod tests {
+    use super::Xlen;
+
+    #[test]
+    fn test_conversions() {
+        assert_eq!(u32::from_i128(-1), u32::MAX);
+        assert_eq!(0x8000_0000u32.to_i128(), i32::MIN as i128);
+        assert_eq!(u64::from_le_slice(&[0x34, 0x12]), 0x1234);
+        assert_eq!(0x1234u32.to_le_vec(), vec![0x34, 0x12, 0, 0]);
+        assert!(u64::MAX.signed_lt(0));
+    }
+
+    #[test]
+    fn test_arith() {
+        assert_eq!(0x8000_0000u32.shl(33), 0);
+        assert_eq!(0x8000_0000u32.sra(31), u32::MAX);
+        assert_eq!(1u64.shl(65), 2);
+        assert_eq!(u64::MAX.mulh(u64::MAX, true, true), 0);
+        assert_eq!(u64::MAX.mulh(u64::MAX, false, false), u64::MAX - 1);
+        assert_eq!(u128::MAX.mulh(2, true, false), u128::MAX);
+        assert_eq!(7u32.div(0), u32::MAX);
+        assert_eq!(0x8000_0000u32.div(u32::MAX), 0x8000_0000);
+        assert_eq!(0x8000_0000u32.rem(u32::MAX), 0);
+        assert_eq!(7u128.remu(0), 7);
+        assert_eq!(u64::from_i128(-7).rem(2), u64::MAX);
+    }
+}
@@ -1,6 +1,6 @@
 use std::{
     fmt,
-    ops::{Add, BitAnd, BitOr, BitXor, Not, Shl, Shr},
+    ops::{Add, BitAnd, BitOr, BitXor, Div, Mul, Not, Rem, Shl, Shr, Sub},
 };
 
 #[derive(Debug, Clone)]
@@ -198,6 +198,74 @@ impl Uint {
             .rev()
             .cmp(other.value.iter().rev())
     }
+
+    // Two's complement ordering, the one of PartialOrd being unsigned
+    pub fn signed_cmp(&self, other: &Self) -> std::cmp::Ordering {
+        let negative = |v: &Self| v.value.last().is_some_and(|b| b & 0x80 != 0);
+
+        match (negative(self), negative(other)) {
+            (true, false) => std::cmp::Ordering::Less,
+            (false, true) => std::cmp::Ordering::Greater,
+            _ => self.cmp(other),
+        }
+    }
+
+    // Upper half of the double-width product, each operand being signed or
+    // not. The unsigned product is corrected for the negative operands
+    pub fn mul_high(&self, rhs: &Self, signed: bool, rhs_signed: bool) -> Self {
+        match self.value.len() {
+            4 => {
+                let (a, b) = (u32::from(self.clone()), u32::from(rhs.clone()));
+                let mut high: u32 = ((a as u64 * b as u64) >> 32) as u32;
+
+                if signed && (a as i32) < 0 {
+                    high = high.wrapping_sub(b);
+                }
+                if rhs_signed && (b as i32) < 0 {
+                    high = high.wrapping_sub(a);
+                }
+                Uint::from(high)
+            }
+            8 => {
+                let (a, b) = (u64::from(self.clone()), u64::from(rhs.clone()));
+                let mut high: u64 = ((a as u128 * b as u128) >> 64) as u64;
+
+                if signed && (a as i64) < 0 {
+                    high = high.wrapping_sub(b);
+                }
+                if rhs_signed && (b as i64) < 0 {
+                    high = high.wrapping_sub(a);
+                }
+                Uint::from(high)
+            }
+            16 => {
+                let (a, b) = (u128::from(self.clone()), u128::from(rhs.clone()));
+                let mut high: u128 = mul_high_u128(a, b);
+
+                if signed && (a as i128) < 0 {
+                    high = high.wrapping_sub(b);
+                }
+                if rhs_signed && (b as i128) < 0 {
+                    high = high.wrapping_sub(a);
+                }
+                Uint::from(high)
+            }
+            _ => panic!("Unsupported width"),
+        }
+    }
+}
+
+// Upper 128 bits of the 256-bit product, from 64-bit partial products
+pub fn mul_high_u128(a: u128, b: u128) -> u128 {
+    let mask: u128 = u64::MAX as u128;
+    let (a1, a0) = (a >> 64, a & mask);
+    let (b1, b0) = (b >> 64, b & mask);
+    let low: u128 = a0 * b0;
+    let cross0: u128 = a0 * b1;
+    let cross1: u128 = a1 * b0;
+    let middle: u128 = (low >> 64) + (cross0 & mask) + (cross1 & mask);
+
+    a1 * b1 + (cross0 >> 64) + (cross1 >> 64) + (middle >> 64)
 }
 
 impl PartialEq for Uint {
@@ -280,6 +348,59 @@ impl Add for Uint {
     }
 }
 
+impl Sub for Uint {
+    type Output = Self;
+
+    fn sub(self, rhs: Self) -> Self::Output {
+        match self.value.len() {
+            4 => Uint::from(u32::from(self).wrapping_sub(u32::from(rhs))),
+            8 => Uint::from(u64::from(self).wrapping_sub(u64::from(rhs))),
+            16 => Uint::from(u128::from(self).wrapping_sub(u128::from(rhs))),
+            _ => panic!("Unsupported width"),
+        }
+    }
+}
+
+impl Mul for Uint {
+    type Output = Self;
+
+    fn mul(self, rhs: Self) -> Self::Output {
+        match self.value.len() {
+            4 => Uint::from(u32::from(self).wrapping_mul(u32::from(rhs))),
+            8 => Uint::from(u64::from(self).wrapping_mul(u64::from(rhs))),
+            16 => Uint::from(u128::from(self).wrapping_mul(u128::from(rhs))),
+            _ => panic!("Unsupported width"),
+        }
+    }
+}
+
+// Unsigned division and remainder, the divisor must not be zero
+impl Div for Uint {
+    type Output = Self;
+
+    fn div(self, rhs: Self) -> Self::Output {
+        match self.value.len() {
+            4 => Uint::from(u32::from(self) / u32::from(rhs)),
+            8 => Uint::from(u64::from(self) / u64::from(rhs)),
+            16 => Uint::from(u128::from(self) / u128::from(rhs)),
+            _ => panic!("Unsupported width"),
+        }
+    }
+}
+
+impl Rem for Uint {
+    type Output = Self;
+
+    fn rem(self, rhs: Self) -> Self::Output {
+        match self.value.len() {
+            4 => Uint::from(u32::from(self) % u32::from(rhs)),
+            8 => Uint::from(u64::from(self) % u64::from(rhs)),
+            16 => Uint::from(u128::from(self) % u128::from(rhs)),
+            _ => panic!("Unsupported width"),
+        }
+    }
+}
+
 impl Shl for Uint {
     type Output = Self;
 
@@ -658,4 +779,76 @@ mod tests {
         let value = Uint::from(255u32) + Uint::from(1u32);
         assert_eq!(u32::from(value), 256u32);
     }
+
+    #[test]
+    fn test_sub_mul_div() {
+        let value = Uint::from(1u128) - Uint::from(2u128);
+        assert_eq!(u128::from(value), u128::MAX);
+
+        let value = Uint::from(1u128 << 100) * Uint::from(3u128 << 20);
+        assert_eq!(u128::from(value), 3 << 120);
+
+        let value = Uint::from(u128::MAX) / Uint::from(1u128 << 64);
+        assert_eq!(u128::from(value), u64::MAX as u128);
+
+        let value = Uint::from(u128::MAX) % Uint::from(1u128 << 64);
+        assert_eq!(u128::from(value), u64::MAX as u128);
+
+        let value = Uint::from(7u32) - Uint::from(9u32);
+        assert_eq!(i32::from(value), -2);
+    }
+
+    #[test]
+    fn test_signed_cmp() {
+        use std::cmp::Ordering;
+
+        assert_eq!(
+            Uint::from(-1i128).signed_cmp(&Uint::from(1i128)),
+            Ordering::Less
+        );
+        assert_eq!(
+            Uint::from(1i128).signed_cmp(&Uint::from(-1i128)),
+            Ordering::Greater
+        );
+        assert_eq!(
+            Uint::from(-2i64).signed_cmp(&Uint::from(-1i64)),
+            Ordering::Less
+        );
+        assert_eq!(
+            Uint::from(3u32).signed_cmp(&Uint::from(3u32)),
+            Ordering::Equal
+        );
+        assert!(Uint::from(-1i128) > Uint::from(1i128));
+    }
+
+    #[test]
+    fn test_mul_high() {
+        let max: Uint = Uint::from(u128::MAX);
+        let minus_two: Uint = Uint::from(-2i128);
+
+        assert_eq!(u128::from(max.mul_high(&max, false, false)), u128::MAX - 1);
+        assert_eq!(i128::from(max.mul_high(&max, true, true)), 0);
+        assert_eq!(i128::from(minus_two.mul_high(&max, true, false)), -2);
+        assert_eq!(
+            u128::from(Uint::from(1u128 << 127).mul_high(&Uint::from(4u128), false, false)),
+            2
+        );
+        assert_eq!(
+            i128::from(Uint::from(i128::MIN).mul_high(&Uint::from(i128::MIN), true, true)),
+            1 << 126
+        );
+
+        assert_eq!(
+            u64::from(Uint::from(u64::MAX).mul_high(&Uint::from(u64::MAX), false, false)),
+            u64::MAX - 1
+        );
+        assert_eq!(
+            i64::from(Uint::from(-1i64).mul_high(&Uint::from(u64::MAX), true, false)),
+            -1
+        );
+        assert_eq!(
+            i32::from(Uint::from(-1i32).mul_high(&Uint::from(-1i32), true, true)),
+            0
+        );
+    }
 }
@@ -1,8 +1,9 @@
 use std::fmt;
 use std::fmt::Debug;
 
-use crate::vsoc::peripheral::Peripheral;
 use crate::vsoc::peripheral::PeripheralInterface;
+use crate::vsoc::peripheral::{Memory, Peripheral};
+use crate::vsoc::snapshot::{Reader, SnapshotError, Writer};
 
 #[derive(Debug)]
 pub enum BusException {
@@ -12,100 +13,311 @@ pub enum BusException {
     StoreAccessFault,
 }
 
+#[derive(Debug)]
+pub enum BusError {
+    Empty(String),
+    OutOfRange(String),
+    Overlap(String, String),
+}
+
+impl fmt::Display for BusError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s: String = match self {
+            Self::Empty(name) => format!("Empty({})", name),
+            Self::OutOfRange(name) => format!("OutOfRange({})", name),
+            Self::Overlap(a, b) => format!("Overlap({}, {})", a, b),
+        };
+        write!(f, "BusError::{}", s)
+    }
+}
+
+#[derive(Debug)]
+struct Region {
+    origin: u64,
+    // Last address decoded by the peripheral
+    end: u64,
+    p: Box<Peripheral>,
+    // Memory of a RAM-like peripheral, accessed without calling it
+    ram: Option<Memory>,
+}
+
+impl Region {
+    // Offset of an access fully inside the region
+    fn offset(&self, width: usize, addr: u64) -> Option<usize> {
+        if addr < self.origin || self.end - addr < width.max(1) as u64 - 1 {
+            return None;
+        }
+
+        Some((addr - self.origin) as usize)
+    }
+}
+
 #[derive(Debug, Default)]
 pub struct Bus {
-    map: Vec<(u64, Box<Peripheral>)>,
+    // Sorted by origin, the regions do not overlap
+    map: Vec<Region>,
+    // Region of the last access, the next one is likely to hit it again
+    hit: usize,
+    // Address and width of the stores since the last `drain_stores`
+    stores: Option<Vec<(u64, usize)>>,
 }
 
 impl Bus {
     pub fn new() -> Bus {
         Bus {
-            map: Vec::<(u64, Box<Peripheral>)>::new(),
+            map: Vec::<Region>::new(),
+            hit: 0,
+            stores: None,
+        }
+    }
+
+    // Keep a log of the stores, for the harts to snoop each other's writes
+    // and drop the instructions they cached from there
+    pub fn track_stores(&mut self) {
+        self.stores = Some(Vec::new());
+    }
+
+    // The log keeps its capacity, stores are frequent
+    pub fn drain_stores(&mut self) -> impl Iterator<Item = (u64, usize)> + '_ {
+        self.stores.iter_mut().flat_map(|s| s.drain(..))
+    }
+
+    pub fn attach(&mut self, origin: u64, p: Box<Peripheral>) -> Result<(), BusError> {
+        if p.size() == 0 {
+            return Err(BusError::Empty(p.name.clone()));
+        }
+
+        let end: u64 = origin
+            .checked_add(p.size() as u64 - 1)
+            .ok_or_else(|| BusError::OutOfRange(p.name.clone()))?;
+        let i: usize = self.map.partition_point(|r| r.origin < origin);
+
+        // Only the neighbours of the new region may overlap it
+        for r in self.map[i.saturating_sub(1)..(i + 1).min(self.map.len())].iter() {
+            if r.origin <= end && origin <= r.end {
+                return Err(BusError::Overlap(r.p.name.clone(), p.name.clone()));
+            }
+        }
+
+        let ram: Option<Memory> = p.memory();
+
+        self.map.insert(
+            i,
+            Region {
+                origin,
+                end,
+                p,
+                ram,
+            },
+        );
+        self.hit = 0;
+
+        Ok(())
+    }
+
+    // Index of the region decoding an address
+    fn find(&mut self, addr: u64) -> Option<usize> {
+        if let Some(r) = self.map.get(self.hit) {
+            if r.origin <= addr && addr <= r.end {
+                return Some(self.hit);
+            }
+        }
+
+        let i: usize = self
+            .map
+            .partition_point(|r| r.origin <= addr)
+            .checked_sub(1)?;
+
+        if addr > self.map[i].end {
+            return None;
+        }
+
+        self.hit = i;
+        Some(i)
+    }
+
+    // Whether a block lies inside a single region
+    pub fn mapped(&mut self, addr: u64, size: u64) -> bool {
+        match (self.find(addr), size.checked_sub(1)) {
+            (Some(i), Some(last)) => self.map[i].end - addr >= last,
+            (_, None) => true,
+            (None, _) => false,
+        }
+    }
+
+    // Copy a block to memory, using the widest aligned accesses possible
+    pub fn write(&mut self, addr: u64, data: &[u8]) -> Option<BusException> {
+        let mut i: usize = 0;
+
+        while i < data.len() {
+            let a: u64 = addr + i as u64;
+            let width: usize = [8, 4, 2, 1]
+                .into_iter()
+                .find(|w| a.is_multiple_of(*w as u64) && i + w <= data.len())
+                .unwrap();
+
+            if let Some(e) = self.store(width, a, &data[i..i + width]) {
+                return Some(e);
+            }
+
+            i += width;
+        }
+
+        None
+    }
+
+    // The map is saved along with the devices, to restore into the same machine
+    pub fn save(&self, w: &mut Writer) {
+        w.u64(self.map.len() as u64);
+        for r in self.map.iter() {
+            w.u64(r.origin);
+            w.str(&r.p.name);
+            w.u64(r.p.size() as u64);
+            r.p.save(w);
+        }
+    }
+
+    pub fn restore(&mut self, r: &mut Reader) -> Result<(), SnapshotError> {
+        r.expect(self.map.len() as u64, "memory map")?;
+        for region in self.map.iter_mut() {
+            let p: &mut Peripheral = &mut region.p;
+
+            r.expect(region.origin, &p.name)?;
+            if r.str()? != p.name {
+                return Err(SnapshotError::Mismatch(p.name.clone()));
+            }
+            r.expect(p.size() as u64, &p.name)?;
+            p.restore(r)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn tick(&mut self) {
+        for r in self.map.iter_mut() {
+            r.p.tick();
         }
     }
 
-    pub fn attach(&mut self, origin: u64, p: Box<Peripheral>) {
-        self.map.push((origin, p));
-        self.map.sort_by(|a, b| a.0.cmp(&b.0));
+    // A load of up to 16 bytes, that does not allocate when it hits RAM
+    pub fn load(&mut self, width: usize, addr: u64) -> Result<u128, BusException> {
+        let i: usize = self.find(addr).ok_or(BusException::LoadAccessFault)?;
+        let r: &Region = &self.map[i];
+        let mut bytes: [u8; 16] = [0; 16];
+
+        if let Some(ram) = &r.ram {
+            let offset: usize = r.offset(width, addr).ok_or(BusException::LoadAccessFault)?;
+
+            bytes[..width].copy_from_slice(&ram.borrow()[offset..offset + width]);
+        } else {
+            bytes[..width].copy_from_slice(&self.fetch(width, addr)?);
+        }
+
+        Ok(u128::from_le_bytes(bytes))
     }
 
     pub fn fetch(&mut self, width: usize, addr: u64) -> Result<Vec<u8>, BusException> {
-        for (origin, p) in self.map.iter_mut() {
-            if addr >= *origin && addr < *origin + p.size() as u64 {
-                if addr % width as u64 == 0 {
-                    let mut i = 0;
-                    let mut w = width;
-                    let mut value: Vec<u8> = Vec::new();
-
-                    while i < width {
-                        if width > p.size() {
-                            w = p.size()
-                        } else {
-                            w = width - i;
-                        }
-
-                        match p.fetch(w, (addr - *origin) as usize) {
-                            Ok(mut v) => value.append(&mut v),
-                            Err(e) => return Err(e),
-                        }
-
-                        i += w;
-                    }
-                    
-                    return Ok(value);
-                } else {
-                    let base: u64 = addr - *origin;
-                    let mut value: Vec<u8> = Vec::new();
+        let i: usize = self.find(addr).ok_or(BusException::LoadAccessFault)?;
+        let r: &mut Region = &mut self.map[i];
+
+        if let Some(ram) = &r.ram {
+            let offset: usize = r.offset(width, addr).ok_or(BusException::LoadAccessFault)?;
+
+            return Ok(ram.borrow()[offset..offset + width].to_vec());
+        }
 
-                    for i in 0..width {
-                        value.push(p.fetch(1, base as usize + i).unwrap()[0]);
-                    }
+        let (origin, p): (u64, &mut Peripheral) = (r.origin, &mut r.p);
 
-                    return Ok(value);
+        if addr % width as u64 == 0 {
+            let mut i = 0;
+            let mut w = width;
+            let mut value: Vec<u8> = Vec::new();
+
+            while i < width {
+                if width > p.size() {
+                    w = p.size()
+                } else {
+                    w = width - i;
+                }
+
+                match p.fetch(w, (addr - origin) as usize) {
+                    Ok(mut v) => value.append(&mut v),
+                    Err(e) => return Err(e),
                 }
+
+                i += w;
             }
+
+            return Ok(value);
         }
 
-        Err(BusException::LoadAccessFault)
+        let base: u64 = addr - origin;
+        let mut value: Vec<u8> = Vec::new();
+
+        for i in 0..width {
+            value.push(p.fetch(1, base as usize + i)?[0]);
+        }
+
+        Ok(value)
     }
 
     pub fn store(&mut self, width: usize, addr: u64, value: &[u8]) -> Option<BusException> {
-        for (origin, p) in self.map.iter_mut() {
-            if *origin <= addr && addr < *origin + p.size() as u64 {
-                if width == 1 || addr % width as u64 == 0 {
-                    let mut i = 0;
-                    let mut w;
-                    let len = std::cmp::min(value.len(), width);
-
-                    while i < len {
-                        if len > p.size() {
-                            w = p.size()
-                        } else {
-                            w = len - i;
-                        }
-
-                        if let Some(e) = p.store(w, (addr - *origin) as usize + i, &value[i..i+w].to_vec()) {
-                            return Some(e);
-                        }
-
-                        i += w;
-                    }
-
-                    return None;
+        if let Some(stores) = self.stores.as_mut() {
+            stores.push((addr, width));
+        }
+
+        let i: usize = match self.find(addr) {
+            Some(i) => i,
+            None => return Some(BusException::StoreAccessFault),
+        };
+        let r: &mut Region = &mut self.map[i];
+
+        if let Some(ram) = &r.ram {
+            let len: usize = std::cmp::min(value.len(), width);
+
+            match r.offset(len, addr) {
+                Some(offset) => {
+                    ram.borrow_mut()[offset..offset + len].copy_from_slice(&value[..len])
+                }
+                None => return Some(BusException::StoreAccessFault),
+            }
+
+            return None;
+        }
+
+        let (origin, p): (u64, &mut Peripheral) = (r.origin, &mut r.p);
+
+        if width == 1 || addr % width as u64 == 0 {
+            let mut i = 0;
+            let mut w;
+            let len = std::cmp::min(value.len(), width);
+
+            while i < len {
+                if len > p.size() {
+                    w = p.size()
                 } else {
-                    let base: u64 = addr - *origin;
-                    for i in 0..width {
-                        if let Some(e) = p.store(1, base as usize + i, &[value[i]].to_vec()) {
-                            return Some(e);
-                        }
-                    }
-
-                    return None;
+                    w = len - i;
                 }
+
+                if let Some(e) = p.store(w, (addr - origin) as usize + i, &value[i..i + w].to_vec())
+                {
+                    return Some(e);
+                }
+
+                i += w;
+            }
+
+            return None;
+        }
+
+        let base: u64 = addr - origin;
+        for i in 0..width {
+            if let Some(e) = p.store(1, base as usize + i, &[value[i]].to_vec()) {
+                return Some(e);
             }
         }
 
-        Some(BusException::StoreAccessFault)
+        None
     }
 }
 
@@ -121,13 +333,13 @@ impl fmt::Display for Bus {
             Err(e) => return Err(e),
         }
 
-        for (origin, p) in self.map.iter() {
+        for r in self.map.iter() {
             match writeln!(
                 f,
                 "  ({:#0x}\t{:#0x}\t{})",
-                origin,
-                origin + p.size as u64,
-                p.name
+                r.origin,
+                r.origin + r.p.size as u64,
+                r.p.name
             ) {
                 Ok(_) => (),
                 Err(e) => return Err(e),
@@ -140,7 +352,8 @@ impl fmt::Display for Bus {
 
 #[cfg(test)]
 mod tests {
-    use crate::vsoc::bus::{Bus, BusException};
+    use crate::vsoc::bus::{Bus, BusError, BusException};
+    use crate::vsoc::dev::sram::Sram;
     use crate::vsoc::peripheral::{Peripheral, PeripheralInterface};
 
     #[derive(Debug)]
@@ -170,7 +383,7 @@ mod tests {
         let binding = Box::new(PeripheralLikeStruct);
         let p = Peripheral::new(String::from("test"), 0x1000, binding);
 
-        b.attach(0x8000_0000, Box::new(p));
+        b.attach(0x8000_0000, Box::new(p)).unwrap();
 
         assert!(b.fetch(1, 0x8000_0000 - 1).is_err());
         assert_eq!(
@@ -180,16 +393,106 @@ mod tests {
         assert!(b.fetch(1, 0x8000_1000).is_err());
     }
 
+    #[test]
+    fn test_write() {
+        let mut b: Bus = Bus::new();
+        let sram = Box::new(Sram::new(0x100));
+
+        b.attach(
+            0x8000_0000,
+            Box::new(Peripheral::new(String::from("sram"), 0x100, sram)),
+        )
+        .unwrap();
+
+        let data: Vec<u8> = (0..19).collect();
+        assert!(b.write(0x8000_0003, &data).is_none());
+        assert_eq!(b.fetch(8, 0x8000_0008).unwrap(), (5..13).collect::<Vec<u8>>());
+        assert_eq!(b.fetch(1, 0x8000_0015).unwrap(), vec![18]);
+        assert!(b.write(0x8000_00ff, &data).is_some());
+    }
+
     #[test]
     fn test_store() {
         let mut b: Bus = Bus::new();
         let binding = Box::new(PeripheralLikeStruct);
         let p = Peripheral::new(String::from("test"), 0x1000, binding);
 
-        b.attach(0x8000_0000, Box::new(p));
+        b.attach(0x8000_0000, Box::new(p)).unwrap();
 
         assert!(b.store(1, 0x8000_0000 - 1, &[1u8; 1].to_vec()).is_some());
         assert!(b.store(1, 0x8000_0000, &[0u8; 1].to_vec()).is_none());
         assert!(b.store(1, 0x8000_1000, &[0u8; 1].to_vec()).is_some());
     }
+
+    fn sram(name: &str, size: usize) -> Box<Peripheral> {
+        Box::new(Peripheral::new(
+            String::from(name),
+            size,
+            Box::new(Sram::new(size)),
+        ))
+    }
+
+    #[test]
+    fn test_attach() {
+        let mut b: Bus = Bus::new();
+
+        assert!(b.attach(0x8000_0000, sram("a", 0x100)).is_ok());
+        assert!(b.attach(0x8000_0200, sram("b", 0x100)).is_ok());
+        // Right between them
+        assert!(b.attach(0x8000_0100, sram("c", 0x100)).is_ok());
+        assert!(matches!(
+            b.attach(0x8000_00ff, sram("d", 1)),
+            Err(BusError::Overlap(a, d)) if a == "a" && d == "d"
+        ));
+        assert!(matches!(
+            b.attach(0x7fff_ff00, sram("e", 0x1000)),
+            Err(BusError::Overlap(..))
+        ));
+        assert!(matches!(
+            b.attach(0x1000, sram("f", 0)),
+            Err(BusError::Empty(_))
+        ));
+        assert!(matches!(
+            b.attach(u64::MAX, sram("g", 2)),
+            Err(BusError::OutOfRange(_))
+        ));
+        assert!(b.attach(u64::MAX, sram("h", 1)).is_ok());
+    }
+
+    #[test]
+    fn test_lookup() {
+        let mut b: Bus = Bus::new();
+        let binding = Box::new(PeripheralLikeStruct);
+        let p = Peripheral::new(String::from("test"), 0x1000, binding);
+
+        b.attach(0x1000_0000, Box::new(p)).unwrap();
+        b.attach(0x8000_0000, sram("sram", 0x100)).unwrap();
+
+        assert!(b.store(4, 0x8000_0010, &[1, 2, 3, 4]).is_none());
+        assert_eq!(b.fetch(1, 0x1000_0fff).unwrap(), vec![0x01]);
+        assert_eq!(b.load(4, 0x8000_0010).unwrap(), 0x0403_0201);
+        assert!(b.load(1, 0x8000_0100).is_err());
+        assert!(b.load(1, 0x2000_0000).is_err());
+        assert!(b.load(1, 0x0fff_ffff).is_err());
+    }
+
+    #[test]
+    fn test_ram() {
+        let mut b: Bus = Bus::new();
+
+        b.attach(0x8000_0000, sram("sram", 0x100)).unwrap();
+
+        // Misaligned accesses go straight to the memory
+        assert!(b
+            .store(8, 0x8000_0003, &0x1122_3344_5566_7788u64.to_le_bytes())
+            .is_none());
+        assert_eq!(b.load(8, 0x8000_0003).unwrap(), 0x1122_3344_5566_7788);
+        assert_eq!(b.load(2, 0x8000_0004).unwrap(), 0x6677);
+        assert_eq!(b.fetch(1, 0x8000_000a).unwrap(), vec![0x11]);
+
+        // Crossing the end of the memory
+        assert!(b.load(4, 0x8000_00fe).is_err());
+        assert!(b.fetch(2, 0x8000_00ff).is_err());
+        assert!(b.store(2, 0x8000_00ff, &[0; 2]).is_some());
+    }
 }
@@ -0,0 +1,167 @@
+use crate::vsoc::bus::BusException;
+use crate::vsoc::peripheral::PeripheralInterface;
+use crate::vsoc::snapshot::{Reader, SnapshotError, Writer};
+
+// SiFive compatible register layout
+pub const REG_MSIP: usize = 0x0000;
+pub const REG_MTIMECMP: usize = 0x4000;
+pub const REG_MTIME: usize = 0xbff8;
+
+// mtime advances once per step, reported to software as a 10 MHz time base
+pub const TIMEBASE_FREQUENCY: u32 = 10_000_000;
+
+#[derive(Debug)]
+pub struct Clint {
+    length: usize,
+    mtime: u64,
+    msip: Vec<u32>,
+    mtimecmp: Vec<u64>,
+}
+
+impl Clint {
+    pub fn new(harts: usize) -> Clint {
+        Clint {
+            length: 0x10000,
+            mtime: 0,
+            msip: vec![0; harts],
+            mtimecmp: vec![u64::MAX; harts],
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        self.length
+    }
+
+    pub fn mtime(&self) -> u64 {
+        self.mtime
+    }
+
+    // Machine software interrupt line of the hart
+    pub fn msip(&self, hart: usize) -> bool {
+        self.msip[hart] & 0x1 != 0
+    }
+
+    // Machine timer interrupt line of the hart
+    pub fn mtip(&self, hart: usize) -> bool {
+        self.mtime >= self.mtimecmp[hart]
+    }
+
+    // Register, hart and byte offset in the register of an address
+    fn decode(&self, addr: usize) -> Option<(usize, usize, usize)> {
+        let harts: usize = self.msip.len();
+
+        if (REG_MSIP..REG_MSIP + 4 * harts).contains(&addr) {
+            Some((REG_MSIP, (addr - REG_MSIP) / 4, addr % 4))
+        } else if (REG_MTIMECMP..REG_MTIMECMP + 8 * harts).contains(&addr) {
+            Some((REG_MTIMECMP, (addr - REG_MTIMECMP) / 8, addr % 8))
+        } else if (REG_MTIME..REG_MTIME + 8).contains(&addr) {
+            Some((REG_MTIME, 0, addr % 8))
+        } else {
+            None
+        }
+    }
+}
+
+impl PeripheralInterface for Clint {
+    fn fetch(&mut self, width: usize, addr: usize) -> Result<Vec<u8>, BusException> {
+        if (width != 4 && width != 8) || !addr.is_multiple_of(width) {
+            return Err(BusException::LoadAccessFault);
+        }
+
+        let (value, offset): (u64, usize) = match self.decode(addr) {
+            Some((REG_MSIP, hart, offset)) => (self.msip[hart] as u64, offset),
+            Some((REG_MTIMECMP, hart, offset)) => (self.mtimecmp[hart], offset),
+            Some((REG_MTIME, _, offset)) => (self.mtime, offset),
+            _ => (0, 0),
+        };
+
+        Ok(u64::to_le_bytes(value)[offset..offset + width].to_vec())
+    }
+
+    fn store(&mut self, width: usize, addr: usize, value: &Vec<u8>) -> Option<BusException> {
+        if (width != 4 && width != 8) || !addr.is_multiple_of(width) {
+            return Some(BusException::StoreAccessFault);
+        }
+
+        // 32-bit accesses update half of the 64-bit registers
+        let update = |old: u64, offset: usize| -> u64 {
+            let mut bytes: [u8; 8] = u64::to_le_bytes(old);
+
+            bytes[offset..offset + width].copy_from_slice(&value[..width]);
+            u64::from_le_bytes(bytes)
+        };
+
+        match self.decode(addr) {
+            Some((REG_MSIP, hart, _)) => self.msip[hart] = (value[0] & 0x1) as u32,
+            Some((REG_MTIMECMP, hart, offset)) => {
+                self.mtimecmp[hart] = update(self.mtimecmp[hart], offset)
+            }
+            Some((REG_MTIME, _, offset)) => self.mtime = update(self.mtime, offset),
+            _ => (),
+        }
+
+        None
+    }
+
+    // Advance the time base by one tick
+    fn tick(&mut self) {
+        self.mtime = self.mtime.wrapping_add(1);
+    }
+
+    fn save(&self, w: &mut Writer) {
+        w.u64(self.mtime);
+        for hart in 0..self.msip.len() {
+            w.u32(self.msip[hart]);
+            w.u64(self.mtimecmp[hart]);
+        }
+    }
+
+    fn restore(&mut self, r: &mut Reader) -> Result<(), SnapshotError> {
+        self.mtime = r.u64()?;
+        for hart in 0..self.msip.len() {
+            self.msip[hart] = r.u32()?;
+            self.mtimecmp[hart] = r.u64()?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Clint, REG_MSIP, REG_MTIME, REG_MTIMECMP};
+    use crate::vsoc::peripheral::PeripheralInterface;
+
+    #[test]
+    fn test_msip() {
+        let mut clint: Clint = Clint::new(2);
+
+        assert!(clint.store(4, REG_MSIP + 4, &vec![1, 0, 0, 0]).is_none());
+        assert!(!clint.msip(0));
+        assert!(clint.msip(1));
+        assert_eq!(clint.fetch(4, REG_MSIP + 4).unwrap(), vec![1, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_mtimecmp() {
+        let mut clint: Clint = Clint::new(1);
+
+        assert!(!clint.mtip(0));
+
+        // 32-bit halves, as written by an RV32 hart
+        clint.store(4, REG_MTIMECMP + 4, &vec![0, 0, 0, 0]);
+        clint.store(4, REG_MTIMECMP, &u32::to_le_bytes(2).to_vec());
+        assert!(!clint.mtip(0));
+        clint.tick();
+        clint.tick();
+        assert!(clint.mtip(0));
+        assert_eq!(
+            clint.fetch(8, REG_MTIME).unwrap(),
+            u64::to_le_bytes(2).to_vec()
+        );
+
+        clint.store(8, REG_MTIMECMP, &u64::to_le_bytes(0x1_0000_0000).to_vec());
+        assert!(!clint.mtip(0));
+        assert_eq!(clint.fetch(4, REG_MTIMECMP + 4).unwrap(), vec![1, 0, 0, 0]);
+    }
+}
@@ -1,5 +1,6 @@
 use crate::vsoc::bus::BusException;
 use crate::vsoc::peripheral::PeripheralInterface;
+use crate::vsoc::snapshot::{Reader, SnapshotError, Writer};
 
 #[derive(Debug)]
 pub struct Flash {
@@ -49,6 +50,13 @@ impl Flash {
         hi | lo
     }
 
+    fn fetch128(&self, addr: usize) -> u128 {
+        let lo: u128 = self.fetch64(addr) as u128;
+        let hi: u128 = (self.fetch64(addr + 8) as u128) << 64;
+
+        hi | lo
+    }
+
     fn store8(&mut self, addr: usize, value: u8) {
         let cell = (addr & !3) >> 2;
         let _offset = (addr % 4) * 8;
@@ -77,6 +85,11 @@ impl Flash {
         self.data[cell] = (value & 0xffffffff) as u32;
         self.data[cell + 1] = (value >> 32) as u32;
     }
+
+    fn store128(&mut self, addr: usize, value: u128) {
+        self.store64(addr, value as u64);
+        self.store64(addr + 8, (value >> 64) as u64);
+    }
 }
 
 impl PeripheralInterface for Flash {
@@ -100,6 +113,7 @@ impl PeripheralInterface for Flash {
             2 => Ok(u16::to_le_bytes(self.fetch16(addr)).to_vec()),
             4 => Ok(u32::to_le_bytes(self.fetch32(addr)).to_vec()),
             8 => Ok(u64::to_le_bytes(self.fetch64(addr)).to_vec()),
+            16 => Ok(u128::to_le_bytes(self.fetch128(addr)).to_vec()),
             _ => Err(BusException::LoadAccessFault),
         }
     }
@@ -127,11 +141,31 @@ impl PeripheralInterface for Flash {
             2 => self.store16(addr, u16::from_le_bytes(v.try_into().unwrap())),
             4 => self.store32(addr, u32::from_le_bytes(v.try_into().unwrap())),
             8 => self.store64(addr, u64::from_le_bytes(v.try_into().unwrap())),
+            16 => self.store128(addr, u128::from_le_bytes(v.try_into().unwrap())),
             _ => return Some(BusException::StoreAccessFault),
         }
 
         None
     }
+
+    // Only the cells backing the memory are kept
+    fn save(&self, w: &mut Writer) {
+        let cells: usize = self.length.div_ceil(4);
+        let data: Vec<u8> = self.data[..cells].iter().flat_map(|c| c.to_le_bytes()).collect();
+
+        w.bytes(&data);
+    }
+
+    fn restore(&mut self, r: &mut Reader) -> Result<(), SnapshotError> {
+        let cells: usize = self.length.div_ceil(4);
+        let data: &[u8] = r.bytes_exact(4 * cells, "memory size")?;
+
+        for (cell, bytes) in self.data.iter_mut().zip(data.chunks_exact(4)) {
+            *cell = u32::from_le_bytes(bytes.try_into().unwrap());
+        }
+
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -1,3 +1,5 @@
+pub mod clint;
 pub mod flash;
+pub mod plic;
 pub mod sram;
 pub mod uart;
@@ -0,0 +1,264 @@
+use crate::vsoc::bus::BusException;
+use crate::vsoc::irq::IrqLine;
+use crate::vsoc::peripheral::PeripheralInterface;
+use crate::vsoc::snapshot::{Reader, SnapshotError, Writer};
+
+// SiFive compatible register layout, context 2n is the M-mode context of
+// hart n and context 2n+1 its S-mode context
+pub const REG_PRIORITY: usize = 0x00_0000;
+pub const REG_PENDING: usize = 0x00_1000;
+pub const REG_ENABLE: usize = 0x00_2000;
+pub const REG_THRESHOLD: usize = 0x20_0000;
+pub const REG_CLAIM: usize = 0x20_0004;
+
+const ENABLE_STRIDE: usize = 0x80;
+const CONTEXT_STRIDE: usize = 0x1000;
+
+// Source 0 does not exist
+pub const SOURCES: usize = 32;
+const PRIORITY_MASK: u32 = 0x7;
+
+#[derive(Debug)]
+pub struct Plic {
+    length: usize,
+    lines: Vec<IrqLine>,
+    priority: Vec<u32>,
+    pending: u32,
+    claimed: u32,
+    enable: Vec<u32>,
+    threshold: Vec<u32>,
+}
+
+impl Plic {
+    pub fn new(harts: usize) -> Plic {
+        Plic {
+            length: 0x400_0000,
+            lines: (0..SOURCES).map(|_| IrqLine::new()).collect(),
+            priority: vec![0; SOURCES],
+            pending: 0,
+            claimed: 0,
+            enable: vec![0; 2 * harts],
+            threshold: vec![0; 2 * harts],
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        self.length
+    }
+
+    // Interrupt line of a source, to be handed to the device driving it
+    pub fn line(&self, source: usize) -> IrqLine {
+        self.lines[source].clone()
+    }
+
+    // Highest priority source pending and enabled above the context threshold,
+    // the lowest identifier wins on equal priorities
+    fn best(&self, context: usize) -> Option<usize> {
+        let candidates: u32 = self.pending & self.enable[context];
+
+        (1..SOURCES)
+            .filter(|s| candidates & (1 << s) != 0)
+            .filter(|s| self.priority[*s] > self.threshold[context])
+            .fold(None, |best: Option<usize>, s| match best {
+                Some(b) if self.priority[b] >= self.priority[s] => Some(b),
+                _ => Some(s),
+            })
+    }
+
+    // External interrupt line of the context
+    pub fn eip(&self, context: usize) -> bool {
+        self.best(context).is_some()
+    }
+
+    fn claim(&mut self, context: usize) -> u32 {
+        match self.best(context) {
+            Some(s) => {
+                self.pending &= !(1 << s);
+                self.claimed |= 1 << s;
+                s as u32
+            }
+            None => 0,
+        }
+    }
+
+    fn complete(&mut self, context: usize, source: u32) {
+        if (source as usize) < SOURCES && self.enable[context] & (1 << source) != 0 {
+            self.claimed &= !(1 << source);
+        }
+    }
+
+    // Context of an enable register address
+    fn enable_context(&self, addr: usize) -> Option<usize> {
+        let context: usize = (addr - REG_ENABLE) / ENABLE_STRIDE;
+
+        if context < self.enable.len() && (addr - REG_ENABLE).is_multiple_of(ENABLE_STRIDE) {
+            Some(context)
+        } else {
+            None
+        }
+    }
+
+    // Context and register (REG_THRESHOLD or REG_CLAIM) of a per-context address
+    fn context_reg(&self, addr: usize) -> Option<(usize, usize)> {
+        let context: usize = (addr - REG_THRESHOLD) / CONTEXT_STRIDE;
+        let reg: usize = REG_THRESHOLD + (addr - REG_THRESHOLD) % CONTEXT_STRIDE;
+
+        if context < self.threshold.len() && (reg == REG_THRESHOLD || reg == REG_CLAIM) {
+            Some((context, reg))
+        } else {
+            None
+        }
+    }
+}
+
+impl PeripheralInterface for Plic {
+    fn fetch(&mut self, width: usize, addr: usize) -> Result<Vec<u8>, BusException> {
+        if width != 4 || !addr.is_multiple_of(4) {
+            return Err(BusException::LoadAccessFault);
+        }
+
+        let value: u32 = match addr {
+            a if (REG_PRIORITY..REG_PRIORITY + 4 * SOURCES).contains(&a) => {
+                self.priority[(a - REG_PRIORITY) / 4]
+            }
+            REG_PENDING => self.pending,
+            a if (REG_ENABLE..REG_THRESHOLD).contains(&a) => match self.enable_context(a) {
+                Some(c) => self.enable[c],
+                None => 0,
+            },
+            a if a >= REG_THRESHOLD => match self.context_reg(a) {
+                Some((c, REG_THRESHOLD)) => self.threshold[c],
+                Some((c, _)) => self.claim(c),
+                None => 0,
+            },
+            _ => 0,
+        };
+
+        Ok(u32::to_le_bytes(value).to_vec())
+    }
+
+    fn store(&mut self, width: usize, addr: usize, value: &Vec<u8>) -> Option<BusException> {
+        if width != 4 || !addr.is_multiple_of(4) {
+            return Some(BusException::StoreAccessFault);
+        }
+
+        let value: u32 = u32::from_le_bytes((*value.clone()).try_into().unwrap());
+
+        match addr {
+            // Source 0 is hardwired to zero
+            a if (REG_PRIORITY + 4..REG_PRIORITY + 4 * SOURCES).contains(&a) => {
+                self.priority[(a - REG_PRIORITY) / 4] = value & PRIORITY_MASK
+            }
+            a if (REG_ENABLE..REG_THRESHOLD).contains(&a) => {
+                if let Some(c) = self.enable_context(a) {
+                    self.enable[c] = value & !0x1;
+                }
+            }
+            a if a >= REG_THRESHOLD => match self.context_reg(a) {
+                Some((c, REG_THRESHOLD)) => self.threshold[c] = value & PRIORITY_MASK,
+                Some((c, _)) => self.complete(c, value),
+                None => (),
+            },
+            _ => (),
+        }
+
+        None
+    }
+
+    // Level-triggered gateways: a source is pending again only once the
+    // previous request has been completed
+    fn tick(&mut self) {
+        for s in 1..SOURCES {
+            if self.lines[s].is_asserted() && self.claimed & (1 << s) == 0 {
+                self.pending |= 1 << s;
+            }
+        }
+    }
+
+    fn save(&self, w: &mut Writer) {
+        for s in 0..SOURCES {
+            w.u32(self.priority[s]);
+        }
+        w.u32(self.pending);
+        w.u32(self.claimed);
+        for context in 0..self.enable.len() {
+            w.u32(self.enable[context]);
+            w.u32(self.threshold[context]);
+        }
+    }
+
+    fn restore(&mut self, r: &mut Reader) -> Result<(), SnapshotError> {
+        for s in 0..SOURCES {
+            self.priority[s] = r.u32()?;
+        }
+        self.pending = r.u32()?;
+        self.claimed = r.u32()?;
+        for context in 0..self.enable.len() {
+            self.enable[context] = r.u32()?;
+            self.threshold[context] = r.u32()?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Plic, REG_CLAIM, REG_ENABLE, REG_PENDING, REG_PRIORITY, REG_THRESHOLD};
+    use crate::vsoc::peripheral::PeripheralInterface;
+
+    fn write(plic: &mut Plic, addr: usize, value: u32) {
+        assert!(plic
+            .store(4, addr, &u32::to_le_bytes(value).to_vec())
+            .is_none());
+    }
+
+    fn read(plic: &mut Plic, addr: usize) -> u32 {
+        u32::from_le_bytes(plic.fetch(4, addr).unwrap().try_into().unwrap())
+    }
+
+    #[test]
+    fn test_claim_complete() {
+        let mut plic: Plic = Plic::new(1);
+        let line = plic.line(3);
+
+        write(&mut plic, REG_PRIORITY + 4 * 3, 1);
+        write(&mut plic, REG_ENABLE, 1 << 3);
+
+        line.assert();
+        plic.tick();
+        assert_eq!(read(&mut plic, REG_PENDING), 1 << 3);
+        assert!(plic.eip(0));
+        assert!(!plic.eip(1));
+
+        assert_eq!(read(&mut plic, REG_CLAIM), 3);
+        assert!(!plic.eip(0));
+        assert_eq!(read(&mut plic, REG_CLAIM), 0);
+
+        // Still asserted, but not forwarded until completion
+        plic.tick();
+        assert!(!plic.eip(0));
+        write(&mut plic, REG_CLAIM, 3);
+        plic.tick();
+        assert!(plic.eip(0));
+    }
+
+    #[test]
+    fn test_priority_threshold() {
+        let mut plic: Plic = Plic::new(1);
+
+        write(&mut plic, REG_PRIORITY + 4 * 2, 2);
+        write(&mut plic, REG_PRIORITY + 4 * 5, 4);
+        write(&mut plic, REG_ENABLE, (1 << 2) | (1 << 5));
+        plic.line(2).assert();
+        plic.line(5).assert();
+        plic.tick();
+
+        write(&mut plic, REG_THRESHOLD, 4);
+        assert!(!plic.eip(0));
+
+        write(&mut plic, REG_THRESHOLD, 1);
+        assert_eq!(read(&mut plic, REG_CLAIM), 5);
+        assert_eq!(read(&mut plic, REG_CLAIM), 2);
+    }
+}
@@ -1,17 +1,22 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
 use crate::vsoc::bus::BusException;
-use crate::vsoc::peripheral::PeripheralInterface;
+use crate::vsoc::peripheral::{Memory, PeripheralInterface};
+use crate::vsoc::snapshot::{Reader, SnapshotError, Writer};
 
 #[derive(Debug)]
 pub struct Sram {
     length: usize,
-    data: Vec<u32>,
+    // Shared with the bus, which reads and writes it directly
+    data: Memory,
 }
 
 impl Sram {
     pub fn new(length: usize) -> Sram {
         Sram {
             length,
-            data: vec![0; length],
+            data: Rc::new(RefCell::new(vec![0; length])),
         }
     }
 
@@ -19,68 +24,7 @@ impl Sram {
         self.length
     }
 
-    fn fetch8(&self, addr: usize) -> u8 {
-        let cell: usize = (addr & !3) >> 2;
-        let _offset: usize = (addr % 4) * 8;
-        let value = self.data[cell];
-
-        ((value >> _offset) & 0xff) as u8
-    }
-
-    fn fetch16(&self, addr: usize) -> u16 {
-        let cell = (addr & !3) >> 2;
-        let _offset = (addr % 4) * 8;
-        let value = self.data[cell];
-
-        ((value >> _offset) & 0xffff) as u16
-    }
-
-    fn fetch32(&self, addr: usize) -> u32 {
-        let cell = addr >> 2;
-
-        self.data[cell]
-    }
-
-    fn fetch64(&self, addr: usize) -> u64 {
-        let cell = addr >> 2;
-        let lo: u64 = self.data[cell] as u64;
-        let hi: u64 = (self.data[cell + 1] as u64) << 32;
-
-        hi | lo
-    }
-
-    fn store8(&mut self, addr: usize, value: u8) {
-        let cell = (addr & !3) >> 2;
-        let _offset = (addr % 4) * 8;
-
-        self.data[cell] &= !(0x000000ffu32 << _offset);
-        self.data[cell] |= (value as u32) << _offset;
-    }
-
-    fn store16(&mut self, addr: usize, value: u16) {
-        let cell = (addr & !3) >> 2;
-        let _offset = (addr % 4) * 8;
-
-        self.data[cell] &= !(0xffffu32 << _offset);
-        self.data[cell] |= (value as u32) << _offset;
-    }
-
-    fn store32(&mut self, addr: usize, value: u32) {
-        let cell: usize = addr >> 2;
-
-        self.data[cell] = value;
-    }
-
-    fn store64(&mut self, addr: usize, value: u64) {
-        let cell: usize = addr >> 2;
-
-        self.data[cell] = (value & 0xffffffff) as u32;
-        self.data[cell + 1] = (value >> 32) as u32;
-    }
-}
-
-impl PeripheralInterface for Sram {
-    fn fetch(&mut self, width: usize, addr: usize) -> Result<Vec<u8>, BusException> {
+    fn check(&self, width: usize, addr: usize) -> Result<(), BusException> {
         let mut align: usize = width;
 
         if width > 4 {
@@ -96,51 +40,53 @@ impl PeripheralInterface for Sram {
         }
 
         match width {
-            1 => Ok(u8::to_le_bytes(self.fetch8(addr)).to_vec()),
-            2 => Ok(u16::to_le_bytes(self.fetch16(addr)).to_vec()),
-            4 => Ok(u32::to_le_bytes(self.fetch32(addr)).to_vec()),
-            8 => Ok(u64::to_le_bytes(self.fetch64(addr)).to_vec()),
+            1 | 2 | 4 | 8 | 16 => Ok(()),
             _ => Err(BusException::LoadAccessFault),
         }
     }
+}
 
-    fn store(&mut self, width: usize, addr: usize, value: &Vec<u8>) -> Option<BusException> {
-        let mut align: usize = width;
-
-        if width > 4 {
-            align = 4;
-        }
+impl PeripheralInterface for Sram {
+    fn fetch(&mut self, width: usize, addr: usize) -> Result<Vec<u8>, BusException> {
+        self.check(width, addr)?;
 
-        if addr + width > self.length {
-            return Some(BusException::StoreAccessFault);
-        }
+        Ok(self.data.borrow()[addr..addr + width].to_vec())
+    }
 
-        if align > 1 && ((addr % align) > 0 || (addr + width) % align > 0) {
-            return Some(BusException::StoreAddressMisaligned);
+    fn store(&mut self, width: usize, addr: usize, value: &Vec<u8>) -> Option<BusException> {
+        match self.check(width, addr) {
+            Ok(()) => (),
+            Err(BusException::LoadAddressMisaligned) => {
+                return Some(BusException::StoreAddressMisaligned)
+            }
+            Err(_) => return Some(BusException::StoreAccessFault),
         }
 
-        match width {
-            1 => self.store8(
-                addr,
-                u8::from_le_bytes((*value.clone()).try_into().unwrap()),
-            ),
-            2 => self.store16(
-                addr,
-                u16::from_le_bytes((*value.clone()).try_into().unwrap()),
-            ),
-            4 => self.store32(
-                addr,
-                u32::from_le_bytes((*value.clone()).try_into().unwrap()),
-            ),
-            8 => self.store64(
-                addr,
-                u64::from_le_bytes((*value.clone()).try_into().unwrap()),
-            ),
-            _ => return Some(BusException::StoreAccessFault),
-        }
+        self.data.borrow_mut()[addr..addr + width].copy_from_slice(&value[..width]);
 
         None
     }
+
+    fn memory(&self) -> Option<Memory> {
+        Some(self.data.clone())
+    }
+
+    // Saved as 32-bit cells, padded with zeros
+    fn save(&self, w: &mut Writer) {
+        let mut data: Vec<u8> = self.data.borrow().clone();
+
+        data.resize(4 * self.length.div_ceil(4), 0);
+        w.bytes(&data);
+    }
+
+    fn restore(&mut self, r: &mut Reader) -> Result<(), SnapshotError> {
+        let cells: usize = self.length.div_ceil(4);
+        let data: &[u8] = r.bytes_exact(4 * cells, "memory size")?;
+
+        self.data.borrow_mut().copy_from_slice(&data[..self.length]);
+
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -1,29 +1,48 @@
+use std::sync::mpsc::Receiver;
+
 use crate::vsoc::bus::BusException;
+use crate::vsoc::irq::IrqLine;
 use crate::vsoc::peripheral::PeripheralInterface;
+use crate::vsoc::snapshot::{Reader, SnapshotError, Writer};
 
-#[derive(Debug, Default, Clone, Copy)]
+#[derive(Debug, Default)]
 pub struct Uart16550 {
     length: usize,
     rbr: u32,
     thr: u32,
+    ier: u32,
     lsr: u32,
+    thre_pending: bool,
+    irq: IrqLine,
+    rx: Option<Receiver<u8>>,
 }
 
 pub const REG_RBR: usize = 0x1000;
 pub const REG_THR: usize = 0x1000;
+pub const REG_IER: usize = 0x1004;
+pub const REG_IIR: usize = 0x1008;
 pub const REG_LSR: usize = 0x1014;
+pub const IER_ERBFI: u32 = 1 << 0;
+pub const IER_ETBEI: u32 = 1 << 1;
+pub const IIR_NONE: u32 = 0x1;
+pub const IIR_THRE: u32 = 0x2;
+pub const IIR_RDA: u32 = 0x4;
 pub const LSR_DR: u32 = 1 << 0;
 pub const LSR_OE: u32 = 1 << 1;
 pub const LSR_THRE: u32 = 1 << 5;
 pub const LSR_TEMT: u32 = 1 << 6;
 
 impl Uart16550 {
-    pub fn new(length: usize) -> Uart16550 {
+    pub fn new(length: usize, irq: IrqLine) -> Uart16550 {
         Uart16550 {
             length,
             rbr: 0,
             thr: 0xff,
+            ier: 0,
             lsr: LSR_THRE | LSR_TEMT,
+            thre_pending: false,
+            irq,
+            rx: None,
         }
     }
 
@@ -31,6 +50,21 @@ impl Uart16550 {
         self.length
     }
 
+    // Characters received from the host
+    pub fn connect(&mut self, rx: Receiver<u8>) {
+        self.rx = Some(rx);
+    }
+
+    pub fn receive(&mut self, c: u8) {
+        if self.lsr & LSR_DR != 0 {
+            self.lsr |= LSR_OE;
+        }
+
+        self.rbr = c as u32;
+        self.lsr |= LSR_DR;
+        self.update_irq();
+    }
+
     fn set(&mut self, reg: usize, value: u32) -> &mut Self {
         match reg {
             REG_THR => self.thr = value,
@@ -40,45 +74,164 @@ impl Uart16550 {
 
         self
     }
+
+    // Highest priority interrupt identification
+    fn iir(&self) -> u32 {
+        if self.ier & IER_ERBFI != 0 && self.lsr & LSR_DR != 0 {
+            IIR_RDA
+        } else if self.ier & IER_ETBEI != 0 && self.thre_pending {
+            IIR_THRE
+        } else {
+            IIR_NONE
+        }
+    }
+
+    fn update_irq(&self) {
+        if self.iir() != IIR_NONE {
+            self.irq.assert();
+        } else {
+            self.irq.deassert();
+        }
+    }
 }
 
 impl PeripheralInterface for Uart16550 {
     // getc()
     fn fetch(&mut self, width: usize, addr: usize) -> Result<Vec<u8>, BusException> {
-        if width != 4 && addr % 4 != 0 {
+        if width != 4 && !addr.is_multiple_of(4) {
             return Err(BusException::LoadAddressMisaligned);
         }
 
-        match addr {
+        let value: u32 = match addr {
             REG_RBR => {
-                self.set(REG_LSR, self.lsr & !LSR_DR);
-                Ok(u32::to_le_bytes(self.rbr).to_vec())
+                self.set(REG_LSR, self.lsr & !(LSR_DR | LSR_OE));
+                self.rbr
             }
-            REG_LSR => Ok(u32::to_le_bytes(self.rbr).to_vec()),
-            _ => Ok(vec![0u8, 0u8, 0u8, 0u8]),
-        }
+            REG_IER => self.ier,
+            REG_IIR => {
+                let iir: u32 = self.iir();
+
+                // Reading the identification acknowledges a THR empty interrupt
+                if iir == IIR_THRE {
+                    self.thre_pending = false;
+                }
+                iir
+            }
+            REG_LSR => self.lsr,
+            _ => 0,
+        };
+
+        self.update_irq();
+        Ok(u32::to_le_bytes(value).to_vec())
     }
 
     // putc()
     fn store(&mut self, width: usize, addr: usize, value: &Vec<u8>) -> Option<BusException> {
-        if width != 4 && addr % 4 != 0 {
+        if width != 4 && !addr.is_multiple_of(4) {
             return Some(BusException::StoreAddressMisaligned);
         }
 
         match addr {
             REG_THR => {
                 if self.lsr & LSR_THRE == LSR_THRE {
-                    self.lsr = self.lsr & !LSR_TEMT;
+                    self.lsr &= !LSR_TEMT;
                     self.thr = u32::from_le_bytes((*value.clone()).try_into().unwrap());
-                    self.lsr = self.lsr & !LSR_THRE;
+                    self.lsr &= !LSR_THRE;
                     print!("{}", (self.thr as u8) as char);
-                    self.lsr = self.lsr | (LSR_THRE | LSR_TEMT);
+                    self.lsr |= LSR_THRE | LSR_TEMT;
+                    self.thre_pending = true;
                 }
             }
-            REG_LSR => self.lsr = (self.lsr & 0xff) as u32,
+            REG_IER => {
+                let ier: u32 = u32::from_le_bytes((*value.clone()).try_into().unwrap()) & 0xf;
+
+                // Enabling the THR empty interrupt fires it when THR is already empty
+                if ier & IER_ETBEI != 0 && self.ier & IER_ETBEI == 0 {
+                    self.thre_pending = self.lsr & LSR_THRE != 0;
+                }
+                self.ier = ier;
+            }
+            REG_LSR => self.lsr &= 0xff,
             _ => return None,
         }
 
+        self.update_irq();
         None
     }
+
+    fn tick(&mut self) {
+        if self.lsr & LSR_DR != 0 {
+            return;
+        }
+
+        let c: Option<u8> = self.rx.as_ref().and_then(|rx| rx.try_recv().ok());
+
+        if let Some(c) = c {
+            self.receive(c);
+        }
+    }
+
+    fn save(&self, w: &mut Writer) {
+        w.u32(self.rbr);
+        w.u32(self.thr);
+        w.u32(self.ier);
+        w.u32(self.lsr);
+        w.bool(self.thre_pending);
+    }
+
+    // The interrupt line follows the restored registers
+    fn restore(&mut self, r: &mut Reader) -> Result<(), SnapshotError> {
+        self.rbr = r.u32()?;
+        self.thr = r.u32()?;
+        self.ier = r.u32()?;
+        self.lsr = r.u32()?;
+        self.thre_pending = r.bool()?;
+        self.update_irq();
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        Uart16550, IER_ERBFI, IER_ETBEI, IIR_NONE, IIR_RDA, IIR_THRE, REG_IER, REG_IIR, REG_RBR,
+        REG_THR,
+    };
+    use crate::vsoc::irq::IrqLine;
+    use crate::vsoc::peripheral::PeripheralInterface;
+
+    fn read(uart: &mut Uart16550, addr: usize) -> u32 {
+        u32::from_le_bytes(uart.fetch(4, addr).unwrap().try_into().unwrap())
+    }
+
+    #[test]
+    fn test_rx_interrupt() {
+        let line: IrqLine = IrqLine::new();
+        let mut uart: Uart16550 = Uart16550::new(0x2000, line.clone());
+
+        uart.receive(b'a');
+        assert!(!line.is_asserted());
+
+        uart.store(4, REG_IER, &u32::to_le_bytes(IER_ERBFI).to_vec());
+        assert!(line.is_asserted());
+        assert_eq!(read(&mut uart, REG_IIR), IIR_RDA);
+        assert_eq!(read(&mut uart, REG_RBR), b'a' as u32);
+        assert!(!line.is_asserted());
+        assert_eq!(read(&mut uart, REG_IIR), IIR_NONE);
+    }
+
+    #[test]
+    fn test_tx_interrupt() {
+        let line: IrqLine = IrqLine::new();
+        let mut uart: Uart16550 = Uart16550::new(0x2000, line.clone());
+
+        uart.store(4, REG_IER, &u32::to_le_bytes(IER_ETBEI).to_vec());
+        assert!(line.is_asserted());
+        assert_eq!(read(&mut uart, REG_IIR), IIR_THRE);
+        assert!(!line.is_asserted());
+
+        uart.store(4, REG_THR, &u32::to_le_bytes(b'\n' as u32).to_vec());
+        assert!(line.is_asserted());
+    }
 }
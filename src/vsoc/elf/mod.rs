@@ -0,0 +1,366 @@
+use std::fmt;
+
+const ELFMAG: [u8; 4] = [0x7f, b'E', b'L', b'F'];
+const ELFCLASS32: u8 = 1;
+const ELFCLASS64: u8 = 2;
+const ELFDATA2LSB: u8 = 1;
+const EM_RISCV: u16 = 0xf3;
+const PT_LOAD: u32 = 1;
+const SHT_SYMTAB: u32 = 2;
+
+#[derive(Debug, PartialEq)]
+pub enum ElfError {
+    Truncated,
+    BadMagic,
+    UnsupportedClass,
+    UnsupportedEndianness,
+    UnsupportedMachine,
+    Unmapped(u64),
+}
+
+impl fmt::Display for ElfError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s: String = match *self {
+            Self::Truncated => String::from("Truncated"),
+            Self::BadMagic => String::from("BadMagic"),
+            Self::UnsupportedClass => String::from("UnsupportedClass"),
+            Self::UnsupportedEndianness => String::from("UnsupportedEndianness"),
+            Self::UnsupportedMachine => String::from("UnsupportedMachine"),
+            Self::Unmapped(addr) => format!("Unmapped({:#x})", addr),
+        };
+        write!(f, "ElfError::{}", s)
+    }
+}
+
+// Loadable segment: `data` goes at `paddr`, the remaining bytes up to
+// `memsz` are zeroed
+#[derive(Debug)]
+pub struct Segment {
+    pub paddr: u64,
+    pub memsz: u64,
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Symbol {
+    pub name: String,
+    pub value: u64,
+    pub size: u64,
+}
+
+#[derive(Debug)]
+pub struct Elf {
+    pub xlen: usize,
+    pub entry: u64,
+    pub segments: Vec<Segment>,
+    pub symbols: Vec<Symbol>,
+}
+
+// Little-endian reader over the file, field widths depend on the ELF class
+struct Reader<'a> {
+    data: &'a [u8],
+    wide: bool,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&self, offset: u64, len: u64) -> Result<&'a [u8], ElfError> {
+        let start: usize = usize::try_from(offset).map_err(|_| ElfError::Truncated)?;
+        let end: usize = start
+            .checked_add(usize::try_from(len).map_err(|_| ElfError::Truncated)?)
+            .ok_or(ElfError::Truncated)?;
+
+        self.data.get(start..end).ok_or(ElfError::Truncated)
+    }
+
+    fn u8(&self, offset: u64) -> Result<u8, ElfError> {
+        Ok(self.bytes(offset, 1)?[0])
+    }
+
+    fn u16(&self, offset: u64) -> Result<u16, ElfError> {
+        Ok(u16::from_le_bytes(self.bytes(offset, 2)?.try_into().unwrap()))
+    }
+
+    fn u32(&self, offset: u64) -> Result<u32, ElfError> {
+        Ok(u32::from_le_bytes(self.bytes(offset, 4)?.try_into().unwrap()))
+    }
+
+    fn u64(&self, offset: u64) -> Result<u64, ElfError> {
+        Ok(u64::from_le_bytes(self.bytes(offset, 8)?.try_into().unwrap()))
+    }
+
+    // Offset of the entry of a table, the fields read from it then cannot
+    // overflow as it starts in the file
+    fn entry(&self, table: u64, index: u64, size: u64) -> Result<u64, ElfError> {
+        let offset: u64 = index
+            .checked_mul(size)
+            .and_then(|o| table.checked_add(o))
+            .ok_or(ElfError::Truncated)?;
+
+        if offset > self.data.len() as u64 {
+            return Err(ElfError::Truncated);
+        }
+
+        Ok(offset)
+    }
+
+    // Elf32_Addr/Elf32_Off or Elf64_Addr/Elf64_Off
+    fn word(&self, offset: u64) -> Result<u64, ElfError> {
+        if self.wide {
+            self.u64(offset)
+        } else {
+            Ok(self.u32(offset)? as u64)
+        }
+    }
+
+    fn string(&self, offset: u64) -> Result<String, ElfError> {
+        let start: usize = usize::try_from(offset).map_err(|_| ElfError::Truncated)?;
+        let tail: &[u8] = self.data.get(start..).ok_or(ElfError::Truncated)?;
+        let len: usize = tail.iter().position(|c| *c == 0).ok_or(ElfError::Truncated)?;
+
+        Ok(String::from_utf8_lossy(&tail[..len]).into_owned())
+    }
+}
+
+pub fn is_elf(data: &[u8]) -> bool {
+    data.starts_with(&ELFMAG)
+}
+
+impl Elf {
+    pub fn parse(data: &[u8]) -> Result<Elf, ElfError> {
+        if data.len() < 16 {
+            return Err(ElfError::Truncated);
+        }
+
+        if !is_elf(data) {
+            return Err(ElfError::BadMagic);
+        }
+
+        let wide: bool = match data[4] {
+            ELFCLASS32 => false,
+            ELFCLASS64 => true,
+            _ => return Err(ElfError::UnsupportedClass),
+        };
+
+        if data[5] != ELFDATA2LSB {
+            return Err(ElfError::UnsupportedEndianness);
+        }
+
+        let r: Reader = Reader { data, wide };
+
+        if r.u16(18)? != EM_RISCV {
+            return Err(ElfError::UnsupportedMachine);
+        }
+
+        // Offsets of the ELF header fields following e_entry
+        let (phoff, shoff, phentsize) = if wide { (32, 40, 54) } else { (28, 32, 42) };
+        let entry: u64 = r.word(24)?;
+        let e_phoff: u64 = r.word(phoff)?;
+        let e_shoff: u64 = r.word(shoff)?;
+        let e_phentsize: u64 = r.u16(phentsize)? as u64;
+        let e_phnum: u64 = r.u16(phentsize + 2)? as u64;
+        let e_shentsize: u64 = r.u16(phentsize + 4)? as u64;
+        let e_shnum: u64 = r.u16(phentsize + 6)? as u64;
+
+        let mut segments: Vec<Segment> = Vec::new();
+
+        for i in 0..e_phnum {
+            let ph: u64 = r.entry(e_phoff, i, e_phentsize)?;
+
+            if r.u32(ph)? != PT_LOAD {
+                continue;
+            }
+
+            let (offset, paddr, filesz, memsz) = if wide {
+                (r.u64(ph + 8)?, r.u64(ph + 24)?, r.u64(ph + 32)?, r.u64(ph + 40)?)
+            } else {
+                (
+                    r.u32(ph + 4)? as u64,
+                    r.u32(ph + 12)? as u64,
+                    r.u32(ph + 16)? as u64,
+                    r.u32(ph + 20)? as u64,
+                )
+            };
+
+            segments.push(Segment {
+                paddr,
+                memsz,
+                data: r.bytes(offset, filesz)?.to_vec(),
+            });
+        }
+
+        let mut symbols: Vec<Symbol> = Vec::new();
+
+        for i in 0..e_shnum {
+            let sh: u64 = r.entry(e_shoff, i, e_shentsize)?;
+
+            if r.u32(sh + 4)? != SHT_SYMTAB {
+                continue;
+            }
+
+            // sh_offset, sh_size, sh_link, sh_entsize
+            let (offset, size, link, entsize) = if wide {
+                (r.u64(sh + 24)?, r.u64(sh + 32)?, r.u32(sh + 40)?, r.u64(sh + 56)?)
+            } else {
+                (
+                    r.u32(sh + 16)? as u64,
+                    r.u32(sh + 20)? as u64,
+                    r.u32(sh + 24)?,
+                    r.u32(sh + 36)? as u64,
+                )
+            };
+            let strtab: u64 =
+                r.word(r.entry(e_shoff, link as u64, e_shentsize)? + if wide { 24 } else { 16 })?;
+
+            if entsize == 0 {
+                continue;
+            }
+
+            for s in 0..size / entsize {
+                let sym: u64 = r.entry(offset, s, entsize)?;
+                let name: u32 = r.u32(sym)?;
+                let (value, size) = if wide {
+                    (r.u64(sym + 8)?, r.u64(sym + 16)?)
+                } else {
+                    (r.u32(sym + 4)? as u64, r.u32(sym + 8)? as u64)
+                };
+
+                // Skip the null symbol, sections and file names
+                let kind: u8 = r.u8(sym + if wide { 4 } else { 12 })? & 0xf;
+                if name == 0 || kind == 3 || kind == 4 {
+                    continue;
+                }
+
+                symbols.push(Symbol {
+                    name: r.string(strtab.checked_add(name as u64).ok_or(ElfError::Truncated)?)?,
+                    value,
+                    size,
+                });
+            }
+        }
+
+        Ok(Elf {
+            xlen: if wide { 64 } else { 32 },
+            entry,
+            segments,
+            symbols,
+        })
+    }
+
+    pub fn symbol(&self, name: &str) -> Option<u64> {
+        self.symbols.iter().find(|s| s.name == name).map(|s| s.value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Elf, ElfError};
+    use crate::vsoc::Vsoc;
+
+    // Minimal ELF64 with one PT_LOAD segment and a symbol table holding `tohost`
+    fn elf64() -> Vec<u8> {
+        let mut f: Vec<u8> = vec![0; 0x200];
+        let put = |f: &mut Vec<u8>, offset: usize, bytes: &[u8]| {
+            f[offset..offset + bytes.len()].copy_from_slice(bytes);
+        };
+
+        put(&mut f, 0, &[0x7f, b'E', b'L', b'F', 2, 1, 1]);
+        put(&mut f, 18, &0xf3u16.to_le_bytes());
+        put(&mut f, 24, &0x8000_0000u64.to_le_bytes()); // e_entry
+        put(&mut f, 32, &0x40u64.to_le_bytes()); // e_phoff
+        put(&mut f, 40, &0x100u64.to_le_bytes()); // e_shoff
+        put(&mut f, 54, &56u16.to_le_bytes()); // e_phentsize
+        put(&mut f, 56, &1u16.to_le_bytes()); // e_phnum
+        put(&mut f, 58, &64u16.to_le_bytes()); // e_shentsize
+        put(&mut f, 60, &3u16.to_le_bytes()); // e_shnum
+
+        // PT_LOAD: 4 bytes from 0x80 at 0x8000_0000, 16 bytes in memory
+        put(&mut f, 0x40, &1u32.to_le_bytes());
+        put(&mut f, 0x48, &0x80u64.to_le_bytes());
+        put(&mut f, 0x58, &0x8000_0000u64.to_le_bytes());
+        put(&mut f, 0x60, &4u64.to_le_bytes());
+        put(&mut f, 0x68, &16u64.to_le_bytes());
+        put(&mut f, 0x80, &[0x13, 0x00, 0x00, 0x00]);
+
+        // Section 1: symtab at 0x1c0 (null symbol + tohost), linked to section 2
+        put(&mut f, 0x140 + 4, &2u32.to_le_bytes());
+        put(&mut f, 0x140 + 24, &0x1c0u64.to_le_bytes());
+        put(&mut f, 0x140 + 32, &48u64.to_le_bytes());
+        put(&mut f, 0x140 + 40, &2u32.to_le_bytes());
+        put(&mut f, 0x140 + 56, &24u64.to_le_bytes());
+        // Section 2: strtab at 0x90
+        put(&mut f, 0x180 + 4, &3u32.to_le_bytes());
+        put(&mut f, 0x180 + 24, &0x90u64.to_le_bytes());
+        put(&mut f, 0x90, b"\0tohost\0");
+
+        put(&mut f, 0x1d8, &1u32.to_le_bytes());
+        put(&mut f, 0x1d8 + 4, &[0x11]);
+        put(&mut f, 0x1d8 + 8, &0x8000_1000u64.to_le_bytes());
+        put(&mut f, 0x1d8 + 16, &8u64.to_le_bytes());
+
+        f
+    }
+
+    #[test]
+    fn test_parse_elf64() {
+        let elf: Elf = Elf::parse(&elf64()).unwrap();
+
+        assert_eq!(elf.xlen, 64);
+        assert_eq!(elf.entry, 0x8000_0000);
+        assert_eq!(elf.segments.len(), 1);
+        assert_eq!(elf.segments[0].paddr, 0x8000_0000);
+        assert_eq!(elf.segments[0].memsz, 16);
+        assert_eq!(elf.segments[0].data, vec![0x13, 0, 0, 0]);
+        assert_eq!(elf.symbol("tohost"), Some(0x8000_1000));
+        assert_eq!(elf.symbol("fromhost"), None);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let mut f: Vec<u8> = elf64();
+
+        assert_eq!(Elf::parse(&f[..8]).err(), Some(ElfError::Truncated));
+        f[18] = 0x3e;
+        assert_eq!(Elf::parse(&f).err(), Some(ElfError::UnsupportedMachine));
+        f[0] = 0;
+        assert_eq!(Elf::parse(&f).err(), Some(ElfError::BadMagic));
+    }
+
+    #[test]
+    fn test_parse_overflow() {
+        let huge: [u8; 8] = u64::MAX.to_le_bytes();
+        let mut f: Vec<u8> = elf64();
+
+        f[32..40].copy_from_slice(&huge); // e_phoff
+        assert_eq!(Elf::parse(&f).err(), Some(ElfError::Truncated));
+
+        f = elf64();
+        f[40..48].copy_from_slice(&huge); // e_shoff
+        assert_eq!(Elf::parse(&f).err(), Some(ElfError::Truncated));
+
+        // The symtab sh_offset and the strtab sh_offset
+        f = elf64();
+        f[0x140 + 24..0x140 + 32].copy_from_slice(&huge);
+        assert_eq!(Elf::parse(&f).err(), Some(ElfError::Truncated));
+        f = elf64();
+        f[0x180 + 24..0x180 + 32].copy_from_slice(&huge);
+        assert_eq!(Elf::parse(&f).err(), Some(ElfError::Truncated));
+    }
+
+    #[test]
+    fn test_load_unmapped() {
+        let arch: String = String::from("rv64i");
+        let mut vsoc: Vsoc = Vsoc::new(&arch);
+        let mut f: Vec<u8> = elf64();
+
+        assert!(vsoc.load(&f).is_ok());
+        // A .bss zeroed in several writes
+        f[0x68..0x70].copy_from_slice(&0x2800u64.to_le_bytes());
+        assert!(vsoc.load(&f).is_ok());
+
+        // The segment is not allocated before its range is checked
+        f[0x68..0x70].copy_from_slice(&(1u64 << 46).to_le_bytes()); // p_memsz
+        assert_eq!(vsoc.load(&f).err(), Some(ElfError::Unmapped(0x8000_0000)));
+        f[0x68..0x70].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert_eq!(vsoc.load(&f).err(), Some(ElfError::Unmapped(0x8000_0000)));
+    }
+}
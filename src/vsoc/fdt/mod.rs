@@ -0,0 +1,444 @@
+use std::fmt::Write;
+
+use super::dev::{clint, plic};
+use super::machine::{DeviceKind, Machine, MemoryKind};
+
+const FDT_MAGIC: u32 = 0xd00d_feed;
+const FDT_VERSION: u32 = 17;
+const FDT_LAST_COMP_VERSION: u32 = 16;
+const FDT_BEGIN_NODE: u32 = 1;
+const FDT_END_NODE: u32 = 2;
+const FDT_PROP: u32 = 3;
+const FDT_END: u32 = 9;
+const HEADER_SIZE: usize = 40;
+
+// The interrupt controller of hart n is PHANDLE_INTC + n
+const PHANDLE_PLIC: u32 = 1;
+const PHANDLE_INTC: u32 = 2;
+
+// Interrupt numbers seen by the hart-local interrupt controller
+const IRQ_M_SOFT: u32 = 3;
+const IRQ_M_TIMER: u32 = 7;
+const IRQ_S_EXT: u32 = 9;
+const IRQ_M_EXT: u32 = 11;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Prop {
+    Empty,
+    Cells(Vec<u32>),
+    Strings(Vec<String>),
+}
+
+impl Prop {
+    fn bytes(&self) -> Vec<u8> {
+        match self {
+            Self::Empty => Vec::new(),
+            Self::Cells(cells) => cells.iter().flat_map(|c| c.to_be_bytes()).collect(),
+            Self::Strings(strings) => strings
+                .iter()
+                .flat_map(|s| s.bytes().chain(std::iter::once(0)))
+                .collect(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Node {
+    pub name: String,
+    pub props: Vec<(String, Prop)>,
+    pub children: Vec<Node>,
+}
+
+fn pad4(out: &mut Vec<u8>) {
+    while !out.len().is_multiple_of(4) {
+        out.push(0);
+    }
+}
+
+fn u32s(v: u32) -> Prop {
+    Prop::Cells(vec![v])
+}
+
+fn string(s: &str) -> Prop {
+    Prop::Strings(vec![String::from(s)])
+}
+
+// Address and size with two cells each
+fn reg(base: u64, size: u64) -> Prop {
+    Prop::Cells(vec![
+        (base >> 32) as u32,
+        base as u32,
+        (size >> 32) as u32,
+        size as u32,
+    ])
+}
+
+impl Node {
+    pub fn new(name: &str) -> Node {
+        Node {
+            name: String::from(name),
+            props: Vec::new(),
+            children: Vec::new(),
+        }
+    }
+
+    pub fn prop(&mut self, name: &str, value: Prop) -> &mut Self {
+        self.props.push((String::from(name), value));
+
+        self
+    }
+
+    pub fn child(&mut self, node: Node) -> &mut Self {
+        self.children.push(node);
+
+        self
+    }
+
+    fn structure(&self, out: &mut Vec<u8>, strings: &mut Vec<u8>) {
+        out.extend(FDT_BEGIN_NODE.to_be_bytes());
+        out.extend(self.name.bytes());
+        out.push(0);
+        pad4(out);
+
+        for (name, value) in self.props.iter() {
+            let data: Vec<u8> = value.bytes();
+            let key: Vec<u8> = [name.as_bytes(), &[0]].concat();
+            // Property names are shared in the strings block
+            let offset: usize = match strings.windows(key.len()).position(|w| w == key) {
+                Some(offset) if offset == 0 || strings[offset - 1] == 0 => offset,
+                _ => {
+                    strings.extend(&key);
+                    strings.len() - key.len()
+                }
+            };
+
+            out.extend(FDT_PROP.to_be_bytes());
+            out.extend((data.len() as u32).to_be_bytes());
+            out.extend((offset as u32).to_be_bytes());
+            out.extend(data);
+            pad4(out);
+        }
+
+        for child in self.children.iter() {
+            child.structure(out, strings);
+        }
+
+        out.extend(FDT_END_NODE.to_be_bytes());
+    }
+
+    // Flattened device tree blob, version 17, without memory reservations
+    pub fn to_dtb(&self) -> Vec<u8> {
+        let mut structure: Vec<u8> = Vec::new();
+        let mut strings: Vec<u8> = Vec::new();
+
+        self.structure(&mut structure, &mut strings);
+        structure.extend(FDT_END.to_be_bytes());
+
+        // The reservation map is a single terminating entry
+        let off_rsvmap: usize = HEADER_SIZE;
+        let off_struct: usize = off_rsvmap + 16;
+        let off_strings: usize = off_struct + structure.len();
+        let total: usize = off_strings + strings.len();
+        let mut out: Vec<u8> = Vec::with_capacity(total);
+
+        for field in [
+            FDT_MAGIC,
+            total as u32,
+            off_struct as u32,
+            off_strings as u32,
+            off_rsvmap as u32,
+            FDT_VERSION,
+            FDT_LAST_COMP_VERSION,
+            0,
+            strings.len() as u32,
+            structure.len() as u32,
+        ] {
+            out.extend(field.to_be_bytes());
+        }
+        out.extend([0; 16]);
+        out.extend(structure);
+        out.extend(strings);
+
+        out
+    }
+
+    fn source(&self, out: &mut String, depth: usize) {
+        let indent: String = "\t".repeat(depth);
+        let name: &str = if depth == 0 { "/" } else { &self.name };
+
+        _ = writeln!(out, "{}{} {{", indent, name);
+        for (name, value) in self.props.iter() {
+            let value: String = match value {
+                Prop::Empty => String::new(),
+                Prop::Cells(cells) => format!(
+                    " = <{}>",
+                    cells
+                        .iter()
+                        .map(|c| format!("{:#x}", c))
+                        .collect::<Vec<String>>()
+                        .join(" ")
+                ),
+                Prop::Strings(strings) => format!(
+                    " = {}",
+                    strings
+                        .iter()
+                        .map(|s| format!("{:?}", s))
+                        .collect::<Vec<String>>()
+                        .join(", ")
+                ),
+            };
+
+            _ = writeln!(out, "{}\t{}{};", indent, name, value);
+        }
+        for child in self.children.iter() {
+            _ = writeln!(out);
+            child.source(out, depth + 1);
+        }
+        _ = writeln!(out, "{}}};", indent);
+    }
+
+    // Device tree source, as printed by `dtc -O dts`
+    pub fn to_dts(&self) -> String {
+        let mut out: String = String::from("/dts-v1/;\n\n");
+
+        self.source(&mut out, 0);
+
+        out
+    }
+}
+
+// Hart-local interrupt lines, for each hart in turn
+fn per_hart(harts: usize, irqs: &[u32]) -> Prop {
+    Prop::Cells(
+        (0..harts as u32)
+            .flat_map(|hart| irqs.iter().flat_map(move |irq| [PHANDLE_INTC + hart, *irq]))
+            .collect(),
+    )
+}
+
+fn cpu(isa: &str, hart: usize) -> Node {
+    let base: &str = isa.trim().split('_').next().unwrap_or("");
+    let mut intc: Node = Node::new("interrupt-controller");
+    let mut cpu: Node = Node::new(&format!("cpu@{}", hart));
+
+    intc.prop("#interrupt-cells", u32s(1))
+        .prop("interrupt-controller", Prop::Empty)
+        .prop("compatible", string("riscv,cpu-intc"))
+        .prop("phandle", u32s(PHANDLE_INTC + hart as u32));
+
+    cpu.prop("device_type", string("cpu"))
+        .prop("reg", u32s(hart as u32))
+        .prop("status", string("okay"))
+        .prop("compatible", string("riscv"))
+        .prop("riscv,isa", string(&isa.trim().to_lowercase()));
+
+    // Widest translation scheme implemented by the MMU
+    if base.contains('s') {
+        if base.starts_with("rv32") {
+            cpu.prop("mmu-type", string("riscv,sv32"));
+        } else if base.starts_with("rv64") {
+            cpu.prop("mmu-type", string("riscv,sv57"));
+        }
+    }
+
+    cpu.child(intc);
+
+    cpu
+}
+
+// Describe the harts, memories and peripherals of a machine
+pub fn generate(machine: &Machine) -> Node {
+    let mut root: Node = Node::new("");
+    let mut chosen: Node = Node::new("chosen");
+    let mut cpus: Node = Node::new("cpus");
+    let mut soc: Node = Node::new("soc");
+    let mut memories: Vec<Node> = Vec::new();
+
+    root.prop("#address-cells", u32s(2))
+        .prop("#size-cells", u32s(2))
+        .prop("compatible", string("rustv,vsoc"))
+        .prop("model", string("rustv"));
+
+    cpus.prop("#address-cells", u32s(1))
+        .prop("#size-cells", u32s(0))
+        .prop("timebase-frequency", u32s(clint::TIMEBASE_FREQUENCY));
+    for hart in 0..machine.harts {
+        cpus.child(cpu(&machine.isa, hart));
+    }
+
+    soc.prop("#address-cells", u32s(2))
+        .prop("#size-cells", u32s(2))
+        .prop("compatible", string("simple-bus"))
+        .prop("ranges", Prop::Empty);
+
+    for m in machine.memory.iter() {
+        match m.kind {
+            MemoryKind::Ram => {
+                let mut node: Node = Node::new(&format!("memory@{:x}", m.base));
+
+                node.prop("device_type", string("memory"))
+                    .prop("reg", reg(m.base, m.size));
+                memories.push(node);
+            }
+            MemoryKind::Flash => {
+                let mut node: Node = Node::new(&format!("flash@{:x}", m.base));
+
+                node.prop("compatible", string("mtd-ram"))
+                    .prop("reg", reg(m.base, m.size))
+                    .prop("bank-width", u32s(4));
+                soc.child(node);
+            }
+        }
+    }
+
+    for d in machine.devices.iter() {
+        let node: Node = match d.kind {
+            DeviceKind::Clint => {
+                let mut node: Node = Node::new(&format!("clint@{:x}", d.base));
+
+                node.prop(
+                    "compatible",
+                    Prop::Strings(vec![
+                        String::from("sifive,clint0"),
+                        String::from("riscv,clint0"),
+                    ]),
+                )
+                .prop("reg", reg(d.base, d.kind.size()))
+                .prop(
+                    "interrupts-extended",
+                    per_hart(machine.harts, &[IRQ_M_SOFT, IRQ_M_TIMER]),
+                );
+
+                node
+            }
+            DeviceKind::Plic => {
+                let mut node: Node = Node::new(&format!("plic@{:x}", d.base));
+
+                // Context 2n is the M-mode context of hart n, context 2n+1 its S-mode one
+                node.prop(
+                    "compatible",
+                    Prop::Strings(vec![
+                        String::from("sifive,plic-1.0.0"),
+                        String::from("riscv,plic0"),
+                    ]),
+                )
+                .prop("reg", reg(d.base, d.kind.size()))
+                .prop("#address-cells", u32s(0))
+                .prop("#interrupt-cells", u32s(1))
+                .prop("interrupt-controller", Prop::Empty)
+                .prop(
+                    "interrupts-extended",
+                    per_hart(machine.harts, &[IRQ_M_EXT, IRQ_S_EXT]),
+                )
+                .prop("riscv,ndev", u32s(plic::SOURCES as u32 - 1))
+                .prop("phandle", u32s(PHANDLE_PLIC));
+
+                node
+            }
+            DeviceKind::Uart16550 => {
+                // Registers are 32-bit wide, from the second half of the window
+                let base: u64 = d.base + d.kind.size() / 2;
+                let mut node: Node = Node::new(&format!("serial@{:x}", base));
+
+                node.prop("compatible", string("ns16550a"))
+                    .prop("reg", reg(base, d.kind.size() / 2))
+                    .prop("reg-shift", u32s(2))
+                    .prop("reg-io-width", u32s(4))
+                    .prop("clock-frequency", u32s(3_686_400));
+                if let Some(irq) = d.irq {
+                    node.prop("interrupt-parent", u32s(PHANDLE_PLIC))
+                        .prop("interrupts", u32s(irq as u32));
+                }
+                if !chosen.props.iter().any(|(name, _)| name == "stdout-path") {
+                    chosen.prop("stdout-path", string(&format!("/soc/{}", node.name)));
+                }
+
+                node
+            }
+        };
+
+        soc.child(node);
+    }
+
+    root.child(chosen).child(cpus);
+    for node in memories {
+        root.child(node);
+    }
+    root.child(soc);
+
+    root
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{generate, Node, Prop};
+    use crate::vsoc::machine::Machine;
+    use crate::vsoc::Vsoc;
+
+    fn be32(data: &[u8], offset: usize) -> u32 {
+        u32::from_be_bytes(data[offset..offset + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn test_dtb() {
+        let mut root: Node = Node::new("");
+        let mut child: Node = Node::new("a@0");
+
+        child.prop("reg", Prop::Cells(vec![0x1234]));
+        root.prop("reg", Prop::Empty)
+            .prop("b", Prop::Strings(vec![String::from("x")]));
+        root.child(child);
+
+        let dtb: Vec<u8> = root.to_dtb();
+
+        assert_eq!(be32(&dtb, 0), 0xd00d_feed);
+        assert_eq!(be32(&dtb, 4) as usize, dtb.len());
+        assert_eq!(be32(&dtb, 20), 17);
+        // "reg" is stored once in the strings block
+        assert_eq!(be32(&dtb, 32), 6);
+        assert_eq!(&dtb[be32(&dtb, 12) as usize..], b"reg\0b\0");
+        assert_eq!(
+            be32(&dtb, 8) as usize + be32(&dtb, 36) as usize,
+            be32(&dtb, 12) as usize
+        );
+        assert_eq!(be32(&dtb, 56), 1);
+        assert_eq!(be32(&dtb, dtb.len() - 6 - 4), 9);
+    }
+
+    #[test]
+    fn test_generate() {
+        let dts: String = generate(&Machine::board("rv64imafdcsu_zicsr")).to_dts();
+
+        assert!(dts.starts_with("/dts-v1/;\n\n/ {\n"));
+        assert!(dts.contains("\t\tstdout-path = \"/soc/serial@40014c00\";\n"));
+        assert!(dts.contains("\t\t\triscv,isa = \"rv64imafdcsu_zicsr\";\n"));
+        assert!(dts.contains("\t\t\tmmu-type = \"riscv,sv57\";\n"));
+        assert!(dts.contains("\tmemory@80000000 {\n\t\tdevice_type = \"memory\";\n"));
+        assert!(dts.contains("\t\treg = <0x0 0x80000000 0x0 0x20000>;\n"));
+        assert!(dts.contains("\t\t\tinterrupts = <0xa>;\n"));
+        assert!(dts.contains("\t\t\tinterrupt-controller;\n"));
+
+        let mut machine: Machine = Machine::board("rv32ima_zicsr");
+
+        machine.harts = 2;
+
+        let dts: String = generate(&machine).to_dts();
+
+        assert!(dts.contains("\t\tcpu@1 {\n\t\t\tdevice_type = \"cpu\";\n\t\t\treg = <0x1>;\n"));
+        assert!(dts.contains("\t\t\tinterrupts-extended = <0x2 0x3 0x2 0x7 0x3 0x3 0x3 0x7>;\n"));
+        assert!(dts.contains("\t\t\tinterrupts-extended = <0x2 0xb 0x2 0x9 0x3 0xb 0x3 0x9>;\n"));
+    }
+
+    #[test]
+    fn test_boot_args() {
+        let arch: String = String::from("rv64imac_zicsr");
+        let mut vsoc: Vsoc = Vsoc::new(&arch);
+        let a1: u64 = u64::from_le_bytes(vsoc.read_register(11).unwrap().try_into().unwrap());
+        let dtb: Vec<u8> = vsoc.device_tree().to_dtb();
+
+        assert_eq!(vsoc.read_register(10), Some(vec![0; 8]));
+        assert_eq!(a1 % 8, 0);
+        assert!(a1 >= 0x8000_0000 && a1 + dtb.len() as u64 <= 0x8002_0000);
+        assert_eq!(vsoc.read_memory(a1, dtb.len()), Some(dtb));
+    }
+}
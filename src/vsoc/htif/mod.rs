@@ -0,0 +1,204 @@
+use std::io::Write;
+
+use crate::vsoc::bus::Bus;
+
+// tohost commands are encoded as device[63:56] cmd[55:48] payload[47:0]
+const DEVICE_SYSCALL: u64 = 0;
+const DEVICE_CONSOLE: u64 = 1;
+const CONSOLE_PUTCHAR: u64 = 1;
+const PAYLOAD_MASK: u64 = (1 << 48) - 1;
+// Bytes of a write copied to the host at a time
+const WRITE_CHUNK: usize = 4096;
+
+// Proxied system calls
+const SYS_WRITE: u64 = 64;
+const SYS_EXIT: u64 = 93;
+
+// Host-target interface of the riscv-tests and proxy kernel environments
+#[derive(Debug)]
+pub struct Htif {
+    tohost: u64,
+    fromhost: Option<u64>,
+}
+
+// Polled after each step, the load does not allocate when tohost is in RAM
+fn read64(bus: &mut Bus, addr: u64) -> Option<u64> {
+    bus.load(8, addr).ok().map(|v| v as u64)
+}
+
+// Copy a guest buffer to the host a chunk at a time, up to the first byte
+// that cannot be read, and return the number of bytes written
+fn write(bus: &mut Bus, addr: u64, len: u64, out: &mut impl Write) -> u64 {
+    let mut chunk: Vec<u8> = Vec::with_capacity(WRITE_CHUNK);
+    let mut written: u64 = 0;
+
+    while written < len {
+        let n: u64 = (len - written).min(WRITE_CHUNK as u64);
+
+        chunk.clear();
+        for i in written..written + n {
+            match addr.checked_add(i).map(|a| bus.load(1, a)) {
+                Some(Ok(b)) => chunk.push(b as u8),
+                _ => break,
+            }
+        }
+
+        if out.write_all(&chunk).is_err() {
+            break;
+        }
+        written += chunk.len() as u64;
+        if (chunk.len() as u64) < n {
+            break;
+        }
+    }
+
+    out.flush().ok();
+    written
+}
+
+impl Htif {
+    pub fn new(tohost: u64, fromhost: Option<u64>) -> Htif {
+        Htif { tohost, fromhost }
+    }
+
+    pub fn tohost(&self) -> u64 {
+        self.tohost
+    }
+
+    pub fn fromhost(&self) -> Option<u64> {
+        self.fromhost
+    }
+
+    // Handle a pending tohost command, returns the exit code once the program is done
+    pub fn poll(&mut self, bus: &mut Bus) -> Option<u64> {
+        let value: u64 = read64(bus, self.tohost)?;
+
+        if value == 0 {
+            return None;
+        }
+
+        let device: u64 = value >> 56;
+        let cmd: u64 = (value >> 48) & 0xff;
+        let payload: u64 = value & PAYLOAD_MASK;
+        let mut exit: Option<u64> = None;
+
+        match (device, cmd) {
+            (DEVICE_SYSCALL, 0) if payload & 1 != 0 => exit = Some(payload >> 1),
+            (DEVICE_SYSCALL, 0) => exit = self.syscall(bus, payload),
+            (DEVICE_CONSOLE, CONSOLE_PUTCHAR) => {
+                print!("{}", (payload as u8) as char);
+                std::io::stdout().flush().ok();
+            }
+            _ => println!("htif: unsupported command {:#x}", value),
+        }
+
+        bus.store(8, self.tohost, &0u64.to_le_bytes());
+        if let Some(fromhost) = self.fromhost {
+            let response: u64 = (device << 56) | (cmd << 48) | 1;
+
+            bus.store(8, fromhost, &response.to_le_bytes());
+        }
+
+        exit
+    }
+
+    // The payload points to the syscall number followed by its arguments,
+    // the return value is written back in place of the number
+    fn syscall(&mut self, bus: &mut Bus, magic: u64) -> Option<u64> {
+        let args: Vec<u64> = (0..4)
+            .map(|i| read64(bus, magic + 8 * i).unwrap_or(0))
+            .collect();
+        let ret: u64 = match args[0] {
+            SYS_WRITE if args[1] == 1 || args[1] == 2 => {
+                write(bus, args[2], args[3], &mut std::io::stdout().lock())
+            }
+            SYS_EXIT => return Some(args[1]),
+            n => {
+                println!("htif: unsupported syscall {}", n);
+                -38i64 as u64 // ENOSYS
+            }
+        };
+
+        bus.store(8, magic, &ret.to_le_bytes());
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{write, Htif};
+    use crate::vsoc::bus::Bus;
+    use crate::vsoc::dev::sram::Sram;
+    use crate::vsoc::peripheral::Peripheral;
+
+    const TOHOST: u64 = 0x8000_1000;
+    const FROMHOST: u64 = 0x8000_1040;
+
+    fn setup() -> Bus {
+        let mut bus: Bus = Bus::new();
+        let sram: Box<Sram> = Box::new(Sram::new(0x2000));
+
+        bus.attach(
+            0x8000_0000,
+            Box::new(Peripheral::new(String::from("sram"), sram.size(), sram)),
+        )
+        .unwrap();
+
+        bus
+    }
+
+    #[test]
+    fn test_exit() {
+        let mut bus: Bus = setup();
+        let mut htif: Htif = Htif::new(TOHOST, Some(FROMHOST));
+
+        assert_eq!(htif.poll(&mut bus), None);
+
+        bus.store(8, TOHOST, &1u64.to_le_bytes());
+        assert_eq!(htif.poll(&mut bus), Some(0));
+        assert_eq!(bus.fetch(8, TOHOST).unwrap(), vec![0; 8]);
+
+        bus.store(8, TOHOST, &((5u64 << 1) | 1).to_le_bytes());
+        assert_eq!(htif.poll(&mut bus), Some(5));
+    }
+
+    #[test]
+    fn test_syscall() {
+        let mut bus: Bus = setup();
+        let mut htif: Htif = Htif::new(TOHOST, Some(FROMHOST));
+        let magic: u64 = 0x8000_0100;
+
+        // write(1, "ok", 2)
+        bus.write(0x8000_0200, b"ok");
+        for (i, arg) in [64u64, 1, 0x8000_0200, 2].iter().enumerate() {
+            bus.store(8, magic + 8 * i as u64, &arg.to_le_bytes());
+        }
+        bus.store(8, TOHOST, &magic.to_le_bytes());
+        assert_eq!(htif.poll(&mut bus), None);
+        assert_eq!(bus.fetch(8, magic).unwrap(), 2u64.to_le_bytes().to_vec());
+        assert_eq!(bus.fetch(8, FROMHOST).unwrap(), 1u64.to_le_bytes().to_vec());
+
+        // exit(3)
+        for (i, arg) in [93u64, 3].iter().enumerate() {
+            bus.store(8, magic + 8 * i as u64, &arg.to_le_bytes());
+        }
+        bus.store(8, TOHOST, &magic.to_le_bytes());
+        assert_eq!(htif.poll(&mut bus), Some(3));
+    }
+
+    #[test]
+    fn test_write() {
+        let mut bus: Bus = setup();
+        let mut out: Vec<u8> = Vec::new();
+
+        // Up to the end of the sram, whatever the length asked for
+        bus.write(0x8000_1ffe, b"ok");
+        assert_eq!(write(&mut bus, 0x8000_1ffe, 1 << 40, &mut out), 2);
+        assert_eq!(out, b"ok");
+
+        out.clear();
+        assert_eq!(write(&mut bus, 0x8000_0000, 0x2000, &mut out), 0x2000);
+        assert_eq!(out.len(), 0x2000);
+        assert_eq!(write(&mut bus, 0x9000_0000, 1, &mut out), 0);
+    }
+}
@@ -0,0 +1,45 @@
+use std::cell::Cell;
+use std::rc::Rc;
+
+// Level-sensitive interrupt wire: the device keeps one end to assert or
+// deassert it, the interrupt controller samples the other end
+#[derive(Debug, Default, Clone)]
+pub struct IrqLine {
+    level: Rc<Cell<bool>>,
+}
+
+impl IrqLine {
+    pub fn new() -> IrqLine {
+        IrqLine {
+            level: Rc::new(Cell::new(false)),
+        }
+    }
+
+    pub fn assert(&self) {
+        self.level.set(true);
+    }
+
+    pub fn deassert(&self) {
+        self.level.set(false);
+    }
+
+    pub fn is_asserted(&self) -> bool {
+        self.level.get()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::IrqLine;
+
+    #[test]
+    fn test_shared_line() {
+        let line: IrqLine = IrqLine::new();
+        let wire: IrqLine = line.clone();
+
+        line.assert();
+        assert!(wire.is_asserted());
+        line.deassert();
+        assert!(!wire.is_asserted());
+    }
+}
@@ -0,0 +1,685 @@
+use std::fmt;
+
+use super::bus::BusError;
+use super::dev::plic;
+
+// The CLINT msip registers of all harts must fit below the mtimecmp ones
+pub const MAX_HARTS: usize = 4095;
+
+#[derive(Debug, PartialEq)]
+pub enum MachineError {
+    Syntax(usize, String),
+    Missing(String),
+    Invalid(String),
+    Overlap(String, String),
+    Image(String, String),
+}
+
+impl fmt::Display for MachineError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s: String = match self {
+            Self::Syntax(line, msg) => format!("Syntax(line {}: {})", line, msg),
+            Self::Missing(key) => format!("Missing({})", key),
+            Self::Invalid(msg) => format!("Invalid({})", msg),
+            Self::Overlap(a, b) => format!("Overlap({}, {})", a, b),
+            Self::Image(path, msg) => format!("Image({}: {})", path, msg),
+        };
+        write!(f, "MachineError::{}", s)
+    }
+}
+
+// Only reached by a machine built without being validated
+impl From<BusError> for MachineError {
+    fn from(e: BusError) -> Self {
+        match e {
+            BusError::Empty(name) => Self::Invalid(format!("{}: empty region", name)),
+            BusError::OutOfRange(name) => {
+                Self::Invalid(format!("{}: region ends beyond the address space", name))
+            }
+            BusError::Overlap(a, b) => Self::Overlap(a, b),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MemoryKind {
+    Ram,
+    Flash,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DeviceKind {
+    Clint,
+    Plic,
+    Uart16550,
+}
+
+impl DeviceKind {
+    fn parse(s: &str) -> Option<DeviceKind> {
+        match s {
+            "clint" => Some(Self::Clint),
+            "plic" => Some(Self::Plic),
+            "uart16550" => Some(Self::Uart16550),
+            _ => None,
+        }
+    }
+
+    // Size of the register window decoded by the device
+    pub fn size(&self) -> u64 {
+        match self {
+            Self::Clint => 0x1_0000,
+            Self::Plic => 0x400_0000,
+            Self::Uart16550 => 0x2000,
+        }
+    }
+}
+
+// Memory region, optionally initialised with the content of a file
+#[derive(Debug, Clone, PartialEq)]
+pub struct Memory {
+    pub name: String,
+    pub kind: MemoryKind,
+    pub base: u64,
+    pub size: u64,
+    pub image: Option<String>,
+}
+
+// Peripheral, `irq` is the PLIC source its interrupt line is wired to
+#[derive(Debug, Clone, PartialEq)]
+pub struct Device {
+    pub name: String,
+    pub kind: DeviceKind,
+    pub base: u64,
+    pub irq: Option<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Machine {
+    pub isa: String,
+    pub harts: usize,
+    // Instructions a hart runs before the next one gets its turn
+    pub quantum: u64,
+    pub reset: u64,
+    pub memory: Vec<Memory>,
+    pub devices: Vec<Device>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    Str(String),
+    Int(u64),
+}
+
+// `[name]` or `[[name]]` table with its `key = value` lines
+#[derive(Debug)]
+struct Table {
+    name: String,
+    line: usize,
+    entries: Vec<(String, Value, usize)>,
+}
+
+impl Table {
+    fn take(&mut self, key: &str) -> Option<(Value, usize)> {
+        let i: usize = self.entries.iter().position(|(k, _, _)| k == key)?;
+        let (_, value, line) = self.entries.remove(i);
+
+        Some((value, line))
+    }
+
+    fn string(&mut self, key: &str) -> Result<Option<String>, MachineError> {
+        match self.take(key) {
+            None => Ok(None),
+            Some((Value::Str(s), _)) => Ok(Some(s)),
+            Some((_, line)) => Err(MachineError::Syntax(
+                line,
+                format!("{} must be a string", key),
+            )),
+        }
+    }
+
+    fn int(&mut self, key: &str) -> Result<Option<u64>, MachineError> {
+        match self.take(key) {
+            None => Ok(None),
+            Some((Value::Int(n), _)) => Ok(Some(n)),
+            Some((_, line)) => Err(MachineError::Syntax(
+                line,
+                format!("{} must be an integer", key),
+            )),
+        }
+    }
+
+    fn missing(&self, key: &str) -> MachineError {
+        MachineError::Missing(format!("{}.{}", self.name, key))
+    }
+
+    fn string_required(&mut self, key: &str) -> Result<String, MachineError> {
+        self.string(key)?.ok_or_else(|| self.missing(key))
+    }
+
+    fn int_required(&mut self, key: &str) -> Result<u64, MachineError> {
+        self.int(key)?.ok_or_else(|| self.missing(key))
+    }
+
+    // Every key must have been consumed
+    fn finish(&self) -> Result<(), MachineError> {
+        match self.entries.first() {
+            Some((key, _, line)) => Err(MachineError::Syntax(
+                *line,
+                format!("unknown key {} in [{}]", key, self.name),
+            )),
+            None => Ok(()),
+        }
+    }
+}
+
+// Drop a `#` comment, unless it is inside a string
+fn strip_comment(line: &str) -> &str {
+    let mut quoted: bool = false;
+    let mut escaped: bool = false;
+
+    for (i, c) in line.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            '#' if !quoted => return &line[..i],
+            _ => (),
+        }
+    }
+
+    line
+}
+
+fn parse_value(s: &str) -> Option<Value> {
+    if let Some(rest) = s.strip_prefix('"') {
+        let mut out: String = String::new();
+        let mut chars = rest.chars();
+
+        loop {
+            match chars.next()? {
+                '"' => break,
+                '\\' => match chars.next()? {
+                    '"' => out.push('"'),
+                    '\\' => out.push('\\'),
+                    'n' => out.push('\n'),
+                    't' => out.push('\t'),
+                    _ => return None,
+                },
+                c => out.push(c),
+            }
+        }
+
+        return match chars.as_str().trim() {
+            "" => Some(Value::Str(out)),
+            _ => None,
+        };
+    }
+
+    let digits: String = s.replace('_', "");
+    let (digits, radix): (&str, u32) = match digits.get(..2) {
+        Some("0x") => (&digits[2..], 16),
+        Some("0o") => (&digits[2..], 8),
+        Some("0b") => (&digits[2..], 2),
+        _ => (&digits, 10),
+    };
+
+    if digits.is_empty() || !digits.chars().all(|c| c.is_digit(radix)) {
+        return None;
+    }
+
+    u64::from_str_radix(digits, radix).ok().map(Value::Int)
+}
+
+fn parse_tables(text: &str) -> Result<Vec<Table>, MachineError> {
+    let mut tables: Vec<Table> = Vec::new();
+
+    for (n, line) in text.lines().enumerate() {
+        let n: usize = n + 1;
+        let line: &str = strip_comment(line).trim();
+
+        if line.is_empty() {
+            continue;
+        }
+
+        if let Some(header) = line.strip_prefix('[') {
+            let (name, array) = match header.strip_prefix('[') {
+                Some(h) => (h.strip_suffix("]]"), true),
+                None => (header.strip_suffix(']'), false),
+            };
+            let name: &str = match name.map(str::trim) {
+                Some(name) if !name.is_empty() => name,
+                _ => return Err(MachineError::Syntax(n, String::from("bad table header"))),
+            };
+
+            // A plain table may only be defined once
+            if !array && tables.iter().any(|t| t.name == name) {
+                return Err(MachineError::Syntax(
+                    n,
+                    format!("duplicate table [{}]", name),
+                ));
+            }
+
+            tables.push(Table {
+                name: String::from(name),
+                line: n,
+                entries: Vec::new(),
+            });
+            continue;
+        }
+
+        let (key, value) = match line.split_once('=') {
+            Some((k, v)) => (k.trim(), v.trim()),
+            None => {
+                return Err(MachineError::Syntax(
+                    n,
+                    String::from("expected key = value"),
+                ))
+            }
+        };
+
+        if key.is_empty() || !key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            return Err(MachineError::Syntax(n, format!("bad key {:?}", key)));
+        }
+
+        let value: Value = match parse_value(value) {
+            Some(v) => v,
+            None => return Err(MachineError::Syntax(n, format!("bad value for {}", key))),
+        };
+        let table: &mut Table = match tables.last_mut() {
+            Some(t) => t,
+            None => {
+                return Err(MachineError::Syntax(
+                    n,
+                    String::from("key outside of a table"),
+                ))
+            }
+        };
+
+        if table.entries.iter().any(|(k, _, _)| k == key) {
+            return Err(MachineError::Syntax(n, format!("duplicate key {}", key)));
+        }
+
+        table.entries.push((String::from(key), value, n));
+    }
+
+    Ok(tables)
+}
+
+impl Machine {
+    // Board used when no description is given
+    pub fn board(isa: &str) -> Machine {
+        Machine {
+            isa: String::from(isa),
+            harts: 1,
+            quantum: 1,
+            reset: 0x8000_0000,
+            memory: vec![
+                Memory {
+                    name: String::from("flash"),
+                    kind: MemoryKind::Flash,
+                    base: 0x2000_0000,
+                    size: 128 * 1024,
+                    image: None,
+                },
+                Memory {
+                    name: String::from("sram"),
+                    kind: MemoryKind::Ram,
+                    base: 0x8000_0000,
+                    size: 128 * 1024,
+                    image: None,
+                },
+            ],
+            devices: vec![
+                Device {
+                    name: String::from("clint"),
+                    kind: DeviceKind::Clint,
+                    base: 0x0200_0000,
+                    irq: None,
+                },
+                Device {
+                    name: String::from("plic"),
+                    kind: DeviceKind::Plic,
+                    base: 0x0c00_0000,
+                    irq: None,
+                },
+                Device {
+                    name: String::from("uart"),
+                    kind: DeviceKind::Uart16550,
+                    base: 0x4001_3c00,
+                    irq: Some(10),
+                },
+            ],
+        }
+    }
+
+    // Parse and validate a description made of a `[cpu]` table and
+    // `[[memory]]` and `[[peripheral]]` arrays of tables
+    pub fn parse(text: &str) -> Result<Machine, MachineError> {
+        let mut isa: Option<String> = None;
+        let mut harts: Option<u64> = None;
+        let mut quantum: Option<u64> = None;
+        let mut reset: Option<u64> = None;
+        let mut memory: Vec<Memory> = Vec::new();
+        let mut devices: Vec<Device> = Vec::new();
+
+        for mut table in parse_tables(text)? {
+            match table.name.as_str() {
+                "cpu" => {
+                    isa = Some(table.string_required("isa")?);
+                    harts = table.int("harts")?;
+                    quantum = table.int("quantum")?;
+                    reset = table.int("reset")?;
+                }
+                "memory" => {
+                    let name: String = table.string_required("name")?;
+                    let base: u64 = table.int_required("base")?;
+                    let size: u64 = table.int_required("size")?;
+                    let kind: MemoryKind = match table.string("type")?.as_deref() {
+                        None | Some("ram") => MemoryKind::Ram,
+                        Some("flash") => MemoryKind::Flash,
+                        Some(t) => {
+                            return Err(MachineError::Invalid(format!(
+                                "{}: unknown memory type {}",
+                                name, t
+                            )))
+                        }
+                    };
+                    let image: Option<String> = table.string("image")?;
+
+                    memory.push(Memory {
+                        name,
+                        kind,
+                        base,
+                        size,
+                        image,
+                    });
+                }
+                "peripheral" => {
+                    let t: String = table.string_required("type")?;
+                    let kind: DeviceKind = match DeviceKind::parse(&t) {
+                        Some(kind) => kind,
+                        None => {
+                            return Err(MachineError::Invalid(format!(
+                                "unknown peripheral type {}",
+                                t
+                            )))
+                        }
+                    };
+                    let name: String = table.string("name")?.unwrap_or(t);
+                    let base: u64 = table.int_required("base")?;
+                    let irq: Option<usize> = table.int("irq")?.map(|n| n as usize);
+
+                    devices.push(Device {
+                        name,
+                        kind,
+                        base,
+                        irq,
+                    });
+                }
+                name => {
+                    return Err(MachineError::Syntax(
+                        table.line,
+                        format!("unknown table [{}]", name),
+                    ))
+                }
+            }
+            table.finish()?;
+        }
+
+        let isa: String = isa.ok_or(MachineError::Missing(String::from("cpu.isa")))?;
+        // The first RAM region is the default reset vector
+        let reset: u64 = match reset {
+            Some(reset) => reset,
+            None => memory
+                .iter()
+                .find(|m| m.kind == MemoryKind::Ram)
+                .map(|m| m.base)
+                .ok_or(MachineError::Missing(String::from("cpu.reset")))?,
+        };
+        let machine: Machine = Machine {
+            isa,
+            harts: harts.unwrap_or(1) as usize,
+            quantum: quantum.unwrap_or(1),
+            reset,
+            memory,
+            devices,
+        };
+
+        machine.validate()?;
+        Ok(machine)
+    }
+
+    pub fn validate(&self) -> Result<(), MachineError> {
+        if !["rv32", "rv64", "rv128"]
+            .iter()
+            .any(|p| self.isa.trim().starts_with(p))
+        {
+            return Err(MachineError::Invalid(format!(
+                "unsupported isa {}",
+                self.isa
+            )));
+        }
+
+        if self.harts == 0 || self.harts > MAX_HARTS {
+            return Err(MachineError::Invalid(format!(
+                "{} harts out of 1..{}",
+                self.harts, MAX_HARTS
+            )));
+        }
+        if self.quantum == 0 {
+            return Err(MachineError::Invalid(String::from("null quantum")));
+        }
+
+        let mut regions: Vec<(&str, u64, u64)> = Vec::new();
+
+        for m in self.memory.iter() {
+            if m.size == 0 {
+                return Err(MachineError::Invalid(format!("{}: empty region", m.name)));
+            }
+            regions.push((&m.name, m.base, m.size));
+        }
+        for d in self.devices.iter() {
+            regions.push((&d.name, d.base, d.kind.size()));
+        }
+
+        for (i, (name, base, size)) in regions.iter().enumerate() {
+            if base.checked_add(*size).is_none() {
+                return Err(MachineError::Invalid(format!(
+                    "{}: region ends beyond the address space",
+                    name
+                )));
+            }
+
+            for (other, obase, osize) in regions[..i].iter() {
+                if name == other {
+                    return Err(MachineError::Invalid(format!("duplicate name {}", name)));
+                }
+                if base < &(obase + osize) && obase < &(base + size) {
+                    return Err(MachineError::Overlap(
+                        String::from(*other),
+                        String::from(*name),
+                    ));
+                }
+            }
+        }
+
+        for kind in [DeviceKind::Clint, DeviceKind::Plic] {
+            if self.devices.iter().filter(|d| d.kind == kind).count() > 1 {
+                return Err(MachineError::Invalid(format!("more than one {:?}", kind)));
+            }
+        }
+
+        let has_plic: bool = self.devices.iter().any(|d| d.kind == DeviceKind::Plic);
+        let mut irqs: Vec<usize> = Vec::new();
+
+        for d in self.devices.iter() {
+            let irq: usize = match d.irq {
+                Some(irq) => irq,
+                None => continue,
+            };
+
+            if d.kind != DeviceKind::Uart16550 {
+                return Err(MachineError::Invalid(format!(
+                    "{}: no interrupt line",
+                    d.name
+                )));
+            }
+            if !has_plic {
+                return Err(MachineError::Invalid(format!(
+                    "{}: irq without a plic",
+                    d.name
+                )));
+            }
+            if irq == 0 || irq >= plic::SOURCES {
+                return Err(MachineError::Invalid(format!(
+                    "{}: irq {} out of 1..{}",
+                    d.name,
+                    irq,
+                    plic::SOURCES - 1
+                )));
+            }
+            if irqs.contains(&irq) {
+                return Err(MachineError::Invalid(format!(
+                    "{}: irq {} already in use",
+                    d.name, irq
+                )));
+            }
+            irqs.push(irq);
+        }
+
+        if !self
+            .memory
+            .iter()
+            .any(|m| self.reset >= m.base && self.reset - m.base < m.size)
+        {
+            return Err(MachineError::Invalid(format!(
+                "reset vector {:#x} outside of memory",
+                self.reset
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DeviceKind, Machine, MachineError, MemoryKind};
+    use crate::vsoc::Vsoc;
+
+    const VIRT: &str = r#"
+# Small board
+[cpu]
+isa = "rv64imac_zicsr"
+harts = 2
+reset = 0x2000_0000
+
+[[memory]]
+name = "boot"
+type = "flash"
+base = 0x2000_0000
+size = 0x1_0000
+
+[[memory]]
+name = "dram"   # main memory
+base = 0x8000_0000
+size = 1_048_576
+
+[[peripheral]]
+type = "plic"
+base = 0x0c00_0000
+
+[[peripheral]]
+name = "uart0"
+type = "uart16550"
+base = 0x1000_0000
+irq = 10
+"#;
+
+    #[test]
+    fn test_parse() {
+        let m: Machine = Machine::parse(VIRT).unwrap();
+
+        assert_eq!(m.isa, "rv64imac_zicsr");
+        assert_eq!(m.harts, 2);
+        assert_eq!(m.quantum, 1);
+        assert_eq!(m.reset, 0x2000_0000);
+        assert_eq!(m.memory.len(), 2);
+        assert_eq!(m.memory[0].kind, MemoryKind::Flash);
+        assert_eq!(m.memory[1].kind, MemoryKind::Ram);
+        assert_eq!(m.memory[1].size, 0x10_0000);
+        assert_eq!(m.devices[0].name, "plic");
+        assert_eq!(m.devices[1].kind, DeviceKind::Uart16550);
+        assert_eq!(m.devices[1].irq, Some(10));
+
+        let m: Machine = Machine::parse(&VIRT.replace("reset = 0x2000_0000", "")).unwrap();
+
+        assert_eq!(m.reset, 0x8000_0000);
+        assert!(Machine::board("rv32i").validate().is_ok());
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(
+            Machine::parse(&VIRT.replace("isa = \"rv64imac_zicsr\"", "")),
+            Err(MachineError::Missing(String::from("cpu.isa")))
+        );
+        assert_eq!(
+            Machine::parse(&VIRT.replace("0x1000_0000", "0x0c00_1000")),
+            Err(MachineError::Overlap(
+                String::from("plic"),
+                String::from("uart0")
+            ))
+        );
+        assert_eq!(
+            Machine::parse(&VIRT.replace("size = 0x1_0000", "size = 0x6000_0001")),
+            Err(MachineError::Overlap(
+                String::from("boot"),
+                String::from("dram")
+            ))
+        );
+        assert!(matches!(
+            Machine::parse(&VIRT.replace("irq = 10", "irq = 32")),
+            Err(MachineError::Invalid(_))
+        ));
+        assert!(matches!(
+            Machine::parse(&VIRT.replace("reset = 0x2000_0000", "reset = 0x4000_0000")),
+            Err(MachineError::Invalid(_))
+        ));
+        assert_eq!(
+            Machine::parse(&VIRT.replace("base = 0x8000_0000", "bsae = 0x8000_0000")),
+            Err(MachineError::Missing(String::from("memory.base")))
+        );
+        assert!(matches!(
+            Machine::parse(&VIRT.replace("irq = 10", "irq = 10\nspeed = 115200")),
+            Err(MachineError::Syntax(28, _))
+        ));
+        assert!(matches!(
+            Machine::parse(&VIRT.replace("harts = 2", "harts = 0")),
+            Err(MachineError::Invalid(_))
+        ));
+        assert!(matches!(
+            Machine::parse("[cpu]\nisa = rv64i\n"),
+            Err(MachineError::Syntax(2, _))
+        ));
+    }
+
+    #[test]
+    fn test_vsoc_image() {
+        let path = std::env::temp_dir().join(format!("rustv-machine-{}.bin", std::process::id()));
+        // addi a0, zero, 42
+        std::fs::write(&path, 0x02a0_0513u32.to_le_bytes()).unwrap();
+
+        let text: String = format!(
+            "[cpu]\nisa = \"rv32i\"\n[[memory]]\nname = \"rom\"\nbase = 0x1000\nsize = 0x1000\nimage = {:?}\n",
+            path.to_str().unwrap()
+        );
+        let machine: Machine = Machine::parse(&text).unwrap();
+        let mut vsoc: Vsoc = Vsoc::with_machine(&machine).unwrap();
+
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(vsoc.pc(), 0x1000);
+        assert!(vsoc.step().is_none());
+        assert_eq!(vsoc.read_register(10), Some(42u32.to_le_bytes().to_vec()));
+    }
+}
@@ -1,11 +1,31 @@
 mod arch;
 mod bus;
 mod dev;
+pub mod elf;
+pub mod fdt;
+mod htif;
+mod irq;
+pub mod machine;
 mod peripheral;
+pub mod replay;
+pub mod snapshot;
+pub mod trace;
 
+use arch::riscv::interrupt::RvInterrupt;
 use bus::Bus;
-use dev::{flash, sram, uart};
+use dev::{clint, flash, plic, sram, uart};
+use elf::{Elf, ElfError};
+use htif::Htif;
+use irq::IrqLine;
+use machine::{DeviceKind, Machine, MachineError, MemoryKind};
+use replay::{Event, Player, Recorder};
+use snapshot::{Reader, SnapshotError, Writer};
+use std::cell::RefCell;
 use std::fmt;
+use std::io::Read;
+use std::rc::Rc;
+use std::sync::mpsc;
+use std::thread;
 
 pub enum VsocException {
     InstructionAddressMisaligned,
@@ -18,9 +38,15 @@ pub enum VsocException {
     StoreAccessFault,
     EnvironmentCallUMode,
     EnvironmentCallSMode,
+    EnvironmentCallVSMode,
+    EnvironmentCallMMode,
     InstructionPageFault,
     LoadPageFault,
     StorePageFault,
+    InstructionGuestPageFault,
+    LoadGuestPageFault,
+    VirtualInstruction,
+    StoreGuestPageFault,
 }
 
 impl fmt::Display for VsocException {
@@ -37,9 +63,15 @@ impl fmt::Display for VsocException {
             Self::StoreAccessFault => String::from("StoreAccessFault"),
             Self::EnvironmentCallUMode => String::from("EnvironmentCallUMode"),
             Self::EnvironmentCallSMode => String::from("EnvironmentCallSMode"),
+            Self::EnvironmentCallVSMode => String::from("EnvironmentCallVSMode"),
+            Self::EnvironmentCallMMode => String::from("EnvironmentCallMMode"),
             Self::InstructionPageFault => String::from("InstructionPageFault"),
             Self::LoadPageFault => String::from("LoadPageFault"),
             Self::StorePageFault => String::from("StorePageFault"),
+            Self::InstructionGuestPageFault => String::from("InstructionGuestPageFault"),
+            Self::LoadGuestPageFault => String::from("LoadGuestPageFault"),
+            Self::VirtualInstruction => String::from("VirtualInstruction"),
+            Self::StoreGuestPageFault => String::from("StoreGuestPageFault"),
         };
         write!(f, "VsocException::{}", s)
     }
@@ -47,53 +79,447 @@ impl fmt::Display for VsocException {
 
 #[derive(Debug)]
 pub struct Vsoc<'a> {
-    cpu: arch::cpu::Cpu<'a>,
+    cpus: Vec<arch::cpu::Cpu<'a>>,
+    // Hart running, and instructions it ran in its current quantum
+    hart: usize,
+    slice: u64,
+    quantum: u64,
     bus: Bus,
+    clint: Option<Rc<RefCell<clint::Clint>>>,
+    plic: Option<Rc<RefCell<plic::Plic>>>,
+    reset: u64,
+    tree: fdt::Node,
+    symbols: Vec<elf::Symbol>,
+    htif: Option<Htif>,
+    exit: Option<u64>,
+    // Steps run so far, the clock of the recorded and replayed inputs
+    steps: u64,
+    console: mpsc::Sender<u8>,
+    stdin: Option<mpsc::Receiver<u8>>,
+    recorder: Option<Recorder>,
+    player: Option<Player>,
 }
 
 impl<'a> Vsoc<'a> {
     pub fn new(arch: &'a String) -> Vsoc<'a> {
+        Self::build(arch, &Machine::board(arch)).expect("default board")
+    }
+
+    // Build the bus from a machine description, its isa drives the CPU
+    pub fn with_machine(machine: &'a Machine) -> Result<Vsoc<'a>, MachineError> {
+        machine.validate()?;
+        Self::build(&machine.isa, machine)
+    }
+
+    fn build(arch: &'a String, machine: &Machine) -> Result<Vsoc<'a>, MachineError> {
         let mut bus: Bus = Bus::new();
-        let flash: Box<flash::Flash> = Box::new(flash::Flash::new(128 * 1024));
-        let sram: Box<sram::Sram> = Box::new(sram::Sram::new(128 * 1024));
-        let uart: Box<uart::uart16550::Uart16550> =
-            Box::new(uart::uart16550::Uart16550::new(0x2000));
-        let p_flash = Box::new(peripheral::Peripheral::new(
-            String::from("flash"),
-            flash.size(),
-            flash,
-        ));
-        let p_sram = Box::new(peripheral::Peripheral::new(
-            String::from("sram"),
-            sram.size(),
-            sram,
-        ));
-        let p_uart = Box::new(peripheral::Peripheral::new(
-            String::from("uart"),
-            uart.size(),
-            uart,
-        ));
-
-        bus.attach(0x2000_0000, p_flash);
-        bus.attach(0x8000_0000, p_sram);
-        bus.attach(0x4001_3c00, p_uart);
-        Vsoc {
-            cpu: arch::cpu::Cpu::new(arch),
+        let mut clint: Option<Rc<RefCell<clint::Clint>>> = None;
+        let mut plic: Option<Rc<RefCell<plic::Plic>>> = None;
+        let (console, rx) = mpsc::channel::<u8>();
+        let mut rx: Option<mpsc::Receiver<u8>> = Some(rx);
+
+        for m in machine.memory.iter() {
+            let (size, device): (usize, Box<dyn peripheral::PeripheralInterface>) = match m.kind {
+                MemoryKind::Ram => {
+                    let sram = sram::Sram::new(m.size as usize);
+
+                    (sram.size(), Box::new(sram))
+                }
+                MemoryKind::Flash => {
+                    let flash = flash::Flash::new(m.size as usize);
+
+                    (flash.size(), Box::new(flash))
+                }
+            };
+
+            bus.attach(
+                m.base,
+                Box::new(peripheral::Peripheral::new(m.name.clone(), size, device)),
+            )?;
+        }
+
+        // The PLIC goes first, the other devices take their interrupt line from it
+        let mut devices: Vec<&machine::Device> = machine.devices.iter().collect();
+
+        devices.sort_by_key(|d| d.kind != DeviceKind::Plic);
+        for d in devices {
+            let (size, device): (usize, Box<dyn peripheral::PeripheralInterface>) = match d.kind {
+                DeviceKind::Clint => {
+                    let c = Rc::new(RefCell::new(clint::Clint::new(machine.harts)));
+                    let size: usize = c.borrow().size();
+
+                    clint = Some(c.clone());
+                    (size, Box::new(c))
+                }
+                DeviceKind::Plic => {
+                    let p = Rc::new(RefCell::new(plic::Plic::new(machine.harts)));
+                    let size: usize = p.borrow().size();
+
+                    plic = Some(p.clone());
+                    (size, Box::new(p))
+                }
+                DeviceKind::Uart16550 => {
+                    let line: IrqLine = match (&plic, d.irq) {
+                        (Some(p), Some(irq)) => p.borrow().line(irq),
+                        _ => IrqLine::new(),
+                    };
+                    let mut uart = uart::uart16550::Uart16550::new(d.kind.size() as usize, line);
+
+                    // The host console is wired to the first UART
+                    if let Some(rx) = rx.take() {
+                        uart.connect(rx);
+                    }
+                    (uart.size(), Box::new(uart))
+                }
+            };
+
+            bus.attach(
+                d.base,
+                Box::new(peripheral::Peripheral::new(d.name.clone(), size, device)),
+            )?;
+        }
+
+        let mut cpus: Vec<arch::cpu::Cpu> = (0..machine.harts)
+            .map(|hartid| arch::cpu::Cpu::new(arch, hartid))
+            .collect();
+        let tree: fdt::Node = fdt::generate(machine);
+        let dtb: Vec<u8> = tree.to_dtb();
+
+        // The device tree goes at the top of the first RAM region large enough
+        if let Some(m) = machine
+            .memory
+            .iter()
+            .find(|m| m.kind == MemoryKind::Ram && m.size >= dtb.len() as u64 + 8)
+        {
+            let addr: u64 = (m.base + m.size - dtb.len() as u64) & !0x7;
+
+            bus.write(addr, &dtb);
+            for cpu in cpus.iter_mut() {
+                cpu.set_boot_args(addr);
+            }
+        }
+
+        for m in machine.memory.iter() {
+            let path: &String = match &m.image {
+                Some(path) => path,
+                None => continue,
+            };
+            let image: Vec<u8> = std::fs::read(path)
+                .map_err(|e| MachineError::Image(path.clone(), e.to_string()))?;
+
+            if image.len() as u64 > m.size {
+                return Err(MachineError::Image(
+                    path.clone(),
+                    format!("larger than {}", m.name),
+                ));
+            }
+            bus.write(m.base, &image);
+        }
+
+        for cpu in cpus.iter_mut() {
+            cpu.set_pc(machine.reset as u128);
+        }
+        bus.track_stores();
+        Ok(Vsoc {
+            cpus,
+            hart: 0,
+            slice: 0,
+            quantum: machine.quantum,
             bus,
+            clint,
+            plic,
+            reset: machine.reset,
+            tree,
+            symbols: Vec::new(),
+            htif: None,
+            exit: None,
+            steps: 0,
+            console,
+            stdin: None,
+            recorder: None,
+            player: None,
+        })
+    }
+
+    // Feed the UART receiver with the host standard input
+    pub fn attach_stdin(&mut self) {
+        let (tx, rx) = mpsc::channel::<u8>();
+
+        self.stdin = Some(rx);
+
+        thread::spawn(move || {
+            for c in std::io::stdin().lock().bytes() {
+                match c {
+                    Ok(c) if tx.send(c).is_ok() => (),
+                    _ => return,
+                }
+            }
+        });
+    }
+
+    // Load an ELF file at the physical addresses of its segments, or a raw
+    // binary at the reset vector
+    pub fn load(&mut self, binary: &[u8]) -> Result<(), ElfError> {
+        if !elf::is_elf(binary) {
+            if self.bus.write(self.reset, binary).is_some() {
+                return Err(ElfError::Unmapped(self.reset));
+            }
+
+            self.set_pc(self.reset);
+            return Ok(());
+        }
+
+        let elf: Elf = Elf::parse(binary)?;
+
+        for segment in elf.segments.iter() {
+            let size: u64 = segment.memsz.max(segment.data.len() as u64);
+
+            // The sizes come from the file, check them before writing anything
+            if segment.paddr.checked_add(size).is_none() || !self.bus.mapped(segment.paddr, size) {
+                return Err(ElfError::Unmapped(segment.paddr));
+            }
+
+            if self.bus.write(segment.paddr, &segment.data).is_some() {
+                return Err(ElfError::Unmapped(segment.paddr));
+            }
+
+            // Zero the rest a page at a time
+            let zero: [u8; 4096] = [0; 4096];
+            let mut addr: u64 = segment.paddr + segment.data.len() as u64;
+
+            while addr < segment.paddr + size {
+                let len: usize = (segment.paddr + size - addr).min(zero.len() as u64) as usize;
+
+                if self.bus.write(addr, &zero[..len]).is_some() {
+                    return Err(ElfError::Unmapped(segment.paddr));
+                }
+                addr += len as u64;
+            }
         }
+
+        self.set_pc(elf.entry);
+        self.symbols = elf.symbols;
+        if let Some(tohost) = self.symbol("tohost") {
+            self.set_tohost(tohost);
+        }
+
+        Ok(())
     }
 
-    pub fn load(&mut self, binary: &Vec<u8>) {
-        for i in 0..binary.len() {
-            self.bus
-                .store(1, 0x8000_0000 + i as u64, &[binary[i]].to_vec());
+    // All harts start from the same address
+    fn set_pc(&mut self, pc: u64) {
+        for cpu in self.cpus.iter_mut() {
+            cpu.set_pc(pc as u128);
         }
+    }
+
+    // Save the harts, the devices and the HTIF state to a file
+    pub fn save(&self, path: &str) -> Result<(), SnapshotError> {
+        std::fs::write(path, self.snapshot()).map_err(|e| SnapshotError::Io(e.to_string()))
+    }
+
+    // Resume a snapshot taken on the same machine
+    pub fn restore(&mut self, path: &str) -> Result<(), SnapshotError> {
+        let data: Vec<u8> = std::fs::read(path).map_err(|e| SnapshotError::Io(e.to_string()))?;
+
+        self.restore_snapshot(&data)
+    }
+
+    fn snapshot(&self) -> Vec<u8> {
+        let mut w: Writer = Writer::new();
+
+        w.str(self.cpus[0].desc());
+        w.u64(self.cpus.len() as u64);
+        for cpu in self.cpus.iter() {
+            cpu.save(&mut w);
+        }
+        self.bus.save(&mut w);
+        w.u64(self.hart as u64);
+        w.u64(self.slice);
+        w.option_u64(self.htif.as_ref().map(|h| h.tohost()));
+        w.option_u64(self.htif.as_ref().and_then(|h| h.fromhost()));
+        w.option_u64(self.exit);
+        w.u64(self.steps);
+
+        w.finish()
+    }
+
+    fn restore_snapshot(&mut self, data: &[u8]) -> Result<(), SnapshotError> {
+        let mut r: Reader = Reader::new(data)?;
+
+        if r.str()? != self.cpus[0].desc() {
+            return Err(SnapshotError::Mismatch(String::from("isa")));
+        }
+        r.expect(self.cpus.len() as u64, "harts")?;
+        for cpu in self.cpus.iter_mut() {
+            cpu.restore(&mut r)?;
+        }
+        self.bus.restore(&mut r)?;
+        self.hart = r.u64()? as usize % self.cpus.len();
+        self.slice = r.u64()? % self.quantum;
+
+        let tohost: Option<u64> = r.option_u64()?;
+        let fromhost: Option<u64> = r.option_u64()?;
+
+        self.htif = tohost.map(|tohost| Htif::new(tohost, fromhost));
+        self.exit = r.option_u64()?;
+        // The clock of the replayed inputs and of the time without a CLINT
+        self.steps = r.u64()?;
+
+        r.finish()
+    }
+
+    // Log the host input with the step it was taken at
+    pub fn record(&mut self, recorder: Recorder) {
+        self.recorder = Some(recorder);
+    }
+
+    // Take the input from a recorded log instead of the host
+    pub fn replay(&mut self, player: Player) {
+        self.player = Some(player);
+    }
+
+    // Hand the input of this step to the UART, the host input is only polled
+    // here so that a replay delivers it at the same step
+    fn input(&mut self) {
+        if let Some(player) = self.player.as_mut() {
+            while let Some(Event::UartRx(c)) = player.next(self.steps) {
+                let _ = self.console.send(c);
+            }
+        } else if let Some(stdin) = &self.stdin {
+            while let Ok(c) = stdin.try_recv() {
+                if let Some(recorder) = self.recorder.as_mut() {
+                    recorder.event(self.steps, Event::UartRx(c));
+                }
+                let _ = self.console.send(c);
+            }
+        }
+    }
+
+    // Enable HTIF, fromhost is taken from the symbol table when available
+    pub fn set_tohost(&mut self, tohost: u64) {
+        self.htif = Some(Htif::new(tohost, self.symbol("fromhost")));
+    }
+
+    // Exit code reported by the program through HTIF
+    pub fn exit_code(&self) -> Option<u64> {
+        self.exit
+    }
+
+    // Log each executed instruction with its register writebacks
+    pub fn set_trace(&mut self, trace: trace::Trace) {
+        if self.cpus.len() == 1 {
+            self.cpus[0].set_trace(trace);
+            return;
+        }
+
+        for (hart, cpu) in self.cpus.iter_mut().enumerate() {
+            cpu.set_trace(trace.for_hart(hart));
+        }
+    }
+
+    pub fn device_tree(&self) -> &fdt::Node {
+        &self.tree
+    }
+
+    pub fn symbol(&self, name: &str) -> Option<u64> {
+        self.symbols.iter().find(|s| s.name == name).map(|s| s.value)
+    }
+
+    pub fn harts(&self) -> usize {
+        self.cpus.len()
+    }
+
+    // The debugger sees the first hart
+    pub fn pc(&self) -> u64 {
+        self.cpus[0].pc()
+    }
+
+    pub fn target_xml(&self) -> String {
+        self.cpus[0].debug_target_xml()
+    }
+
+    pub fn read_register(&self, regnum: usize) -> Option<Vec<u8>> {
+        self.cpus[0].debug_read_register(regnum)
+    }
+
+    pub fn write_register(&mut self, regnum: usize, value: &[u8]) -> bool {
+        self.cpus[0].debug_write_register(regnum, value)
+    }
+
+    // Debugger accesses go straight to the bus, bypassing translation and PMP
+    pub fn read_memory(&mut self, addr: u64, len: usize) -> Option<Vec<u8>> {
+        (0..len as u64)
+            .map(|i| self.bus.fetch(1, addr + i).ok().map(|b| b[0]))
+            .collect()
+    }
+
+    pub fn write_memory(&mut self, addr: u64, data: &[u8]) -> bool {
+        self.bus.write(addr, data).is_none()
+    }
 
-        self.cpu.set_pc(0x8000_0000);
+    // Stores break the reservations other harts hold on the same location, and
+    // drop the instructions every hart cached from there. `from` is the hart
+    // that did them, None for another agent
+    fn snoop(&mut self, from: Option<usize>) {
+        for (addr, width) in self.bus.drain_stores() {
+            for (i, cpu) in self.cpus.iter_mut().enumerate() {
+                if Some(i) == from {
+                    cpu.invalidate(addr, width);
+                } else {
+                    cpu.snoop(addr, width);
+                }
+            }
+        }
     }
 
+    // Run one instruction of the current hart, the harts take turns every
+    // `quantum` instructions
     pub fn step(&mut self) -> Option<VsocException> {
-        self.cpu.step(&mut self.bus)
+        self.input();
+        // Stores of the debugger, the loader or HTIF since the last step
+        self.snoop(None);
+
+        let hart: usize = self.hart;
+        let cpu: &mut arch::cpu::Cpu = &mut self.cpus[hart];
+
+        self.bus.tick();
+
+        if let Some(clint) = &self.clint {
+            let clint = clint.borrow();
+
+            cpu.set_pending(RvInterrupt::MachineSwInt, clint.msip(hart));
+            cpu.set_pending(RvInterrupt::MachineTimerInt, clint.mtip(hart));
+            cpu.set_time(clint.mtime());
+        } else {
+            // Same pace as the CLINT time base
+            cpu.set_time(self.steps);
+        }
+        if let Some(plic) = &self.plic {
+            let plic = plic.borrow();
+
+            cpu.set_pending(RvInterrupt::MachineExternalInt, plic.eip(2 * hart));
+            cpu.set_pending(RvInterrupt::SupervisorExternalInt, plic.eip(2 * hart + 1));
+        }
+
+        let e: Option<VsocException> = cpu.step(&mut self.bus);
+
+        self.snoop(Some(hart));
+
+        self.steps += 1;
+        self.slice += 1;
+        if self.slice == self.quantum {
+            self.slice = 0;
+            self.hart = (hart + 1) % self.cpus.len();
+        }
+
+        if let Some(htif) = self.htif.as_mut() {
+            if let Some(code) = htif.poll(&mut self.bus) {
+                self.exit = Some(code);
+            }
+        }
+
+        e
     }
 }
 
@@ -104,6 +530,360 @@ impl<'a> fmt::Display for Vsoc<'a> {
         // stream: `f`. Returns `fmt::Result` which indicates whether the
         // operation succeeded or failed. Note that `write!` uses syntax which
         // is very similar to `println!`.
-        write!(f, "(vsoc\n {}\n {})", self.cpu, self.bus)
+        writeln!(f, "(vsoc")?;
+        for cpu in self.cpus.iter() {
+            writeln!(f, " {}", cpu)?;
+        }
+        write!(f, " {})", self.bus)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::machine::Machine;
+    use super::replay::{Player, Recorder};
+    use super::snapshot::SnapshotError;
+    use super::Vsoc;
+    use std::sync::mpsc;
+
+    // lui a2, 0x80001; csrr t0, mhartid; bnez t0, 1f
+    // lr.w t1, (a2); nop; sc.w a3, t1, (a2)
+    // 1: sw zero, 0(a2); j .
+    const PROGRAM: [u32; 8] = [
+        0x8000_1637,
+        0xf140_22f3,
+        0x0002_9863,
+        0x1006_232f,
+        0x0000_0013,
+        0x1866_26af,
+        0x0006_2023,
+        0x0000_006f,
+    ];
+
+    fn run(machine: &Machine) -> Vsoc<'_> {
+        let mut vsoc: Vsoc = Vsoc::with_machine(machine).unwrap();
+        let binary: Vec<u8> = PROGRAM.iter().flat_map(|i| i.to_le_bytes()).collect();
+
+        vsoc.load(&binary).unwrap();
+        for _ in 0..6 * machine.harts {
+            assert!(vsoc.step().is_none());
+        }
+
+        vsoc
+    }
+
+    fn x(vsoc: &Vsoc, hart: usize, reg: usize) -> u32 {
+        let value: Vec<u8> = vsoc.cpus[hart].debug_read_register(reg).unwrap();
+
+        u32::from_le_bytes(value.try_into().unwrap())
+    }
+
+    #[test]
+    fn test_smp() {
+        let mut machine: Machine = Machine::board("rv32ia_zicsr");
+        let vsoc: Vsoc = run(&machine);
+
+        // The reservation holds on a single hart
+        assert_eq!(x(&vsoc, 0, 13), 0);
+
+        machine.harts = 2;
+
+        let vsoc: Vsoc = run(&machine);
+
+        assert_eq!(vsoc.harts(), 2);
+        assert_eq!(x(&vsoc, 0, 5), 0);
+        assert_eq!(x(&vsoc, 1, 5), 1);
+        assert_eq!(x(&vsoc, 1, 10), 1);
+        assert_eq!(x(&vsoc, 0, 11), x(&vsoc, 1, 11));
+        // Hart 1 stored to the reserved word between the LR and the SC of hart 0
+        assert_eq!(x(&vsoc, 0, 13), 1);
+    }
+
+    #[test]
+    fn test_snapshot() {
+        let mut machine: Machine = Machine::board("rv32ia_zicsr");
+
+        machine.harts = 2;
+
+        let mut vsoc: Vsoc = Vsoc::with_machine(&machine).unwrap();
+        let binary: Vec<u8> = PROGRAM.iter().flat_map(|i| i.to_le_bytes()).collect();
+
+        vsoc.load(&binary).unwrap();
+        // Hart 0 holds a reservation, hart 1 has not stored yet
+        for _ in 0..7 {
+            assert!(vsoc.step().is_none());
+        }
+
+        let data: Vec<u8> = vsoc.snapshot();
+        let mut copy: Vsoc = Vsoc::with_machine(&machine).unwrap();
+
+        copy.restore_snapshot(&data).unwrap();
+        assert_eq!(copy.snapshot(), data);
+        assert_eq!(copy.steps, 7);
+        for _ in 0..5 {
+            assert!(vsoc.step().is_none());
+            assert!(copy.step().is_none());
+        }
+        for hart in 0..2 {
+            for reg in 0..32 {
+                assert_eq!(x(&vsoc, hart, reg), x(&copy, hart, reg));
+            }
+        }
+        assert_eq!(x(&copy, 0, 13), 1);
+        assert_eq!(copy.pc(), vsoc.pc());
+        assert_eq!(copy.read_memory(0x8000_1000, 4), Some(vec![0; 4]));
+
+        let single: Machine = Machine::board("rv32ia_zicsr");
+        let mut other: Vsoc = Vsoc::with_machine(&single).unwrap();
+
+        assert_eq!(
+            other.restore_snapshot(&data),
+            Err(SnapshotError::Mismatch(String::from("harts")))
+        );
+        assert_eq!(copy.restore_snapshot(&data[..data.len() - 1]), Err(SnapshotError::Truncated));
+    }
+
+    #[test]
+    fn test_counters() {
+        let arch: String = String::from("rv32i_zicsr_zicntr_zihpm");
+        let mut vsoc: Vsoc = Vsoc::new(&arch);
+        // mhpmevent3 = taken branches, mhpmevent4 = loads
+        // li a0, 3; auipc gp, 0; 1: lw t1, 0(gp); addi a0, a0, -1; bnez a0, 1b
+        // csrr a1..a5, mhpmcounter3/mhpmcounter4/minstret/time/mcycle; j .
+        let binary: Vec<u8> = [
+            0x0030_0293u32,
+            0x3232_9073,
+            0x0010_0293,
+            0x3242_9073,
+            0x0030_0513,
+            0x0000_0197,
+            0x0001_a303,
+            0xfff5_0513,
+            0xfe05_1ce3,
+            0xb030_25f3,
+            0xb040_2673,
+            0xb020_26f3,
+            0xc010_2773,
+            0xb000_27f3,
+            0x0000_006f,
+        ]
+        .iter()
+        .flat_map(|i| i.to_le_bytes())
+        .collect();
+
+        vsoc.load(&binary).unwrap();
+        for _ in 0..20 {
+            assert!(vsoc.step().is_none());
+        }
+        assert_eq!(x(&vsoc, 0, 11), 2);
+        assert_eq!(x(&vsoc, 0, 12), 3);
+        // Instructions retired before the csrr
+        assert_eq!(x(&vsoc, 0, 13), 17);
+        assert_eq!(x(&vsoc, 0, 14), 19);
+        assert_eq!(x(&vsoc, 0, 15), 19);
+    }
+
+    #[test]
+    fn test_replay() {
+        let arch: String = String::from("rv32i_zicsr");
+        let path: std::path::PathBuf = std::env::temp_dir().join("rustv-test-replay.log");
+        let path: &str = path.to_str().unwrap();
+        // lui a1, 0x40015; 1: lw a0, LSR(a1); andi a0, a0, 1; beqz a0, 1b
+        // lw a0, RBR(a1); j .
+        let binary: Vec<u8> = [
+            0x4001_55b7u32,
+            0xc145_a503,
+            0x0015_7513,
+            0xfe05_0ce3,
+            0xc005_a503,
+            0x0000_006f,
+        ]
+        .iter()
+        .flat_map(|i| i.to_le_bytes())
+        .collect();
+        let run = |vsoc: &mut Vsoc| -> Option<u64> {
+            for _ in 0..100 {
+                assert!(vsoc.step().is_none());
+                if x(vsoc, 0, 10) == 0x41 {
+                    return Some(vsoc.steps);
+                }
+            }
+            None
+        };
+
+        let mut vsoc: Vsoc = Vsoc::new(&arch);
+        let (tx, rx) = mpsc::channel::<u8>();
+
+        vsoc.load(&binary).unwrap();
+        vsoc.stdin = Some(rx);
+        vsoc.record(Recorder::new(path).unwrap());
+        for _ in 0..10 {
+            vsoc.step();
+        }
+        tx.send(0x41).unwrap();
+
+        let recorded: Option<u64> = run(&mut vsoc);
+
+        assert!(recorded.is_some());
+        assert_eq!(
+            std::fs::read_to_string(path).unwrap(),
+            "rustv-replay 1\n10 uart-rx 0x41\n"
+        );
+
+        // The input comes back at the same step without the host
+        let mut vsoc: Vsoc = Vsoc::new(&arch);
+
+        vsoc.load(&binary).unwrap();
+        vsoc.replay(Player::open(path).unwrap());
+        assert_eq!(run(&mut vsoc), recorded);
+        std::fs::remove_file(path).unwrap();
+    }
+    #[test]
+    fn test_rv128() {
+        let arch: String = String::from("rv128im_zicsr");
+        let mut vsoc: Vsoc = Vsoc::new(&arch);
+        // auipc gp, 0; li a0, -1; slli a0, a0, 100
+        // sq a0, 128(gp); lq a1, 128(gp); ldu a2, 136(gp)
+        // li t0, -1; srli t0, t0, 65; addid a3, t0, 1; addd a4, t0, t0; mulh a5, a0, a0
+        // auipc t2, 0; addi t2, t2, 24; csrw mtvec, t2; li t1, 1; slli t1, t1, 64; jr t1
+        // csrr s0, mcause; csrr s1, mtval; j .
+        let binary: Vec<u8> = [
+            0x0000_0197u32,
+            0xfff0_0513,
+            0x0645_1513,
+            0x08a1_c023,
+            0x0801_a58f,
+            0x0881_f603,
+            0xfff0_0293,
+            0x0412_d293,
+            0x0012_86db,
+            0x0052_877b,
+            0x02a5_17b3,
+            0x0000_0397,
+            0x0183_8393,
+            0x3053_9073,
+            0x0010_0313,
+            0x0403_1313,
+            0x0003_0067,
+            0x3420_2473,
+            0x3430_24f3,
+            0x0000_006f,
+        ]
+        .iter()
+        .flat_map(|i| i.to_le_bytes())
+        .collect();
+        let x = |vsoc: &Vsoc, reg: usize| -> u128 {
+            let value: Vec<u8> = vsoc.cpus[0].debug_read_register(reg).unwrap();
+
+            u128::from_le_bytes(value.try_into().unwrap())
+        };
+
+        vsoc.load(&binary).unwrap();
+        for _ in 0..20 {
+            assert!(vsoc.step().is_none());
+        }
+        assert_eq!(x(&vsoc, 11), u128::MAX << 100);
+        assert_eq!(x(&vsoc, 12), 0xffff_fff0_0000_0000);
+        assert_eq!(x(&vsoc, 13), (i64::MIN as i128) as u128);
+        assert_eq!(x(&vsoc, 14), u128::MAX - 1);
+        assert_eq!(x(&vsoc, 15), 1 << 72);
+        // The pc is beyond the 64-bit physical address space
+        assert_eq!(x(&vsoc, 8), 1);
+        assert_eq!(x(&vsoc, 9), 1 << 64);
+    }
+
+    #[test]
+    fn test_self_modifying() {
+        let arch: String = String::from("rv32i_zicsr_zifencei");
+        let mut vsoc: Vsoc = Vsoc::new(&arch);
+        // auipc t0, 0; li a0, 0; li t2, 0
+        // 1: addi a0, a0, 1; bnez t2, 2f; li t2, 1
+        // lw t1, 40(t0); sw t1, 12(t0); fence.i; j 1b
+        // addi a0, a0, 16
+        // 2: j 2b
+        let binary: Vec<u8> = [
+            0x0000_0297u32,
+            0x0000_0513,
+            0x0000_0393,
+            0x0015_0513,
+            0x0003_9e63,
+            0x0010_0393,
+            0x0282_a303,
+            0x0062_a623,
+            0x0000_100f,
+            0xfe9f_f06f,
+            0x0105_0513,
+            0x0000_006f,
+        ]
+        .iter()
+        .flat_map(|i| i.to_le_bytes())
+        .collect();
+
+        vsoc.load(&binary).unwrap();
+        for _ in 0..14 {
+            assert!(vsoc.step().is_none());
+        }
+        // The patched instruction ran instead of the cached one
+        assert_eq!(x(&vsoc, 0, 10), 17);
+
+        // As do the debugger writes, addi a0, a0, 1 over the last loop
+        assert!(vsoc.write_memory(0x8000_002c, &0x0015_0513u32.to_le_bytes()));
+        vsoc.step();
+        assert_eq!(x(&vsoc, 0, 10), 18);
+    }
+
+    // Run a jump to a target that is not 4-byte aligned at 0x8000_0010, after
+    // a branch not taken to another one, and return mepc, mcause, mtval and rd
+    fn misaligned_jump(jump: u32, rd: usize) -> (u32, u32, u32, u32) {
+        let arch: String = String::from("rv32i_zicsr");
+        let mut vsoc: Vsoc = Vsoc::new(&arch);
+        // auipc t2, 0; addi t2, t2, 0x40; csrw mtvec, t2; bne zero, zero, 6; <jump>
+        // 0x40: csrr s0, mepc; csrr s1, mcause; csrr a2, mtval; j .
+        let mut program: Vec<u32> = vec![0x0000_0397, 0x0403_8393, 0x3053_9073, 0x0000_1363, jump];
+
+        program.resize(16, 0x0000_0013);
+        program.extend([0x3410_2473, 0x3420_24f3, 0x3430_2673, 0x0000_006f]);
+
+        let binary: Vec<u8> = program.iter().flat_map(|i| i.to_le_bytes()).collect();
+
+        vsoc.load(&binary).unwrap();
+        for _ in 0..9 {
+            assert!(vsoc.step().is_none());
+        }
+
+        (
+            x(&vsoc, 0, 8),
+            x(&vsoc, 0, 9),
+            x(&vsoc, 0, 12),
+            x(&vsoc, 0, rd),
+        )
+    }
+
+    #[test]
+    fn test_misaligned_jal() {
+        // jal ra, 6
+        // rd is not written
+        assert_eq!(
+            misaligned_jump(0x0060_00ef, 1),
+            (0x8000_0010, 0, 0x8000_0016, 0)
+        );
+    }
+
+    #[test]
+    fn test_misaligned_jalr() {
+        // jalr t2, 6(t2)
+        assert_eq!(
+            misaligned_jump(0x0063_83e7, 7),
+            (0x8000_0010, 0, 0x8000_0046, 0x8000_0040)
+        );
+    }
+
+    #[test]
+    fn test_misaligned_branch() {
+        // beq zero, zero, 6
+        assert_eq!(
+            misaligned_jump(0x0000_0363, 0),
+            (0x8000_0010, 0, 0x8000_0016, 0)
+        );
     }
 }
@@ -1,6 +1,9 @@
 use crate::vsoc::bus::BusException;
+use crate::vsoc::snapshot::{Reader, SnapshotError, Writer};
+use std::cell::RefCell;
 use std::fmt;
 use std::fmt::Debug;
+use std::rc::Rc;
 
 #[derive(Debug)]
 pub struct Peripheral {
@@ -9,9 +12,55 @@ pub struct Peripheral {
     pub io: Box<dyn PeripheralInterface>,
 }
 
+// Host memory of a RAM-like device
+pub type Memory = Rc<RefCell<Vec<u8>>>;
+
 pub trait PeripheralInterface: Debug {
     fn fetch(&mut self, width: usize, addr: usize) -> Result<Vec<u8>, BusException>;
     fn store(&mut self, width: usize, addr: usize, value: &Vec<u8>) -> Option<BusException>;
+
+    // Called once per emulation step, before the harts run
+    fn tick(&mut self) {}
+
+    // RAM-like devices hand their memory to the bus, which then accesses it
+    // without going through them
+    fn memory(&self) -> Option<Memory> {
+        None
+    }
+
+    // Device state kept in snapshots, nothing for stateless devices
+    fn save(&self, _w: &mut Writer) {}
+
+    fn restore(&mut self, _r: &mut Reader) -> Result<(), SnapshotError> {
+        Ok(())
+    }
+}
+
+// A device shared between the bus and its owner, e.g. to sample its interrupt lines
+impl<T: PeripheralInterface> PeripheralInterface for Rc<RefCell<T>> {
+    fn fetch(&mut self, width: usize, addr: usize) -> Result<Vec<u8>, BusException> {
+        self.borrow_mut().fetch(width, addr)
+    }
+
+    fn store(&mut self, width: usize, addr: usize, value: &Vec<u8>) -> Option<BusException> {
+        self.borrow_mut().store(width, addr, value)
+    }
+
+    fn tick(&mut self) {
+        self.borrow_mut().tick()
+    }
+
+    fn memory(&self) -> Option<Memory> {
+        self.borrow().memory()
+    }
+
+    fn save(&self, w: &mut Writer) {
+        self.borrow().save(w)
+    }
+
+    fn restore(&mut self, r: &mut Reader) -> Result<(), SnapshotError> {
+        self.borrow_mut().restore(r)
+    }
 }
 
 impl Peripheral {
@@ -43,15 +92,31 @@ impl PeripheralInterface for Peripheral {
 
     fn store(&mut self, width: usize, addr: usize, value: &Vec<u8>) -> Option<BusException> {
         if addr + width > self.size {
-            return Some(BusException::LoadAccessFault);
+            return Some(BusException::StoreAccessFault);
         }
 
-        if width != 1 && width != 2 && width != 4 && width != 8 {
-            return Some(BusException::LoadAccessFault);
+        if width != 1 && width != 2 && width != 4 && width != 8 && width != 16 {
+            return Some(BusException::StoreAccessFault);
         }
 
         self.io.store(width, addr, value)
     }
+
+    fn tick(&mut self) {
+        self.io.tick()
+    }
+
+    fn memory(&self) -> Option<Memory> {
+        self.io.memory()
+    }
+
+    fn save(&self, w: &mut Writer) {
+        self.io.save(w)
+    }
+
+    fn restore(&mut self, r: &mut Reader) -> Result<(), SnapshotError> {
+        self.io.restore(r)
+    }
 }
 
 impl fmt::Display for Peripheral {
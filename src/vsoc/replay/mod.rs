@@ -0,0 +1,177 @@
+use std::fmt;
+use std::fs::File;
+use std::io::{self, LineWriter, Write};
+
+// Log layout: HEADER, then one "<step> <event>" line per input, in the order
+// the machine took them
+pub const HEADER: &str = "rustv-replay 1";
+
+#[derive(Debug, PartialEq)]
+pub enum ReplayError {
+    Io(String),
+    BadHeader,
+    Syntax(usize, String),
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s: String = match self {
+            Self::Io(e) => format!("Io({})", e),
+            Self::BadHeader => String::from("BadHeader"),
+            Self::Syntax(line, what) => format!("Syntax({}, {})", line, what),
+        };
+        write!(f, "ReplayError::{}", s)
+    }
+}
+
+// Input the machine cannot compute by itself
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Event {
+    UartRx(u8),
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::UartRx(c) => write!(f, "uart-rx {:#04x}", c),
+        }
+    }
+}
+
+impl Event {
+    fn parse(kind: &str, value: &str) -> Option<Event> {
+        let value: &str = value.strip_prefix("0x")?;
+
+        match kind {
+            "uart-rx" => u8::from_str_radix(value, 16).ok().map(Self::UartRx),
+            _ => None,
+        }
+    }
+}
+
+// Log of the events of a run, written as they happen so that it survives
+// the process exiting
+pub struct Recorder {
+    out: Box<dyn Write>,
+}
+
+impl Recorder {
+    pub fn new(path: &str) -> io::Result<Recorder> {
+        Recorder::from_writer(Box::new(LineWriter::new(File::create(path)?)))
+    }
+
+    pub fn from_writer(mut out: Box<dyn Write>) -> io::Result<Recorder> {
+        writeln!(out, "{}", HEADER)?;
+
+        Ok(Recorder { out })
+    }
+
+    pub fn event(&mut self, step: u64, event: Event) {
+        // A failing log must not stop the emulation
+        let _ = writeln!(self.out, "{} {}", step, event);
+    }
+}
+
+impl fmt::Debug for Recorder {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Recorder")
+    }
+}
+
+// Recorded events, handed back at the step they were taken
+#[derive(Debug, Default)]
+pub struct Player {
+    events: Vec<(u64, Event)>,
+    next: usize,
+}
+
+impl Player {
+    pub fn open(path: &str) -> Result<Player, ReplayError> {
+        let text: String =
+            std::fs::read_to_string(path).map_err(|e| ReplayError::Io(e.to_string()))?;
+
+        Player::parse(&text)
+    }
+
+    pub fn parse(text: &str) -> Result<Player, ReplayError> {
+        let mut lines = text.lines().enumerate();
+        let mut player: Player = Player::default();
+
+        if lines.next().map(|(_, l)| l.trim()) != Some(HEADER) {
+            return Err(ReplayError::BadHeader);
+        }
+
+        for (n, line) in lines {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+
+            if fields.is_empty() {
+                continue;
+            }
+
+            let syntax = || ReplayError::Syntax(n + 1, String::from(line));
+            let step: u64 = fields[0].parse().map_err(|_| syntax())?;
+            let event: Event = match fields[1..] {
+                [kind, value] => Event::parse(kind, value).ok_or_else(syntax)?,
+                _ => return Err(syntax()),
+            };
+
+            if player.events.last().is_some_and(|(last, _)| *last > step) {
+                return Err(syntax());
+            }
+            player.events.push((step, event));
+        }
+
+        Ok(player)
+    }
+
+    // Next event due at this step, if any
+    pub fn next(&mut self, step: u64) -> Option<Event> {
+        match self.events.get(self.next) {
+            Some((at, event)) if *at <= step => {
+                self.next += 1;
+                Some(*event)
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Event, Player, ReplayError};
+
+    #[test]
+    fn test_player() {
+        let mut player: Player =
+            Player::parse("rustv-replay 1\n12 uart-rx 0x41\n12 uart-rx 0x0a\n\n40 uart-rx 0x42\n")
+                .unwrap();
+
+        assert_eq!(player.next(0), None);
+        assert_eq!(player.next(12), Some(Event::UartRx(0x41)));
+        assert_eq!(player.next(12), Some(Event::UartRx(0x0a)));
+        assert_eq!(player.next(12), None);
+        assert_eq!(player.next(41), Some(Event::UartRx(0x42)));
+        assert_eq!(player.next(100), None);
+        assert_eq!(format!("{} {}", 12, Event::UartRx(0x0a)), "12 uart-rx 0x0a");
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(
+            Player::parse("12 uart-rx 0x41\n").unwrap_err(),
+            ReplayError::BadHeader
+        );
+        assert_eq!(
+            Player::parse("rustv-replay 1\n12 uart-tx 0x41\n").unwrap_err(),
+            ReplayError::Syntax(2, String::from("12 uart-tx 0x41"))
+        );
+        assert_eq!(
+            Player::parse("rustv-replay 1\n12 uart-rx 0x141\n").unwrap_err(),
+            ReplayError::Syntax(2, String::from("12 uart-rx 0x141"))
+        );
+        assert_eq!(
+            Player::parse("rustv-replay 1\n12 uart-rx 0x41\n3 uart-rx 0x41\n").unwrap_err(),
+            ReplayError::Syntax(3, String::from("3 uart-rx 0x41"))
+        );
+    }
+}
@@ -0,0 +1,213 @@
+use std::fmt;
+
+// File layout: MAGIC, VERSION, then the sections written by the Vsoc, all
+// integers little-endian and byte strings prefixed by their length
+pub const MAGIC: [u8; 8] = *b"RUSTVSNP";
+// 2 adds the vector registers of the harts, 3 the virtualization mode and 4
+// the step count
+pub const VERSION: u32 = 4;
+
+#[derive(Debug, PartialEq)]
+pub enum SnapshotError {
+    Io(String),
+    BadMagic,
+    UnsupportedVersion(u32),
+    Truncated,
+    Mismatch(String),
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s: String = match self {
+            Self::Io(e) => format!("Io({})", e),
+            Self::BadMagic => String::from("BadMagic"),
+            Self::UnsupportedVersion(v) => format!("UnsupportedVersion({})", v),
+            Self::Truncated => String::from("Truncated"),
+            Self::Mismatch(what) => format!("Mismatch({})", what),
+        };
+        write!(f, "SnapshotError::{}", s)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Writer {
+    buf: Vec<u8>,
+}
+
+impl Writer {
+    pub fn new() -> Writer {
+        let mut w: Writer = Writer::default();
+
+        w.buf.extend(MAGIC);
+        w.u32(VERSION);
+
+        w
+    }
+
+    pub fn u8(&mut self, v: u8) {
+        self.buf.push(v);
+    }
+
+    pub fn bool(&mut self, v: bool) {
+        self.u8(v as u8);
+    }
+
+    pub fn u32(&mut self, v: u32) {
+        self.buf.extend(v.to_le_bytes());
+    }
+
+    pub fn u64(&mut self, v: u64) {
+        self.buf.extend(v.to_le_bytes());
+    }
+
+    pub fn u128(&mut self, v: u128) {
+        self.buf.extend(v.to_le_bytes());
+    }
+
+    pub fn bytes(&mut self, v: &[u8]) {
+        self.u64(v.len() as u64);
+        self.buf.extend(v);
+    }
+
+    pub fn str(&mut self, v: &str) {
+        self.bytes(v.as_bytes());
+    }
+
+    pub fn option_u64(&mut self, v: Option<u64>) {
+        self.bool(v.is_some());
+        self.u64(v.unwrap_or(0));
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        self.buf
+    }
+}
+
+#[derive(Debug)]
+pub struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(data: &'a [u8]) -> Result<Reader<'a>, SnapshotError> {
+        let mut r: Reader = Reader { data, pos: 0 };
+
+        if r.take(MAGIC.len()).map_err(|_| SnapshotError::BadMagic)? != MAGIC {
+            return Err(SnapshotError::BadMagic);
+        }
+
+        match r.u32()? {
+            VERSION => Ok(r),
+            v => Err(SnapshotError::UnsupportedVersion(v)),
+        }
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], SnapshotError> {
+        let end: usize = self.pos.checked_add(len).ok_or(SnapshotError::Truncated)?;
+        let v: &'a [u8] = self.data.get(self.pos..end).ok_or(SnapshotError::Truncated)?;
+
+        self.pos = end;
+        Ok(v)
+    }
+
+    pub fn u8(&mut self) -> Result<u8, SnapshotError> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn bool(&mut self) -> Result<bool, SnapshotError> {
+        Ok(self.u8()? != 0)
+    }
+
+    pub fn u32(&mut self) -> Result<u32, SnapshotError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, SnapshotError> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    pub fn u128(&mut self) -> Result<u128, SnapshotError> {
+        Ok(u128::from_le_bytes(self.take(16)?.try_into().unwrap()))
+    }
+
+    pub fn bytes(&mut self) -> Result<&'a [u8], SnapshotError> {
+        let len: u64 = self.u64()?;
+
+        self.take(usize::try_from(len).map_err(|_| SnapshotError::Truncated)?)
+    }
+
+    pub fn str(&mut self) -> Result<String, SnapshotError> {
+        Ok(String::from_utf8_lossy(self.bytes()?).into_owned())
+    }
+
+    pub fn option_u64(&mut self) -> Result<Option<u64>, SnapshotError> {
+        let some: bool = self.bool()?;
+        let v: u64 = self.u64()?;
+
+        Ok(if some { Some(v) } else { None })
+    }
+
+    // Byte string of a known length, e.g. the content of a memory
+    pub fn bytes_exact(&mut self, len: usize, what: &str) -> Result<&'a [u8], SnapshotError> {
+        let v: &'a [u8] = self.bytes()?;
+
+        if v.len() != len {
+            return Err(SnapshotError::Mismatch(String::from(what)));
+        }
+
+        Ok(v)
+    }
+
+    // Configuration value that must match the machine being restored
+    pub fn expect(&mut self, v: u64, what: &str) -> Result<(), SnapshotError> {
+        if self.u64()? != v {
+            return Err(SnapshotError::Mismatch(String::from(what)));
+        }
+
+        Ok(())
+    }
+
+    pub fn finish(&self) -> Result<(), SnapshotError> {
+        if self.pos != self.data.len() {
+            return Err(SnapshotError::Mismatch(String::from("trailing data")));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Reader, SnapshotError, Writer, MAGIC, VERSION};
+
+    #[test]
+    fn test_round_trip() {
+        let mut w: Writer = Writer::new();
+
+        w.u8(0x12);
+        w.u64(0x8000_0000);
+        w.str("sram");
+        w.option_u64(None);
+        w.u128(u128::MAX);
+
+        let data: Vec<u8> = w.finish();
+        let mut r: Reader = Reader::new(&data).unwrap();
+
+        assert_eq!(r.u8(), Ok(0x12));
+        assert!(r.expect(0x8000_0000, "base").is_ok());
+        assert_eq!(r.str(), Ok(String::from("sram")));
+        assert_eq!(r.option_u64(), Ok(None));
+        assert_eq!(r.u128(), Ok(u128::MAX));
+        assert!(r.finish().is_ok());
+        assert_eq!(r.u8(), Err(SnapshotError::Truncated));
+
+        let newer: Vec<u8> = [&MAGIC[..], &(VERSION + 1).to_le_bytes()].concat();
+
+        assert_eq!(
+            Reader::new(&newer).unwrap_err(),
+            SnapshotError::UnsupportedVersion(VERSION + 1)
+        );
+        assert_eq!(Reader::new(b"RUST").unwrap_err(), SnapshotError::BadMagic);
+    }
+}
@@ -0,0 +1,98 @@
+use std::cell::RefCell;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, LineWriter, Write};
+use std::rc::Rc;
+
+// Destination of the instruction trace, shared by the harts
+#[derive(Clone)]
+pub struct Trace {
+    out: Rc<RefCell<Box<dyn Write>>>,
+    prefix: String,
+}
+
+impl Trace {
+    // `-` traces to stderr, so that it does not mix with the guest console
+    pub fn new(path: &str) -> io::Result<Trace> {
+        let out: Box<dyn Write> = match path {
+            "-" => Box::new(io::stderr()),
+            _ => Box::new(LineWriter::new(File::create(path)?)),
+        };
+
+        Ok(Trace::from_writer(out))
+    }
+
+    pub fn from_writer(out: Box<dyn Write>) -> Trace {
+        Trace {
+            out: Rc::new(RefCell::new(out)),
+            prefix: String::new(),
+        }
+    }
+
+    // Same output, with lines tagged by the hart id
+    pub fn for_hart(&self, hart: usize) -> Trace {
+        Trace {
+            out: self.out.clone(),
+            prefix: format!("[{}] ", hart),
+        }
+    }
+
+    pub fn line(&mut self, s: &str) {
+        // A failing trace output must not stop the emulation
+        let _ = writeln!(self.out.borrow_mut(), "{}{}", self.prefix, s);
+    }
+}
+
+impl fmt::Debug for Trace {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Trace")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Trace;
+    use crate::vsoc::Vsoc;
+    use std::cell::RefCell;
+    use std::io::{self, Write};
+    use std::rc::Rc;
+
+    #[derive(Clone, Default)]
+    struct Buffer(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_trace() {
+        let arch: String = String::from("rv32ic_zicsr");
+        let mut vsoc: Vsoc = Vsoc::new(&arch);
+        let buffer: Buffer = Buffer::default();
+        // li a0, 1; c.addi a0, 1; ebreak
+        let binary: [u8; 10] = [0x13, 0x05, 0x10, 0x00, 0x05, 0x05, 0x73, 0x00, 0x10, 0x00];
+
+        vsoc.load(&binary).unwrap();
+        vsoc.set_trace(Trace::from_writer(Box::new(buffer.clone())));
+        for _ in 0..3 {
+            vsoc.step();
+        }
+
+        let out: String = String::from_utf8(buffer.0.borrow().clone()).unwrap();
+        let lines: Vec<&str> = out.lines().collect();
+
+        assert_eq!(lines[0], "80000000 (00100513) li\ta0,1\ta0=0x1");
+        assert_eq!(lines[1], "80000004 (0505    ) addi\ta0,a0,1\ta0=0x2");
+        assert_eq!(
+            lines[2],
+            "80000006 (00100073) ebreak\t# RvException::Breakpoint"
+        );
+    }
+}